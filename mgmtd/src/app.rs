@@ -4,25 +4,32 @@ mod runtime;
 #[cfg(test)]
 pub(crate) mod test;
 
-use crate::StaticInfo;
+use crate::cap_pool::CapPoolEvent;
 use crate::license::LicensedFeature;
+use crate::{DynamicInfo, StaticInfo};
 use anyhow::Result;
 use protobuf::license::GetCertDataResult;
 pub(crate) use runtime::RuntimeApp;
 use rusqlite::{Connection, Transaction};
 use shared::bee_msg::Msg;
+use shared::bee_msg::target::RefreshTargetStates;
 use shared::bee_serde::{Deserializable, Serializable};
-use shared::types::{NodeId, NodeType, Uid};
+use shared::types::{AckId, NodeId, NodeType, Uid};
 use std::fmt::Debug;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
 
 pub(crate) trait App: Debug + Clone + Send + 'static {
     /// Return a borrow to the applications static, immutable config and derived info
     fn static_info(&self) -> &StaticInfo;
 
+    /// Return a copy of the applications runtime changeable settings
+    fn dynamic_info(&self) -> DynamicInfo;
+
     // Database access
 
     /// DB Read transaction
@@ -43,7 +50,8 @@ pub(crate) trait App: Debug + Clone + Send + 'static {
         op: T,
     ) -> impl Future<Output = Result<R>> + Send;
 
-    /// Provides access to a DB connection handle, no transaction
+    /// Provides access to a DB connection handle, no transaction. Meant for writing, so it fails in
+    /// read-only mode like the write transactions.
     fn db_conn<T: Send + 'static + FnOnce(&mut Connection) -> Result<R>, R: Send + 'static>(
         &self,
         op: T,
@@ -58,6 +66,16 @@ pub(crate) trait App: Debug + Clone + Send + 'static {
         msg: &M,
     ) -> impl Future<Output = Result<R>> + Send;
 
+    /// Send a [Msg] to a node via TCP and receive the response, failing with
+    /// [RequestTimeout](shared::conn::outgoing::RequestTimeout) if it doesn't arrive within
+    /// `timeout`
+    fn request_with_timeout<M: Msg + Serializable, R: Msg + Deserializable>(
+        &self,
+        node_uid: Uid,
+        msg: &M,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<R>> + Send;
+
     /// Send a [Msg] to all nodes of a type via UDP
     fn send_notifications<M: Msg + Serializable>(
         &self,
@@ -68,6 +86,13 @@ pub(crate) trait App: Debug + Clone + Send + 'static {
     /// Replace all stored BeeMsg network addresses of a node in the store
     fn replace_node_addrs(&self, node_uid: Uid, new_addrs: impl Into<Arc<[SocketAddr]>>);
 
+    // Events
+
+    /// Publish a capacity pool event to all current subscribers
+    fn publish_cap_pool_event(&self, event: CapPoolEvent);
+    /// Subscribe to capacity pool events published after this call
+    fn subscribe_cap_pool_events(&self) -> broadcast::Receiver<CapPoolEvent>;
+
     // Run state
 
     /// Check if management is in pre shutdown state
@@ -87,9 +112,45 @@ pub(crate) trait App: Debug + Clone + Send + 'static {
     /// Get license certificate data
     fn get_license_cert_data(&self) -> Result<GetCertDataResult>;
 
+    /// Get the expiry date of the loaded license certificate
+    fn get_license_expiry(&self) -> Result<Option<SystemTime>>;
+
     /// Get licensed number of machines
     fn get_licensed_machines(&self) -> Result<u32>;
 
     /// Verify a feature is licensed
     fn verify_licensed_feature(&self, feature: LicensedFeature) -> Result<()>;
 }
+
+/// Accumulates target state changes made while handling a request.
+///
+/// Allows sending out a single [RefreshTargetStates] notification after all changes have been
+/// made, instead of one per change. This avoids broadcast storms on large systems when many
+/// targets change their state at once.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub(crate) struct TargetStateChanges {
+    pub consistency: usize,
+    pub reachability: usize,
+}
+
+impl TargetStateChanges {
+    /// Returns true if no changes have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.consistency == 0 && self.reachability == 0
+    }
+
+    /// Notifies all nodes to refresh their target states, but only if any change has been
+    /// recorded
+    pub async fn notify(self, app: &impl App) {
+        if !self.is_empty() {
+            app.send_notifications(
+                &[NodeType::Meta, NodeType::Storage, NodeType::Client],
+                &RefreshTargetStates {
+                    ack_id: AckId::empty(),
+                },
+            )
+            .await;
+        }
+    }
+}
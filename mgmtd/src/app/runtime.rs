@@ -1,7 +1,9 @@
 use super::*;
 use crate::ClientPulledStateNotification;
 use crate::bee_msg::dispatch_request;
+use crate::error::TypedError;
 use crate::license::LicenseVerifier;
+use crate::metrics::time_db_op;
 use anyhow::Result;
 use protobuf::license::GetCertDataResult;
 use rusqlite::{Connection, Transaction};
@@ -11,7 +13,11 @@ use shared::run_state::WeakRunStateHandle;
 use sqlite::Connections;
 use std::fmt::Debug;
 use std::ops::Deref;
-use tokio::sync::mpsc;
+use std::sync::RwLock;
+use tokio::sync::{broadcast, mpsc};
+
+/// Number of capacity pool events buffered for each subscriber
+const CAP_POOL_EVENTS_BUF_SIZE: usize = 128;
 
 /// A collection of Handles used for interacting and accessing the different components of the app.
 ///
@@ -27,8 +33,10 @@ pub(crate) struct InnerAppHandles {
     pub db: Connections,
     pub license: LicenseVerifier,
     pub info: &'static StaticInfo,
+    dynamic_info: RwLock<DynamicInfo>,
     pub run_state: WeakRunStateHandle,
     shutdown_client_id: mpsc::Sender<ClientPulledStateNotification>,
+    cap_pool_events: broadcast::Sender<CapPoolEvent>,
 }
 
 impl RuntimeApp {
@@ -40,6 +48,7 @@ impl RuntimeApp {
         db: Connections,
         license: LicenseVerifier,
         info: &'static StaticInfo,
+        dynamic_info: DynamicInfo,
         run_state: WeakRunStateHandle,
         shutdown_client_id: mpsc::Sender<ClientPulledStateNotification>,
     ) -> Self {
@@ -48,12 +57,21 @@ impl RuntimeApp {
             db,
             license,
             info,
+            dynamic_info: RwLock::new(dynamic_info),
             run_state,
             shutdown_client_id,
+            cap_pool_events: broadcast::Sender::new(CAP_POOL_EVENTS_BUF_SIZE),
         }))
     }
 }
 
+impl RuntimeApp {
+    /// Replaces the runtime changeable settings
+    pub(crate) fn set_dynamic_info(&self, dynamic_info: DynamicInfo) {
+        *self.dynamic_info.write().unwrap() = dynamic_info;
+    }
+}
+
 /// Derefs to InnerAppHandle which stores all the handles.
 ///
 /// Allows transparent access.
@@ -77,18 +95,23 @@ impl App for RuntimeApp {
         self.info
     }
 
+    fn dynamic_info(&self) -> DynamicInfo {
+        self.dynamic_info.read().unwrap().clone()
+    }
+
     async fn read_tx<T: Send + 'static + FnOnce(&Transaction) -> Result<R>, R: Send + 'static>(
         &self,
         op: T,
     ) -> Result<R> {
-        Connections::read_tx(&self.db, op).await
+        time_db_op("read_tx", Connections::read_tx(&self.db, op)).await
     }
 
     async fn write_tx<T: Send + 'static + FnOnce(&Transaction) -> Result<R>, R: Send + 'static>(
         &self,
         op: T,
     ) -> Result<R> {
-        Connections::write_tx(&self.db, op).await
+        fail_on_read_only(&self.db)?;
+        time_db_op("write_tx", Connections::write_tx(&self.db, op)).await
     }
 
     async fn write_tx_no_sync<
@@ -98,7 +121,12 @@ impl App for RuntimeApp {
         &self,
         op: T,
     ) -> Result<R> {
-        Connections::write_tx_no_sync(&self.db, op).await
+        fail_on_read_only(&self.db)?;
+        time_db_op(
+            "write_tx_no_sync",
+            Connections::write_tx_no_sync(&self.db, op),
+        )
+        .await
     }
 
     async fn db_conn<
@@ -108,7 +136,10 @@ impl App for RuntimeApp {
         &self,
         op: T,
     ) -> Result<R> {
-        Connections::conn(&self.db, op).await
+        // The connection handle is only used for writing operations that need more control than
+        // a transaction provides
+        fail_on_read_only(&self.db)?;
+        time_db_op("conn", Connections::conn(&self.db, op)).await
     }
 
     async fn request<M: Msg + Serializable, R: Msg + Deserializable>(
@@ -119,6 +150,15 @@ impl App for RuntimeApp {
         Pool::request(&self.conn, node_uid, msg).await
     }
 
+    async fn request_with_timeout<M: Msg + Serializable, R: Msg + Deserializable>(
+        &self,
+        node_uid: Uid,
+        msg: &M,
+        timeout: Duration,
+    ) -> Result<R> {
+        Pool::request_with_timeout(&self.conn, node_uid, msg, timeout).await
+    }
+
     async fn send_notifications<M: Msg + Serializable>(
         &self,
         node_types: &'static [NodeType],
@@ -126,10 +166,22 @@ impl App for RuntimeApp {
     ) {
         log::trace!("NOTIFICATION to {node_types:?}: {msg:?}");
 
+        // If enabled, offline server nodes are skipped. Nodes within the grace period are still
+        // notified.
+        let max_age = self.info.user_config.notify_skip_offline.then(|| {
+            let dynamic_info = self.dynamic_info();
+            dynamic_info.node_offline_timeout + dynamic_info.node_offline_grace
+        });
+
         for t in node_types {
             if let Err(err) = async {
                 let nodes = self
-                    .read_tx(move |tx| crate::db::node::get_with_type(tx, *t))
+                    .read_tx(move |tx| match max_age {
+                        Some(max_age) if *t != NodeType::Client => {
+                            crate::db::node::get_reachable_with_type(tx, *t, max_age)
+                        }
+                        _ => crate::db::node::get_with_type(tx, *t),
+                    })
                     .await?;
 
                 self.conn
@@ -149,6 +201,15 @@ impl App for RuntimeApp {
         Pool::replace_node_addrs(&self.conn, node_uid, new_addrs)
     }
 
+    fn publish_cap_pool_event(&self, event: CapPoolEvent) {
+        // Sending only fails if there are no subscribers, which is fine
+        let _ = self.cap_pool_events.send(event);
+    }
+
+    fn subscribe_cap_pool_events(&self) -> broadcast::Receiver<CapPoolEvent> {
+        self.cap_pool_events.subscribe()
+    }
+
     fn is_pre_shutdown(&self) -> bool {
         WeakRunStateHandle::pre_shutdown(&self.run_state)
     }
@@ -177,6 +238,10 @@ impl App for RuntimeApp {
         LicenseVerifier::get_license_cert_data(&self.license)
     }
 
+    fn get_license_expiry(&self) -> Result<Option<SystemTime>> {
+        LicenseVerifier::get_license_expiry(&self.license)
+    }
+
     fn get_licensed_machines(&self) -> Result<u32> {
         LicenseVerifier::get_licensed_machines(&self.license)
     }
@@ -185,3 +250,137 @@ impl App for RuntimeApp {
         self.license.verify_licensed_feature(feature)
     }
 }
+
+/// Fails with [TypedError::ReadOnly] if the database has been opened read-only.
+///
+/// Checked before starting a write transaction, so the caller gets a clear error instead of the
+/// one SQLite returns on the first write statement.
+fn fail_on_read_only(db: &Connections) -> Result<()> {
+    if db.is_read_only() {
+        return Err(TypedError::ReadOnly.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use shared::bee_msg::misc::RefreshCapacityPools;
+    use tokio::net::UdpSocket;
+
+    #[tokio::test]
+    async fn send_notifications_skip_offline() {
+        let info: &'static StaticInfo = Box::leak(Box::new(StaticInfo {
+            user_config: Config {
+                notify_skip_offline: true,
+                ..Default::default()
+            },
+            auth_secret: None,
+            network_addrs: vec![],
+            use_ipv6: false,
+            start_time: std::time::Instant::now(),
+        }));
+
+        let db = crate::db::test::setup_with_test_data().await;
+        // Storage node 1 is offline, storage node 2 within the grace period
+        db.write_tx(|tx| {
+            tx.execute(
+                "UPDATE nodes SET last_contact = DATETIME('now', '-1 hour') WHERE node_uid = 102001",
+                [],
+            )?;
+            tx.execute(
+                "UPDATE nodes SET last_contact = DATETIME('now', '-200 seconds')
+                WHERE node_uid = 102002",
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let conn = Pool::new(
+            vec![Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap())],
+            1,
+            None,
+            false,
+        );
+
+        // Each storage node gets its own receiving socket
+        let mut receivers = vec![];
+        for uid in [102001, 102002, 102003, 102004] {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            conn.replace_node_addrs(uid, vec![socket.local_addr().unwrap()]);
+            receivers.push((uid, socket));
+        }
+
+        let (run_state, _run_state_control) = shared::run_state::new();
+        let (shutdown_client_tx, _shutdown_client_rx) = mpsc::channel(1);
+        let mut dynamic_info = DynamicInfo::from_config(&info.user_config);
+        dynamic_info.node_offline_grace = Duration::from_secs(60);
+
+        let app = RuntimeApp::new(
+            conn,
+            db,
+            LicenseVerifier::with_no_lib(),
+            info,
+            dynamic_info,
+            run_state.clone_weak(),
+            shutdown_client_tx,
+        );
+
+        app.send_notifications(&[NodeType::Storage], &RefreshCapacityPools::default())
+            .await;
+
+        let mut buf = [0; 1024];
+        for (uid, socket) in receivers {
+            let received = tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut buf))
+                .await
+                .is_ok();
+            assert_eq!(received, uid != 102001, "node {uid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let info: &'static StaticInfo = Box::leak(Box::new(StaticInfo {
+            user_config: Config {
+                read_only: true,
+                ..Default::default()
+            },
+            auth_secret: None,
+            network_addrs: vec![],
+            use_ipv6: false,
+            start_time: std::time::Instant::now(),
+        }));
+
+        let conn = Pool::new(
+            vec![Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap())],
+            1,
+            None,
+            false,
+        );
+        // Writes are rejected before a connection is opened, so the file doesn't need to exist
+        let db = Connections::new_read_only("/nonexistent/mgmtd.sqlite", Duration::from_secs(1));
+
+        let (run_state, _run_state_control) = shared::run_state::new();
+        let (shutdown_client_tx, _shutdown_client_rx) = mpsc::channel(1);
+        let app = RuntimeApp::new(
+            conn,
+            db,
+            LicenseVerifier::with_no_lib(),
+            info,
+            DynamicInfo::from_config(&info.user_config),
+            run_state.clone_weak(),
+            shutdown_client_tx,
+        );
+
+        let err = app.write_tx(|_| Ok(())).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TypedError::ReadOnly)));
+        let err = app.write_tx_no_sync(|_| Ok(())).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TypedError::ReadOnly)));
+        let err = app.db_conn(|_| Ok(())).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TypedError::ReadOnly)));
+    }
+}
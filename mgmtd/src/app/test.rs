@@ -3,7 +3,7 @@ use crate::config::Config;
 use shared::bee_msg::MsgId;
 pub use shared::conn::msg_dispatch::test::TestRequest;
 use shared::nic::{NicFilter, query_nics};
-use shared::types::AuthSecret;
+use shared::types::{AuthSecret, NicType};
 use sqlite::Connections;
 use std::any::Any;
 use std::net::Ipv4Addr;
@@ -17,6 +17,8 @@ use std::sync::Mutex;
 pub struct TestApp {
     pub db: Connections,
     pub info: Arc<StaticInfo>,
+    pub dynamic_info: Arc<Mutex<DynamicInfo>>,
+    pub cap_pool_events: broadcast::Sender<CapPoolEvent>,
     data: Arc<Mutex<TestData>>,
 }
 
@@ -26,6 +28,12 @@ type RequestHandler = dyn FnMut(&dyn Any) -> Result<Box<dyn Any>> + Send;
 struct TestData {
     pub notifications: Vec<(MsgId, Vec<NodeType>)>,
     request_handler: Option<Box<RequestHandler>>,
+    request_delay: Duration,
+    requests_in_flight: usize,
+    max_requests_in_flight: usize,
+    license_denied: bool,
+    licensed_machines: Option<u32>,
+    pre_shutdown: bool,
 }
 
 impl Debug for TestData {
@@ -45,6 +53,8 @@ impl TestApp {
         let db = crate::db::test::setup_with_test_data().await;
         Self {
             db,
+            dynamic_info: Arc::new(Mutex::new(DynamicInfo::from_config(&user_config))),
+            cap_pool_events: broadcast::Sender::new(16),
             info: Arc::new(StaticInfo {
                 user_config,
                 auth_secret: Some(AuthSecret::hash_from_bytes("secret")),
@@ -54,9 +64,12 @@ impl TestApp {
                         ..Default::default()
                     }],
                     true,
+                    false,
+                    Some(NicType::Tcp),
                 )
                 .unwrap(),
                 use_ipv6: false,
+                start_time: std::time::Instant::now(),
             }),
             data: Arc::new(Mutex::new(TestData::default())),
         }
@@ -68,6 +81,32 @@ impl TestApp {
     ) {
         self.data.lock().unwrap().request_handler = Some(Box::new(handler));
     }
+
+    /// Makes every following request take the given time before being handled, so concurrent
+    /// requests overlap
+    pub fn set_request_delay(&self, delay: Duration) {
+        self.data.lock().unwrap().request_delay = delay;
+    }
+
+    /// The maximum number of requests that have been in flight at the same time
+    pub fn max_requests_in_flight(&self) -> usize {
+        self.data.lock().unwrap().max_requests_in_flight
+    }
+
+    /// Makes all following licensed feature verifications fail
+    pub fn deny_licensed_features(&self) {
+        self.data.lock().unwrap().license_denied = true;
+    }
+
+    /// Sets the number of licensed machines reported by the app
+    pub fn set_licensed_machines(&self, machines: u32) {
+        self.data.lock().unwrap().licensed_machines = Some(machines);
+    }
+
+    /// Makes the app report being in pre-shutdown
+    pub fn enter_pre_shutdown(&self) {
+        self.data.lock().unwrap().pre_shutdown = true;
+    }
 }
 
 impl TestApp {
@@ -95,6 +134,10 @@ impl App for TestApp {
         &self.info
     }
 
+    fn dynamic_info(&self) -> DynamicInfo {
+        self.dynamic_info.lock().unwrap().clone()
+    }
+
     async fn read_tx<T: Send + 'static + FnOnce(&Transaction) -> Result<R>, R: Send + 'static>(
         &self,
         op: T,
@@ -132,7 +175,19 @@ impl App for TestApp {
         _node_uid: Uid,
         msg: &M,
     ) -> Result<R> {
+        let delay = {
+            let mut d = self.data.lock().unwrap();
+            d.requests_in_flight += 1;
+            d.max_requests_in_flight = d.max_requests_in_flight.max(d.requests_in_flight);
+            d.request_delay
+        };
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
         let mut d = self.data.lock().unwrap();
+        d.requests_in_flight -= 1;
         if let Some(ref mut h) = d.request_handler {
             h(msg).map(|r| *r.downcast().unwrap())
         } else {
@@ -140,6 +195,15 @@ impl App for TestApp {
         }
     }
 
+    async fn request_with_timeout<M: Msg + Serializable, R: Msg + Deserializable>(
+        &self,
+        node_uid: Uid,
+        msg: &M,
+        _timeout: Duration,
+    ) -> Result<R> {
+        self.request(node_uid, msg).await
+    }
+
     async fn send_notifications<M: Msg + Serializable>(
         &self,
         node_types: &'static [NodeType],
@@ -154,8 +218,16 @@ impl App for TestApp {
 
     fn replace_node_addrs(&self, _node_uid: Uid, _new_addrs: impl Into<Arc<[SocketAddr]>>) {}
 
+    fn publish_cap_pool_event(&self, event: CapPoolEvent) {
+        let _ = self.cap_pool_events.send(event);
+    }
+
+    fn subscribe_cap_pool_events(&self) -> broadcast::Receiver<CapPoolEvent> {
+        self.cap_pool_events.subscribe()
+    }
+
     fn is_pre_shutdown(&self) -> bool {
-        false
+        self.data.lock().unwrap().pre_shutdown
     }
 
     fn notify_client_pulled_state(&self, _node_type: NodeType, _node_id: NodeId) {}
@@ -172,11 +244,19 @@ impl App for TestApp {
         Ok(protobuf::license::GetCertDataResult::default())
     }
 
+    fn get_license_expiry(&self) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
     fn get_licensed_machines(&self) -> Result<u32> {
-        Ok(128)
+        Ok(self.data.lock().unwrap().licensed_machines.unwrap_or(128))
     }
 
-    fn verify_licensed_feature(&self, _feature: LicensedFeature) -> Result<()> {
+    fn verify_licensed_feature(&self, feature: LicensedFeature) -> Result<()> {
+        if self.data.lock().unwrap().license_denied {
+            anyhow::bail!("Feature {feature:?} is not licensed");
+        }
+
         Ok(())
     }
 }
@@ -95,16 +95,11 @@ pub(crate) async fn dispatch_request(app: &RuntimeApp, mut req: impl Request) ->
             match req.header().msg_id() {
                 $(
                     <$msg_type>::ID => {
-                        let des: $msg_type = req.deserialize_msg().with_context(|| {
-                            format!(
-                                "{} ({}) from {:?}",
-                                stringify!($msg_type),
-                                req.header().msg_id(),
-                                req.addr()
-                            )
-                        })?;
+                        // The error contains the message type and the peer
+                        let des: $msg_type = req.deserialize_msg()?;
 
                         log::trace!("INCOMING from {:?}: {:?}", req.addr(), des);
+                        crate::metrics::BEEMSG_MESSAGES.inc(&[stringify!($msg_type)]);
 
                         let res = des.handle(app, &mut req).await;
                         dispatch_msg!(@HANDLE res, $msg_type => $r, $ctx_str)
@@ -131,6 +126,11 @@ pub(crate) async fn dispatch_request(app: &RuntimeApp, mut req: impl Request) ->
                         }).await;
                     }
 
+                    if let Some(TypedError::ReadOnly) = err.downcast_ref::<TypedError>() {
+                        log::debug!("{}: {err}", $ctx_str);
+                        return req.respond(&<$msg_type>::error_response()).await;
+                    }
+
                     log::error!("{}: {err:#}", $ctx_str);
                     <$msg_type>::error_response()
                 }
@@ -21,7 +21,8 @@ impl HandleWithResponse for ChangeTargetConsistencyStates {
             "The lengths of the target_ids, new_states and old_states lists don't match up"
         );
 
-        let node_offline_timeout = app.static_info().user_config.node_offline_timeout;
+        let node_offline_timeout = app.dynamic_info().node_offline_timeout;
+        let node_offline_grace = app.dynamic_info().node_offline_grace;
         let target_ids = self.target_ids.clone();
         let (consistencies_changed, reachabilities_changed) = app
             .write_tx(move |tx| {
@@ -32,8 +33,13 @@ impl HandleWithResponse for ChangeTargetConsistencyStates {
 
                 // Old management updates contact time while handling this message (comes usually in
                 // every 30 seconds), so we do it as well.
-                let reachabilities_changed =
-                    update_last_contact_times(tx, &target_ids, node_type, node_offline_timeout)?;
+                let reachabilities_changed = update_last_contact_times(
+                    tx,
+                    &target_ids,
+                    node_type,
+                    node_offline_timeout,
+                    node_offline_grace,
+                )?;
 
                 // Check reported old_state
                 let mut check = tx.prepare_cached(sql!(
@@ -74,13 +80,12 @@ doesn't match stored state {old_stored}, no consistency state changes will be ma
 
         // To avoid spamming, we only send out the refresh notification if there is any actual
         // change
-        if consistencies_changed.unwrap_or(0) > 0 || reachabilities_changed > 0 {
-            app.send_notifications(
-                &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-                &RefreshTargetStates { ack_id: "".into() },
-            )
-            .await;
+        TargetStateChanges {
+            consistency: consistencies_changed.unwrap_or(0),
+            reachability: reachabilities_changed,
         }
+        .notify(app)
+        .await;
 
         Ok(ChangeTargetConsistencyStatesResp {
             result: match consistencies_changed {
@@ -120,7 +125,7 @@ mod test {
             target_ids: vec![1, 5],
             old_states: vec![TargetConsistencyState::Good, TargetConsistencyState::Good],
             new_states: vec![TargetConsistencyState::Good, TargetConsistencyState::Good],
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
         };
         let resp = msg.clone().handle(&app, &mut req).await.unwrap();
 
@@ -143,7 +148,7 @@ mod test {
                 TargetConsistencyState::NeedsResync,
                 TargetConsistencyState::Bad,
             ],
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
         };
         msg.handle(&app, &mut req).await.unwrap();
 
@@ -183,7 +188,7 @@ mod test {
             target_ids: vec![1],
             old_states: vec![TargetConsistencyState::NeedsResync],
             new_states: vec![TargetConsistencyState::Bad],
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
         };
         let resp = msg.handle(&app, &mut req).await.unwrap();
 
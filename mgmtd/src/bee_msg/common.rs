@@ -18,6 +18,10 @@ pub(super) async fn update_node(msg: RegisterNode, app: &impl App, reject: bool)
     let nics = msg.nics.clone();
     let requested_node_id = msg.node_id;
     let registration_disable = app.static_info().user_config.registration_disable;
+    let machine_uuid_strict = app
+        .static_info()
+        .user_config
+        .registration_machine_uuid_strict;
 
     let licensed_clients: Option<u32> = if msg.node_type == NodeType::Client {
         match app.get_license_cert_data() {
@@ -76,48 +80,80 @@ pub(super) async fn update_node(msg: RegisterNode, app: &impl App, reject: bool)
                 None
             };
 
+            let machine_uuid_changed = if let Some(ref node) = node {
+                db::node::get_machine_uuid(tx, node.uid)?.as_deref() != machine_uuid
+            } else {
+                true
+            };
+
+            // Only enforce the machine limit for new nodes or nodes moving to another machine.
+            // Nodes already registered on their machine keep working if the limit is lowered (e.g.
+            // due to a missing license certificate after an upgrade).
             if let Some(machine_uuid) = machine_uuid
+                && machine_uuid_changed
                 && db::node::count_machines(tx, machine_uuid, node.as_ref().map(|n| n.uid))?
                     >= licensed_machines
             {
-                bail!("Licensed machine limit reached. Node registration denied.");
+                bail!(
+                    "Licensed machine limit ({licensed_machines}) reached. Node registration denied."
+                );
             }
 
+            // Only check for conflicts if the machine UUID is new or changed. Otherwise, every
+            // heartbeat of an already known conflicting node would warn again.
+            if let Some(machine_uuid) = machine_uuid
+                && machine_uuid_changed
+            {
+                let conflicts = db::node::get_machine_uuid_conflicts(
+                    tx,
+                    machine_uuid,
+                    msg.node_type,
+                    node.as_ref().map(|n| n.uid),
+                )?;
+
+                if !conflicts.is_empty() {
+                    let warning = format!(
+                        "Registering {} node uses machine UUID {machine_uuid} which is already in \
+use by {} node(s) {conflicts:?}. The machines might have been cloned from the same image",
+                        msg.node_type.user_str(),
+                        msg.node_type.user_str()
+                    );
+
+                    if machine_uuid_strict {
+                        bail!("{warning}. Node registration denied.");
+                    }
+
+                    log::warn!("{warning}");
+                }
+            }
+
+            // Remember the previously advertised addresses. Address conflicts are only warned about
+            // when they change, not on every heartbeat.
+            let mut prev_addrs = match node {
+                Some(ref node) => db::node_nic::get_with_node(tx, node.uid)?
+                    .into_iter()
+                    .map(|e| SocketAddr::new(e.addr, e.port))
+                    .collect(),
+                None => vec![],
+            };
+            prev_addrs.sort();
+
             let new_alias_or_reg_token = String::from_utf8(msg.node_alias)?;
 
             let (node, is_new) = if let Some(node) = node {
                 // Existing node, update data
                 db::node::update(tx, node.uid, msg.port, machine_uuid)?;
 
-                // If the updated node is a meta node, check if its corresponding target has a
-                // registration token
+                // If the updated node is a meta node, check and record the registration token of
+                // its corresponding target. Meta target IDs always equal their node ID.
                 if msg.node_type == NodeType::Meta {
-                    let stored_reg_token: Option<String> = tx.query_row(
-                        sql!("SELECT reg_token FROM meta_targets WHERE node_id = ?1"),
-                        [node.num_id()],
-                        |row| row.get(0),
-                    )?;
-
-                    if let Some(ref t) = stored_reg_token
-                        && t != &new_alias_or_reg_token
-                    {
-                        bail!(
-                            "Meta node {node} has already been registered and its \
-registration token ({new_alias_or_reg_token}) does not match the stored token ({t})",
-                        );
-                    } else if stored_reg_token.is_none() && !new_alias_or_reg_token.is_empty() {
-                        tx.execute(
-                            sql!(
-                                "UPDATE targets SET reg_token = ?1
-                                WHERE node_id = ?2 AND node_type = ?3"
-                            ),
-                            rusqlite::params![
-                                new_alias_or_reg_token,
-                                node.num_id(),
-                                NodeType::Meta.sql_variant()
-                            ],
-                        )?;
-                    }
+                    db::target::check_and_record_reg_token(
+                        tx,
+                        node.num_id().try_into()?,
+                        NodeTypeServer::Meta,
+                        &new_alias_or_reg_token,
+                    )
+                    .with_context(|| format!("Meta node {node} re-registration failed"))?;
                 }
 
                 (node, false)
@@ -148,12 +184,12 @@ registration token ({new_alias_or_reg_token}) does not match the stored token ({
 
                 let new_alias = if msg.node_type == NodeType::Client {
                     // In versions prior to 8.0 the string node ID generated by the client
-                    // started with a number which is not allowed by the new alias schema.
-                    // As part of BeeGFS 8 the nodeID generated for each client mount was
-                    // updated to no longer start with a number, thus it is unlikely this
-                    // would happen unless BeeGFS 8 was mounted by a BeeGFS 7 client.
+                    // started with a number which is not allowed by the new alias schema (see
+                    // Alias::new()). As part of BeeGFS 8 the nodeID generated for each client
+                    // mount was updated to no longer start with a number, thus it is unlikely
+                    // this would happen unless BeeGFS 8 was mounted by a BeeGFS 7 client.
 
-                    let new_alias = Alias::try_from(new_alias_or_reg_token.clone()).ok();
+                    let new_alias = Alias::new(new_alias_or_reg_token.clone()).ok();
 
                     if new_alias.is_none() {
                         log::warn!(
@@ -223,6 +259,21 @@ client version < 8.0)"
                 }),
             )?;
 
+            let mut new_addrs: Vec<_> = msg
+                .nics
+                .iter()
+                .map(|e| SocketAddr::new(e.addr, msg.port))
+                .collect();
+            new_addrs.sort();
+
+            // Two nodes claiming the same address confuse the BeeMsg routing. This might also
+            // happen temporarily on legitimate re-registrations, so only warn about it.
+            if new_addrs != prev_addrs {
+                for warning in addr_conflict_warnings(tx, &node)? {
+                    log::warn!("{warning}");
+                }
+            }
+
             let meta_root = match node.node_type() {
                 // In case this is a meta node, the requester expects info about the meta
                 // root
@@ -263,7 +314,7 @@ client version < 8.0)"
             nic_list_version: 0,
             node_type: node.node_type(),
             node_alias: String::from(node.alias).into_bytes(),
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
             node_num_id,
             root_num_id: match meta_root {
                 MetaRoot::Unknown => 0,
@@ -285,11 +336,26 @@ client version < 8.0)"
     Ok(node_num_id)
 }
 
+/// Checks whether the given node advertises an address and port that is also used by a different
+/// node and returns a warning for each conflict.
+pub(super) fn addr_conflict_warnings(tx: &Transaction, node: &EntityIdSet) -> Result<Vec<String>> {
+    Ok(db::node_nic::get_addr_conflicts(tx, node.uid)?
+        .into_iter()
+        .map(|(addr, other)| {
+            format!(
+                "Node {node} advertises address {addr} which is also used by node {other}. \
+Communication with these nodes might fail or reach the wrong node"
+            )
+        })
+        .collect())
+}
+
 pub(super) fn get_targets_with_states(
     tx: &Transaction,
     pre_shutdown: bool,
     node_type: NodeTypeServer,
     node_offline_timeout: Duration,
+    node_offline_grace: Duration,
 ) -> Result<Vec<(TargetId, TargetConsistencyState, TargetReachabilityState)>> {
     let targets = tx.query_map_collect(
         sql!(
@@ -314,7 +380,9 @@ pub(super) fn get_targets_with_states(
 
                     // We never want to report a primary node of a buddy group as offline since this
                     // is considered invalid. Instead we just report ProbablyOffline and wait for the switchover.
-                    if !is_primary && age > node_offline_timeout {
+                    // Within the grace period after the timeout, targets are also only reported as
+                    // ProbablyOffline.
+                    if !is_primary && age > node_offline_timeout + node_offline_grace {
                         TargetReachabilityState::Offline
                     } else if age > node_offline_timeout / 2 {
                         TargetReachabilityState::ProbablyOffline
@@ -336,12 +404,15 @@ pub(super) fn get_targets_with_states(
 ///
 /// # Return Value
 /// Returns the number of targets (not nodes) that were not considered "online" before. Meaning they
-/// were at least "probably offline".
+/// were at least "probably offline". For these, the transitions to "probably offline", "offline"
+/// (if reached) and back to "online" are recorded in the target state history, each with the time
+/// it happened.
 pub(super) fn update_last_contact_times(
     tx: &Transaction,
     target_ids: &[TargetId],
     node_type: NodeTypeServer,
     offline_timeout: Duration,
+    offline_grace: Duration,
 ) -> Result<usize> {
     let target_ids_param = sqlite::rarray_param(target_ids.iter().copied());
 
@@ -355,20 +426,51 @@ pub(super) fn update_last_contact_times(
         rusqlite::params![&target_ids_param, node_type.sql_variant()],
     )?;
 
-    let reachabilities_changed: usize = tx.query_row_cached(
+    let probably_offline_after = offline_timeout / 2;
+    let offline_after = offline_timeout + offline_grace;
+
+    let reachabilities_changed: Vec<(TargetId, Duration, bool)> = tx.query_map_collect(
         sql!(
-            "SELECT COUNT(*) FROM targets
-            WHERE target_id IN rarray(?1) AND node_type = ?2
-                AND UNIXEPOCH(last_update) < UNIXEPOCH('now') - ?3"
+            "SELECT t.target_id, (UNIXEPOCH('now') - UNIXEPOCH(t.last_update)),
+                gp.p_target_id IS NOT NULL
+            FROM targets AS t
+            LEFT JOIN buddy_groups AS gp ON gp.p_target_id = t.target_id AND gp.node_type = t.node_type
+            WHERE t.target_id IN rarray(?1) AND t.node_type = ?2
+                AND UNIXEPOCH(t.last_update) < UNIXEPOCH('now') - ?3"
         ),
         rusqlite::params![
             &target_ids_param,
             node_type.sql_variant(),
-            offline_timeout.as_secs() / 2
+            probably_offline_after.as_secs()
         ],
-        |row| row.get(0),
+        |row| Ok((row.get(0)?, Duration::from_secs(row.get(1)?), row.get(2)?)),
     )?;
 
+    // The transitions to (probably) offline happen passively without contact, so they are
+    // recorded now, dated back to when they happened. Primaries are never reported as offline
+    // (see get_targets_with_states()).
+    for (target_id, age, is_primary) in &reachabilities_changed {
+        db::target_state_history::insert(
+            tx,
+            *target_id,
+            node_type,
+            "probably_offline",
+            age.saturating_sub(probably_offline_after),
+        )?;
+
+        if !is_primary && *age > offline_after {
+            db::target_state_history::insert(
+                tx,
+                *target_id,
+                node_type,
+                "offline",
+                age.saturating_sub(offline_after),
+            )?;
+        }
+
+        db::target_state_history::insert(tx, *target_id, node_type, "online", Duration::ZERO)?;
+    }
+
     tx.execute_cached(
         sql!(
             "UPDATE targets SET last_update = DATETIME('now')
@@ -377,5 +479,109 @@ pub(super) fn update_last_contact_times(
         rusqlite::params![&target_ids_param, node_type.sql_variant()],
     )?;
 
-    Ok(reachabilities_changed)
+    Ok(reachabilities_changed.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::with_test_data;
+
+    #[test]
+    fn targets_with_states_grace() {
+        with_test_data(|tx| {
+            // Meta target 3 is not part of a buddy group
+            tx.execute(
+                "UPDATE targets SET last_update = DATETIME('now', '-150 seconds')
+                WHERE target_uid = 201003",
+                [],
+            )
+            .unwrap();
+
+            let state = |timeout, grace| {
+                get_targets_with_states(
+                    tx,
+                    false,
+                    NodeTypeServer::Meta,
+                    Duration::from_secs(timeout),
+                    Duration::from_secs(grace),
+                )
+                .unwrap()
+                .into_iter()
+                .find(|e| e.0 == 3)
+                .unwrap()
+                .2
+            };
+
+            // Without grace, the timeout applies immediately
+            assert_eq!(state(100, 0), TargetReachabilityState::Offline);
+            // Within the grace period, the target is only probably offline
+            assert_eq!(state(100, 100), TargetReachabilityState::ProbablyOffline);
+            // After the grace period elapsed, the target is offline
+            assert_eq!(state(100, 40), TargetReachabilityState::Offline);
+            assert_eq!(state(400, 0), TargetReachabilityState::Online);
+        })
+    }
+
+    #[test]
+    fn update_last_contact_times_history() {
+        with_test_data(|tx| {
+            let set_age = |secs: u64| {
+                tx.execute(
+                    "UPDATE targets SET last_update = DATETIME('now', '-' || ?1 || ' seconds')
+                    WHERE target_uid = 201003",
+                    [secs],
+                )
+                .unwrap();
+            };
+
+            // Returns the recorded states of meta target 3 and how long ago they happened
+            let history = || -> Vec<(String, i64)> {
+                tx.query_map_collect(
+                    "SELECT state, UNIXEPOCH('now') - time FROM target_state_history
+                    WHERE target_uid = 201003 ORDER BY id",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap()
+            };
+
+            let update = || {
+                update_last_contact_times(
+                    tx,
+                    &[3],
+                    NodeTypeServer::Meta,
+                    Duration::from_secs(100),
+                    Duration::from_secs(20),
+                )
+                .unwrap()
+            };
+
+            // Online, no transition
+            set_age(10);
+            assert_eq!(update(), 0);
+            assert!(history().is_empty());
+
+            // Online -> probably offline -> online
+            set_age(60);
+            assert_eq!(update(), 1);
+            let h = history();
+            assert_eq!(h.len(), 2);
+            assert_eq!(h[0].0, "probably_offline");
+            assert!((9..=11).contains(&h[0].1), "{h:?}");
+            assert_eq!(h[1].0, "online");
+            assert!((0..=1).contains(&h[1].1), "{h:?}");
+
+            // Online -> probably offline -> offline -> online
+            set_age(150);
+            assert_eq!(update(), 1);
+            let h = history();
+            assert_eq!(h.len(), 5);
+            assert_eq!(h[2].0, "probably_offline");
+            assert!((99..=101).contains(&h[2].1), "{h:?}");
+            assert_eq!(h[3].0, "offline");
+            assert!((29..=31).contains(&h[3].1), "{h:?}");
+            assert_eq!(h[4].0, "online");
+        })
+    }
 }
@@ -9,6 +9,8 @@ struct TargetOrBuddyGroup {
     pool_id: Option<PoolId>,
     free_space: Option<u64>,
     free_inodes: Option<u64>,
+    /// The pool the target / buddy group has last been classified into
+    cap_pool: Option<CapacityPool>,
 }
 
 impl CapacityInfo for &TargetOrBuddyGroup {
@@ -27,7 +29,7 @@ fn load_targets_info_by_type(
 ) -> Result<Vec<TargetOrBuddyGroup>> {
     let targets = tx.query_map_collect(
         sql!(
-            "SELECT target_id, pool_id, free_space, free_inodes
+            "SELECT target_id, pool_id, free_space, free_inodes, cap_pool
             FROM targets
             WHERE node_type = ?1"
         ),
@@ -38,6 +40,7 @@ fn load_targets_info_by_type(
                 pool_id: row.get(1)?,
                 free_space: row.get(2)?,
                 free_inodes: row.get(3)?,
+                cap_pool: CapacityPool::from_row_opt(row, 4)?,
             })
         },
     )?;
@@ -53,7 +56,8 @@ fn load_buddy_groups_info_by_type(
         sql!(
             "SELECT g.group_id, g.pool_id,
                 MIN(p_t.free_space, s_t.free_space),
-                MIN(p_t.free_inodes, s_t.free_inodes)
+                MIN(p_t.free_inodes, s_t.free_inodes),
+                MAX(p_t.cap_pool, s_t.cap_pool)
             FROM buddy_groups_ext AS g
             INNER JOIN targets AS p_t ON p_t.target_uid = g.p_target_uid AND p_t.node_type = g.node_type
             INNER JOIN targets AS s_t ON s_t.target_uid = g.s_target_uid AND s_t.node_type = g.node_type
@@ -66,6 +70,7 @@ fn load_buddy_groups_info_by_type(
                 pool_id: row.get(1)?,
                 free_space: row.get(2)?,
                 free_inodes: row.get(3)?,
+                cap_pool: CapacityPool::from_row_opt(row, 4)?,
             })
         },
     )?;
@@ -98,7 +103,7 @@ impl HandleWithResponse for GetNodeCapacityPools {
                 let mut res = vec![Vec::<u16>::new(), vec![], vec![]];
                 for t in &targets {
                     let cp = cp_calc
-                        .cap_pool(t.free_space(), t.free_inodes())
+                        .cap_pool_with_previous(t.free_space(), t.free_inodes(), t.cap_pool)
                         .bee_msg_vec_index();
                     res[cp].push(t.id);
                 }
@@ -140,7 +145,7 @@ impl HandleWithResponse for GetNodeCapacityPools {
                     res.insert(sp, vec![Vec::<u16>::new(), vec![], vec![]]);
                     for t in f_targets {
                         let cp = cp_calc
-                            .cap_pool(t.free_space(), t.free_inodes())
+                            .cap_pool_with_previous(t.free_space(), t.free_inodes(), t.cap_pool)
                             .bee_msg_vec_index();
                         res.get_mut(&sp).unwrap()[cp].push(t.id);
                     }
@@ -167,7 +172,7 @@ impl HandleWithResponse for GetNodeCapacityPools {
 
                 for e in &groups {
                     let cp = cp_calc
-                        .cap_pool(e.free_space(), e.free_inodes())
+                        .cap_pool_with_previous(e.free_space(), e.free_inodes(), e.cap_pool)
                         .bee_msg_vec_index();
                     res[cp].push(e.id);
                 }
@@ -209,7 +214,7 @@ impl HandleWithResponse for GetNodeCapacityPools {
                     cap_pools.insert(sp, vec![Vec::<u16>::new(), vec![], vec![]]);
                     for t in f_groups {
                         let cp = cp_calc
-                            .cap_pool(t.free_space(), t.free_inodes())
+                            .cap_pool_with_previous(t.free_space(), t.free_inodes(), t.cap_pool)
                             .bee_msg_vec_index();
                         cap_pools.get_mut(&sp).unwrap()[cp].push(t.id);
                     }
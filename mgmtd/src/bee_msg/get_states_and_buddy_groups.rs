@@ -9,7 +9,8 @@ impl HandleWithResponse for GetStatesAndBuddyGroups {
         let node_type: NodeTypeServer = self.node_type.try_into()?;
 
         let pre_shutdown = app.is_pre_shutdown();
-        let node_offline_timeout = app.static_info().user_config.node_offline_timeout;
+        let node_offline_timeout = app.dynamic_info().node_offline_timeout;
+        let node_offline_grace = app.dynamic_info().node_offline_grace;
 
         let (targets, groups) = app
             .read_tx(move |tx| {
@@ -18,6 +19,7 @@ impl HandleWithResponse for GetStatesAndBuddyGroups {
                     pre_shutdown,
                     self.node_type.try_into()?,
                     node_offline_timeout,
+                    node_offline_grace,
                 )?;
 
                 let groups: Vec<(BuddyGroupId, TargetId, TargetId)> = tx.query_map_collect(
@@ -60,6 +62,7 @@ impl HandleWithResponse for GetStatesAndBuddyGroups {
                 })
                 .collect(),
             states,
+            pre_shutdown,
         };
 
         // If it's a client that requested it, notify the run controller that it pulled states
@@ -70,3 +73,42 @@ impl HandleWithResponse for GetStatesAndBuddyGroups {
         Ok(resp)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::Header;
+    use shared::bee_msg::target::TargetReachabilityState;
+
+    #[tokio::test]
+    async fn get_states_and_buddy_groups_pre_shutdown() {
+        let app = TestApp::new().await;
+
+        let get = || async {
+            GetStatesAndBuddyGroups {
+                node_type: NodeType::Storage,
+                requested_by_client_id: 0,
+            }
+            .handle(&app, &mut TestRequest::new(Header::default()))
+            .await
+            .unwrap()
+        };
+
+        let resp = get().await;
+        assert!(!resp.pre_shutdown);
+        assert!(!resp.groups.is_empty());
+
+        app.enter_pre_shutdown();
+
+        let resp = get().await;
+        assert!(resp.pre_shutdown);
+        // Primaries are never reported as online during pre-shutdown
+        for g in resp.groups.values() {
+            assert_eq!(
+                resp.states[&g.primary_target_id].reachability,
+                TargetReachabilityState::ProbablyOffline
+            );
+        }
+    }
+}
@@ -9,6 +9,8 @@ struct TargetOrBuddyGroup {
     pool_id: PoolId,
     free_space: Option<u64>,
     free_inodes: Option<u64>,
+    /// The pool the target / buddy group has last been classified into
+    cap_pool: Option<CapacityPool>,
 }
 
 impl CapacityInfo for &TargetOrBuddyGroup {
@@ -38,7 +40,7 @@ impl HandleWithResponse for GetStoragePools {
 
                 let targets: Vec<TargetOrBuddyGroup> = tx.query_map_collect(
                     sql!(
-                        "SELECT target_id, node_id, pool_id, free_space, free_inodes
+                        "SELECT target_id, node_id, pool_id, free_space, free_inodes, cap_pool
                         FROM storage_targets
                         WHERE node_id IS NOT NULL"
                     ),
@@ -50,6 +52,7 @@ impl HandleWithResponse for GetStoragePools {
                             pool_id: row.get(2)?,
                             free_space: row.get(3)?,
                             free_inodes: row.get(4)?,
+                            cap_pool: CapacityPool::from_row_opt(row, 5)?,
                         })
                     },
                 )?;
@@ -58,7 +61,8 @@ impl HandleWithResponse for GetStoragePools {
                     sql!(
                         "SELECT group_id, g.pool_id,
                             MIN(p_t.free_space, s_t.free_space),
-                            MIN(p_t.free_inodes, s_t.free_inodes)
+                            MIN(p_t.free_inodes, s_t.free_inodes),
+                            MAX(p_t.cap_pool, s_t.cap_pool)
                         FROM storage_buddy_groups AS g
                         INNER JOIN targets AS p_t ON p_t.target_id = g.p_target_id
                             AND p_t.node_type = g.node_type
@@ -73,6 +77,7 @@ impl HandleWithResponse for GetStoragePools {
                             pool_id: row.get(1)?,
                             free_space: row.get(2)?,
                             free_inodes: row.get(3)?,
+                            cap_pool: CapacityPool::from_row_opt(row, 4)?,
                         })
                     },
                 )?;
@@ -130,7 +135,11 @@ impl HandleWithResponse for GetStoragePools {
                 // Only collect targets belonging to the current pool
                 for target in f_targets {
                     let cp = cp_targets_calc
-                        .cap_pool(target.free_space(), target.free_inodes())
+                        .cap_pool_with_previous(
+                            target.free_space(),
+                            target.free_inodes(),
+                            target.cap_pool,
+                        )
                         .bee_msg_vec_index();
 
                     let target_id: TargetId = target.id;
@@ -151,7 +160,11 @@ impl HandleWithResponse for GetStoragePools {
                     buddy_group_vec.push(group.id);
 
                     let cp = cp_buddy_groups_calc
-                        .cap_pool(group.free_space(), group.free_inodes())
+                        .cap_pool_with_previous(
+                            group.free_space(),
+                            group.free_inodes(),
+                            group.cap_pool,
+                        )
                         .bee_msg_vec_index();
                     buddy_group_cap_pools[cp].push(group.id);
                 }
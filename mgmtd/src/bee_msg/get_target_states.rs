@@ -7,7 +7,8 @@ impl HandleWithResponse for GetTargetStates {
 
     async fn handle(self, app: &impl App, _req: &mut impl Request) -> Result<Self::Response> {
         let pre_shutdown = app.is_pre_shutdown();
-        let node_offline_timeout = app.static_info().user_config.node_offline_timeout;
+        let node_offline_timeout = app.dynamic_info().node_offline_timeout;
+        let node_offline_grace = app.dynamic_info().node_offline_grace;
 
         let targets = app
             .read_tx(move |tx| {
@@ -16,6 +17,7 @@ impl HandleWithResponse for GetTargetStates {
                     pre_shutdown,
                     self.node_type.try_into()?,
                     node_offline_timeout,
+                    node_offline_grace,
                 )
             })
             .await?;
@@ -34,8 +36,85 @@ impl HandleWithResponse for GetTargetStates {
             targets: target_ids,
             consistency_states,
             reachability_states,
+            pre_shutdown,
         };
 
         Ok(resp)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::{Header, deserialize, deserialize_header, serialize};
+
+    #[tokio::test]
+    async fn get_target_states_pre_shutdown() {
+        let app = TestApp::new().await;
+
+        let get = || async {
+            GetTargetStates {
+                node_type: NodeType::Storage,
+            }
+            .handle(&app, &mut TestRequest::new(Header::default()))
+            .await
+            .unwrap()
+        };
+
+        let resp = get().await;
+        assert!(!resp.pre_shutdown);
+        assert!(!resp.targets.is_empty());
+
+        app.enter_pre_shutdown();
+
+        let resp = get().await;
+        assert!(resp.pre_shutdown);
+
+        // Apart from secondaries, all targets are reported as probably offline
+        let secondaries: Vec<TargetId> = app
+            .read_tx(|tx| {
+                tx.query_map_collect(
+                    sql!("SELECT s_target_id FROM buddy_groups WHERE node_type = 2"),
+                    [],
+                    |row| row.get(0),
+                )
+                .map_err(Into::into)
+            })
+            .await
+            .unwrap();
+        for (id, state) in resp.targets.iter().zip(&resp.reachability_states) {
+            if !secondaries.contains(id) {
+                assert_eq!(*state, TargetReachabilityState::ProbablyOffline);
+            }
+        }
+
+        // The flag is transferred in the header and doesn't change the body
+        let mut buf = vec![0; 4096];
+        let len = serialize(&resp, &mut buf).unwrap();
+        let header = deserialize_header(&buf).unwrap();
+        assert_eq!(
+            header.msg_compat_feature_flags & COMPAT_FLAG_MGMTD_PRE_SHUTDOWN,
+            COMPAT_FLAG_MGMTD_PRE_SHUTDOWN
+        );
+        assert_eq!(
+            deserialize::<GetTargetStatesResp>(&buf[..len]).unwrap(),
+            resp
+        );
+
+        let len_without_flag = serialize(
+            &GetTargetStatesResp {
+                pre_shutdown: false,
+                ..resp.clone()
+            },
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(len_without_flag, len);
+        assert!(
+            !deserialize::<GetTargetStatesResp>(&buf[..len])
+                .unwrap()
+                .pre_shutdown
+        );
+    }
+}
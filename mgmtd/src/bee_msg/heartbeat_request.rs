@@ -24,7 +24,7 @@ impl HandleWithResponse for HeartbeatRequest {
             nic_list_version: 0,
             node_type: shared::types::NodeType::Management,
             node_alias: alias.into_bytes(),
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
             node_num_id: MGMTD_ID,
             root_num_id: 0,
             is_root_mirrored: 0,
@@ -1,4 +1,5 @@
 use super::*;
+use rusqlite::params;
 use shared::bee_msg::storage_pool::RefreshStoragePools;
 use shared::bee_msg::target::*;
 
@@ -9,6 +10,7 @@ impl HandleWithResponse for MapTargets {
         fail_on_pre_shutdown(app)?;
 
         let target_ids = self.target_ids.keys().copied().collect::<Vec<_>>();
+        let max_targets = app.static_info().user_config.max_targets_per_node;
 
         let updated = app
             .write_tx(move |tx| {
@@ -23,6 +25,24 @@ impl HandleWithResponse for MapTargets {
                 // Due to the check above, this must always match all the given ids
                 let updated =
                     db::target::update_storage_node_mappings(tx, &target_ids, node.num_id())?;
+
+                // Check the limit after mapping, so targets that are already mapped to the node
+                // are not counted twice. Exceeding it rolls back the whole transaction.
+                if let Some(max_targets) = max_targets {
+                    let count: usize = tx.query_row_cached(
+                        sql!("SELECT COUNT(*) FROM targets WHERE node_type = ?1 AND node_id = ?2"),
+                        params![NodeType::Storage.sql_variant(), node.num_id()],
+                        |row| row.get(0),
+                    )?;
+
+                    if count > max_targets {
+                        bail!(
+                            "Mapping storage targets {target_ids:?} to node {node} would exceed \
+                            the limit of {max_targets} targets per node"
+                        );
+                    }
+                }
+
                 Ok(updated)
             })
             .await?;
@@ -40,7 +60,7 @@ impl HandleWithResponse for MapTargets {
             &MapTargets {
                 target_ids: self.target_ids.clone(),
                 node_id: self.node_id,
-                ack_id: "".into(),
+                ack_id: AckId::empty(),
             },
         )
         .await;
@@ -49,7 +69,9 @@ impl HandleWithResponse for MapTargets {
         if updated > 0 {
             app.send_notifications(
                 &[NodeType::Meta, NodeType::Storage],
-                &RefreshStoragePools { ack_id: "".into() },
+                &RefreshStoragePools {
+                    ack_id: AckId::empty(),
+                },
             )
             .await;
         }
@@ -69,3 +91,57 @@ impl HandleWithResponse for MapTargets {
         Ok(resp)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use shared::bee_msg::Header;
+
+    #[tokio::test]
+    async fn max_targets_per_node() {
+        // Storage node 1 owns the storage targets 1 to 4
+        let app = TestApp::with_config(Config {
+            max_targets_per_node: Some(5),
+            ..Default::default()
+        })
+        .await;
+        let mut req = TestRequest::new(Header::default());
+
+        let msg = |target_ids: &[TargetId]| MapTargets {
+            target_ids: target_ids.iter().map(|e| (*e, 1)).collect(),
+            node_id: 1,
+            ack_id: AckId::empty(),
+        };
+
+        let count_sql = "SELECT COUNT(*) FROM targets WHERE node_type = 2 AND node_id = 1";
+
+        // Up to the limit
+        msg(&[99]).handle(&app, &mut req).await.unwrap();
+        assert_eq_db!(app, count_sql, [], 5);
+
+        // Targets already mapped to the node don't count again
+        msg(&[1, 2, 99]).handle(&app, &mut req).await.unwrap();
+        assert_eq_db!(app, count_sql, [], 5);
+
+        // Past the limit, nothing is mapped
+        RegisterTarget {
+            reg_token: vec![],
+            target_id: 1000,
+        }
+        .handle(&app, &mut req)
+        .await
+        .unwrap();
+
+        let err = msg(&[1, 1000]).handle(&app, &mut req).await.unwrap_err();
+        assert!(err.to_string().contains("limit of 5 targets"), "{err:#}");
+        assert_eq_db!(app, count_sql, [], 5);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE node_type = 2 AND target_id = 1000 AND node_id IS NULL",
+            [],
+            1
+        );
+    }
+}
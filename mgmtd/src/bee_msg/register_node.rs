@@ -27,3 +27,89 @@ impl HandleWithResponse for RegisterNode {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::Header;
+
+    #[tokio::test]
+    async fn register_node_addr_conflict() {
+        let app = TestApp::new().await;
+        let mut req = TestRequest::new(Header::default());
+
+        let msg = |addr: &str| RegisterNode {
+            nics: vec![Nic {
+                addr: addr.parse().unwrap(),
+                name: b"eth0".to_vec(),
+                nic_type: NicType::Tcp,
+            }],
+            node_type: NodeType::Storage,
+            port: 9000,
+            ..Default::default()
+        };
+
+        let first = msg("10.0.0.1").handle(&app, &mut req).await.unwrap();
+        let second = msg("10.0.0.1").handle(&app, &mut req).await.unwrap();
+        let third = msg("10.0.0.2").handle(&app, &mut req).await.unwrap();
+
+        let warnings = app
+            .read_tx(move |tx| {
+                let mut warnings = vec![];
+                for num_id in [first.node_num_id, second.node_num_id, third.node_num_id] {
+                    let node = resolve_num_id(tx, EntityType::Node, NodeType::Storage, num_id)?;
+                    warnings.push(common::addr_conflict_warnings(tx, &node)?);
+                }
+                Ok(warnings)
+            })
+            .await
+            .unwrap();
+
+        // The colliding nodes are named in each others warning
+        assert_eq!(warnings[0].len(), 1);
+        assert!(warnings[0][0].contains("10.0.0.1:9000"));
+        assert!(warnings[0][0].contains(&format!("node_storage_{}", second.node_num_id)));
+        assert_eq!(warnings[1].len(), 1);
+        assert!(warnings[1][0].contains(&format!("node_storage_{}", first.node_num_id)));
+
+        assert!(warnings[2].is_empty());
+    }
+
+    #[tokio::test]
+    async fn register_node_machine_limit() {
+        let app = TestApp::new().await;
+        let mut req = TestRequest::new(Header::default());
+
+        // Storage nodes 1 and 2 have been registered on different machines before the limit was
+        // lowered
+        app.write_tx(|tx| {
+            tx.execute(
+                "UPDATE nodes SET machine_uuid = 'm' || node_id
+                WHERE node_type = 2 AND node_id IN (1, 2)",
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        app.set_licensed_machines(1);
+
+        let msg = |node_id: NodeId, machine_uuid: &str| RegisterNode {
+            node_id,
+            node_type: NodeType::Storage,
+            port: 8003,
+            machine_uuid: machine_uuid.as_bytes().to_vec(),
+            ..Default::default()
+        };
+
+        // Already registered nodes keep working on their machine
+        msg(1, "m1").handle(&app, &mut req).await.unwrap();
+        msg(2, "m2").handle(&app, &mut req).await.unwrap();
+
+        // New machines are denied, for new and for moving nodes
+        let err = msg(0, "m3").handle(&app, &mut req).await.unwrap_err();
+        assert!(err.to_string().contains("limit (1)"), "{err:#}");
+        msg(2, "m3").handle(&app, &mut req).await.unwrap_err();
+    }
+}
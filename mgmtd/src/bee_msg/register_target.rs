@@ -1,4 +1,5 @@
 use super::*;
+use rusqlite::params;
 use shared::bee_msg::target::*;
 
 impl HandleWithResponse for RegisterTarget {
@@ -8,6 +9,7 @@ impl HandleWithResponse for RegisterTarget {
         fail_on_pre_shutdown(app)?;
 
         let registration_disable = app.static_info().user_config.registration_disable;
+        let max_unmapped = app.static_info().user_config.max_unmapped_targets;
 
         let (id, is_new) = app
             .write_tx(move |tx| {
@@ -20,45 +22,44 @@ impl HandleWithResponse for RegisterTarget {
                     self.target_id.into(),
                 )? {
                     // If the target already exists, check if the registration tokens match
-                    let stored_reg_token: Option<String> = tx.query_row(
+                    let target_id: TargetId = id.num_id().try_into()?;
+                    db::target::check_and_record_reg_token(
+                        tx,
+                        target_id,
+                        NodeTypeServer::Storage,
+                        reg_token,
+                    )?;
+
+                    return Ok((target_id, false));
+                }
+
+                if registration_disable {
+                    bail!("Registration of new targets is not allowed");
+                }
+
+                if let Some(max_unmapped) = max_unmapped {
+                    let count: usize = tx.query_row_cached(
                         sql!(
-                            "SELECT reg_token FROM targets
-                            WHERE target_id = ?1 AND node_type = ?2"
+                            "SELECT COUNT(*) FROM targets WHERE node_type = ?1 AND node_id IS NULL"
                         ),
-                        rusqlite::params![id.num_id(), NodeType::Storage.sql_variant()],
+                        params![NodeType::Storage.sql_variant()],
                         |row| row.get(0),
                     )?;
 
-                    if let Some(ref t) = stored_reg_token
-                        && t != reg_token
-                    {
+                    if count >= max_unmapped {
                         bail!(
-                            "Storage target {id} has already been registered and its \
-registration token ({reg_token}) does not match the stored token ({t})"
+                            "Registering storage target {} would exceed the limit of \
+                            {max_unmapped} unmapped targets",
+                            self.target_id
                         );
-                    } else if stored_reg_token.is_none() {
-                        tx.execute(
-                            sql!(
-                                "UPDATE targets SET reg_token = ?1
-                                WHERE target_id = ?2 AND node_type = ?3"
-                            ),
-                            rusqlite::params![
-                                reg_token,
-                                id.num_id(),
-                                NodeType::Storage.sql_variant()
-                            ],
-                        )?;
                     }
-
-                    return Ok((id.num_id().try_into()?, false));
                 }
 
-                if registration_disable {
-                    bail!("Registration of new targets is not allowed");
-                }
+                // Do not record an empty registration token as sent by older storage nodes
+                let reg_token = (!reg_token.is_empty()).then_some(reg_token);
 
                 Ok((
-                    db::target::insert_storage(tx, self.target_id, Some(reg_token))?,
+                    db::target::insert_storage(tx, self.target_id, reg_token)?,
                     true,
                 ))
             })
@@ -73,3 +74,97 @@ registration token ({reg_token}) does not match the stored token ({t})"
         Ok(RegisterTargetResp { id })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use shared::bee_msg::Header;
+
+    #[tokio::test]
+    async fn register_target() {
+        let app = TestApp::new().await;
+        let mut req = TestRequest::new(Header::default());
+
+        let msg = |reg_token: &str| RegisterTarget {
+            reg_token: reg_token.as_bytes().to_vec(),
+            target_id: 1,
+        };
+
+        // First registration of an existing target records the token
+        let resp = msg("token").handle(&app, &mut req).await.unwrap();
+        assert_eq!(resp.id, 1);
+        assert_eq_db!(
+            app,
+            "SELECT reg_token FROM targets WHERE node_type = 2 AND target_id = ?1",
+            [1],
+            "token"
+        );
+
+        // Re-registration with the same token
+        msg("token").handle(&app, &mut req).await.unwrap();
+
+        // Re-registration with a different token
+        msg("other").handle(&app, &mut req).await.unwrap_err();
+
+        // Re-registration without a token
+        msg("").handle(&app, &mut req).await.unwrap();
+        assert_eq_db!(
+            app,
+            "SELECT reg_token FROM targets WHERE node_type = 2 AND target_id = ?1",
+            [1],
+            "token"
+        );
+
+        // New target without token doesn't record an empty one
+        let resp = RegisterTarget {
+            reg_token: vec![],
+            target_id: 1000,
+        }
+        .handle(&app, &mut req)
+        .await
+        .unwrap();
+        assert_eq!(resp.id, 1000);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE node_type = 2 AND target_id = ?1 AND reg_token IS NULL",
+            [1000],
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn max_unmapped_targets() {
+        // Storage target 99 is already unmapped
+        let app = TestApp::with_config(Config {
+            max_unmapped_targets: Some(3),
+            ..Default::default()
+        })
+        .await;
+        let mut req = TestRequest::new(Header::default());
+
+        let msg = |target_id| RegisterTarget {
+            reg_token: vec![],
+            target_id,
+        };
+
+        // Up to the limit
+        msg(1000).handle(&app, &mut req).await.unwrap();
+        msg(1001).handle(&app, &mut req).await.unwrap();
+
+        // Past the limit, no new target is registered
+        let err = msg(1002).handle(&app, &mut req).await.unwrap_err();
+        assert!(err.to_string().contains("limit of 3 unmapped"), "{err:#}");
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE node_type = 2 AND node_id IS NULL",
+            [],
+            3
+        );
+
+        // Existing targets can still re-register
+        msg(1000).handle(&app, &mut req).await.unwrap();
+        msg(1).handle(&app, &mut req).await.unwrap();
+    }
+}
@@ -45,7 +45,7 @@ For server nodes, the grpc handler must be used."
                 _ => &[],
             },
             &RemoveNode {
-                ack_id: "".into(),
+                ack_id: AckId::empty(),
                 ..self
             },
         )
@@ -35,10 +35,11 @@ impl HandleWithResponse for RequestExceededQuota {
                         "SELECT DISTINCT e.quota_id FROM quota_usage AS e
                         INNER JOIN targets AS st USING(node_type, target_id)
                         LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+                        LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
                         LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
                         WHERE e.id_type = ?1 AND e.quota_type = ?2 AND st.pool_id = ?3
                         GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
-                        HAVING SUM(e.value) > COALESCE(l.value, d.value)"
+                        HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)"
                     ),
                     params![
                         self.id_type.sql_variant(),
@@ -1,5 +1,7 @@
 use super::*;
+use crate::cap_pool::{CapPoolCalculator, CapPoolEvent, is_emergency_transition};
 use db::target::TargetCapacities;
+use rusqlite::params;
 use shared::bee_msg::target::*;
 
 impl HandleWithResponse for SetStorageTargetInfo {
@@ -15,27 +17,124 @@ impl HandleWithResponse for SetStorageTargetInfo {
         fail_on_pre_shutdown(app)?;
 
         let node_type = self.node_type;
-        app.write_tx(move |tx| {
-            db::target::get_and_update_capacities(
-                tx,
-                self.info.into_iter().map(|e| {
-                    Ok((
-                        e.target_id,
-                        TargetCapacities {
-                            total_space: Some(e.total_space.try_into()?),
-                            total_inodes: Some(e.total_inodes.try_into()?),
-                            free_space: Some(e.free_space.try_into()?),
-                            free_inodes: Some(e.free_inodes.try_into()?),
-                        },
-                    ))
-                }),
-                self.node_type.try_into()?,
-            )
-        })
-        .await?;
+        let node_type_server: NodeTypeServer = self.node_type.try_into()?;
+
+        let new_values = self
+            .info
+            .into_iter()
+            .map(|e| {
+                Ok((
+                    e.target_id,
+                    TargetCapacities {
+                        total_space: Some(e.total_space.try_into()?),
+                        total_inodes: Some(e.total_inodes.try_into()?),
+                        free_space: Some(e.free_space.try_into()?),
+                        free_inodes: Some(e.free_inodes.try_into()?),
+                    },
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let config = &app.static_info().user_config;
+        let (limits, dynamic_limits) = match node_type_server {
+            NodeTypeServer::Meta => (
+                config.cap_pool_meta_limits.clone(),
+                config.cap_pool_dynamic_meta_limits.clone(),
+            ),
+            NodeTypeServer::Storage => (
+                config.cap_pool_storage_limits.clone(),
+                config.cap_pool_dynamic_storage_limits.clone(),
+            ),
+        };
+
+        let events = app
+            .write_tx(move |tx| {
+                let old_values = db::target::get_and_update_capacities(
+                    tx,
+                    new_values.iter().cloned().map(Ok),
+                    node_type_server,
+                )?;
+
+                let mut events = vec![];
+                for ((target_id, old), (_, new)) in old_values.into_iter().zip(new_values) {
+                    let (Some(new_space), Some(new_inodes)) = (new.free_space, new.free_inodes)
+                    else {
+                        continue;
+                    };
+
+                    // The stored pool is used as the previous pool when serving the pools, so it
+                    // must be calculated using the same limits. The dynamic limits depend on the
+                    // capacities of all targets in the same pool.
+                    let capacities = if dynamic_limits.is_some() {
+                        db::target::get_pool_free_capacities(tx, target_id, node_type_server)?
+                    } else {
+                        vec![]
+                    };
+                    let cap_pool_calc = CapPoolCalculator::new(
+                        limits.clone(),
+                        dynamic_limits.as_ref(),
+                        &capacities,
+                    )?;
+
+                    // Targets that haven't been classified before (e.g. before the pool was
+                    // stored) are classified using their old capacities
+                    let previous = db::target::get_cap_pool(tx, target_id, node_type_server)?
+                        .or_else(|| {
+                            let (space, inodes) = old.free_space.zip(old.free_inodes)?;
+                            Some(cap_pool_calc.cap_pool(space, inodes))
+                        });
+
+                    let current =
+                        cap_pool_calc.cap_pool_with_previous(new_space, new_inodes, previous);
+                    db::target::set_cap_pool(tx, target_id, node_type_server, current)?;
+
+                    let Some(previous) = previous else {
+                        continue;
+                    };
+                    if !is_emergency_transition(previous, current) {
+                        continue;
+                    }
+
+                    let (target_uid, alias) = tx.query_row_cached(
+                        sql!(
+                            "SELECT target_uid, alias FROM targets_ext
+                            WHERE target_id = ?1 AND node_type = ?2"
+                        ),
+                        params![target_id, node_type_server.sql_variant()],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )?;
+
+                    events.push(CapPoolEvent {
+                        target_uid,
+                        target_id,
+                        alias,
+                        node_type: node_type_server,
+                        previous,
+                        current,
+                    });
+                }
+
+                Ok(events)
+            })
+            .await?;
 
         log::debug!("Updated {node_type:?} target info");
 
+        for event in events {
+            log::warn!(
+                target_uid = event.target_uid,
+                target_id = event.target_id;
+                "{} target {} ({}) moved from the {} into the {} capacity pool",
+                event.node_type,
+                event.alias,
+                event.target_id,
+                event.previous,
+                event.current
+            );
+
+            app.publish_cap_pool_event(event);
+        }
+
         // in the old mgmtd, a notice to refresh cap pools is sent out here if a cap pool
         // changed I consider this being to expensive to check here and just don't
         // do it. Nodes refresh their cap pool every two minutes (by default) anyway
@@ -45,3 +144,138 @@ impl HandleWithResponse for SetStorageTargetInfo {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::cap_pool::{CapPoolDynamicLimits, CapPoolLimits};
+    use crate::config::Config;
+    use shared::bee_msg::Header;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[tokio::test]
+    async fn set_storage_target_info() {
+        let app = TestApp::with_config(Config {
+            cap_pool_storage_limits: CapPoolLimits {
+                inodes_low: 200000,
+                inodes_emergency: 100000,
+                space_low: 200000,
+                space_emergency: 100000,
+                space_margin: 20000,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let mut req = TestRequest::new(Header::default());
+        let mut events = app.subscribe_cap_pool_events();
+
+        let msg = |free_space| SetStorageTargetInfo {
+            node_type: NodeType::Storage,
+            info: vec![TargetInfo {
+                target_id: 1,
+                total_space: 1000000,
+                free_space,
+                total_inodes: 1000000,
+                free_inodes: 450000,
+                ..Default::default()
+            }],
+        };
+
+        // Target 1 moves from normal into emergency
+        let resp = msg(50000).handle(&app, &mut req).await.unwrap();
+        assert_eq!(resp.result, OpsErr::SUCCESS);
+
+        assert_eq_db!(
+            app,
+            "SELECT free_space FROM storage_targets WHERE target_id = ?1",
+            [1],
+            50000
+        );
+        assert_eq_db!(
+            app,
+            "SELECT UNIXEPOCH('now') - UNIXEPOCH(last_capacity_update) < 10
+            FROM storage_targets WHERE target_id = ?1",
+            [1],
+            1
+        );
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.target_uid, 202001);
+        assert_eq!(event.node_type, NodeTypeServer::Storage);
+        assert_eq!(event.previous, CapacityPool::Normal);
+        assert_eq!(event.current, CapacityPool::Emergency);
+
+        assert_eq_db!(
+            app,
+            "SELECT cap_pool FROM storage_targets WHERE target_id = ?1",
+            [1],
+            CapacityPool::Emergency.sql_variant()
+        );
+
+        // Staying in emergency must not emit another event
+        msg(40000).handle(&app, &mut req).await.unwrap();
+        assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        // Exceeding the emergency limit by less than the margin keeps the target in emergency
+        msg(110000).handle(&app, &mut req).await.unwrap();
+        assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        msg(120000).handle(&app, &mut req).await.unwrap();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.previous, CapacityPool::Emergency);
+        assert_eq!(event.current, CapacityPool::Low);
+    }
+
+    #[tokio::test]
+    async fn set_storage_target_info_dynamic_limits() {
+        let app = TestApp::with_config(Config {
+            cap_pool_storage_limits: CapPoolLimits {
+                inodes_low: 200000,
+                inodes_emergency: 100000,
+                space_low: 200000,
+                space_emergency: 100000,
+                ..Default::default()
+            },
+            cap_pool_dynamic_storage_limits: Some(CapPoolDynamicLimits {
+                inodes_normal_threshold: 1000000,
+                inodes_low_threshold: 1000000,
+                space_normal_threshold: 50000,
+                space_low_threshold: 1000000,
+                inodes_low: 200000,
+                inodes_emergency: 100000,
+                space_low: 120000,
+                space_emergency: 100000,
+            }),
+            ..Default::default()
+        })
+        .await;
+        let mut req = TestRequest::new(Header::default());
+
+        // The other targets in pool 1 have 450000 - 550000 free space, which exceeds the normal
+        // threshold and lowers the low limit to 120000. The static limits would put target 1 into
+        // the low pool.
+        SetStorageTargetInfo {
+            node_type: NodeType::Storage,
+            info: vec![TargetInfo {
+                target_id: 1,
+                total_space: 1000000,
+                free_space: 150000,
+                total_inodes: 1000000,
+                free_inodes: 450000,
+                ..Default::default()
+            }],
+        }
+        .handle(&app, &mut req)
+        .await
+        .unwrap();
+
+        assert_eq_db!(
+            app,
+            "SELECT cap_pool FROM storage_targets WHERE target_id = ?1",
+            [1],
+            CapacityPool::Normal.sql_variant()
+        );
+    }
+}
@@ -16,34 +16,85 @@ impl HandleWithResponse for SetTargetConsistencyStates {
 
         let node_type = self.node_type.try_into()?;
         let msg = self.clone();
-        let node_offline_timeout = app.static_info().user_config.node_offline_timeout;
+        let node_offline_timeout = app.dynamic_info().node_offline_timeout;
+        let node_offline_grace = app.dynamic_info().node_offline_grace;
 
-        app.write_tx(move |tx| {
-            // Check given target Ids exist
-            db::target::validate_ids(tx, &msg.target_ids, node_type)?;
+        let changes = app
+            .write_tx(move |tx| {
+                // Check given target Ids exist
+                db::target::validate_ids(tx, &msg.target_ids, node_type)?;
 
-            if msg.set_online > 0 {
-                update_last_contact_times(tx, &msg.target_ids, node_type, node_offline_timeout)?;
-            }
+                let mut changes = TargetStateChanges::default();
 
-            db::target::update_consistency_states(
-                tx,
-                msg.target_ids.into_iter().zip(msg.states.iter().copied()),
-                node_type,
-            )
-        })
-        .await?;
+                if msg.set_online > 0 {
+                    changes.reachability = update_last_contact_times(
+                        tx,
+                        &msg.target_ids,
+                        node_type,
+                        node_offline_timeout,
+                        node_offline_grace,
+                    )?;
+                }
+
+                changes.consistency = db::target::update_consistency_states(
+                    tx,
+                    msg.target_ids.into_iter().zip(msg.states.iter().copied()),
+                    node_type,
+                )?;
+
+                Ok(changes)
+            })
+            .await?;
 
         log::info!("Set consistency state for targets {:?}", self.target_ids,);
 
-        app.send_notifications(
-            &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-            &RefreshTargetStates { ack_id: "".into() },
-        )
-        .await;
+        // All changes are announced using one notification
+        changes.notify(app).await;
 
         Ok(SetTargetConsistencyStatesResp {
             result: OpsErr::SUCCESS,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::Header;
+
+    #[tokio::test]
+    async fn set_target_consistency_states() {
+        let app = TestApp::new().await;
+        let mut req = TestRequest::new(Header::default());
+
+        let msg = SetTargetConsistencyStates {
+            node_type: NodeType::Storage,
+            target_ids: vec![1, 2, 3, 4],
+            states: vec![
+                TargetConsistencyState::NeedsResync,
+                TargetConsistencyState::NeedsResync,
+                TargetConsistencyState::Bad,
+                TargetConsistencyState::Bad,
+            ],
+            ack_id: AckId::empty(),
+            set_online: 0,
+        };
+        let resp = msg.clone().handle(&app, &mut req).await.unwrap();
+
+        assert_eq!(resp.result, OpsErr::SUCCESS);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE node_type = 2 AND consistency != ?1",
+            [TargetConsistencyState::Good.sql_variant()],
+            4
+        );
+
+        // Several targets changed, but only one notification must be sent
+        assert_eq!(app.sent_notifications::<RefreshTargetStates>(), 1);
+
+        // Nothing changes, so no additional notification must be sent
+        msg.handle(&app, &mut req).await.unwrap();
+        assert_eq!(app.sent_notifications::<RefreshTargetStates>(), 1);
+    }
+}
@@ -4,20 +4,70 @@
 
 use anyhow::{Context, Result, bail};
 use serde::Deserialize;
-use shared::parser::integer_unit;
-use shared::types::CapacityPool;
+use shared::parser::{byte_size, integer_unit};
+use shared::types::{CapacityPool, NodeTypeServer, TargetId, Uid};
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CapPoolLimits {
     #[serde(with = "integer_unit")]
     pub inodes_low: u64,
     #[serde(with = "integer_unit")]
     pub inodes_emergency: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_low: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_emergency: u64,
+    /// The amount of inodes a target must exceed a limit by to move into the better pool or fall
+    /// below a limit by to move into the worse pool. Prevents targets close to a limit from
+    /// flipping between two pools on every capacity update.
+    #[serde(default, with = "integer_unit")]
+    pub inodes_margin: u64,
+    /// Same as `inodes_margin`, for free space.
+    #[serde(default, with = "byte_size")]
+    pub space_margin: u64,
+    /// How free space and free inodes are weighted against each other.
+    #[serde(default)]
+    pub weighting: CapPoolWeighting,
+}
+
+/// Determines how free space and free inodes are combined into a capacity pool.
+///
+/// Each of the two values is first classified on its own against its limits. Regardless of the
+/// weighting, a target without any free space or free inodes left is always put into the
+/// emergency pool, as it can't store new data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CapPoolWeighting {
+    /// The worse of the two pools is used, so a target is put into the emergency pool if it
+    /// runs out of either space or inodes.
+    #[default]
+    Worst,
+    /// Free space is weighted higher: Inode pressure alone can only move a target down by one
+    /// pool less than it would on its own (e.g. exhausted inodes lead to the low pool).
+    Space,
+    /// Free inodes are weighted higher: Space pressure alone can only move a target down by one
+    /// pool less than it would on its own.
+    Inodes,
+}
+
+/// Classifies a single value against its low and emergency limit
+fn pool_for(value: u64, low: u64, emergency: u64) -> CapacityPool {
+    if value >= low {
+        CapacityPool::Normal
+    } else if value >= emergency {
+        CapacityPool::Low
+    } else {
+        CapacityPool::Emergency
+    }
+}
+
+/// Returns the next better pool
+fn relieved(pool: CapacityPool) -> CapacityPool {
+    match pool {
+        CapacityPool::Normal | CapacityPool::Low => CapacityPool::Normal,
+        CapacityPool::Emergency => CapacityPool::Low,
+    }
 }
 
 impl CapPoolLimits {
@@ -30,24 +80,24 @@ impl CapPoolLimits {
     }
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CapPoolDynamicLimits {
     #[serde(with = "integer_unit")]
     pub inodes_normal_threshold: u64,
     #[serde(with = "integer_unit")]
     pub inodes_low_threshold: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_normal_threshold: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_low_threshold: u64,
     #[serde(with = "integer_unit")]
     pub inodes_low: u64,
     #[serde(with = "integer_unit")]
     pub inodes_emergency: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_low: u64,
-    #[serde(with = "integer_unit")]
+    #[serde(with = "byte_size")]
     pub space_emergency: u64,
 }
 
@@ -66,6 +116,17 @@ pub(crate) trait CapacityInfo {
     fn free_inodes(&self) -> u64;
 }
 
+/// (free space, free inodes)
+impl CapacityInfo for &(u64, u64) {
+    fn free_space(&self) -> u64 {
+        self.0
+    }
+
+    fn free_inodes(&self) -> u64 {
+        self.1
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct CapPoolCalculator {
     limits: CapPoolLimits,
@@ -95,7 +156,6 @@ impl CapPoolCalculator {
         dynamic_limits: &CapPoolDynamicLimits,
         values: impl IntoIterator<Item = impl CapacityInfo>,
     ) -> Result<Self> {
-        limits.check().context("cap pool calculator")?;
         dynamic_limits.check().context("cap pool calculator")?;
 
         let mut normal_space = MinMax::default();
@@ -103,15 +163,21 @@ impl CapPoolCalculator {
         let mut low_space = MinMax::default();
         let mut low_inodes = MinMax::default();
 
+        // Classify using the static limits and the configured weighting, so the spreads are
+        // calculated over the same pools the targets are put into
+        let static_calc = Self::new_static(limits.clone())?;
+
         for e in values.into_iter() {
-            if e.free_space() >= limits.space_low && e.free_inodes() >= limits.inodes_low {
-                normal_space.apply(e.free_space());
-                normal_inodes.apply(e.free_inodes());
-            } else if e.free_space() >= limits.space_emergency
-                && e.free_inodes() >= limits.inodes_emergency
-            {
-                low_space.apply(e.free_space());
-                low_inodes.apply(e.free_inodes());
+            match static_calc.cap_pool(e.free_space(), e.free_inodes()) {
+                CapacityPool::Normal => {
+                    normal_space.apply(e.free_space());
+                    normal_inodes.apply(e.free_inodes());
+                }
+                CapacityPool::Low => {
+                    low_space.apply(e.free_space());
+                    low_inodes.apply(e.free_inodes());
+                }
+                CapacityPool::Emergency => {}
             }
         }
 
@@ -131,17 +197,82 @@ impl CapPoolCalculator {
         Ok(Self { limits })
     }
 
+    /// Determines the capacity pool for the given free space and inodes.
+    ///
+    /// Both values are classified on their own and then combined according to the configured
+    /// [CapPoolWeighting]. By default, the worse pool is used.
     pub(crate) fn cap_pool(&self, space: u64, inodes: u64) -> CapacityPool {
-        if space >= self.limits.space_low && inodes >= self.limits.inodes_low {
-            CapacityPool::Normal
-        } else if space >= self.limits.space_emergency && inodes >= self.limits.inodes_emergency {
-            CapacityPool::Low
+        // A target that can't store anything new must not be softened by the weighting
+        if space == 0 || inodes == 0 {
+            return CapacityPool::Emergency;
+        }
+
+        let space = pool_for(space, self.limits.space_low, self.limits.space_emergency);
+        let inodes = pool_for(inodes, self.limits.inodes_low, self.limits.inodes_emergency);
+
+        // Pools are ordered from best to worst
+        match self.limits.weighting {
+            CapPoolWeighting::Worst => space.max(inodes),
+            CapPoolWeighting::Space => space.max(relieved(inodes)),
+            CapPoolWeighting::Inodes => inodes.max(relieved(space)),
+        }
+    }
+
+    /// Determines the capacity pool, taking the pool the target / buddy group previously was in
+    /// into account.
+    ///
+    /// The limits are the center points of a hysteresis: Moving into a worse pool requires falling
+    /// below a limit by the configured margin, moving into a better one requires exceeding it by
+    /// the margin. Otherwise, the previous pool is kept. Without a previous pool, this is the same
+    /// as [Self::cap_pool()].
+    pub(crate) fn cap_pool_with_previous(
+        &self,
+        space: u64,
+        inodes: u64,
+        previous: Option<CapacityPool>,
+    ) -> CapacityPool {
+        // Margins don't apply to exhausted targets, see Self::cap_pool()
+        let Some(previous) = previous.filter(|_| space > 0 && inodes > 0) else {
+            return self.cap_pool(space, inodes);
+        };
+
+        // The best and the worst pool the values could belong to considering the margins
+        let best = self.cap_pool(
+            space.saturating_add(self.limits.space_margin),
+            inodes.saturating_add(self.limits.inodes_margin),
+        );
+        let worst = self.cap_pool(
+            space.saturating_sub(self.limits.space_margin),
+            inodes.saturating_sub(self.limits.inodes_margin),
+        );
+
+        if best > previous {
+            best
+        } else if worst < previous {
+            worst
         } else {
-            CapacityPool::Emergency
+            previous
         }
     }
 }
 
+/// Determines whether a target moved into or out of the emergency pool.
+pub(crate) fn is_emergency_transition(previous: CapacityPool, current: CapacityPool) -> bool {
+    previous != current
+        && (previous == CapacityPool::Emergency || current == CapacityPool::Emergency)
+}
+
+/// A target moving into or out of the emergency capacity pool
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CapPoolEvent {
+    pub target_uid: Uid,
+    pub target_id: TargetId,
+    pub alias: String,
+    pub node_type: NodeTypeServer,
+    pub previous: CapacityPool,
+    pub current: CapacityPool,
+}
+
 #[derive(Default)]
 struct MinMax {
     min: u64,
@@ -169,22 +300,13 @@ impl MinMax {
 mod test {
     use super::*;
 
-    impl CapacityInfo for &(u64, u64) {
-        fn free_space(&self) -> u64 {
-            self.0
-        }
-
-        fn free_inodes(&self) -> u64 {
-            self.1
-        }
-    }
-
     fn limits() -> CapPoolLimits {
         CapPoolLimits {
             inodes_low: 70,
             inodes_emergency: 30,
             space_low: 70,
             space_emergency: 30,
+            ..Default::default()
         }
     }
 
@@ -214,6 +336,76 @@ mod test {
         assert_eq!(CapacityPool::Emergency, c.cap_pool(100, 10));
     }
 
+    #[test]
+    fn inode_exhaustion() {
+        // Plenty of space, but (almost) no free inodes left
+        let c = CapPoolCalculator::new_static(limits()).unwrap();
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(u64::MAX, 0));
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(u64::MAX, 29));
+        assert_eq!(CapacityPool::Low, c.cap_pool(u64::MAX, 30));
+        assert_eq!(
+            CapacityPool::Emergency,
+            c.cap_pool_with_previous(u64::MAX, 0, Some(CapacityPool::Normal))
+        );
+
+        // Same with the dynamic limits applied
+        let c = CapPoolCalculator::new_dynamic(
+            limits(),
+            dynamic_limits(),
+            &[(1000, 100), (100, 100), (1000, 40), (100, 40)],
+        )
+        .unwrap();
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(u64::MAX, 0));
+
+        // Weighting inodes higher doesn't change that
+        let c = CapPoolCalculator::new_static(CapPoolLimits {
+            weighting: CapPoolWeighting::Inodes,
+            ..limits()
+        })
+        .unwrap();
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(u64::MAX, 0));
+        assert_eq!(CapacityPool::Low, c.cap_pool(10, u64::MAX));
+        assert_eq!(CapacityPool::Normal, c.cap_pool(50, u64::MAX));
+        assert_eq!(CapacityPool::Low, c.cap_pool(50, 50));
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(0, 0));
+        // Without any free space left, the weighting doesn't apply
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(0, u64::MAX));
+
+        // Weighting space higher softens inode pressure by one pool
+        let c = CapPoolCalculator::new_static(CapPoolLimits {
+            weighting: CapPoolWeighting::Space,
+            ..limits()
+        })
+        .unwrap();
+        assert_eq!(CapacityPool::Low, c.cap_pool(u64::MAX, 10));
+        assert_eq!(CapacityPool::Normal, c.cap_pool(u64::MAX, 50));
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(0, u64::MAX));
+        assert_eq!(CapacityPool::Low, c.cap_pool(50, 10));
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(0, 0));
+        // Without any free inodes left, the weighting doesn't apply
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(u64::MAX, 0));
+    }
+
+    #[test]
+    fn weighted_spread() {
+        // With space weighted higher, the first two targets are in the low pool instead of the
+        // emergency pool. Their spread switches to the dynamic emergency limit.
+        let c = CapPoolCalculator::new_dynamic(
+            CapPoolLimits {
+                weighting: CapPoolWeighting::Space,
+                ..limits()
+            },
+            dynamic_limits(),
+            &[(40, 10), (80, 10), (100, 100), (150, 100)],
+        )
+        .unwrap();
+
+        assert_eq!(CapacityPool::Normal, c.cap_pool(170, 100));
+        assert_eq!(CapacityPool::Low, c.cap_pool(169, 100));
+        assert_eq!(CapacityPool::Low, c.cap_pool(130, 100));
+        assert_eq!(CapacityPool::Emergency, c.cap_pool(129, 100));
+    }
+
     #[test]
     fn no_spread() {
         let c =
@@ -229,6 +421,96 @@ mod test {
         assert_eq!(CapacityPool::Emergency, c.cap_pool(100, 10));
     }
 
+    #[test]
+    fn emergency_transition() {
+        let c = CapPoolCalculator::new_static(limits()).unwrap();
+        let t = |previous: (u64, u64), current: (u64, u64)| {
+            is_emergency_transition(
+                c.cap_pool(previous.0, previous.1),
+                c.cap_pool(current.0, current.1),
+            )
+        };
+
+        assert!(!t((100, 100), (100, 100)));
+        assert!(!t((100, 100), (50, 50)));
+        assert!(!t((10, 10), (10, 20)));
+        assert!(t((100, 100), (10, 10)));
+        assert!(t((50, 50), (100, 10)));
+        assert!(t((10, 10), (50, 50)));
+    }
+
+    #[test]
+    fn hysteresis() {
+        let c = CapPoolCalculator::new_static(CapPoolLimits {
+            space_margin: 5,
+            inodes_margin: 5,
+            ..limits()
+        })
+        .unwrap();
+
+        // Without a previous pool, the limits are applied as they are
+        assert_eq!(
+            CapacityPool::Normal,
+            c.cap_pool_with_previous(70, 100, None)
+        );
+        assert_eq!(CapacityPool::Low, c.cap_pool_with_previous(69, 100, None));
+
+        // Free space jittering around the low limit doesn't flip the pool on every update
+        let mut pool = Some(CapacityPool::Normal);
+        let mut changes = 0;
+        for space in [72, 68, 71, 67, 73, 69, 66, 70, 74, 68] {
+            let current = c.cap_pool_with_previous(space, 100, pool);
+            if Some(current) != pool {
+                changes += 1;
+            }
+            pool = Some(current);
+        }
+        assert_eq!(0, changes);
+        assert_eq!(Some(CapacityPool::Normal), pool);
+
+        // Crossing the limit by the margin changes the pool
+        assert_eq!(
+            CapacityPool::Low,
+            c.cap_pool_with_previous(64, 100, Some(CapacityPool::Normal))
+        );
+        assert_eq!(
+            CapacityPool::Low,
+            c.cap_pool_with_previous(74, 100, Some(CapacityPool::Low))
+        );
+        assert_eq!(
+            CapacityPool::Normal,
+            c.cap_pool_with_previous(75, 100, Some(CapacityPool::Low))
+        );
+        assert_eq!(
+            CapacityPool::Emergency,
+            c.cap_pool_with_previous(100, 24, Some(CapacityPool::Low))
+        );
+        assert_eq!(
+            CapacityPool::Low,
+            c.cap_pool_with_previous(100, 28, Some(CapacityPool::Low))
+        );
+        assert_eq!(
+            CapacityPool::Emergency,
+            c.cap_pool_with_previous(100, 34, Some(CapacityPool::Emergency))
+        );
+
+        // Exhausted targets go into the emergency pool immediately
+        assert_eq!(
+            CapacityPool::Emergency,
+            c.cap_pool_with_previous(0, 100, Some(CapacityPool::Low))
+        );
+
+        // Far enough from the limits, the previous pool doesn't matter
+        assert_eq!(
+            CapacityPool::Normal,
+            c.cap_pool_with_previous(100, 100, Some(CapacityPool::Emergency))
+        );
+        assert_eq!(
+            CapacityPool::Emergency,
+            c.cap_pool_with_previous(10, 100, Some(CapacityPool::Normal))
+        );
+    }
+
     #[test]
     fn space_spread() {
         let normal_only = CapPoolCalculator::new_dynamic(
@@ -283,6 +565,47 @@ mod test {
         assert_eq!(CapacityPool::Emergency, both.cap_pool(100, 129));
     }
 
+    #[test]
+    fn deserialize_limits() {
+        let limits: CapPoolLimits = toml::from_str(
+            r#"
+            inodes-low = "10M"
+            inodes-emergency = 1000
+            space-low = "1.5TiB"
+            space-emergency = "512GB"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(limits.inodes_low, 10_000_000);
+        assert_eq!(limits.inodes_emergency, 1000);
+        assert_eq!(limits.space_low, 3 * 2u64.pow(39));
+        assert_eq!(limits.space_emergency, 512_000_000_000);
+        assert_eq!(limits.weighting, CapPoolWeighting::Worst);
+
+        let limits: CapPoolLimits = toml::from_str(
+            r#"
+            inodes-low = "10M"
+            inodes-emergency = 1000
+            space-low = "1.5TiB"
+            space-emergency = "512GB"
+            weighting = "inodes"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(limits.weighting, CapPoolWeighting::Inodes);
+
+        toml::from_str::<CapPoolLimits>(
+            r#"
+            inodes-low = "10M"
+            inodes-emergency = "1M"
+            space-low = "10 GiBs"
+            space-emergency = "1GiB"
+            "#,
+        )
+        .unwrap_err();
+    }
+
     #[test]
     fn limit_validity() {
         CapPoolCalculator::new_static(CapPoolLimits {
@@ -290,6 +613,7 @@ mod test {
             inodes_emergency: 0,
             space_low: 0,
             space_emergency: 0,
+            ..Default::default()
         })
         .unwrap();
 
@@ -298,6 +622,7 @@ mod test {
             inodes_emergency: 100,
             space_low: 100,
             space_emergency: 100,
+            ..Default::default()
         })
         .unwrap();
 
@@ -306,6 +631,7 @@ mod test {
             inodes_emergency: 200,
             space_low: 100,
             space_emergency: 100,
+            ..Default::default()
         })
         .unwrap_err();
 
@@ -314,6 +640,7 @@ mod test {
             inodes_emergency: 100,
             space_low: 100,
             space_emergency: 200,
+            ..Default::default()
         })
         .unwrap_err();
 
@@ -323,6 +650,7 @@ mod test {
                 inodes_emergency: 0,
                 space_low: 0,
                 space_emergency: 0,
+                ..Default::default()
             },
             &CapPoolDynamicLimits {
                 inodes_normal_threshold: 0,
@@ -344,6 +672,7 @@ mod test {
                 inodes_emergency: 0,
                 space_low: 0,
                 space_emergency: 0,
+                ..Default::default()
             },
             &CapPoolDynamicLimits {
                 inodes_normal_threshold: 0,
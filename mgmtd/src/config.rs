@@ -1,15 +1,18 @@
 //! Program wide config definition and tools for reading and parsing
 
-use crate::cap_pool::{CapPoolDynamicLimits, CapPoolLimits};
+use crate::cap_pool::{CapPoolDynamicLimits, CapPoolLimits, CapPoolWeighting};
 use anyhow::{Context, Result, bail};
 use clap::{Parser, ValueEnum};
 use log::LevelFilter;
 use serde::{Deserialize, Deserializer};
 use shared::nic::{self, NicFilter};
-use shared::parser::{duration, integer_range};
-use shared::types::{Port, QuotaId};
+use shared::parser::{byte_size, duration, integer_range};
+use shared::types::{AuthSecret, Port, QuotaId};
 use std::fmt::Debug;
+use std::io::Read;
+use std::net::IpAddr;
 use std::ops::RangeInclusive;
+use std::os::fd::{FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::time::Duration;
 use uuid::Uuid;
@@ -57,6 +60,17 @@ macro_rules! generate_structs {
                     }
                 )*
             }
+
+            /// Returns the names of the settings that differ between `self` and `other`
+            pub fn changed_settings(&self, other: &Self) -> Vec<&'static str> {
+                let mut res = vec![];
+                $(
+                    if self.$var != other.$var {
+                        res.push(stringify!($var));
+                    }
+                )*
+                res
+            }
         }
 
         // The below text is used as general help text
@@ -99,6 +113,10 @@ fn deserialize_duration<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Durat
     Ok(Some(duration::deserialize(de)?))
 }
 
+fn deserialize_byte_size<'de, D: Deserializer<'de>>(de: D) -> Result<Option<u64>, D::Error> {
+    Ok(Some(byte_size::deserialize(de)?))
+}
+
 generate_structs! {
     /// Creates and initializes a new database, then exits.
     ///
@@ -119,14 +137,34 @@ generate_structs! {
     #[serde(skip)]
     fs_uuid: Option<Uuid> = None,
 
-    /// Used to upgrade the database. Deprecated, does nothing but exiting the program. Upgrading
-    /// happens automatically now.
+    /// Optionally assigns aliases to the initial entities when initializing the database.
+    ///
+    /// The given TOML file maps the entities to their aliases, e.g. `management-node = "mgmtd"`
+    /// and `default-storage-pool = "default"`. Invalid or duplicate aliases abort the
+    /// initialization. Only valid together with `--init`.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    #[serde(skip)]
+    aliases: Option<PathBuf> = None,
+
+    /// Used to upgrade the database. Deprecated, does nothing but exiting the program unless
+    /// combined with `--dry-run`. Upgrading happens automatically now.
     #[arg(long)]
     #[arg(num_args = 0..=1, default_missing_value = "true")]
     #[arg(hide = true)]
     #[serde(skip)]
     upgrade: bool = false,
 
+    /// Together with `--upgrade`, reports the current and the latest database schema version and
+    /// the migrations that would be applied to the database, then exits.
+    ///
+    /// Opens the database read-only, it is neither modified nor backed up.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    #[serde(skip)]
+    dry_run: bool = false,
+
     /// Imports a BeeGFS v7 installation from the provided directory into a new database.
     ///
     /// The database file must not exist yet. Before importing a production BeeGFS, ensure that
@@ -141,6 +179,70 @@ generate_structs! {
     #[serde(skip)]
     import_from_v7: Option<PathBuf> = None,
 
+    /// Exports the management state from the database into the given file, then exits.
+    ///
+    /// The export contains nodes, targets, buddy groups, storage pools, quota limits, the meta
+    /// root and the file system UUID. It can be imported into a new database using `--import`.
+    /// The management should not be running during the export.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    #[serde(skip)]
+    export: Option<PathBuf> = None,
+
+    /// Exports the last fetched quota usage and the effective limits from the database into the
+    /// given file, then exits.
+    ///
+    /// The file is gzip compressed and contains one JSON object per line, the first one being a
+    /// header. It is meant for analyzing the quota usage with external tools.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    #[serde(skip)]
+    export_quota_usage: Option<PathBuf> = None,
+
+    /// Imports a management state created with `--export` from the given file into a new database.
+    ///
+    /// The database file must not exist yet and will only be created if the whole import succeeds.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    #[serde(skip)]
+    import: Option<PathBuf> = None,
+
+    /// Imports quota limits from the given file into the existing database, then exits.
+    ///
+    /// Each line contains the comma or tab separated fields `<id_type>,<quota_id>,<pool>,
+    /// <space_limit>,<inode_limit>`, with `id_type` being `user`, `group` or `project` and `pool`
+    /// the numeric storage pool ID. A limit can be `unlimited` to remove it or left empty to keep
+    /// it. Empty lines and lines starting with `#` are ignored. If any line is invalid, nothing is
+    /// imported. The management should not be running during the import.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    #[serde(skip)]
+    import_quota_limits: Option<PathBuf> = None,
+
+    /// Checks the configuration, prints the effective result and exits.
+    ///
+    /// Loads the configuration from the default values, the config file and the command line,
+    /// checks it for validity and prints the resulting settings. Does not access the database or
+    /// open any sockets.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    #[serde(skip)]
+    check_config: bool = false,
+
+    /// Prints the serialized form of a canonical instance of the given BeeMsg and exits.
+    ///
+    /// Meant for comparing the wire format against other BeeGFS implementations.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(hide = true)]
+    #[arg(value_name = "NAME")]
+    #[serde(skip)]
+    dump_msg: Option<String> = None,
+
     /// Loads additional configuration from the given file. [default = "/etc/beegfs/beegfs-mgmtd.toml"]
     ///
     /// Config file settings overwrite the default settings and command line settings
@@ -151,15 +253,67 @@ generate_structs! {
     config_file: PathBuf = "/etc/beegfs/beegfs-mgmtd.toml".into(),
 
     /// Managements database file location. [default: /var/lib/beegfs/mgmtd.sqlite]
+    ///
+    /// While the management is running, the additional files `<db-file>-wal` and `<db-file>-shm`
+    /// are created next to it. They belong to the database and must not be deleted.
     #[arg(long)]
     #[arg(value_name = "PATH")]
     db_file: PathBuf = "/var/lib/beegfs/mgmtd.sqlite".into(),
 
+    /// Maximum time to wait for the database write lock. [default: 30s]
+    ///
+    /// Write transactions wait up to this long for a concurrent write to finish before failing.
+    #[arg(long)]
+    #[arg(value_name = "DURATION")]
+    #[arg(value_parser = duration::parse)]
+    #[serde(deserialize_with = "deserialize_duration")]
+    db_busy_timeout: Duration = Duration::from_secs(30),
+
+    /// How durable committed database transactions are. [default: full]
+    ///
+    /// All modes keep the database in WAL mode, they differ in how often it is synced to disk:
+    /// * `full`: Sync after each committed transaction. Survives power loss without losing
+    ///   committed changes.
+    /// * `normal`: Only sync on WAL checkpoints. Faster, but the most recent changes can be lost on
+    ///   power loss or OS crash. The database stays consistent.
+    /// * `fast`: Never sync. Fastest, but on power loss or OS crash, recent changes can be lost and
+    ///   the database might get corrupted.
+    #[arg(long)]
+    #[arg(value_name = "IDENT")]
+    db_durability: DbDurability = DbDurability::Full,
+
+    /// Number of separate read-only database connections for read requests. [default: 0]
+    ///
+    /// If set, read transactions (e.g. listing nodes and targets or querying quota usage) use up to
+    /// this many additional read-only connections and no longer compete with writes for the
+    /// regular connections. 0 disables them. Ignored in read-only mode.
+    #[arg(long)]
+    #[arg(value_name = "COUNT")]
+    db_read_connections: usize = 0,
+
+    /// Runs the management in read-only mode. [default: false]
+    ///
+    /// The database is opened read-only and all requests modifying the system state are denied.
+    /// Timed tasks that write to the database (removing stale clients, buddy group switchover and
+    /// quota updates) are disabled. Meant for maintenance and inspecting a database copy. Requires
+    /// the database schema to be up to date.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    read_only: bool = false,
+
     /// The log target to use. [default: journald]
     #[arg(long)]
     #[arg(value_name = "IDENT")]
     log_target: LogTarget = LogTarget::Journald,
 
+    /// The format of log messages written to stderr. [default: text]
+    ///
+    /// "json" writes one JSON object per record, containing the timestamp, level, target, message
+    /// and any structured fields. Has no effect when logging to journald.
+    #[arg(long)]
+    #[arg(value_name = "IDENT")]
+    log_format: LogFormat = LogFormat::Text,
+
     /// The log level to use. [default: warn]
     ///
     /// Sets the maximum level to log. When logging to std, the logging behavior can be fine
@@ -170,6 +324,21 @@ generate_structs! {
     #[arg(value_name = "IDENT")]
     log_level: LogLevel = LogLevel::Warn,
 
+    /// The number of most recent log records to keep in memory. [default: 1000]
+    ///
+    /// The records can be retrieved remotely using `beegfs logs tail`, which is useful if there is
+    /// no access to the host. 0 disables keeping them.
+    #[arg(long)]
+    #[arg(value_name = "COUNT")]
+    log_recent_records: usize = 1000,
+
+    /// The maximum level of log records to keep in memory. [default: warn]
+    ///
+    /// Records exceeding `log-level` are never kept, regardless of this setting.
+    #[arg(long)]
+    #[arg(value_name = "IDENT")]
+    log_recent_level: LogLevel = LogLevel::Warn,
+
     // Connection
 
     /// Sets the BeeMsg / "classic" port (TCP and UDP) to listen on. [default: 8008]
@@ -182,6 +351,15 @@ generate_structs! {
     #[arg(value_name = "PORT")]
     grpc_port: Port = 8010,
 
+    /// Enables the HTTP metrics endpoint on this port.
+    ///
+    /// Serves counters and histograms in the Prometheus text format under `/metrics`. Disabled
+    /// if not set.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PORT")]
+    metrics_port: Option<Port> = None,
+
     /// Optionally shift all listening ports by this number. [default: 0]
     #[arg(long)]
     #[arg(hide = true)]
@@ -207,15 +385,18 @@ generate_structs! {
     /// Restricts and prioritizes network interfaces reported to other nodes for incoming BeeMsg
     /// communication.
     ///
-    /// Accepts a comma separated list of interface/nic filters. Interfaces can be filtered by
-    /// name, address and protocol (ipv4 or ipv6). Each filter entry has the form `[!] [<name>|*]
-    /// [<addr>|*] [<protocol>|*]`, where protocol can be "4" or "6". Each field can be set to
-    /// "*" to match any value. Stars on the right can be omitted. The order of the filter entries
-    /// determines the priority of the interfaces as they should be used by other nodes for BeeMsg
-    /// communication. The first entry an interface matches is that interfaces priority - the
+    /// Accepts a comma separated list of interface/nic filters. Interfaces can be filtered by name,
+    /// address and protocol (ipv4 or ipv6). Each filter entry has the form `[!] [<name>|*]
+    /// [<addr>|*] [<protocol>|*]`, where protocol can be "4" or "6". The address can be given as a
+    /// subnet in CIDR notation (e.g. `10.0.0.0/8`) to match all addresses within it. Each field can
+    /// be set to "*" to match any value. Stars on the right can be omitted. The order of the filter
+    /// entries determines the priority of the interfaces as they should be used by other nodes for
+    /// BeeMsg communication. The first entry an interface matches is that interfaces priority - the
     /// earlier the match, the higher the priority. Any interface that doesn't match any entry is
     /// not reported and will thus not be contacted by other nodes. A single `!` before the entry
     /// blacklists the matching interfaces - it is not reported even if a later entry does match it.
+    /// An entry can end with `@<weight>` to use the weight as priority instead of the entries
+    /// position (which counts from 0). The lower the weight, the higher the priority.
     ///
     /// If not given, all suitable interfaces can be used and are reported in default order.
     ///
@@ -226,32 +407,128 @@ generate_structs! {
     /// * Only the eth0 interface using IPv6: `eth0 * 6`
     /// * Prefer one IPv6 address, allow only IPv4 otherwise: `* fd00::1,* * 4`
     /// * Deny eth0 interface, allow everything else: `! eth0,*`
+    /// * Deny the 10.0.0.0/8 subnet, allow everything else: `! * 10.0.0.0/8,*`
+    /// * Prefer RDMA, then the 10.0.0.0/8 subnet: `* 10.0.0.0/8 @50,* * * rdma @10`
     #[arg(long)]
     #[arg(value_name = "FILTERS")]
     #[arg(value_delimiter = ',')]
     #[arg(value_parser = nic::NicFilter::parse)]
     interfaces: Vec<NicFilter> = vec![],
 
+    /// Fails on startup if an interface name in `interfaces` doesn't match any existing interface.
+    ///
+    /// By default, only a warning is logged.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    strict_interfaces: bool = false,
+
+    /// Reports the interfaces strictly in the order of the `interfaces` filter entries.
+    ///
+    /// By default, interfaces matching the same filter entry are additionally ordered by
+    /// non-loopback first, then IPv4 first, then RDMA first. When set, they are kept in the order
+    /// reported by the operating system instead. Has no effect if `interfaces` is empty.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    interfaces_strict_order: bool = false,
+
     /// Force disable IPv6.
     #[arg(long)]
     #[arg(num_args = 0..=1, default_missing_value = "true")]
     ipv6_disable: bool = false,
 
+    /// Binds the BeeMsg, gRPC and metrics sockets to this address only.
+    ///
+    /// By default, the sockets are bound to all addresses, using dual stack IPv6 sockets if
+    /// available. Setting an IPv4 address disables IPv6.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "ADDRESS")]
+    bind_addr: Option<IpAddr> = None,
+
     /// Maximum number of outgoing BeeMsg connections per node. [default: 12]
     #[arg(long)]
     #[arg(value_name = "LIMIT")]
     connection_limit: usize = 12,
 
+    /// Size of the queue for incoming BeeMsg TCP connections not accepted yet. [default: 1024]
+    ///
+    /// Connections exceeding the queue might be dropped, e.g. during a cluster wide restart. The
+    /// kernel caps the value to `net.core.somaxconn`.
+    #[arg(long)]
+    #[arg(value_name = "LENGTH")]
+    tcp_listen_backlog: u32 = 1024,
+
+    /// Number of tasks accepting incoming BeeMsg TCP connections concurrently. [default: 1]
+    #[arg(long)]
+    #[arg(value_name = "COUNT")]
+    tcp_accept_tasks: usize = 1,
+
+    /// Maximum number of incoming UDP datagrams being handled concurrently. [default: 128]
+    ///
+    /// Datagrams received while the limit is reached are dropped.
+    #[arg(long)]
+    #[arg(value_name = "LIMIT")]
+    udp_handler_concurrency: usize = 128,
+
+    /// Number of UDP sockets to receive datagrams on. [default: 1]
+    ///
+    /// If greater than 1, the sockets are bound to the same port using `SO_REUSEPORT` and the
+    /// kernel spreads incoming datagrams over them, so receiving scales over multiple cores. Meant
+    /// for large systems with many nodes.
+    #[arg(long)]
+    #[arg(value_name = "COUNT")]
+    udp_sockets: usize = 1,
+
+    /// Doesn't handle incoming UDP datagrams that exactly match one received from the same peer
+    /// within this window. [default: 0s]
+    ///
+    /// Avoids handling datagrams duplicated by the network multiple times. Instead, the reply sent
+    /// to the original datagram is sent again, so retries after a lost reply still get one. Must
+    /// not exceed 10s, so periodically repeated messages (e.g. heartbeats) are not affected. 0
+    /// disables the check.
+    #[arg(long)]
+    #[arg(value_name = "DURATION")]
+    #[arg(value_parser = duration::parse)]
+    #[serde(deserialize_with = "deserialize_duration")]
+    udp_dedup_window: Duration = Duration::ZERO,
+
     /// Disables requiring authentication (BeeMsg and gRPC).
     #[arg(long)]
     #[arg(num_args = 0..=1, default_missing_value = "true")]
     auth_disable: bool = false,
 
+    /// Allows gRPC requests that only read the management state without authentication.
+    /// [default: false]
+    ///
+    /// Requests that modify the state or read the logs or the audit log still require the
+    /// authentication secret. Meant for monitoring tools that shouldn't have access to the secret.
+    /// Has no effect if authentication is disabled.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    auth_allow_unauthenticated_reads: bool = false,
+
     /// The authentication file location [default: /etc/beegfs/conn.auth]
     #[arg(long)]
     #[arg(value_name = "PATH")]
     auth_file: PathBuf = "/etc/beegfs/conn.auth".into(),
 
+    /// Reads the authentication secret from this environment variable instead of `auth-file`.
+    ///
+    /// Can't be combined with `auth-file` or `auth-secret-fd`.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "NAME")]
+    auth_secret_env: Option<String> = None,
+
+    /// Reads the authentication secret from this inherited file descriptor instead of `auth-file`.
+    ///
+    /// The descriptor is read until EOF and closed afterwards. Can't be combined with `auth-file`
+    /// or `auth-secret-env`.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "FD")]
+    auth_secret_fd: Option<RawFd> = None,
+
     /// General
 
     /// Disables registration of new nodes and targets (clients excluded).
@@ -259,8 +536,41 @@ generate_structs! {
     #[arg(num_args = 0..=1, default_missing_value = "true")]
     registration_disable: bool = false,
 
+    /// Rejects node registrations using a machine UUID that is already in use by another node of
+    /// the same type.
+    ///
+    /// Such a conflict usually means the machines have been cloned from the same image. By
+    /// default, only a warning is logged. Note that enabling this prevents running multiple meta
+    /// or multiple storage services on the same machine.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    registration_machine_uuid_strict: bool = false,
+
+    /// Limits the number of storage targets that can be mapped to a single storage node.
+    /// [default: unlimited]
+    ///
+    /// Mapping requests that would exceed the limit are rejected as a whole. Protects the
+    /// database from a misbehaving node mapping an unbounded number of targets.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "COUNT")]
+    max_targets_per_node: Option<usize> = None,
+
+    /// Limits the number of registered storage targets that are not mapped to any node.
+    /// [default: unlimited]
+    ///
+    /// Registration of new targets is rejected while the limit is reached. Complements
+    /// `max-targets-per-node`, protecting the database from a misbehaving node registering an
+    /// unbounded number of targets without ever mapping them.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "COUNT")]
+    max_unmapped_targets: Option<usize> = None,
+
     /// Defines after which time without contact a node/target is considered offline. [default: 180s]
     ///
+    /// Must be at least 6s.
+    ///
     /// IMPORTANT: This setting must be the same on all nodes in the system, especially when using
     /// mirroring.
     #[arg(long, )]
@@ -269,6 +579,18 @@ generate_structs! {
     #[serde(deserialize_with = "deserialize_duration")]
     node_offline_timeout: Duration = Duration::from_secs(180),
 
+    /// Additional time a target is given after exceeding `node-offline-timeout` before it is
+    /// considered offline. [default: 0s]
+    ///
+    /// During the grace period, the target is reported as probably offline and doesn't count
+    /// towards a buddy group switchover yet. Avoids unnecessary switchovers caused by brief
+    /// network interruptions.
+    #[arg(long)]
+    #[arg(value_name = "DURATION")]
+    #[arg(value_parser = duration::parse)]
+    #[serde(deserialize_with = "deserialize_duration")]
+    node_offline_grace: Duration = Duration::ZERO,
+
     /// Defines after which time without contact a client is considered gone and will be removed.
     /// [default: 30m]
     #[arg(long)]
@@ -277,6 +599,90 @@ generate_structs! {
     #[serde(deserialize_with = "deserialize_duration")]
     client_auto_remove_timeout: Duration = Duration::from_secs(30 * 60),
 
+    /// Maximum number of stale clients removed at once. [default: 1000]
+    ///
+    /// If more clients are stale, the remaining ones are removed in further batches shortly
+    /// after, spreading the load caused by a large number of removals.
+    #[arg(long)]
+    #[arg(value_name = "LIMIT")]
+    client_auto_remove_batch: usize = 1000,
+
+    /// Defines how often stale and orphaned runtime data is removed from the database.
+    /// [default: 1h]
+    ///
+    /// Removes clients without contact for more than twice `client-auto-remove-timeout` (as a
+    /// backstop for the regular removal) and nic, quota usage and target state history entries of
+    /// nodes and targets that don't exist anymore. 0 disables trimming.
+    #[arg(long)]
+    #[arg(value_name = "DURATION")]
+    #[arg(value_parser = duration::parse)]
+    #[serde(deserialize_with = "deserialize_duration")]
+    db_trim_interval: Duration = Duration::from_secs(60 * 60),
+
+    /// Maximum number of rows of each kind removed per database trim run. [default: 1000]
+    ///
+    /// If more rows are left, trimming continues shortly after.
+    #[arg(long)]
+    #[arg(value_name = "LIMIT")]
+    db_trim_batch: usize = 1000,
+
+    /// Skips offline server nodes when sending notifications. [default: false]
+    ///
+    /// Meta and storage nodes whose last contact is longer ago than `node-offline-timeout` plus
+    /// `node-offline-grace` don't receive notifications, reducing pointless traffic during large
+    /// outages. Nodes within the grace period still receive them. Clients are always notified.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    notify_skip_offline: bool = false,
+
+    /// Defines how recent the secondaries last contact must be for a switchover. [default: 2]
+    ///
+    /// A buddy group is only switched over if the secondaries last contact is less than
+    /// `node-offline-timeout` divided by this value ago. Must be at least 1. Lower values make the
+    /// switchover more aggressive: With 1, a secondary which is about to go offline itself can
+    /// still be promoted, which risks switching over to a target that is not reachable either.
+    #[arg(long)]
+    #[arg(value_name = "DIVISOR")]
+    switchover_secondary_divisor: u32 = 2,
+
+    /// Also switches over buddy groups whose primary target needs a resync.
+    ///
+    /// This was the behavior of the old management. It allows restarting a crashed primary
+    /// immediately, but is dangerous as the primary might not be known as offline in the whole
+    /// system yet. Only enable this if you know what you are doing.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    switchover_on_primary_needs_resync: bool = false,
+
+    /// Restarts timed tasks the watchdog finds stuck or exited. [default: false]
+    ///
+    /// The timed tasks (stale client removal, switchover and quota updates) are monitored and an
+    /// error is logged if one of them hasn't run for several of its intervals or exited
+    /// unexpectedly. When set, the affected task is also aborted and started again.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    restart_stuck_timer_tasks: bool = false,
+
+    /// Maximum number of resyncs started by the management that may be active at the same time.
+    /// [default: 0]
+    ///
+    /// Start resync requests exceeding the limit wait until another resync has finished. A resync
+    /// counts as active while the secondary needs a resync and its source node reports it as
+    /// running or not yet started. 0 means unlimited.
+    #[arg(long)]
+    #[arg(value_name = "LIMIT")]
+    max_concurrent_resyncs: usize = 0,
+
+    /// Defines how long to wait for outstanding requests to complete on shutdown. [default: 10s]
+    ///
+    /// On shutdown, no new connections and requests are accepted anymore. Requests that did not
+    /// complete within this time are cancelled.
+    #[arg(long)]
+    #[arg(value_name = "DURATION")]
+    #[arg(value_parser = duration::parse)]
+    #[serde(deserialize_with = "deserialize_duration")]
+    shutdown_drain_timeout: Duration = Duration::from_secs(10),
+
     /// Disables loading the license library.
     ///
     /// Deprecated. Loading a license is now mandatory.
@@ -310,6 +716,15 @@ generate_structs! {
     #[arg(value_name = "LIMIT")]
     max_blocking_threads: usize = 128,
 
+    /// Stack size of each worker and blocking thread. [default: 16MiB]
+    ///
+    /// Must be between 1MiB and 1GiB. This setting should usually be left alone.
+    #[arg(long)]
+    #[arg(value_name = "SIZE")]
+    #[arg(value_parser = byte_size::parse)]
+    #[serde(deserialize_with = "deserialize_byte_size")]
+    worker_stack_size: u64 = 16 * 1024 * 1024,
+
     // Quota
 
     /// Enables quota data collection and checks.
@@ -338,6 +753,14 @@ generate_structs! {
     #[serde(deserialize_with = "deserialize_duration")]
     quota_update_interval: Duration = Duration::from_secs(30),
 
+    /// Maximum number of storage targets to fetch quota info from concurrently. [default: 16]
+    ///
+    /// Higher values shorten the time a quota update cycle takes on large systems, but put more
+    /// load on the storage nodes at the same time.
+    #[arg(long)]
+    #[arg(value_name = "LIMIT")]
+    quota_fetch_concurrency: usize = 16,
+
     /// Defines the minimum id of the existing system users to be quota checked and enforced.
     ///
     /// Note that this uses the users from the local machine the management is running on.
@@ -394,15 +817,41 @@ generate_structs! {
     #[serde(deserialize_with = "deserialize_optional_u32_range")]
     quota_group_ids_range: Option<RangeInclusive<u32>> = None,
 
+    /// Loads the project ids to be quota queried and enforced from a file.
+    ///
+    /// Ids must be numeric only and separated by any whitespace. Project quota is only queried
+    /// and enforced if at least one of the project id options is set. All storage and meta
+    /// servers must support project quota in that case.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "PATH")]
+    quota_project_ids_file: Option<PathBuf> = None,
+    /// Defines a range of project ids to be quota queried and enforced.
+    ///
+    /// IMPORTANT: This setting may only be used for reasonable small ranges (hundreds or
+    /// thousands). For a large range of ids, the file should be used instead.
+    #[arg(long)]
+    #[arg(num_args = 1)]
+    #[arg(value_name = "RANGE")]
+    #[arg(value_parser = integer_range::parse::<u32>)]
+    #[serde(deserialize_with = "deserialize_optional_u32_range")]
+    quota_project_ids_range: Option<RangeInclusive<u32>> = None,
+
     // Capacity pools
 
     /// Sets the limits / boundaries of the meta capacity pools.
+    ///
+    /// The margins define by how much a target must exceed or fall below a limit before it
+    /// changes its pool.
     #[arg(skip)]
     cap_pool_meta_limits: CapPoolLimits = CapPoolLimits {
         inodes_low: 10 * 1000 * 1000,
         inodes_emergency: 1000 * 1000,
         space_low: 10 * 1024 * 1024 * 1024,
         space_emergency: 3 * 1024 * 1024 * 1024,
+        inodes_margin: 0,
+        space_margin: 0,
+        weighting: CapPoolWeighting::Worst,
     },
     /// Sets the limits / boundaries of the dynamic meta capacity pools and the thresholds that determine
     /// which limits shall be used.
@@ -416,6 +865,9 @@ generate_structs! {
         inodes_emergency: 1000 * 1000,
         space_low: 512 * 1024 * 1024 * 1024,
         space_emergency: 10 * 1024 * 1024 * 1024,
+        inodes_margin: 0,
+        space_margin: 0,
+        weighting: CapPoolWeighting::Worst,
     },
     /// Sets the limits / boundaries of the dynamic meta capacity pools and the thresholds that determine
     /// which limits shall be used.
@@ -437,8 +889,24 @@ generate_structs! {
     #[arg(hide = true)]
     #[arg(value_name = "PATH")]
     daemonize_pid_file: PathBuf = "/run/beegfs/mgmtd.pid".into(),
+
+    /// Stays in the foreground, even if daemonization is enabled in the config file.
+    #[arg(long)]
+    #[arg(num_args = 0..=1, default_missing_value = "true")]
+    #[serde(skip)]
+    foreground: bool = false,
 }
 
+/// The minimum node offline timeout. The switchover check runs every sixth of the timeout, so
+/// this keeps its interval at one second or above.
+const NODE_OFFLINE_TIMEOUT_MIN: Duration = Duration::from_secs(6);
+
+/// The maximum UDP dedup window
+const UDP_DEDUP_WINDOW_MAX: Duration = Duration::from_secs(10);
+
+/// The allowed range for the worker thread stack size
+const WORKER_STACK_SIZE_RANGE: RangeInclusive<u64> = 1024 * 1024..=1024 * 1024 * 1024;
+
 impl Config {
     pub fn check_validity(&self) -> Result<()> {
         if let Some(ref uuid) = self.fs_uuid
@@ -447,10 +915,90 @@ impl Config {
             bail!("Provided file system UUID is not a valid v4 UUID");
         }
 
+        if self.import.is_some() && (self.import_from_v7.is_some() || self.fs_uuid.is_some()) {
+            bail!("import can't be combined with import-from-v7 or fs-uuid");
+        }
+
         if self.quota_enforce && !self.quota_enable {
             bail!("Quota enforcement requires quota being enabled");
         }
 
+        if self.node_offline_timeout < NODE_OFFLINE_TIMEOUT_MIN {
+            bail!(
+                "Node offline timeout must be at least {}s",
+                NODE_OFFLINE_TIMEOUT_MIN.as_secs()
+            );
+        }
+
+        if self.quota_fetch_concurrency == 0 {
+            bail!("Quota fetch concurrency must be at least 1");
+        }
+
+        if self.max_blocking_threads == 0 {
+            bail!("Maximum number of blocking threads must be at least 1");
+        }
+
+        if !WORKER_STACK_SIZE_RANGE.contains(&self.worker_stack_size) {
+            bail!(
+                "Worker stack size must be between {} and {} bytes",
+                WORKER_STACK_SIZE_RANGE.start(),
+                WORKER_STACK_SIZE_RANGE.end()
+            );
+        }
+
+        if self.tcp_listen_backlog == 0 {
+            bail!("TCP listen backlog must be at least 1");
+        }
+
+        if self.tcp_accept_tasks == 0 {
+            bail!("Number of TCP accept tasks must be at least 1");
+        }
+
+        if self.udp_handler_concurrency == 0 {
+            bail!("UDP handler concurrency must be at least 1");
+        }
+
+        if self.udp_dedup_window > UDP_DEDUP_WINDOW_MAX {
+            bail!(
+                "UDP dedup window must not exceed {}s",
+                UDP_DEDUP_WINDOW_MAX.as_secs()
+            );
+        }
+
+        if self.udp_sockets == 0 {
+            bail!("Number of UDP sockets must be at least 1");
+        }
+
+        if self.switchover_secondary_divisor == 0 {
+            bail!("Switchover secondary divisor must be at least 1");
+        }
+
+        if self.client_auto_remove_batch == 0 {
+            bail!("Client auto remove batch size must be at least 1");
+        }
+
+        if self.db_trim_batch == 0 {
+            bail!("Database trim batch size must be at least 1");
+        }
+
+        if self.aliases.is_some()
+            && (!self.init || self.import.is_some() || self.import_from_v7.is_some())
+        {
+            bail!("aliases can only be used with init and without an import");
+        }
+
+        if self.dry_run && !self.upgrade {
+            bail!("dry-run can only be used with upgrade");
+        }
+
+        if self.ipv6_disable && self.bind_addr.is_some_and(|a| a.is_ipv6()) {
+            bail!("An IPv6 bind-addr can't be used with ipv6-disable");
+        }
+
+        if self.auth_secret_env.is_some() && self.auth_secret_fd.is_some() {
+            bail!("Only one of auth-secret-env and auth-secret-fd can be set");
+        }
+
         self.cap_pool_meta_limits
             .check()
             .context("Capacity pool meta limits")?;
@@ -489,6 +1037,82 @@ impl Config {
 
         Ok(())
     }
+
+    /// Checks that `auth-file` is not combined with another secret source.
+    ///
+    /// `auth_file_set` tells whether `auth-file` has been set explicitly (in the config file or on
+    /// the command line). This can't be derived from the value as it might be set to the default.
+    fn check_auth_sources(&self, auth_file_set: bool) -> Result<()> {
+        if auth_file_set && (self.auth_secret_env.is_some() || self.auth_secret_fd.is_some()) {
+            bail!("auth-file can't be combined with auth-secret-env or auth-secret-fd");
+        }
+
+        Ok(())
+    }
+
+    /// Loads the authentication secret from the configured source.
+    ///
+    /// Uses `auth_secret_env` or `auth_secret_fd` if set, `auth_file` otherwise. Returns `None` if
+    /// authentication is disabled.
+    pub fn load_auth_secret(&self) -> Result<Option<AuthSecret>> {
+        if self.auth_disable {
+            return Ok(None);
+        }
+
+        let secret = if let Some(ref name) = self.auth_secret_env {
+            std::env::var_os(name)
+                .with_context(|| format!("Environment variable {name} is not set"))?
+                .into_encoded_bytes()
+        } else if let Some(fd) = self.auth_secret_fd {
+            let mut file = adopt_secret_fd(fd)?;
+            let mut secret = vec![];
+            file.read_to_end(&mut secret).with_context(|| {
+                format!("Could not read authentication secret from file descriptor {fd}")
+            })?;
+            secret
+        } else {
+            std::fs::read(&self.auth_file).with_context(|| {
+                format!("Could not open authentication file {:?}", self.auth_file)
+            })?
+        };
+
+        Ok(Some(AuthSecret::hash_from_bytes(secret)))
+    }
+}
+
+/// Takes ownership of the inherited file descriptor `fd` containing the authentication secret.
+///
+/// The descriptor must be open and refer to a regular file or a pipe. This is checked before taking
+/// ownership, so a wrong descriptor number is rejected instead of being read and closed.
+fn adopt_secret_fd(fd: RawFd) -> Result<std::fs::File> {
+    if fd < 0 {
+        bail!("Invalid authentication file descriptor {fd}");
+    }
+
+    // SAFETY: fcntl() and fstat() only query the descriptor, stat is a local buffer
+    let file_type = unsafe {
+        if libc::fcntl(fd, libc::F_GETFD) < 0 {
+            bail!("Authentication file descriptor {fd} is not open");
+        }
+
+        let mut stat: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd, &mut stat) < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Could not stat authentication file descriptor {fd}"));
+        }
+
+        stat.st_mode & libc::S_IFMT
+    };
+
+    if file_type != libc::S_IFREG && file_type != libc::S_IFIFO {
+        bail!("Authentication file descriptor {fd} must refer to a regular file or a pipe");
+    }
+
+    // SAFETY:
+    // The descriptor is meant to be inherited from the parent process for the sole purpose of
+    // passing the secret and has been checked to be open above. We take ownership of it here and
+    // close it after reading.
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
 }
 
 /// Loads and parses configuration.
@@ -505,19 +1129,27 @@ impl Config {
 /// messages. Since the log system might not  be initialized yet, this allows the caller to log
 /// the messages later.
 pub fn load_and_parse() -> Result<(Config, Vec<String>)> {
+    load_and_parse_with(OptionalConfig::parse())
+}
+
+/// Loads and parses configuration like [load_and_parse()], using the given command line
+/// parameters.
+fn load_and_parse_with(command_config: OptionalConfig) -> Result<(Config, Vec<String>)> {
     let mut info_log = vec![];
     let mut config = Config::default();
-    let command_config = OptionalConfig::parse();
 
     let config_file = command_config
         .config_file
         .as_ref()
         .unwrap_or(&config.config_file);
 
+    let mut auth_file_set = command_config.auth_file.is_some();
+
     match std::fs::read_to_string(config_file) {
         Ok(ref toml_config) => {
             let file_config: OptionalConfig =
                 toml::from_str(toml_config).with_context(|| "Could not parse config file")?;
+            auth_file_set |= file_config.auth_file.is_some();
 
             info_log.push(format!("Loaded config file from {config_file:?}"));
             config.update_from_optional(file_config);
@@ -534,6 +1166,9 @@ pub fn load_and_parse() -> Result<(Config, Vec<String>)> {
 
     config.update_from_optional(command_config);
     config.check_validity().context("Invalid config")?;
+    config
+        .check_auth_sources(auth_file_set)
+        .context("Invalid config")?;
 
     if config.port_shift != 0 {
         // these additions are allowed to overflow, but we will let the user know
@@ -546,15 +1181,38 @@ pub fn load_and_parse() -> Result<(Config, Vec<String>)> {
         if oflow {
             info_log.push("Overflow while adding port shift to gRPC port. Resulting port might be unexpected.".to_string())
         }
+        if let Some(ref mut metrics_port) = config.metrics_port {
+            (*metrics_port, oflow) = metrics_port.overflowing_add(config.port_shift);
+            if oflow {
+                info_log.push("Overflow while adding port shift to metrics port. Resulting port might be unexpected.".to_string())
+            }
+        }
     }
 
     Ok((config, info_log))
 }
 
+/// Builds the output of `--check-config` from the loaded config and the log messages returned by
+/// [load_and_parse()].
+///
+/// Only meant to be called for a config that has been loaded successfully. If loading fails, the
+/// error is printed instead and the management exits with a non-zero code.
+pub fn check_config_report(config: &Config, info_log: &[String]) -> String {
+    let mut report = String::new();
+    for l in info_log {
+        report.push_str(l);
+        report.push('\n');
+    }
+    report.push_str(&format!(
+        "Configuration is valid. Effective configuration:\n{config:#?}\n"
+    ));
+    report
+}
+
 // Custom types for user input
 
 /// Defines where log messages shall be sent to
-#[derive(Clone, Debug, ValueEnum, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum LogTarget {
     Journald,
@@ -563,8 +1221,36 @@ pub enum LogTarget {
     Stderr,
 }
 
+/// Defines the format of log messages written to stderr
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Defines the durability of database transactions
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DbDurability {
+    Fast,
+    Normal,
+    Full,
+}
+
+/// Conversion of user given durability into type used by the sqlite crate
+impl From<DbDurability> for sqlite::Durability {
+    fn from(value: DbDurability) -> Self {
+        match value {
+            DbDurability::Fast => sqlite::Durability::Fast,
+            DbDurability::Normal => sqlite::Durability::Normal,
+            DbDurability::Full => sqlite::Durability::Full,
+        }
+    }
+}
+
 /// Defines the log level
-#[derive(Clone, Debug, ValueEnum, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum LogLevel {
     Off,
@@ -588,3 +1274,356 @@ impl From<LogLevel> for LevelFilter {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_validity() {
+        Config::default().check_validity().unwrap();
+
+        let config = Config {
+            quota_enforce: true,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Quota enforcement requires quota being enabled"
+        );
+
+        for node_offline_timeout in [Duration::ZERO, Duration::from_millis(5999)] {
+            let config = Config {
+                node_offline_timeout,
+                ..Default::default()
+            };
+            let err = config.check_validity().unwrap_err();
+            assert_eq!(err.to_string(), "Node offline timeout must be at least 6s");
+        }
+
+        Config {
+            node_offline_timeout: Duration::from_secs(6),
+            ..Default::default()
+        }
+        .check_validity()
+        .unwrap();
+
+        let config = Config {
+            quota_fetch_concurrency: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Quota fetch concurrency must be at least 1"
+        );
+
+        let config = Config {
+            max_blocking_threads: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Maximum number of blocking threads must be at least 1"
+        );
+
+        for worker_stack_size in [0, 1024 * 1024 - 1, 1024 * 1024 * 1024 + 1] {
+            let config = Config {
+                worker_stack_size,
+                ..Default::default()
+            };
+            let err = config.check_validity().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Worker stack size must be between 1048576 and 1073741824 bytes"
+            );
+        }
+
+        let config = Config {
+            udp_handler_concurrency: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "UDP handler concurrency must be at least 1"
+        );
+
+        let config = Config {
+            tcp_listen_backlog: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(err.to_string(), "TCP listen backlog must be at least 1");
+
+        let config = Config {
+            tcp_accept_tasks: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Number of TCP accept tasks must be at least 1"
+        );
+
+        let config = Config {
+            udp_sockets: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(err.to_string(), "Number of UDP sockets must be at least 1");
+
+        let config = Config {
+            switchover_secondary_divisor: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Switchover secondary divisor must be at least 1"
+        );
+
+        let config = Config {
+            client_auto_remove_batch: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client auto remove batch size must be at least 1"
+        );
+
+        let config = Config {
+            ipv6_disable: true,
+            bind_addr: Some("::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "An IPv6 bind-addr can't be used with ipv6-disable"
+        );
+
+        Config {
+            ipv6_disable: true,
+            bind_addr: Some("127.0.0.1".parse().unwrap()),
+            ..Default::default()
+        }
+        .check_validity()
+        .unwrap();
+
+        let config = Config {
+            db_trim_batch: 0,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Database trim batch size must be at least 1"
+        );
+
+        let config = Config {
+            dry_run: true,
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(err.to_string(), "dry-run can only be used with upgrade");
+
+        let config = Config {
+            auth_secret_env: Some("SECRET".to_string()),
+            auth_secret_fd: Some(3),
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Only one of auth-secret-env and auth-secret-fd can be set"
+        );
+
+        let config = Config {
+            import: Some("/some/file".into()),
+            import_from_v7: Some("/some/dir".into()),
+            ..Default::default()
+        };
+        let err = config.check_validity().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "import can't be combined with import-from-v7 or fs-uuid"
+        );
+    }
+
+    #[test]
+    fn config_file() {
+        let file_config: OptionalConfig = toml::from_str(
+            r#"
+            worker-stack-size = "32MiB"
+            node-offline-timeout = "60s"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.update_from_optional(file_config);
+        assert_eq!(config.worker_stack_size, 32 * 1024 * 1024);
+        assert_eq!(config.node_offline_timeout, Duration::from_secs(60));
+
+        toml::from_str::<OptionalConfig>(r#"worker-stack-size = "32 apples""#).unwrap_err();
+    }
+
+    #[test]
+    fn check_config() {
+        use std::ffi::OsStr;
+
+        let path = std::env::temp_dir().join(format!("mgmtd-check-config-{}", std::process::id()));
+        let load = |file_content: &str| {
+            std::fs::write(&path, file_content).unwrap();
+            load_and_parse_with(
+                OptionalConfig::try_parse_from([
+                    OsStr::new("beegfs-mgmtd"),
+                    OsStr::new("--check-config"),
+                    OsStr::new("--config-file"),
+                    path.as_os_str(),
+                ])
+                .unwrap(),
+            )
+        };
+
+        let (config, info_log) = load(r#"node-offline-timeout = "60s""#).unwrap();
+        assert!(config.check_config);
+
+        let report = check_config_report(&config, &info_log);
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            format!("Loaded config file from {path:?}")
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "Configuration is valid. Effective configuration:"
+        );
+        assert!(report.contains("node_offline_timeout: 60s"));
+
+        // An invalid config fails loading, which main() turns into exit code 1
+        let err = load(r#"node-offline-timeout = "1s""#).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid config");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn changed_settings() {
+        let config = Config::default();
+        assert!(config.changed_settings(&Config::default()).is_empty());
+
+        let other = Config {
+            node_offline_timeout: Duration::from_secs(1),
+            log_level: LogLevel::Debug,
+            cap_pool_meta_limits: CapPoolLimits {
+                space_low: 1,
+                ..config.cap_pool_meta_limits.clone()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            config.changed_settings(&other),
+            ["log_level", "node_offline_timeout", "cap_pool_meta_limits"]
+        );
+    }
+
+    #[test]
+    fn check_auth_sources() {
+        let config = Config {
+            auth_secret_fd: Some(3),
+            ..Default::default()
+        };
+        config.check_auth_sources(false).unwrap();
+        // Explicitly set, even if it is the default path
+        let err = config.check_auth_sources(true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "auth-file can't be combined with auth-secret-env or auth-secret-fd"
+        );
+
+        let config = Config {
+            auth_secret_env: Some("SECRET".to_string()),
+            ..Default::default()
+        };
+        config.check_auth_sources(true).unwrap_err();
+
+        Config::default().check_auth_sources(true).unwrap();
+    }
+
+    #[test]
+    fn load_auth_secret() {
+        let secret = b"the secret";
+        let expected = Some(AuthSecret::hash_from_bytes(secret));
+
+        let path = std::env::temp_dir().join(format!("mgmtd-auth-{}", std::process::id()));
+        std::fs::write(&path, secret).unwrap();
+
+        // File
+        let config = Config {
+            auth_file: path.clone(),
+            ..Default::default()
+        };
+        assert_eq!(config.load_auth_secret().unwrap(), expected);
+
+        // File descriptor
+        let fd = std::os::fd::IntoRawFd::into_raw_fd(std::fs::File::open(&path).unwrap());
+        let config = Config {
+            auth_secret_fd: Some(fd),
+            ..Default::default()
+        };
+        assert_eq!(config.load_auth_secret().unwrap(), expected);
+
+        // Invalid file descriptors are rejected without being taken over
+        let config = Config {
+            auth_secret_fd: Some(1_000_000),
+            ..Default::default()
+        };
+        let err = config.load_auth_secret().unwrap_err();
+        assert!(err.to_string().contains("is not open"), "{err:#}");
+
+        let dir = std::fs::File::open(std::env::temp_dir()).unwrap();
+        let config = Config {
+            auth_secret_fd: Some(std::os::fd::AsRawFd::as_raw_fd(&dir)),
+            ..Default::default()
+        };
+        let err = config.load_auth_secret().unwrap_err();
+        assert!(
+            err.to_string().contains("regular file or a pipe"),
+            "{err:#}"
+        );
+        // Still open
+        dir.metadata().unwrap();
+
+        // Environment variable. Cargo sets CARGO_PKG_NAME when running tests, which avoids
+        // modifying the environment of the multi-threaded test binary.
+        let config = Config {
+            auth_secret_env: Some("CARGO_PKG_NAME".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.load_auth_secret().unwrap(),
+            Some(AuthSecret::hash_from_bytes(env!("CARGO_PKG_NAME")))
+        );
+
+        let config = Config {
+            auth_secret_env: Some("MGMTD_TEST_AUTH_SECRET_UNSET".to_string()),
+            ..Default::default()
+        };
+        config.load_auth_secret().unwrap_err();
+
+        // Disabled
+        let config = Config {
+            auth_disable: true,
+            auth_secret_env: Some("MGMTD_TEST_AUTH_SECRET_UNSET".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.load_auth_secret().unwrap(), None);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
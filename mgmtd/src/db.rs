@@ -6,15 +6,21 @@
 //! it. The latter hide the raw SQL and resemble a primitive ORM, defining data models in terms of
 //! Rust and interfaces to obtain the data.
 
+pub(crate) mod audit_log;
 pub(crate) mod buddy_group;
 pub(crate) mod config;
+pub(crate) mod consistency;
 pub(crate) mod entity;
+pub mod export;
 mod import_v7;
 pub(crate) mod misc;
 pub(crate) mod node;
 pub(crate) mod node_nic;
+pub mod quota;
 pub(crate) mod storage_pool;
 pub(crate) mod target;
+pub(crate) mod target_state_history;
+pub(crate) mod trim;
 
 use self::config::Config;
 use crate::error::TypedError;
@@ -22,6 +28,7 @@ use crate::types::*;
 use anyhow::{Result, anyhow, bail};
 pub use import_v7::import_v7;
 use rusqlite::{OptionalExtension, Row, Transaction, params};
+use serde::Deserialize;
 use shared::types::*;
 use sqlite::*;
 use sqlite_check::sql;
@@ -34,17 +41,59 @@ use uuid::Uuid;
 /// SQL text to execute. The elements are guaranteed to be contiguous, but may start later than 1.
 pub const MIGRATIONS: &[sqlite::Migration] = include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
 
+/// Aliases to assign to the entities created with the schema when initializing a new database
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct InitialAliases {
+    pub management_node: Option<String>,
+    pub default_storage_pool: Option<String>,
+}
+
+impl InitialAliases {
+    /// Parses the aliases from the contents of a TOML file
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
 /// Inserts initial entries into a new database. Remember to commit the transaction after calling
 /// this function.
 ///
-/// If `fs_uuid` is provided, it will be used. Otherwise, a new FsUUID will be generated.
-pub fn initial_entries(tx: &Transaction, fs_uuid: Option<Uuid>) -> Result<()> {
+/// If `fs_uuid` is provided, it will be used. Otherwise, a new FsUUID will be generated. The given
+/// aliases must be valid and unique, otherwise the function fails.
+pub fn initial_entries(
+    tx: &Transaction,
+    fs_uuid: Option<Uuid>,
+    aliases: &InitialAliases,
+) -> Result<()> {
     config::set(tx, Config::FsUuid, fs_uuid.unwrap_or_else(Uuid::new_v4))?;
     config::set(
         tx,
         Config::FsInitDateSecs,
         SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
     )?;
+
+    // The UIDs of the management node and the default storage pool as created by the schema
+    for (uid, alias) in [
+        (1, &aliases.management_node),
+        (2, &aliases.default_storage_pool),
+    ] {
+        let Some(alias) = alias else {
+            continue;
+        };
+
+        let alias = Alias::new(alias.as_str())?;
+        if entity::get_uid(tx, alias.as_ref())?.is_some_and(|e| e != uid) {
+            bail!(TypedError::value_exists("Alias", alias));
+        }
+
+        let affected = tx.execute(
+            sql!("UPDATE entities SET alias = ?1 WHERE uid = ?2"),
+            params![alias.as_ref(), uid],
+        )?;
+        check_affected_rows(affected, [1])?;
+    }
+
     Ok(())
 }
 
@@ -96,4 +145,66 @@ pub(crate) mod test {
         op(&mut tx);
         tx.commit().unwrap();
     }
+
+    /// Checks that migrating a new database step by step and applying all migrations at once (as
+    /// the queries are checked against by `sqlite_check`) both result in the committed schema
+    /// snapshot. The snapshot must be updated deliberately when adding a migration.
+    #[test]
+    fn incremental_migrations() {
+        let snapshot = include_str!("db/schema/current.sql");
+
+        sqlite::check_incremental_migrations(MIGRATIONS, snapshot).unwrap();
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/current.sql")) == snapshot,
+            "The flattened migrations differ from src/db/schema/current.sql"
+        );
+    }
+
+    fn init(aliases: &str) -> Result<Vec<String>> {
+        let mut conn = sqlite::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        sqlite::migrate_schema(&tx, MIGRATIONS).unwrap();
+
+        initial_entries(&tx, None, &InitialAliases::parse(aliases)?)?;
+
+        Ok(tx
+            .query_map_collect(sql!("SELECT alias FROM entities ORDER BY uid"), [], |row| {
+                row.get(0)
+            })
+            .unwrap())
+    }
+
+    #[test]
+    fn initial_aliases() {
+        assert_eq!(init("").unwrap(), ["management", "storage_pool_default"]);
+        assert_eq!(
+            init(
+                r#"
+                management-node = "mgmtd"
+                default-storage-pool = "pool_fast"
+                "#
+            )
+            .unwrap(),
+            ["mgmtd", "pool_fast"]
+        );
+        assert_eq!(
+            init(r#"management-node = "management""#).unwrap(),
+            ["management", "storage_pool_default"]
+        );
+
+        // Duplicate alias
+        init(
+            r#"
+            management-node = "pool"
+            default-storage-pool = "pool"
+            "#,
+        )
+        .unwrap_err();
+        init(r#"management-node = "storage_pool_default""#).unwrap_err();
+
+        // Invalid alias
+        init(r#"management-node = "1mgmtd""#).unwrap_err();
+        // Unknown entity
+        init(r#"meta-node = "meta""#).unwrap_err();
+    }
 }
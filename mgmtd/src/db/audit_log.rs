@@ -0,0 +1,84 @@
+//! Functions for the audit log, recording administrative operations
+
+use super::*;
+
+/// Represents an audit log entry.
+#[derive(Clone, Debug)]
+pub(crate) struct AuditLogEntry {
+    /// Unix timestamp in seconds
+    pub time: i64,
+    pub operation: String,
+    pub entity: String,
+    pub peer: Option<String>,
+}
+
+/// Inserts an audit log entry with the current time.
+///
+/// Meant to be called within the transaction that performs the recorded change, so the entry is
+/// only stored if the change is committed.
+pub(crate) fn insert(
+    tx: &Transaction,
+    operation: &str,
+    entity: &str,
+    peer: Option<&str>,
+) -> Result<()> {
+    let affected = tx.execute_cached(
+        sql!(
+            "INSERT INTO audit_log (time, operation, entity, peer)
+            VALUES (UNIXEPOCH('now'), ?1, ?2, ?3)"
+        ),
+        params![operation, entity, peer],
+    )?;
+
+    check_affected_rows(affected, [1])
+}
+
+/// Retrieve a page of audit log entries recorded within the given time range (inclusive, unix
+/// timestamp in seconds), ordered by insertion.
+pub(crate) fn get_range(
+    tx: &Transaction,
+    from: i64,
+    to: i64,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<AuditLogEntry>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT time, operation, entity, peer FROM audit_log
+            WHERE time BETWEEN ?1 AND ?2
+            ORDER BY id ASC
+            LIMIT ?3, ?4"
+        ),
+        params![from, to, offset, limit],
+        |row| {
+            Ok(AuditLogEntry {
+                time: row.get(0)?,
+                operation: row.get(1)?,
+                entity: row.get(2)?,
+                peer: row.get(3)?,
+            })
+        },
+    )?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get() {
+        with_test_data(|tx| {
+            insert(tx, "Create pool", "storage:1", Some("127.0.0.1:12345")).unwrap();
+            insert(tx, "Delete pool", "storage:1", None).unwrap();
+
+            let entries = get_range(tx, 0, i64::MAX, 0, 100).unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].operation, "Create pool");
+            assert_eq!(entries[0].peer.as_deref(), Some("127.0.0.1:12345"));
+            assert_eq!(entries[1].peer, None);
+
+            assert_eq!(get_range(tx, 0, i64::MAX, 1, 100).unwrap().len(), 1);
+            assert!(get_range(tx, 0, 1000, 0, 100).unwrap().is_empty());
+        })
+    }
+}
@@ -112,7 +112,7 @@ pub(crate) fn insert(
     let alias = if let Some(alias) = alias {
         alias
     } else {
-        format!("buddy_group_{}_{}", node_type.user_str(), group_id).try_into()?
+        Alias::new(format!("buddy_group_{}_{}", node_type.user_str(), group_id))?
     };
 
     // Insert entity
@@ -148,6 +148,73 @@ pub(crate) fn insert(
     Ok((new_uid, group_id))
 }
 
+/// A buddy group as returned by [get_overlapping()]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BuddyGroup {
+    pub uid: Uid,
+    pub id: BuddyGroupId,
+    pub alias: String,
+    pub p_target_id: TargetId,
+    pub s_target_id: TargetId,
+}
+
+impl BuddyGroup {
+    /// Whether the group consists of the two given targets. Since primary and secondary are swapped
+    /// on switchover, their order doesn't matter.
+    pub fn has_members(&self, target_a: TargetId, target_b: TargetId) -> bool {
+        (self.p_target_id, self.s_target_id) == (target_a, target_b)
+            || (self.p_target_id, self.s_target_id) == (target_b, target_a)
+    }
+}
+
+impl std::fmt::Display for BuddyGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (id {}, targets {} and {})",
+            self.alias, self.id, self.p_target_id, self.s_target_id
+        )
+    }
+}
+
+/// Returns the buddy groups of `node_type` that use the given `group_id` or `alias` or contain one
+/// of the given targets. These are the groups preventing a new group with these properties from
+/// being inserted.
+pub(crate) fn get_overlapping(
+    tx: &Transaction,
+    node_type: NodeTypeServer,
+    group_id: BuddyGroupId,
+    alias: &str,
+    p_target_id: TargetId,
+    s_target_id: TargetId,
+) -> Result<Vec<BuddyGroup>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT group_uid, group_id, alias, p_target_id, s_target_id FROM buddy_groups_ext
+            WHERE node_type = ?1
+            AND (group_id = ?2 OR alias = ?3
+                OR p_target_id IN (?4, ?5) OR s_target_id IN (?4, ?5))
+            ORDER BY group_id"
+        ),
+        params![
+            node_type.sql_variant(),
+            group_id,
+            alias,
+            p_target_id,
+            s_target_id
+        ],
+        |row| {
+            Ok(BuddyGroup {
+                uid: row.get(0)?,
+                id: row.get(1)?,
+                alias: row.get(2)?,
+                p_target_id: row.get(3)?,
+                s_target_id: row.get(4)?,
+            })
+        },
+    )?)
+}
+
 /// Changes the storage pool of the given buddy group IDs to a new one.
 pub(crate) fn update_storage_pools(
     tx: &Transaction,
@@ -179,16 +246,17 @@ pub(crate) fn update_storage_pools(
 ///
 /// # Conditions for a swap
 /// A swap happens, if
-/// * primaries last contact was more than `timeout` ago
+/// * primaries last contact was more than `timeout + grace` ago (or, if
+///   `swap_on_primary_needs_resync` is set, the primaries consistency state is `needs resync`)
 /// * AND secondaries consistency state is `good`
-/// * AND secondaries last contact was less than `timeout / 2` ago
+/// * AND secondaries last contact was less than `timeout / secondary_divisor` ago
 ///
 /// Note that old management also switched primary and secondary on the primary node being `needs
 /// resync`. A switchover under that condition (without it being known as offline in the whole
 /// system first) is actually dangerous, although it most likely only happened if the primary
 /// crashed while the secondary was still good. This allowed the user to immediately restart the
 /// primary and not having to wait for a resync due to the primary being offlined.
-/// We decided to leave this out for now and can still re-add it later if there are problems.
+/// It is therefore disabled by default and must be explicitly enabled by the user.
 ///
 /// # Return value
 /// Returns a Vec containing tuples with the ID and the node type of buddy groups which have been
@@ -196,17 +264,31 @@ pub(crate) fn update_storage_pools(
 pub(crate) fn check_and_swap_buddies(
     tx: &Transaction,
     timeout: Duration,
+    grace: Duration,
+    secondary_divisor: u32,
+    swap_on_primary_needs_resync: bool,
 ) -> Result<Vec<(BuddyGroupId, NodeTypeServer)>> {
+    if secondary_divisor == 0 {
+        bail!("Secondary divisor must be at least 1");
+    }
+
     let affected_groups: Vec<(BuddyGroupId, NodeTypeServer)> = tx.query_map_collect(
         sql!(
             "SELECT g.group_id, g.node_type FROM buddy_groups_ext AS g
             INNER JOIN targets_ext AS p_t ON p_t.target_uid = p_target_uid
             INNER JOIN targets_ext AS s_t ON s_t.target_uid = s_target_uid
-            WHERE (UNIXEPOCH('now') - UNIXEPOCH(p_t.last_update)) >= ?1
+            WHERE ((UNIXEPOCH('now') - UNIXEPOCH(p_t.last_update)) >= ?1 + ?5
+                    OR (?3 AND p_t.consistency == ?4))
                 AND s_t.consistency == 1
-                AND (UNIXEPOCH('now') - UNIXEPOCH(s_t.last_update)) < (?1 / 2)"
+                AND (UNIXEPOCH('now') - UNIXEPOCH(s_t.last_update)) < (?1 / ?2)"
         ),
-        [timeout.as_secs()],
+        params![
+            timeout.as_secs(),
+            secondary_divisor,
+            swap_on_primary_needs_resync,
+            TargetConsistencyState::NeedsResync.sql_variant(),
+            grace.as_secs()
+        ],
         |row| Ok((row.get(0)?, NodeTypeServer::from_row(row, 1)?)),
     )?;
 
@@ -232,12 +314,7 @@ pub(crate) fn check_and_swap_buddies(
 /// Returns the UIDs of the primary and the secondary node which own the primary and secondary
 /// target of the given group.
 pub(crate) fn prepare_storage_deletion(tx: &Transaction, id: BuddyGroupId) -> Result<(Uid, Uid)> {
-    if tx.query_row(sql!("SELECT COUNT(*) FROM client_nodes"), [], |row| {
-        row.get::<_, i64>(0)
-    })? > 0
-    {
-        bail!("Can't remove storage buddy group while clients are still mounted",);
-    }
+    super::node::ensure_no_clients(tx, "remove storage buddy group")?;
 
     let node_uids = tx.query_row(
         sql!(
@@ -319,6 +396,33 @@ mod test {
         })
     }
 
+    #[test]
+    fn get_overlapping() {
+        with_test_data(|tx| {
+            let ids = |group_id, alias, p, s| {
+                super::get_overlapping(tx, NodeTypeServer::Storage, group_id, alias, p, s)
+                    .unwrap()
+                    .into_iter()
+                    .map(|g| g.id)
+                    .collect::<Vec<_>>()
+            };
+
+            assert!(ids(10, "new_group", 2, 6).is_empty());
+            assert_eq!(ids(1, "new_group", 2, 6), [1]);
+            assert_eq!(ids(10, "storage_buddy_group_2", 2, 6), [2]);
+            assert_eq!(ids(10, "new_group", 13, 6), [2]);
+            assert_eq!(ids(1, "new_group", 9, 6), [1, 2]);
+            // Meta groups are not considered
+            assert!(ids(10, "meta_buddy_group_1", 2, 6).is_empty());
+
+            let group =
+                &super::get_overlapping(tx, NodeTypeServer::Storage, 0, "", 1, 5).unwrap()[0];
+            assert!(group.has_members(1, 5));
+            assert!(group.has_members(5, 1));
+            assert!(!group.has_members(1, 6));
+        })
+    }
+
     /// Test updating the storage pool of a buddy group
     #[test]
     fn update_storage_pool() {
@@ -366,7 +470,14 @@ mod test {
             )
             .unwrap();
 
-            let swaps = super::check_and_swap_buddies(tx, Duration::from_secs(100)).unwrap();
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(100),
+                Duration::ZERO,
+                2,
+                false,
+            )
+            .unwrap();
 
             assert_eq!(2, swaps.len());
             assert!(
@@ -384,6 +495,44 @@ mod test {
         })
     }
 
+    /// Test that a primary exceeding the timeout doesn't cause a switchover within the grace period
+    #[test]
+    fn swap_buddies_after_grace() {
+        with_test_data(|tx| {
+            tx.execute(
+                "UPDATE targets
+                SET last_update = DATETIME('now', '-150 seconds')
+                WHERE target_uid IN (201001, 202001)",
+                [],
+            )
+            .unwrap();
+
+            // 100s + 100s grace > 150s
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(100),
+                Duration::from_secs(100),
+                1,
+                false,
+            )
+            .unwrap();
+            assert!(swaps.is_empty());
+            ensure_no_swapped_buddies(tx);
+
+            // 100s + 40s grace < 150s
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(100),
+                Duration::from_secs(40),
+                1,
+                false,
+            )
+            .unwrap();
+            assert_eq!(2, swaps.len());
+            ensure_swapped_buddies(tx);
+        })
+    }
+
     /// Test that buddies are not swapped if secodary doesn't satisfy the conditions
     #[test]
     fn no_swap_buddies_on_secondary_timeout() {
@@ -398,7 +547,8 @@ mod test {
             )
             .unwrap();
 
-            super::check_and_swap_buddies(tx, Duration::from_secs(99999)).unwrap();
+            super::check_and_swap_buddies(tx, Duration::from_secs(99999), Duration::ZERO, 2, false)
+                .unwrap();
 
             ensure_no_swapped_buddies(tx);
         })
@@ -427,9 +577,89 @@ mod test {
             )
             .unwrap();
 
-            super::check_and_swap_buddies(tx, Duration::from_secs(99999)).unwrap();
+            super::check_and_swap_buddies(tx, Duration::from_secs(99999), Duration::ZERO, 2, false)
+                .unwrap();
+
+            ensure_no_swapped_buddies(tx);
+        })
+    }
+
+    /// Test the secondaries last contact boundary at the configured divisor
+    #[test]
+    fn swap_buddies_secondary_divisor() {
+        with_test_data(|tx| {
+            // Primaries timed out, secondaries last contact 40s ago
+            tx.execute(
+                "UPDATE targets SET last_update = DATETIME('now', '-1 hour')
+                WHERE target_uid IN (201001, 202001)",
+                [],
+            )
+            .unwrap();
+            tx.execute(
+                "UPDATE targets SET last_update = DATETIME('now', '-40 seconds')
+                WHERE target_uid IN (201002, 202005)",
+                [],
+            )
+            .unwrap();
+
+            // 100s / 3 = 33s < 40s, secondaries are not recent enough
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(100),
+                Duration::ZERO,
+                3,
+                false,
+            );
+            assert!(swaps.unwrap().is_empty());
+            ensure_no_swapped_buddies(tx);
+
+            // 100s / 2 = 50s > 40s
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(100),
+                Duration::ZERO,
+                2,
+                false,
+            );
+            assert_eq!(2, swaps.unwrap().len());
+            ensure_swapped_buddies(tx);
+
+            super::check_and_swap_buddies(tx, Duration::from_secs(100), Duration::ZERO, 0, false)
+                .unwrap_err();
+        })
+    }
 
+    #[test]
+    fn swap_buddies_on_primary_needs_resync() {
+        with_test_data(|tx| {
+            target::update_consistency_states(
+                tx,
+                [(1, TargetConsistencyState::NeedsResync)],
+                NodeTypeServer::Meta,
+            )
+            .unwrap();
+            target::update_consistency_states(
+                tx,
+                [(1, TargetConsistencyState::NeedsResync)],
+                NodeTypeServer::Storage,
+            )
+            .unwrap();
+
+            // Disabled by default
+            super::check_and_swap_buddies(tx, Duration::from_secs(99999), Duration::ZERO, 2, false)
+                .unwrap();
             ensure_no_swapped_buddies(tx);
+
+            let swaps = super::check_and_swap_buddies(
+                tx,
+                Duration::from_secs(99999),
+                Duration::ZERO,
+                2,
+                true,
+            )
+            .unwrap();
+            assert_eq!(2, swaps.len());
+            ensure_swapped_buddies(tx);
         })
     }
 
@@ -0,0 +1,198 @@
+//! Read-only integrity checks over the management database.
+//!
+//! Most of these states are prevented by foreign key constraints, but databases that have been
+//! imported, migrated or edited manually might still contain them.
+
+use super::*;
+
+/// How serious an inconsistency is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Severity {
+    /// The system works, but the state is probably not intended
+    Warning,
+    /// The state is invalid and likely breaks operations on the affected entities
+    Error,
+}
+
+/// A single inconsistency found by [check]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Finding {
+    pub severity: Severity,
+    pub description: String,
+}
+
+/// Runs all integrity checks and returns the found inconsistencies.
+pub(crate) fn check(tx: &Transaction) -> Result<Vec<Finding>> {
+    let mut findings = vec![];
+
+    let mut add = |severity, sql: &str| -> Result<()> {
+        let mut stmt = tx.prepare_cached(sql)?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for description in rows {
+            findings.push(Finding {
+                severity,
+                description: description?,
+            });
+        }
+        Ok(())
+    };
+
+    add(
+        Severity::Warning,
+        sql!(
+            "SELECT 'Target ' || e.alias || ' is not mapped to a node'
+            FROM targets AS t
+            INNER JOIN entities AS e ON e.uid = t.target_uid
+            WHERE t.node_id IS NULL
+            ORDER BY t.target_uid"
+        ),
+    )?;
+
+    add(
+        Severity::Error,
+        sql!(
+            "SELECT 'Target ' || e.alias || ' is mapped to node ' || t.node_id
+                || ' which does not exist'
+            FROM targets AS t
+            INNER JOIN entities AS e ON e.uid = t.target_uid
+            LEFT JOIN nodes AS n ON n.node_type = t.node_type AND n.node_id = t.node_id
+            WHERE t.node_id IS NOT NULL AND n.node_uid IS NULL
+            ORDER BY t.target_uid"
+        ),
+    )?;
+
+    add(
+        Severity::Error,
+        sql!(
+            "SELECT 'Target ' || e.alias || ' is assigned to pool ' || t.pool_id
+                || ' which does not exist'
+            FROM targets AS t
+            INNER JOIN entities AS e ON e.uid = t.target_uid
+            LEFT JOIN pools AS p ON p.node_type = t.node_type AND p.pool_id = t.pool_id
+            WHERE t.pool_id IS NOT NULL AND p.pool_uid IS NULL
+            ORDER BY t.target_uid"
+        ),
+    )?;
+
+    add(
+        Severity::Error,
+        sql!(
+            "SELECT 'Buddy group ' || e.alias || ' references ' || m.role || ' target '
+                || m.target_id || ' which does not exist'
+            FROM (
+                SELECT group_uid, node_type, 'primary' AS role, p_target_id AS target_id
+                FROM buddy_groups
+                UNION ALL
+                SELECT group_uid, node_type, 'secondary', s_target_id FROM buddy_groups
+            ) AS m
+            INNER JOIN entities AS e ON e.uid = m.group_uid
+            LEFT JOIN targets AS t ON t.node_type = m.node_type AND t.target_id = m.target_id
+            WHERE t.target_uid IS NULL
+            ORDER BY m.group_uid, m.role"
+        ),
+    )?;
+
+    add(
+        Severity::Error,
+        sql!(
+            "SELECT 'Buddy group ' || e.alias || ' is assigned to pool ' || g.pool_id
+                || ' which does not exist'
+            FROM buddy_groups AS g
+            INNER JOIN entities AS e ON e.uid = g.group_uid
+            LEFT JOIN pools AS p ON p.node_type = g.node_type AND p.pool_id = g.pool_id
+            WHERE g.pool_id IS NOT NULL AND p.pool_uid IS NULL
+            ORDER BY g.group_uid"
+        ),
+    )?;
+
+    add(
+        Severity::Error,
+        sql!(
+            "SELECT 'Root inode is placed on '
+                || IIF(r.target_id IS NOT NULL, 'meta target ' || r.target_id,
+                    'meta buddy group ' || r.group_id)
+                || ' which does not exist'
+            FROM root_inode AS r
+            LEFT JOIN targets AS t ON t.node_type = r.node_type AND t.target_id = r.target_id
+            LEFT JOIN buddy_groups AS g ON g.node_type = r.node_type AND g.group_id = r.group_id
+            WHERE (r.target_id IS NOT NULL AND t.target_uid IS NULL)
+                OR (r.group_id IS NOT NULL AND g.group_uid IS NULL)"
+        ),
+    )?;
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::with_test_data;
+
+    #[test]
+    fn check() {
+        with_test_data(|tx| {
+            // The test data only contains an unmapped target
+            assert_eq!(
+                super::check(tx).unwrap(),
+                vec![Finding {
+                    severity: Severity::Warning,
+                    description: "Target storage_target_unmapped is not mapped to a node".into(),
+                }]
+            );
+
+            // Foreign key checks are deferred to the end of the transaction. Each inconsistency is
+            // rolled back after checking, so the transaction can be committed in the end.
+            tx.pragma_update(None, "defer_foreign_keys", true).unwrap();
+
+            for (sql, expected) in [
+                (
+                    "UPDATE targets SET node_id = 99 WHERE target_uid = 202002",
+                    "Target storage_target_2 is mapped to node 99 which does not exist",
+                ),
+                (
+                    "UPDATE targets SET pool_id = 99 WHERE target_uid = 202002",
+                    "Target storage_target_2 is assigned to pool 99 which does not exist",
+                ),
+                (
+                    "DELETE FROM targets WHERE target_uid = 202013",
+                    "Buddy group storage_buddy_group_2 references secondary target 13 which does \
+                    not exist",
+                ),
+                (
+                    "UPDATE buddy_groups SET p_target_id = 98 WHERE group_uid = 302002",
+                    "Buddy group storage_buddy_group_2 references primary target 98 which does not \
+                    exist",
+                ),
+                (
+                    "UPDATE buddy_groups SET pool_id = 99 WHERE group_uid = 302002",
+                    "Buddy group storage_buddy_group_2 is assigned to pool 99 which does not exist",
+                ),
+                (
+                    "UPDATE root_inode SET target_id = 99",
+                    "Root inode is placed on meta target 99 which does not exist",
+                ),
+                (
+                    "UPDATE root_inode SET target_id = NULL, group_id = 99",
+                    "Root inode is placed on meta buddy group 99 which does not exist",
+                ),
+            ] {
+                tx.execute_batch("SAVEPOINT inconsistent").unwrap();
+                tx.execute_batch(sql).unwrap();
+
+                let findings = super::check(tx).unwrap();
+                assert!(
+                    findings.contains(&Finding {
+                        severity: Severity::Error,
+                        description: expected.into(),
+                    }),
+                    "{sql}: {findings:?}"
+                );
+
+                tx.execute_batch("ROLLBACK TO inconsistent; RELEASE inconsistent")
+                    .unwrap();
+            }
+
+            assert_eq!(super::check(tx).unwrap().len(), 1);
+        })
+    }
+}
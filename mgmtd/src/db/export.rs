@@ -0,0 +1,544 @@
+//! Export and import of the logical management state.
+//!
+//! An export contains everything needed to reconstruct the management state in a new database:
+//! Nodes, targets, buddy groups, storage pools, quota limits, the meta root and the file system
+//! UUID. Ephemeral data like client nodes, nics, capacities or quota usage is not included.
+//!
+//! The export is serialized using BeeSerde. It starts with a magic string and the format version,
+//! followed by one named section per table. Each section contains the column names and the rows,
+//! with each value being tagged with its type.
+//!
+//! The quota usage can be exported separately into the line based [shared::quota_export] format.
+
+use super::*;
+use anyhow::Context;
+use rusqlite::types::Value;
+use shared::bee_serde::{Deserializable, Deserializer, Serializable, Serializer};
+use shared::quota_export::{QuotaUsageEntry, Writer};
+use std::io::Write;
+
+const MAGIC: &[u8] = b"beegfs-mgmtd-export";
+/// Must be increased on incompatible changes to the format or the contents of the sections
+const FORMAT_VERSION: u32 = 2;
+/// The oldest format version that can still be imported
+const MIN_FORMAT_VERSION: u32 = 1;
+/// Initial size of the serialization buffer. Grows as needed.
+const INITIAL_BUF_SIZE: usize = 64 * 1024;
+/// Maximum size of the serialized export
+const MAX_SIZE: usize = 1024 * 1024 * 1024;
+
+/// A table to export, with the statements to read all rows from and to insert a row into it. The
+/// columns returned by `select` must match the parameters of `insert`.
+struct Table {
+    name: &'static str,
+    /// The format version the table has been added to the export in. Exports of older versions
+    /// don't contain the section and the table stays empty on import.
+    since_version: u32,
+    select: &'static str,
+    insert: &'static str,
+}
+
+/// The exported tables. Imported in the given order, so referenced entries must come first.
+const TABLES: &[Table] = &[
+    Table {
+        name: "config",
+        since_version: 1,
+        select: sql!(
+            "SELECT key, value FROM config
+            WHERE key IN ('fs_uuid', 'fs_init_date_secs') ORDER BY key"
+        ),
+        insert: sql!("INSERT INTO config (key, value) VALUES (?1, ?2)"),
+    },
+    Table {
+        name: "entities",
+        since_version: 1,
+        // Client nodes are ephemeral
+        select: sql!(
+            "SELECT uid, entity_type, alias FROM entities
+            WHERE uid NOT IN (SELECT node_uid FROM nodes WHERE node_type = 3)
+            ORDER BY uid"
+        ),
+        // The management node and the default pool exist in every database
+        insert: sql!(
+            "INSERT INTO entities (uid, entity_type, alias) VALUES (?1, ?2, ?3)
+            ON CONFLICT (uid) DO UPDATE SET alias = excluded.alias"
+        ),
+    },
+    Table {
+        name: "nodes",
+        since_version: 1,
+        select: sql!(
+            "SELECT node_uid, node_type, node_id, port, machine_uuid FROM nodes
+            WHERE node_type IN (1, 2) ORDER BY node_uid"
+        ),
+        insert: sql!(
+            "INSERT INTO nodes (node_uid, node_type, node_id, port, machine_uuid, last_contact)
+            VALUES (?1, ?2, ?3, ?4, ?5, DATETIME('now'))"
+        ),
+    },
+    Table {
+        name: "pools",
+        since_version: 1,
+        select: sql!("SELECT pool_uid, node_type, pool_id FROM pools ORDER BY pool_uid"),
+        insert: sql!(
+            "INSERT INTO pools (pool_uid, node_type, pool_id) VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING"
+        ),
+    },
+    Table {
+        name: "targets",
+        since_version: 1,
+        select: sql!(
+            "SELECT target_uid, node_type, target_id, node_id, pool_id, consistency, reg_token
+            FROM targets ORDER BY target_uid"
+        ),
+        insert: sql!(
+            "INSERT INTO targets (target_uid, node_type, target_id, node_id, pool_id, consistency,
+                reg_token)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+        ),
+    },
+    Table {
+        name: "buddy_groups",
+        since_version: 1,
+        select: sql!(
+            "SELECT group_uid, node_type, group_id, p_target_id, s_target_id, pool_id
+            FROM buddy_groups ORDER BY group_uid"
+        ),
+        insert: sql!(
+            "INSERT INTO buddy_groups (group_uid, node_type, group_id, p_target_id, s_target_id,
+                pool_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+        ),
+    },
+    Table {
+        name: "root_inode",
+        since_version: 1,
+        select: sql!("SELECT target_id, group_id FROM root_inode"),
+        insert: sql!("INSERT INTO root_inode (target_id, group_id) VALUES (?1, ?2)"),
+    },
+    Table {
+        name: "quota_default_limits",
+        since_version: 1,
+        select: sql!(
+            "SELECT id_type, quota_type, pool_id, value FROM quota_default_limits
+            ORDER BY id_type, quota_type, pool_id"
+        ),
+        insert: sql!(
+            "INSERT INTO quota_default_limits (id_type, quota_type, pool_id, value)
+            VALUES (?1, ?2, ?3, ?4)"
+        ),
+    },
+    Table {
+        name: "quota_system_default_limits",
+        since_version: 2,
+        select: sql!(
+            "SELECT id_type, quota_type, value FROM quota_system_default_limits
+            ORDER BY id_type, quota_type"
+        ),
+        insert: sql!(
+            "INSERT INTO quota_system_default_limits (id_type, quota_type, value)
+            VALUES (?1, ?2, ?3)"
+        ),
+    },
+    Table {
+        name: "quota_limits",
+        since_version: 1,
+        select: sql!(
+            "SELECT quota_id, id_type, quota_type, pool_id, value FROM quota_limits
+            ORDER BY quota_id, id_type, quota_type, pool_id"
+        ),
+        insert: sql!(
+            "INSERT INTO quota_limits (quota_id, id_type, quota_type, pool_id, value)
+            VALUES (?1, ?2, ?3, ?4, ?5)"
+        ),
+    },
+];
+
+/// The exported content of one table
+#[derive(Debug, PartialEq)]
+struct Section {
+    name: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Export {
+    /// The format version. Always [FORMAT_VERSION] when serializing.
+    version: u32,
+    sections: Vec<Section>,
+}
+
+impl Serializable for Export {
+    fn serialize(&self, ser: &mut Serializer<'_>) -> Result<()> {
+        ser.cstr(MAGIC, 0)?;
+        ser.u32(FORMAT_VERSION)?;
+
+        ser.seq(self.sections.iter(), false, |ser, section| {
+            ser.cstr(section.name.as_bytes(), 0)?;
+            ser.seq(section.columns.iter(), false, |ser, c| {
+                ser.cstr(c.as_bytes(), 0)
+            })?;
+            ser.seq(section.rows.iter(), false, |ser, row| {
+                ser.seq(row.iter(), false, serialize_value)
+            })
+        })
+    }
+}
+
+impl Deserializable for Export {
+    fn deserialize(des: &mut Deserializer<'_>) -> Result<Self> {
+        if des.cstr(0).ok().as_deref() != Some(MAGIC) {
+            bail!("Not a management export");
+        }
+
+        let version = des.u32()?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+            bail!(
+                "Unsupported export format version {version}: Expected {MIN_FORMAT_VERSION} to \
+{FORMAT_VERSION}"
+            );
+        }
+
+        let sections = des.seq(false, |des| {
+            Ok(Section {
+                name: String::from_utf8(des.cstr(0)?)?,
+                columns: des.seq(false, |des| Ok(String::from_utf8(des.cstr(0)?)?))?,
+                rows: des.seq(false, |des| des.seq(false, deserialize_value))?,
+            })
+        })?;
+
+        Ok(Self { version, sections })
+    }
+}
+
+fn serialize_value(ser: &mut Serializer<'_>, value: &Value) -> Result<()> {
+    match value {
+        Value::Null => ser.u8(0),
+        Value::Integer(v) => {
+            ser.u8(1)?;
+            ser.i64(*v)
+        }
+        Value::Text(v) => {
+            ser.u8(2)?;
+            ser.cstr(v.as_bytes(), 0)
+        }
+        v => bail!("Unsupported value type {:?}", v.data_type()),
+    }
+}
+
+fn deserialize_value(des: &mut Deserializer<'_>) -> Result<Value> {
+    Ok(match des.u8()? {
+        0 => Value::Null,
+        1 => Value::Integer(des.i64()?),
+        2 => Value::Text(String::from_utf8(des.cstr(0)?)?),
+        t => bail!("Invalid value type tag {t}"),
+    })
+}
+
+/// Exports the logical management state into a self-describing binary format.
+pub fn export(tx: &Transaction) -> Result<Vec<u8>> {
+    let mut sections = vec![];
+
+    for table in TABLES {
+        let mut stmt = tx.prepare(table.select)?;
+        let columns = stmt.column_names().into_iter().map(String::from).collect();
+        let column_count = stmt.column_count();
+
+        let rows = stmt
+            .query_map([], |row| {
+                (0..column_count)
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .with_context(|| format!("Reading {} failed", table.name))?;
+
+        sections.push(Section {
+            name: table.name.to_string(),
+            columns,
+            rows,
+        });
+    }
+
+    let export = Export {
+        version: FORMAT_VERSION,
+        sections,
+    };
+
+    // The size is not known beforehand, so grow the buffer until the serialized data fits
+    let mut size = INITIAL_BUF_SIZE;
+    loop {
+        let mut buf = vec![0; size];
+        let mut ser = Serializer::new(&mut buf);
+
+        match export.serialize(&mut ser) {
+            Ok(()) => {
+                let len = ser.bytes_written();
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            Err(err) if size >= MAX_SIZE => {
+                return Err(err.context(format!("Export exceeds {MAX_SIZE} bytes")));
+            }
+            Err(_) => size *= 2,
+        }
+    }
+}
+
+/// Imports a logical management state created by [export()]. The database must be new, there
+/// must be no entries except for the default ones.
+pub fn import(tx: &Transaction, data: &[u8]) -> Result<()> {
+    let max_uid: Uid = tx.query_row(sql!("SELECT MAX(uid) FROM entities"), [], |row| row.get(0))?;
+    if max_uid > 2 || config::get::<String>(tx, config::Config::FsUuid)?.is_some() {
+        bail!("Database is not new");
+    }
+
+    let mut des = Deserializer::new(data);
+    let export = Export::deserialize(&mut des)?;
+    des.finish()?;
+
+    for table in TABLES {
+        let section = export.sections.iter().find(|s| s.name == table.name);
+        let section = match section {
+            Some(section) => section,
+            // Added after the export's version, so the table stays empty as it was back then
+            None if export.version < table.since_version => continue,
+            None => bail!("Section {} is missing", table.name),
+        };
+
+        let expected_columns: Vec<String> = tx
+            .prepare(table.select)?
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        if section.columns != expected_columns {
+            bail!(
+                "Section {} contains columns {:?}, expected {expected_columns:?}",
+                table.name,
+                section.columns
+            );
+        }
+
+        let mut stmt = tx.prepare(table.insert)?;
+        for row in &section.rows {
+            if row.len() != expected_columns.len() {
+                bail!("Section {} contains a row of invalid length", table.name);
+            }
+
+            stmt.execute(rusqlite::params_from_iter(row))
+                .with_context(|| format!("Importing {} row {row:?} failed", table.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the quota usage of all IDs per pool, together with their effective limits, to a quota
+/// usage snapshot.
+///
+/// # Return value
+/// The number of written entries.
+pub fn export_quota_usage(tx: &Transaction, writer: &mut Writer<impl Write>) -> Result<usize> {
+    let mut stmt = tx.prepare_cached(sql!(
+        "SELECT u.quota_id, u.id_type, st.pool_id,
+            MAX(CASE WHEN u.quota_type = 1 THEN COALESCE(l.value, d.value, s.value) END),
+            MAX(CASE WHEN u.quota_type = 2 THEN COALESCE(l.value, d.value, s.value) END),
+            SUM(CASE WHEN u.quota_type = 1 THEN u.value END),
+            SUM(CASE WHEN u.quota_type = 2 THEN u.value END)
+        FROM quota_usage AS u
+        INNER JOIN targets AS st USING(node_type, target_id)
+        LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+        LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+        LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+        WHERE st.pool_id IS NOT NULL
+        GROUP BY u.quota_id, u.id_type, st.pool_id
+        ORDER BY st.pool_id, u.id_type, u.quota_id"
+    ))?;
+
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+
+    while let Some(row) = rows.next()? {
+        writer.write(&QuotaUsageEntry {
+            quota_id: row.get(0)?,
+            id_type: QuotaIdType::from_row(row, 1)?,
+            pool: row.get(2)?,
+            space_limit: row.get(3)?,
+            inode_limit: row.get(4)?,
+            space_used: row.get(5)?,
+            inode_used: row.get(6)?,
+        })?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared::quota_export::Header;
+
+    #[test]
+    fn export_import() {
+        with_test_data(|tx| {
+            config::set(
+                tx,
+                config::Config::FsUuid,
+                "ee7bc3b4-8cc6-4f2d-b26f-1a0e7e1b2a11",
+            )
+            .unwrap();
+            config::set(tx, config::Config::FsInitDateSecs, 1234).unwrap();
+            tx.execute(
+                "INSERT INTO quota_system_default_limits (id_type, quota_type, value)
+                VALUES (1, 1, 5000)",
+                [],
+            )
+            .unwrap();
+
+            let exported = export(tx).unwrap();
+
+            // Import into a new database
+            let mut conn = sqlite::open_in_memory().unwrap();
+            let new_tx = conn.transaction().unwrap();
+            sqlite::migrate_schema(&new_tx, MIGRATIONS).unwrap();
+
+            import(&new_tx, &exported).unwrap();
+
+            // A database can only be imported into once
+            import(&new_tx, &exported).unwrap_err();
+
+            // The logical contents match
+            assert_eq!(export(&new_tx).unwrap(), exported);
+
+            let count = |tx: &Transaction, sql: &str| -> usize {
+                tx.query_row(sql, [], |row| row.get(0)).unwrap()
+            };
+            for sql in [
+                "SELECT COUNT(*) FROM nodes WHERE node_type IN (1, 2)",
+                "SELECT COUNT(*) FROM targets",
+                "SELECT COUNT(*) FROM buddy_groups",
+                "SELECT COUNT(*) FROM pools",
+                "SELECT COUNT(*) FROM quota_limits",
+                "SELECT COUNT(*) FROM quota_system_default_limits",
+            ] {
+                assert_eq!(count(tx, sql), count(&new_tx, sql), "{sql}");
+            }
+            assert_eq!(count(&new_tx, "SELECT COUNT(*) FROM client_nodes"), 0);
+            assert_eq!(
+                config::get::<String>(&new_tx, config::Config::FsUuid).unwrap(),
+                Some("ee7bc3b4-8cc6-4f2d-b26f-1a0e7e1b2a11".to_string())
+            );
+        })
+    }
+
+    #[test]
+    fn import_v1() {
+        with_test_data(|tx| {
+            let exported = export(tx).unwrap();
+
+            // Version 1 didn't contain the system wide default quota limits
+            let mut des = Deserializer::new(&exported);
+            let mut old = Export::deserialize(&mut des).unwrap();
+            old.sections
+                .retain(|s| s.name != "quota_system_default_limits");
+
+            let mut buf = vec![0; exported.len()];
+            let mut ser = Serializer::new(&mut buf);
+            old.serialize(&mut ser).unwrap();
+            let len = ser.bytes_written();
+
+            // serialize() always writes the current version, it follows the magic string
+            let version = 4 + MAGIC.len() + 1;
+            buf[version..version + 4].copy_from_slice(&1u32.to_le_bytes());
+
+            let import_into_new = |data: &[u8]| {
+                let mut conn = sqlite::open_in_memory().unwrap();
+                let new_tx = conn.transaction().unwrap();
+                sqlite::migrate_schema(&new_tx, MIGRATIONS).unwrap();
+                import(&new_tx, data).map(|_| export(&new_tx).unwrap())
+            };
+
+            // Imported with no system wide defaults, as in version 1
+            assert_eq!(import_into_new(&buf[..len]).unwrap(), exported);
+
+            // The section is required from version 2 on
+            buf[version..version + 4].copy_from_slice(&2u32.to_le_bytes());
+            import_into_new(&buf[..len]).unwrap_err();
+        })
+    }
+
+    #[test]
+    fn import_invalid() {
+        let mut conn = sqlite::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        sqlite::migrate_schema(&tx, MIGRATIONS).unwrap();
+
+        import(&tx, b"garbage").unwrap_err();
+
+        let mut wrong_columns = Export {
+            version: FORMAT_VERSION,
+            sections: vec![Section {
+                name: "config".to_string(),
+                columns: vec!["key".to_string()],
+                rows: vec![],
+            }],
+        };
+        let mut buf = vec![0; 1024];
+        let mut ser = Serializer::new(&mut buf);
+        wrong_columns.serialize(&mut ser).unwrap();
+        let len = ser.bytes_written();
+        import(&tx, &buf[..len]).unwrap_err();
+
+        // Roundtrip of the format itself
+        wrong_columns.sections[0].rows = vec![vec![
+            Value::Null,
+            Value::Integer(-5),
+            Value::Text("text".to_string()),
+        ]];
+        let mut ser = Serializer::new(&mut buf);
+        wrong_columns.serialize(&mut ser).unwrap();
+        let len = ser.bytes_written();
+        let mut des = Deserializer::new(&buf[..len]);
+        assert_eq!(Export::deserialize(&mut des).unwrap(), wrong_columns);
+    }
+
+    #[test]
+    fn export_quota_usage() {
+        with_test_data(|tx| {
+            let mut writer = Writer::new(vec![], &Header::new(Some(30))).unwrap();
+            assert_eq!(super::export_quota_usage(tx, &mut writer).unwrap(), 15);
+            let buf = writer.finish().unwrap();
+
+            let (header, entries) = shared::quota_export::read(buf.as_slice()).unwrap();
+            assert_eq!(header.refresh_period_s, Some(30));
+            assert_eq!(entries.len(), 15);
+
+            // Specific limits take precedence over the pools default limits
+            assert_eq!(
+                entries[0],
+                QuotaUsageEntry {
+                    id_type: QuotaIdType::User,
+                    quota_id: 1,
+                    pool: 1,
+                    space_limit: Some(10000),
+                    inode_limit: Some(10000),
+                    space_used: Some(1000),
+                    inode_used: Some(1000),
+                }
+            );
+
+            // No limits set at all
+            let unlimited = entries
+                .iter()
+                .find(|e| e.quota_id == 10 && e.id_type == QuotaIdType::User && e.pool == 2)
+                .unwrap();
+            assert_eq!(unlimited.space_limit, None);
+            assert_eq!(unlimited.inode_limit, None);
+        })
+    }
+}
@@ -8,6 +8,7 @@ use shared::bee_msg::storage_pool::StoragePool;
 use shared::bee_serde::{BeeSerdeConversion, Deserializable, Deserializer};
 use shared::types::*;
 use sqlite_check::sql;
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
 
@@ -15,10 +16,10 @@ use std::path::Path;
 /// except for the default ones.
 ///
 /// The import only works with a "standard" setup from BeeGFS 7.2 or 7.4. The format.conf file
-/// must be unmodified. Certain data is ignored as it is ephemeral anyway and will be filled
-/// automatically on the running management. This includes quota usage data, client nodes and the
-/// nodes nic lists. The old BeeGFS should be completely shut down before upgrading and all targets
-/// must be in GOOD state.
+/// must declare a compatible version and the default node and target states formats. Certain data
+/// is ignored as it is ephemeral anyway and will be filled automatically on the running
+/// management. This includes quota usage data, client nodes and the nodes nic lists. The old
+/// BeeGFS should be completely shut down before upgrading and all targets must be in GOOD state.
 pub fn import_v7(tx: &rusqlite::Transaction, base_path: &Path) -> Result<()> {
     // Check DB is new
     let max_uid: Uid = tx.query_row(sql!("SELECT MAX(uid) FROM entities"), [], |row| row.get(0))?;
@@ -61,16 +62,52 @@ pub fn import_v7(tx: &rusqlite::Transaction, base_path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Checks that the format.conf file is in the original state
+/// The format.conf versions with a data layout compatible to the importer
+const COMPATIBLE_FORMAT_VERSIONS: &[u32] = &[4, 5];
+
+/// Checks that the format.conf file declares a compatible on-disk-format
 fn check_format_conf(f: &Path) -> Result<()> {
     let s = std::fs::read_to_string(f)?;
+    check_format_conf_content(&s)
+}
+
+/// Parses the `key=value` lines of a format.conf file and checks that the version is compatible
+/// and the node and target states are stored in the expected format. Empty lines, comments and
+/// unknown keys are ignored.
+fn check_format_conf_content(s: &str) -> Result<()> {
+    let mut entries = HashMap::new();
+
+    for line in s.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid line {line:?}: Expected \"key=value\""))?;
 
-    if s.matches("version").count() != 1 {
-        bail!("Unexpected version in format.conf: version field ambiguous or not found");
+        if entries.insert(key.trim(), value.trim()).is_some() {
+            bail!("Key {key:?} is defined more than once");
+        }
     }
 
-    if !s.contains("\nversion=5\n") {
-        bail!("Unexpected version in format.conf: Expected \"version=5\"");
+    let version = entries
+        .get("version")
+        .ok_or_else(|| anyhow!("version field not found"))?;
+    let version: u32 = version
+        .parse()
+        .with_context(|| format!("Invalid version {version:?}"))?;
+
+    if !COMPATIBLE_FORMAT_VERSIONS.contains(&version) {
+        bail!("Unsupported version {version}: Expected one of {COMPATIBLE_FORMAT_VERSIONS:?}");
+    }
+
+    for key in ["nodeStates", "targetStates"] {
+        match entries.get(key) {
+            Some(&"1") => {}
+            Some(value) => bail!("Unsupported {key} format {value:?}: Expected \"1\""),
+            None => bail!("{key} field not found"),
+        }
     }
 
     Ok(())
@@ -295,7 +332,7 @@ fn storage_pools(tx: &Transaction, f: &Path) -> Result<()> {
         let mut alias_input = String::from_utf8_lossy(&pool.alias).to_string();
 
         let alias = loop {
-            match Alias::try_from(alias_input.trim()) {
+            match Alias::new(alias_input.trim()) {
                 Ok(a) => {
                     if used_aliases.contains(&a) {
                         println!(
@@ -399,6 +436,39 @@ fn quota(tx: &Transaction, quota_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Serialized length of the default quota limits file: Four u64 limits
+const QUOTA_DEFAULT_LIMITS_LEN: usize = 4 * size_of::<u64>();
+/// Serialized length of one [QuotaEntry]: space (u64), inodes (u64), id (u32), id_type (i32),
+/// valid (u8)
+const QUOTA_ENTRY_LEN: usize = 2 * size_of::<u64>() + 2 * size_of::<u32>() + size_of::<u8>();
+
+/// Checks that a quota limits file consists of exactly the number of entries declared in its
+/// leading u32 count field.
+///
+/// Catches truncated or otherwise damaged files before anything from them is imported.
+fn check_quota_limits_len(s: &[u8], f: &Path) -> Result<()> {
+    let Some(count) = s
+        .first_chunk::<4>()
+        .map(|c| u32::from_le_bytes(*c) as usize)
+    else {
+        bail!(
+            "Quota limits file {f:?} is too short to contain the entry count: {} bytes",
+            s.len()
+        );
+    };
+
+    let expected = size_of::<u32>() + count * QUOTA_ENTRY_LEN;
+    if s.len() != expected {
+        bail!(
+            "Quota limits file {f:?} declares {count} entries, which requires {expected} bytes, but \
+the file has {} bytes",
+            s.len()
+        );
+    }
+
+    Ok(())
+}
+
 /// Imports the default quota limits
 fn quota_default_limits(tx: &Transaction, f: &Path, pool_id: PoolId) -> Result<()> {
     // If the file is missing, skip it
@@ -411,6 +481,13 @@ fn quota_default_limits(tx: &Transaction, f: &Path, pool_id: PoolId) -> Result<(
         Err(err) => return Err(err.into()),
     };
 
+    if s.len() != QUOTA_DEFAULT_LIMITS_LEN {
+        bail!(
+            "Quota default limits file {f:?} must have {QUOTA_DEFAULT_LIMITS_LEN} bytes, but has {}",
+            s.len()
+        );
+    }
+
     let mut des = Deserializer::new(&s);
     let user_inode_limit = des.u64()?;
     let user_space_limit = des.u64()?;
@@ -500,9 +577,12 @@ fn quota_limits(
         Err(err) => return Err(err.into()),
     };
 
+    check_quota_limits_len(&s, f)?;
+
     let mut des = Deserializer::new(&s);
     let limits = des.seq(false, |des| QuotaEntry::deserialize(des))?;
-    des.finish()?;
+    des.finish()
+        .with_context(|| format!("Quota limits file {f:?} has not been fully consumed"))?;
 
     // We filter out where the quota ID is 0 because old management seems to store the default
     // settings for a pool together with the specific limits. But this is redundant, the default
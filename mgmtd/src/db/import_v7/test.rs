@@ -53,7 +53,7 @@ fn import_v7_inner(base_path: &Path) {
     let tx = conn.transaction().unwrap();
 
     migrate_schema(&tx, MIGRATIONS).unwrap();
-    initial_entries(&tx, None).unwrap();
+    initial_entries(&tx, None, &Default::default()).unwrap();
     super::import_v7(&tx, base_path).unwrap();
 
     // Check nodes
@@ -231,3 +231,95 @@ fn import_v7_inner(base_path: &Path) {
 
     tx.commit().unwrap();
 }
+
+#[test]
+fn check_format_conf() {
+    use super::check_format_conf_content as check;
+
+    // Accepted
+    check(
+        "# This file was auto-generated. Do not modify it!\n\
+        version=5\nnodeStates=1\ntargetStates=1\n",
+    )
+    .unwrap();
+    check("version=4\n\nnodeStates=1\ntargetStates=1").unwrap();
+    check("\n targetStates = 1 \nnodeStates=1\nversion=5\nunknownKey=abc\n\n").unwrap();
+
+    // Rejected
+    let err = check("version=3\nnodeStates=1\ntargetStates=1\n").unwrap_err();
+    assert!(err.to_string().contains("Unsupported version 3"));
+    check("version=abc\nnodeStates=1\ntargetStates=1\n").unwrap_err();
+    check("nodeStates=1\ntargetStates=1\n").unwrap_err();
+    check("version=5\nversion=5\nnodeStates=1\ntargetStates=1\n").unwrap_err();
+    check("version=5\nnodeStates=2\ntargetStates=1\n").unwrap_err();
+    check("version=5\nnodeStates=1\n").unwrap_err();
+    check("version=5\nnodeStates=1\ntargetStates=1\ngarbage\n").unwrap_err();
+}
+
+#[test]
+fn truncated_quota_files() {
+    let pid = std::process::id();
+    let tmp_dir = std::env::temp_dir().join(format!(".beegfs_import_v7_quota_test_{pid}"));
+    create_dir_all(&tmp_dir).unwrap();
+
+    let res = catch_unwind(|| truncated_quota_files_inner(&tmp_dir));
+
+    remove_dir_all(&tmp_dir).unwrap();
+
+    res.unwrap();
+}
+
+fn truncated_quota_files_inner(base_path: &Path) {
+    let mut conn = open_in_memory().unwrap();
+    let tx = conn.transaction().unwrap();
+
+    migrate_schema(&tx, MIGRATIONS).unwrap();
+    initial_entries(&tx, None, &Default::default()).unwrap();
+
+    let entry = [
+        100u64.to_le_bytes().as_slice(),
+        &10u64.to_le_bytes(),
+        &1000u32.to_le_bytes(),
+        &1i32.to_le_bytes(),
+        &[1],
+    ]
+    .concat();
+    assert_eq!(entry.len(), super::QUOTA_ENTRY_LEN);
+
+    let limits_file = base_path.join("quotaUserLimits.store");
+    let check_limits = |content: &[u8], msg: &str| {
+        std::fs::write(&limits_file, content).unwrap();
+        let err = super::quota_limits(&tx, &limits_file, 1, QuotaIdType::User).unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("quotaUserLimits.store"), "{err}");
+        assert!(err.contains(msg), "{err}");
+    };
+
+    // Two declared entries, but only one present
+    check_limits(
+        &[2u32.to_le_bytes().as_slice(), &entry].concat(),
+        "declares 2 entries, which requires 54 bytes, but the file has 29 bytes",
+    );
+    // Trailing garbage after the declared entries
+    check_limits(
+        &[1u32.to_le_bytes().as_slice(), &entry, &[0]].concat(),
+        "declares 1 entries, which requires 29 bytes, but the file has 30 bytes",
+    );
+    // Not even the entry count
+    check_limits(&[1, 0], "too short to contain the entry count");
+
+    let defaults_file = base_path.join("quotaDefaultLimits.store");
+    std::fs::write(&defaults_file, [0; 20]).unwrap();
+    let err = super::quota_default_limits(&tx, &defaults_file, 1).unwrap_err();
+    let err = format!("{err:#}");
+    assert!(err.contains("quotaDefaultLimits.store"), "{err}");
+    assert!(err.contains("must have 32 bytes, but has 20"), "{err}");
+
+    // Nothing must have been imported
+    let count: usize = tx
+        .query_row(sql!("SELECT COUNT(*) FROM quota_limits"), [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(count, 0);
+}
@@ -38,6 +38,40 @@ pub(crate) fn get_with_type(tx: &Transaction, node_type: NodeType) -> Result<Vec
     )?)
 }
 
+/// Retrieve a list of nodes filtered by node type, excluding the ones whose last contact is longer
+/// than `max_age` ago.
+pub(crate) fn get_reachable_with_type(
+    tx: &Transaction,
+    node_type: NodeType,
+    max_age: Duration,
+) -> Result<Vec<Node>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT node_uid, node_id, node_type, alias, port
+            FROM nodes_ext
+            WHERE node_type = ?1 AND UNIXEPOCH('now') - UNIXEPOCH(last_contact) <= ?2"
+        ),
+        params![node_type.sql_variant(), max_age.as_secs()],
+        Node::from_row,
+    )?)
+}
+
+/// Retrieve a page of nodes of all types, ordered by their uid.
+///
+/// Meant for paging through big node lists, e.g. when streaming them to a requester.
+pub(crate) fn get_page(tx: &Transaction, offset: usize, limit: usize) -> Result<Vec<Node>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT node_uid, node_id, node_type, alias, port
+            FROM nodes_ext
+            ORDER BY node_uid ASC
+            LIMIT ?1, ?2"
+        ),
+        [offset, limit],
+        Node::from_row,
+    )?)
+}
+
 /// Retrieve a node by its alias.
 pub(crate) fn get_by_alias(tx: &Transaction, alias: &str) -> Result<Node> {
     Ok(tx.query_row(
@@ -53,16 +87,31 @@ pub(crate) fn get_by_alias(tx: &Transaction, alias: &str) -> Result<Node> {
 
 /// Delete client nodes with a last contact time bigger than `timeout`.
 ///
+/// At most `limit` clients are deleted, the ones with the oldest last contact time first.
+///
 /// # Return value
 /// Returns the number of deleted clients.
-pub(crate) fn delete_stale_clients(tx: &Transaction, timeout: Duration) -> Result<usize> {
+pub(crate) fn delete_stale_clients(
+    tx: &Transaction,
+    timeout: Duration,
+    limit: usize,
+) -> Result<usize> {
     let affected = {
         let mut stmt = tx.prepare_cached(sql!(
             "DELETE FROM nodes
-            WHERE DATETIME(last_contact) < DATETIME('now', '-' || ?1 || ' seconds')
-            AND node_type = ?2"
+            WHERE node_uid IN (
+                SELECT node_uid FROM nodes
+                WHERE DATETIME(last_contact) < DATETIME('now', '-' || ?1 || ' seconds')
+                AND node_type = ?2
+                ORDER BY DATETIME(last_contact) ASC
+                LIMIT ?3
+            )"
         ))?;
-        stmt.execute(params![timeout.as_secs(), NodeType::Client.sql_variant()])?
+        stmt.execute(params![
+            timeout.as_secs(),
+            NodeType::Client.sql_variant(),
+            limit
+        ])?
     };
 
     Ok(affected)
@@ -115,7 +164,7 @@ pub(crate) fn insert(
     let alias = if let Some(alias) = alias {
         alias
     } else {
-        format!("node_{}_{}", node_type.user_str(), num_id).try_into()?
+        Alias::new(format!("node_{}_{}", node_type.user_str(), num_id))?
     };
 
     let uid = entity::insert(tx, EntityType::Node, &alias)?;
@@ -194,6 +243,57 @@ pub(crate) fn count_machines(
     .map_err(|e| anyhow!(e))
 }
 
+/// Retrieves the machine UUID a node was last registered with.
+///
+/// # Return value
+/// Returns `None` if the node has no machine UUID stored.
+pub(crate) fn get_machine_uuid(tx: &Transaction, node_uid: Uid) -> Result<Option<String>> {
+    Ok(tx.query_row(
+        sql!("SELECT machine_uuid FROM nodes WHERE node_uid = ?1"),
+        [node_uid],
+        |row| row.get(0),
+    )?)
+}
+
+/// Finds other nodes of the given type that are registered with the given machine UUID.
+///
+/// Meant to be called during node registration to detect misconfigured machines (e.g. cloned from
+/// the same image), as a machine UUID is expected to map to at most one node per node type. Meta
+/// and storage nodes running on the same machine share the UUID and do not conflict.
+///
+/// # Return value
+/// Returns the numeric IDs of the conflicting nodes, excluding the node with `node_uid`.
+pub(crate) fn get_machine_uuid_conflicts(
+    tx: &Transaction,
+    machine_uuid: &str,
+    node_type: NodeType,
+    node_uid: Option<Uid>,
+) -> Result<Vec<NodeId>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT node_id FROM nodes
+            WHERE machine_uuid = ?1 AND node_type = ?2 AND node_uid IS NOT ?3
+            ORDER BY node_id ASC"
+        ),
+        params![machine_uuid, node_type.sql_variant(), node_uid],
+        |row| row.get(0),
+    )?)
+}
+
+/// Fails if any clients are registered. Used to guard operations that must not happen while the
+/// file system is mounted. `operation` is used in the error message.
+pub(crate) fn ensure_no_clients(tx: &Transaction, operation: &str) -> Result<()> {
+    let clients: i64 = tx.query_row(sql!("SELECT COUNT(*) FROM client_nodes"), [], |row| {
+        row.get(0)
+    })?;
+
+    if clients > 0 {
+        bail!("Can't {operation} while clients are still mounted");
+    }
+
+    Ok(())
+}
+
 /// Delete a node from the database.
 pub(crate) fn delete(tx: &Transaction, node_uid: Uid) -> Result<()> {
     let affected = tx.execute_cached(sql!("DELETE FROM nodes WHERE node_uid = ?1"), [node_uid])?;
@@ -241,6 +341,30 @@ mod test {
         });
     }
 
+    #[test]
+    fn get_page() {
+        with_test_data(|tx| {
+            let all: usize = tx
+                .query_row("SELECT COUNT(*) FROM nodes", [], |row| row.get(0))
+                .unwrap();
+
+            let mut paged = vec![];
+            let mut offset = 0;
+            loop {
+                let page = super::get_page(tx, offset, 4).unwrap();
+                let len = page.len();
+                paged.extend(page);
+                if len < 4 {
+                    break;
+                }
+                offset += 4;
+            }
+
+            assert_eq!(all, paged.len());
+            assert!(paged.windows(2).all(|w| w[0].uid < w[1].uid));
+        })
+    }
+
     #[test]
     fn query_by_alias() {
         with_test_data(|tx| {
@@ -264,10 +388,54 @@ mod test {
         })
     }
 
+    #[test]
+    fn get_reachable_with_type() {
+        with_test_data(|tx| {
+            tx.execute(
+                r#"
+                UPDATE nodes
+                SET last_contact = DATETIME("now", "-1 hour")
+                WHERE node_uid IN (102001, 102002)
+                "#,
+                [],
+            )
+            .unwrap();
+            tx.execute(
+                r#"
+                UPDATE nodes
+                SET last_contact = DATETIME("now", "-150 seconds")
+                WHERE node_uid = 102003
+                "#,
+                [],
+            )
+            .unwrap();
+
+            let uids = |max_age| {
+                let mut uids: Vec<_> =
+                    super::get_reachable_with_type(tx, NodeType::Storage, max_age)
+                        .unwrap()
+                        .into_iter()
+                        .map(|n| n.uid)
+                        .collect();
+                uids.sort();
+                uids
+            };
+
+            assert_eq!(uids(Duration::from_secs(100)), [102004]);
+            // Nodes within the grace period are included
+            assert_eq!(uids(Duration::from_secs(200)), [102003, 102004]);
+            assert_eq!(
+                uids(Duration::from_secs(7200)),
+                [102001, 102002, 102003, 102004]
+            );
+        })
+    }
+
     #[test]
     fn delete_stale_clients() {
         with_test_data(|tx| {
-            let deleted = super::delete_stale_clients(tx, Duration::from_secs(99999)).unwrap();
+            let deleted =
+                super::delete_stale_clients(tx, Duration::from_secs(99999), 1000).unwrap();
             assert_eq!(0, deleted);
 
             tx.execute(
@@ -280,11 +448,75 @@ mod test {
             )
             .unwrap();
 
-            let deleted = super::delete_stale_clients(tx, Duration::from_secs(100)).unwrap();
+            let deleted = super::delete_stale_clients(tx, Duration::from_secs(100), 1000).unwrap();
             assert_eq!(2, deleted);
 
             let clients = node::get_with_type(tx, NodeType::Client).unwrap();
             assert_eq!(2, clients.len());
         })
     }
+
+    #[test]
+    fn delete_stale_clients_limit() {
+        with_test_data(|tx| {
+            for _ in 0..20 {
+                super::insert(tx, 0, None, NodeType::Client, 8008).unwrap();
+            }
+            tx.execute(
+                r#"UPDATE nodes SET last_contact = DATETIME("now", "-1 hour") WHERE node_type = 3"#,
+                [],
+            )
+            .unwrap();
+
+            // 24 stale clients in total
+            for expected in [10, 10, 4, 0] {
+                let deleted =
+                    super::delete_stale_clients(tx, Duration::from_secs(100), 10).unwrap();
+                assert_eq!(expected, deleted);
+            }
+
+            let clients = node::get_with_type(tx, NodeType::Client).unwrap();
+            assert_eq!(0, clients.len());
+        })
+    }
+
+    #[test]
+    fn get_machine_uuid_conflicts() {
+        with_test_data(|tx| {
+            assert_eq!(super::get_machine_uuid(tx, 102001).unwrap(), None);
+
+            update(tx, 102001, 8003, Some("uuid_1")).unwrap();
+            update(tx, 101001, 8005, Some("uuid_1")).unwrap();
+
+            assert_eq!(
+                super::get_machine_uuid(tx, 102001).unwrap().as_deref(),
+                Some("uuid_1")
+            );
+
+            // Consistent re-registration of the same node
+            assert!(
+                super::get_machine_uuid_conflicts(tx, "uuid_1", NodeType::Storage, Some(102001))
+                    .unwrap()
+                    .is_empty()
+            );
+            // Meta and storage node on the same machine
+            assert!(
+                super::get_machine_uuid_conflicts(tx, "uuid_1", NodeType::Meta, Some(101001))
+                    .unwrap()
+                    .is_empty()
+            );
+
+            // Another storage node with the same uuid
+            assert_eq!(
+                super::get_machine_uuid_conflicts(tx, "uuid_1", NodeType::Storage, Some(102002))
+                    .unwrap(),
+                [1]
+            );
+            // New storage node with the same uuid
+            assert_eq!(
+                super::get_machine_uuid_conflicts(tx, "uuid_1", NodeType::Storage, None).unwrap(),
+                [1]
+            );
+        })
+    }
 }
@@ -131,6 +131,46 @@ pub(crate) fn replace<'a>(
     Ok(())
 }
 
+/// Finds addresses of the given node that are also used by other nodes on the same port.
+///
+/// Loopback addresses are ignored as they are local to each host.
+///
+/// # Return value
+/// A Vec containing (address, other node) entries, ordered by the other nodes uid.
+pub(crate) fn get_addr_conflicts(
+    tx: &Transaction,
+    node_uid: Uid,
+) -> Result<Vec<(SocketAddr, EntityIdSet)>> {
+    let conflicts: Vec<(String, Port, Uid)> = tx.query_map_collect(
+        sql!(
+            "SELECT nn.addr, n.port, onn.node_uid
+            FROM node_nics AS nn
+            INNER JOIN nodes AS n USING(node_uid)
+            INNER JOIN node_nics AS onn ON onn.addr = nn.addr AND onn.node_uid != nn.node_uid
+            INNER JOIN nodes AS o ON o.node_uid = onn.node_uid AND o.port = n.port
+            WHERE nn.node_uid = ?1
+            ORDER BY onn.node_uid ASC, nn.addr ASC"
+        ),
+        [node_uid],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    let mut res = vec![];
+    for (addr, port, other_uid) in conflicts {
+        let addr: IpAddr = addr.parse()?;
+        if addr.is_loopback() {
+            continue;
+        }
+
+        res.push((
+            SocketAddr::new(addr, port),
+            other_uid.resolve(tx, EntityType::Node)?,
+        ));
+    }
+
+    Ok(res)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -183,4 +223,37 @@ mod test {
             assert_eq!(1, nics.iter().filter(|e| e.node_uid == 102001).count());
         })
     }
+
+    #[test]
+    fn get_addr_conflicts() {
+        with_test_data(|tx| {
+            assert!(super::get_addr_conflicts(tx, 101001).unwrap().is_empty());
+
+            let nic = |addr: &'static IpAddr| ReplaceNic {
+                addr,
+                name: "eth0".into(),
+                nic_type: NicType::Tcp,
+            };
+
+            static SHARED: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+            static LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+            // Meta nodes 101001 and 101002 use the same port
+            super::replace(tx, 101001, [nic(&SHARED), nic(&LOOPBACK)]).unwrap();
+            super::replace(tx, 101002, [nic(&SHARED), nic(&LOOPBACK)]).unwrap();
+
+            let conflicts = super::get_addr_conflicts(tx, 101001).unwrap();
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].0, SocketAddr::new(SHARED, 8005));
+            assert_eq!(conflicts[0].1.uid, 101002);
+
+            let conflicts = super::get_addr_conflicts(tx, 101002).unwrap();
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].1.uid, 101001);
+
+            // Storage node 102001 uses a different port
+            super::replace(tx, 102001, [nic(&SHARED)]).unwrap();
+            assert!(super::get_addr_conflicts(tx, 102001).unwrap().is_empty());
+        })
+    }
 }
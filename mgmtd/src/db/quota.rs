@@ -0,0 +1,305 @@
+//! Functions for quota management
+
+use super::*;
+use shared::parser::quota_limits::QuotaLimitEntry;
+
+/// The part of the `quota_exceeded` table to recalculate in [update_exceeded()]
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ExceededScope<'a> {
+    /// All entries. After the quota usage or the system wide default limits have been changed.
+    All,
+    /// The entries of the given pools. After their default limits or their targets have been
+    /// changed.
+    Pools(&'a [PoolId]),
+    /// The entries of the given (quota ID, ID type, pool) combinations. After their specific limits
+    /// have been changed.
+    Ids(&'a [(QuotaId, QuotaIdType, PoolId)]),
+}
+
+/// Recalculates the `quota_exceeded` table within `scope` from the current quota usage and the
+/// effective limits.
+///
+/// Must be called after the quota usage, the limits or the pool membership of targets have been
+/// changed.
+///
+/// # Return value
+/// The number of exceeded (quota ID, ID type, quota type, pool) combinations within `scope`.
+pub(crate) fn update_exceeded(tx: &Transaction, scope: ExceededScope) -> Result<usize> {
+    match scope {
+        ExceededScope::All => {
+            tx.execute_cached(sql!("DELETE FROM quota_exceeded"), [])?;
+
+            Ok(tx.execute_cached(
+                sql!(
+                    "INSERT INTO quota_exceeded (quota_id, id_type, quota_type, pool_id)
+                    SELECT e.quota_id, e.id_type, e.quota_type, st.pool_id
+                    FROM quota_usage AS e
+                    INNER JOIN targets AS st USING(node_type, target_id)
+                    LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+                    LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+                    LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+                    GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
+                    HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)"
+                ),
+                [],
+            )?)
+        }
+        ExceededScope::Pools(pool_ids) => {
+            let mut count = 0;
+            for pool_id in pool_ids {
+                tx.execute_cached(
+                    sql!("DELETE FROM quota_exceeded WHERE pool_id = ?1"),
+                    [pool_id],
+                )?;
+
+                count += tx.execute_cached(
+                    sql!(
+                        "INSERT INTO quota_exceeded (quota_id, id_type, quota_type, pool_id)
+                        SELECT e.quota_id, e.id_type, e.quota_type, st.pool_id
+                        FROM targets AS st
+                        INNER JOIN quota_usage AS e USING(node_type, target_id)
+                        LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+                        LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+                        LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+                        WHERE st.pool_id = ?1
+                        GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
+                        HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)"
+                    ),
+                    [pool_id],
+                )?;
+            }
+
+            Ok(count)
+        }
+        ExceededScope::Ids(ids) => {
+            let mut count = 0;
+            for (quota_id, id_type, pool_id) in ids {
+                tx.execute_cached(
+                    sql!(
+                        "DELETE FROM quota_exceeded
+                        WHERE quota_id = ?1 AND id_type = ?2 AND pool_id = ?3"
+                    ),
+                    params![quota_id, id_type.sql_variant(), pool_id],
+                )?;
+
+                count += tx.execute_cached(
+                    sql!(
+                        "INSERT INTO quota_exceeded (quota_id, id_type, quota_type, pool_id)
+                        SELECT e.quota_id, e.id_type, e.quota_type, st.pool_id
+                        FROM quota_usage AS e
+                        INNER JOIN targets AS st USING(node_type, target_id)
+                        LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+                        LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+                        LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+                        WHERE e.quota_id = ?1 AND e.id_type = ?2 AND st.pool_id = ?3
+                        GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
+                        HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)"
+                    ),
+                    params![quota_id, id_type.sql_variant(), pool_id],
+                )?;
+            }
+
+            Ok(count)
+        }
+    }
+}
+
+/// Sets a single quota limit. A negative `value` removes the limit.
+///
+/// Doesn't update the `quota_exceeded` table, [update_exceeded()] must be called afterwards.
+pub(crate) fn set_limit(
+    tx: &Transaction,
+    quota_id: QuotaId,
+    id_type: QuotaIdType,
+    quota_type: QuotaType,
+    pool_id: PoolId,
+    value: i64,
+) -> Result<()> {
+    if value > -1 {
+        tx.execute_cached(
+            sql!(
+                "REPLACE INTO quota_limits
+                (quota_id, id_type, quota_type, pool_id, value)
+                VALUES (?1, ?2, ?3, ?4, ?5)"
+            ),
+            params![
+                quota_id,
+                id_type.sql_variant(),
+                quota_type.sql_variant(),
+                pool_id,
+                value
+            ],
+        )?;
+    } else {
+        tx.execute_cached(
+            sql!(
+                "DELETE FROM quota_limits
+                WHERE quota_id = ?1 AND id_type = ?2 AND quota_type = ?3 AND pool_id = ?4"
+            ),
+            params![
+                quota_id,
+                id_type.sql_variant(),
+                quota_type.sql_variant(),
+                pool_id
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Sets the quota limits read from a quota limit file (see [shared::parser::quota_limits]) and
+/// updates the `quota_exceeded` table accordingly.
+///
+/// All referenced storage pools must exist, otherwise an error is returned.
+pub fn import_limits(tx: &Transaction, entries: &[QuotaLimitEntry]) -> Result<()> {
+    let mut changed = Vec::with_capacity(entries.len());
+
+    for e in entries {
+        let pool_exists: bool = tx.query_row_cached(
+            sql!("SELECT COUNT(*) > 0 FROM pools WHERE node_type = ?1 AND pool_id = ?2"),
+            params![NodeType::Storage.sql_variant(), e.pool],
+            |row| row.get(0),
+        )?;
+        if !pool_exists {
+            bail!("Storage pool {} doesn't exist", e.pool);
+        }
+
+        if let Some(value) = e.space_limit {
+            set_limit(tx, e.quota_id, e.id_type, QuotaType::Space, e.pool, value)?;
+        }
+        if let Some(value) = e.inode_limit {
+            set_limit(tx, e.quota_id, e.id_type, QuotaType::Inode, e.pool, value)?;
+        }
+
+        changed.push((e.quota_id, e.id_type, e.pool));
+    }
+
+    update_exceeded(tx, ExceededScope::Ids(&changed))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn update_exceeded() {
+        with_test_data(|tx| {
+            assert_eq!(super::update_exceeded(tx, ExceededScope::All).unwrap(), 13);
+
+            let exceeded: Vec<(QuotaId, PoolId)> = tx
+                .query_map_collect(
+                    sql!(
+                        "SELECT quota_id, pool_id FROM quota_exceeded
+                        WHERE id_type = ?1 AND quota_type = ?2
+                        ORDER BY pool_id, quota_id"
+                    ),
+                    [
+                        QuotaIdType::User.sql_variant(),
+                        QuotaType::Space.sql_variant(),
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            assert_eq!(exceeded, [(2, 1), (4, 1), (10, 1), (20, 2)]);
+
+            // Raising the limit removes the ID on the next update
+            tx.execute(
+                sql!("UPDATE quota_limits SET value = 1000 WHERE quota_id = 20"),
+                [],
+            )
+            .unwrap();
+            assert_eq!(super::update_exceeded(tx, ExceededScope::All).unwrap(), 12);
+
+            let count = |tx: &Transaction| -> usize {
+                tx.query_row(sql!("SELECT COUNT(*) FROM quota_exceeded"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+            };
+
+            // Only the given IDs are recalculated, ID 2 stays exceeded
+            tx.execute(
+                sql!("UPDATE quota_limits SET value = 1 WHERE quota_id = 20"),
+                [],
+            )
+            .unwrap();
+            tx.execute(
+                sql!("UPDATE quota_limits SET value = 1000000 WHERE quota_id = 2"),
+                [],
+            )
+            .unwrap();
+            assert_eq!(
+                super::update_exceeded(tx, ExceededScope::Ids(&[(20, QuotaIdType::User, 2)]))
+                    .unwrap(),
+                1
+            );
+            assert_eq!(count(tx), 13);
+
+            // Only the given pools are recalculated
+            let pool_1: usize = tx
+                .query_row(
+                    sql!("SELECT COUNT(*) FROM quota_exceeded WHERE pool_id = 1"),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            tx.execute(sql!("DELETE FROM quota_limits WHERE pool_id = 1"), [])
+                .unwrap();
+            tx.execute(sql!("DELETE FROM quota_default_limits"), [])
+                .unwrap();
+            assert_eq!(
+                super::update_exceeded(tx, ExceededScope::Pools(&[1])).unwrap(),
+                0
+            );
+            assert_eq!(count(tx), 13 - pool_1);
+
+            // Everything is consistent again after a full update
+            assert_eq!(super::update_exceeded(tx, ExceededScope::All).unwrap(), 1);
+        })
+    }
+
+    #[test]
+    fn import_limits() {
+        with_test_data(|tx| {
+            let limit = |quota_id: QuotaId, quota_type: QuotaType, pool_id: PoolId| {
+                tx.query_row(
+                    sql!(
+                        "SELECT value FROM quota_limits
+                        WHERE quota_id = ?1 AND id_type = ?2 AND quota_type = ?3 AND pool_id = ?4"
+                    ),
+                    params![
+                        quota_id,
+                        QuotaIdType::User.sql_variant(),
+                        quota_type.sql_variant(),
+                        pool_id
+                    ],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+                .unwrap()
+            };
+
+            let (entries, _) = shared::parser::quota_limits::parse(
+                "user,1,1,unlimited,\nuser,500,2,1MiB,1k\n",
+                true,
+            )
+            .unwrap();
+            super::import_limits(tx, &entries).unwrap();
+
+            // The space limit is removed, the inode limit is unchanged
+            assert_eq!(limit(1, QuotaType::Space, 1), None);
+            assert_eq!(limit(1, QuotaType::Inode, 1), Some(10000));
+
+            assert_eq!(limit(500, QuotaType::Space, 2), Some(1024 * 1024));
+            assert_eq!(limit(500, QuotaType::Inode, 2), Some(1000));
+
+            // Nonexisting pool
+            let (entries, _) =
+                shared::parser::quota_limits::parse("user,600,99,1MiB,1k\n", true).unwrap();
+            super::import_limits(tx, &entries).unwrap_err();
+        })
+    }
+}
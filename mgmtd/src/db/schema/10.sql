@@ -0,0 +1,13 @@
+CREATE TABLE cap_pool_types (
+    cap_pool_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+INSERT INTO cap_pool_types VALUES
+    (1, "normal"),
+    (2, "low"),
+    (3, "emergency")
+;
+
+-- The capacity pool a target has last been classified into. Needed to apply hysteresis.
+ALTER TABLE targets ADD COLUMN cap_pool INTEGER
+    REFERENCES cap_pool_types (cap_pool_type) ON DELETE RESTRICT;
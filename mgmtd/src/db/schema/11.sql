@@ -0,0 +1,3 @@
+-- The time the capacity columns have last been updated from a node report. NULL if there hasn't
+-- been one yet.
+ALTER TABLE targets ADD COLUMN last_capacity_update TEXT;
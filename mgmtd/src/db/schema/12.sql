@@ -0,0 +1,11 @@
+-- System wide default quota limits. Apply to all pools that don't define the respective default
+-- limit themselves.
+CREATE TABLE quota_system_default_limits (
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    value INTEGER NOT NULL,
+
+    PRIMARY KEY (id_type, quota_type)
+) STRICT, WITHOUT ROWID;
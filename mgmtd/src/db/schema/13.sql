@@ -0,0 +1,28 @@
+-- The IDs currently exceeding their effective quota limit, per pool and quota type. Recalculated
+-- after each quota usage update and on limit changes. Allows querying the exceeded IDs without
+-- scanning the whole quota_usage table.
+CREATE TABLE quota_exceeded (
+    quota_id INTEGER NOT NULL,
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    pool_id INTEGER NOT NULL,
+
+    node_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    PRIMARY KEY (quota_id, id_type, quota_type, pool_id),
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO quota_exceeded (quota_id, id_type, quota_type, pool_id)
+    SELECT e.quota_id, e.id_type, e.quota_type, st.pool_id
+    FROM quota_usage AS e
+    INNER JOIN targets AS st USING(node_type, target_id)
+    LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+    LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+    LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+    GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
+    HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)
+;
@@ -0,0 +1,3 @@
+-- Allows recalculating quota_exceeded for a single pool without scanning the whole quota_usage
+-- table.
+CREATE INDEX quota_usage_target ON quota_usage (target_id);
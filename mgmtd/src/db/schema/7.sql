@@ -0,0 +1,9 @@
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    operation TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    peer TEXT
+) STRICT;
+
+CREATE INDEX audit_log_time ON audit_log (time);
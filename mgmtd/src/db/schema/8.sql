@@ -0,0 +1 @@
+INSERT INTO quota_id_types VALUES (3, "project");
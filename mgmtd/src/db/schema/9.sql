@@ -0,0 +1,9 @@
+CREATE TABLE target_state_history (
+    id INTEGER PRIMARY KEY,
+    target_uid INTEGER NOT NULL
+        REFERENCES targets (target_uid) ON DELETE CASCADE,
+    time INTEGER NOT NULL,
+    state TEXT NOT NULL
+) STRICT;
+
+CREATE INDEX target_state_history_target ON target_state_history (target_uid, time);
@@ -0,0 +1,338 @@
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    operation TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    peer TEXT
+) STRICT;
+
+CREATE TABLE buddy_groups (
+    group_uid INTEGER PRIMARY KEY,
+    node_type INTEGER NOT NULL
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+    group_id INTEGER,
+    entity_type INTEGER GENERATED ALWAYS AS (4)
+        REFERENCES entity_types (entity_type) ON DELETE RESTRICT,
+
+    p_target_id INTEGER NOT NULL,
+    s_target_id INTEGER NOT NULL,
+    pool_id INTEGER,
+
+    UNIQUE(node_type, group_id),
+    FOREIGN KEY (group_uid, entity_type) REFERENCES entities (uid, entity_type) ON DELETE CASCADE,
+    FOREIGN KEY (node_type, p_target_id) REFERENCES targets (node_type, target_id) ON DELETE RESTRICT,
+    FOREIGN KEY (node_type, s_target_id) REFERENCES targets (node_type, target_id) ON DELETE RESTRICT,
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE RESTRICT
+) STRICT;
+
+CREATE TABLE cap_pool_types (
+    cap_pool_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE config (
+    key TEXT PRIMARY KEY,
+    value ANY NOT NULL
+) STRICT;
+
+CREATE TABLE consistency_types (
+    consistency_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE entities (
+    uid INTEGER PRIMARY KEY AUTOINCREMENT
+        CHECK(uid > 0),
+    entity_type INTEGER NOT NULL
+        REFERENCES entity_types (entity_type) ON DELETE RESTRICT,
+    alias TEXT UNIQUE NOT NULL
+        CHECK(LENGTH(alias) > 0),
+
+    UNIQUE(entity_type, uid)
+) STRICT;
+
+CREATE TABLE entity_types (
+    entity_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE nic_types (
+    nic_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE node_nics (
+    node_uid INTEGER NOT NULL
+        REFERENCES nodes (node_uid) ON DELETE CASCADE,
+    nic_type INTEGER NOT NULL
+        REFERENCES nic_types (nic_type) ON DELETE RESTRICT,
+    name TEXT NOT NULL
+        -- Nic names tend to contain null bytes which we don't want to be in the database.
+        -- This feels dirty, but I don't know any better way to check for that
+        CHECK(HEX(name) NOT LIKE '%00%')
+, addr TEXT NOT NULL) STRICT;
+
+CREATE TABLE node_types (
+    node_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE nodes (
+    node_uid INTEGER PRIMARY KEY,
+    node_type INTEGER NOT NULL
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+    node_id INTEGER NOT NULL,
+    entity_type INTEGER GENERATED ALWAYS AS (1)
+        REFERENCES entity_types (entity_type) ON DELETE RESTRICT,
+
+    port INTEGER NOT NULL
+        CHECK(port BETWEEN 0 AND 0xFFFF),
+    last_contact TEXT NOT NULL,
+    machine_uuid TEXT,
+
+    UNIQUE (node_type, node_id),
+    FOREIGN KEY (node_uid, entity_type) REFERENCES entities (uid, entity_type) ON DELETE CASCADE
+) STRICT;
+
+CREATE TABLE pools (
+    pool_uid INTEGER PRIMARY KEY,
+    node_type INTEGER NOT NULL
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+    pool_id INTEGER
+        CHECK(pool_id BETWEEN 1 AND 0xFFFF),
+    entity_type INTEGER GENERATED ALWAYS AS (3)
+        REFERENCES entity_types (entity_type) ON DELETE RESTRICT,
+
+    UNIQUE (node_type, pool_id),
+    FOREIGN KEY (pool_uid, entity_type) REFERENCES entities (uid, entity_type) ON DELETE CASCADE
+) STRICT;
+
+CREATE TABLE quota_default_limits (
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    pool_id INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+
+    node_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    PRIMARY KEY (id_type, quota_type, pool_id),
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE quota_exceeded (
+    quota_id INTEGER NOT NULL,
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    pool_id INTEGER NOT NULL,
+
+    node_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    PRIMARY KEY (quota_id, id_type, quota_type, pool_id),
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE quota_id_types (
+    quota_id_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE quota_limits (
+    quota_id INTEGER NOT NULL,
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    pool_id INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+
+    node_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    PRIMARY KEY (quota_id, id_type, quota_type, pool_id),
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE quota_system_default_limits (
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    value INTEGER NOT NULL,
+
+    PRIMARY KEY (id_type, quota_type)
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE quota_types (
+    quota_type INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+) STRICT;
+
+CREATE TABLE quota_usage (
+    quota_id INTEGER NOT NULL,
+    id_type INTEGER NOT NULL
+        REFERENCES quota_id_types (quota_id_type) ON DELETE RESTRICT,
+    quota_type INTEGER NOT NULL
+        REFERENCES quota_types (quota_type) ON DELETE RESTRICT,
+    target_id INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+
+    node_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    PRIMARY KEY (quota_id, id_type, quota_type, target_id),
+    FOREIGN KEY (node_type, target_id) REFERENCES targets (node_type, target_id) ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE root_inode (
+    target_id INTEGER,
+    group_id INTEGER,
+
+    _only_one_row INTEGER PRIMARY KEY DEFAULT 1
+        CHECK(_only_one_row = 1),
+    node_type INTEGER GENERATED ALWAYS AS (1)
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+
+    -- Ensure that one and only one of target_id or group_id is set
+    CHECK (target_id IS NOT NULL OR group_id IS NOT NULL),
+    CHECK (target_id IS NULL OR group_id IS NULL),
+    
+    FOREIGN KEY (node_type, target_id) REFERENCES targets (node_type, target_id) ON DELETE RESTRICT,
+    FOREIGN KEY (node_type, group_id) REFERENCES buddy_groups (node_type, group_id) ON DELETE RESTRICT
+) STRICT;
+
+CREATE TABLE target_state_history (
+    id INTEGER PRIMARY KEY,
+    target_uid INTEGER NOT NULL
+        REFERENCES targets (target_uid) ON DELETE CASCADE,
+    time INTEGER NOT NULL,
+    state TEXT NOT NULL
+) STRICT;
+
+CREATE TABLE targets (
+    target_uid INTEGER PRIMARY KEY,
+    node_type INTEGER NOT NULL
+        REFERENCES node_types (node_type) ON DELETE RESTRICT,
+    target_id INTEGER NOT NULL,
+    entity_type INTEGER GENERATED ALWAYS AS (2)
+        REFERENCES entity_types (entity_type) ON DELETE RESTRICT,
+
+    node_id INTEGER,
+    pool_id INTEGER,
+    total_space INTEGER
+        CHECK(total_space >= 0),
+    total_inodes INTEGER
+        CHECK(total_inodes >= 0),
+    free_space INTEGER
+        CHECK(free_space >= 0),
+    free_inodes INTEGER
+        CHECK(free_inodes >= 0),
+    consistency INTEGER NOT NULL DEFAULT 1
+        REFERENCES consistency_types (consistency_type) ON DELETE RESTRICT, last_update TEXT NOT NULL DEFAULT '1970-01-01 00:00:00', reg_token TEXT, cap_pool INTEGER
+    REFERENCES cap_pool_types (cap_pool_type) ON DELETE RESTRICT, last_capacity_update TEXT,
+
+
+    UNIQUE (node_type, target_id),
+    FOREIGN KEY (target_uid, entity_type) REFERENCES entities (uid, entity_type) ON DELETE CASCADE,
+    FOREIGN KEY (node_type, node_id) REFERENCES nodes (node_type, node_id) ON DELETE RESTRICT
+    FOREIGN KEY (node_type, pool_id) REFERENCES pools (node_type, pool_id) ON DELETE RESTRICT
+) STRICT;
+
+CREATE INDEX audit_log_time ON audit_log (time);
+
+CREATE INDEX index_node_nics_1 ON node_nics(node_uid);
+
+CREATE INDEX quota_usage_target ON quota_usage (target_id);
+
+CREATE INDEX target_state_history_target ON target_state_history (target_uid, time);
+
+CREATE VIEW buddy_groups_ext AS
+    SELECT
+        e.alias, g.*, p.pool_uid, p_t.target_uid AS p_target_uid, s_t.target_uid AS s_target_uid
+    FROM buddy_groups AS g
+    INNER JOIN entities AS e ON e.uid = g.group_uid
+    INNER JOIN targets AS p_t ON p_t.target_id = g.p_target_id AND p_t.node_type = g.node_type
+    INNER JOIN targets AS s_t ON s_t.target_id = g.s_target_id AND s_t.node_type = g.node_type
+    LEFT JOIN pools AS p USING (node_type, pool_id);
+
+CREATE VIEW client_nodes AS
+    SELECT * FROM nodes WHERE node_type = 3;
+
+CREATE VIEW meta_buddy_groups AS
+    SELECT * FROM buddy_groups WHERE node_type = 1;
+
+CREATE VIEW meta_nodes AS
+    SELECT * FROM nodes WHERE node_type = 1;
+
+CREATE VIEW meta_targets AS
+    SELECT * FROM targets WHERE node_type = 1;
+
+CREATE VIEW nodes_ext AS
+    SELECT e.alias, n.*
+    FROM nodes AS n
+    INNER JOIN entities AS e ON e.uid = n.node_uid;
+
+CREATE VIEW pools_ext AS
+    SELECT e.alias, p.*
+    FROM pools AS p
+    INNER JOIN entities AS e ON e.uid = p.pool_uid;
+
+CREATE VIEW storage_buddy_groups AS
+    SELECT * FROM buddy_groups WHERE node_type = 2;
+
+CREATE VIEW storage_nodes AS
+    SELECT * FROM nodes WHERE node_type = 2;
+
+CREATE VIEW storage_pools AS
+    SELECT * FROM pools WHERE node_type = 2;
+
+CREATE VIEW storage_targets AS
+    SELECT * FROM targets WHERE node_type = 2;
+
+CREATE VIEW targets_ext AS
+    SELECT e.alias, t.*, n.node_uid
+    FROM targets AS t
+    INNER JOIN entities AS e ON e.uid = t.target_uid
+    LEFT JOIN nodes AS n USING(node_type, node_id);
+
+CREATE TRIGGER auto_delete_entity_after_buddy_group AFTER DELETE ON buddy_groups
+FOR EACH ROW
+BEGIN
+    DELETE FROM entities WHERE uid = OLD.group_uid;
+END;
+
+CREATE TRIGGER auto_delete_entity_after_node AFTER DELETE ON nodes
+FOR EACH ROW
+BEGIN
+    DELETE FROM entities WHERE uid = OLD.node_uid;
+END;
+
+CREATE TRIGGER auto_delete_entity_after_pool AFTER DELETE ON pools
+FOR EACH ROW
+BEGIN
+    DELETE FROM entities WHERE uid = OLD.pool_uid;
+END;
+
+CREATE TRIGGER auto_delete_entity_after_target AFTER DELETE ON targets
+FOR EACH ROW
+BEGIN
+    DELETE FROM entities WHERE uid = OLD.target_uid;
+END;
+
+CREATE TRIGGER keep_default_management_node BEFORE DELETE ON nodes
+FOR EACH ROW WHEN OLD.node_uid == 1
+BEGIN
+    SELECT RAISE (ABORT, "Deleting the management node is not allowed");
+END;
+
+CREATE TRIGGER keep_default_storage_pool BEFORE DELETE ON pools
+FOR EACH ROW WHEN OLD.pool_uid == 2
+BEGIN
+    SELECT RAISE (ABORT, "Deleting the default storage pool is not allowed");
+END;
+
@@ -3,6 +3,7 @@
 use super::*;
 use itertools::Itertools;
 use std::cmp::Ordering;
+use std::time::Duration;
 
 /// Ensures that the list of given targets actually exists and returns an appropriate error if not.
 pub(crate) fn validate_ids(
@@ -65,7 +66,7 @@ pub fn insert(
 ) -> Result<()> {
     anyhow::ensure!(target_id > 0, "A target id must be > 0");
 
-    let alias = format!("target_{}_{target_id}", node_type.user_str()).try_into()?;
+    let alias = Alias::new(format!("target_{}_{target_id}", node_type.user_str()))?;
     let new_uid = entity::insert(tx, EntityType::Target, &alias)?;
 
     tx.execute(
@@ -90,8 +91,50 @@ pub fn insert(
     Ok(())
 }
 
+/// Checks the registration token of an existing target and records it if none is stored yet.
+///
+/// An empty token is ignored for backwards compatibility with nodes that don't send one. It is
+/// neither checked nor recorded.
+///
+/// # Return value
+/// Fails if the target already has a different registration token stored.
+pub(crate) fn check_and_record_reg_token(
+    tx: &Transaction,
+    target_id: TargetId,
+    node_type: NodeTypeServer,
+    reg_token: &str,
+) -> Result<()> {
+    if reg_token.is_empty() {
+        return Ok(());
+    }
+
+    let stored_reg_token: Option<String> = tx.query_row_cached(
+        sql!("SELECT reg_token FROM targets WHERE target_id = ?1 AND node_type = ?2"),
+        params![target_id, node_type.sql_variant()],
+        |row| row.get(0),
+    )?;
+
+    match stored_reg_token {
+        Some(ref t) if t != reg_token => bail!(
+            "{node_type} target {target_id} has already been registered and its registration \
+token ({reg_token}) does not match the stored token ({t})"
+        ),
+        Some(_) => {}
+        None => {
+            tx.execute_cached(
+                sql!("UPDATE targets SET reg_token = ?1 WHERE target_id = ?2 AND node_type = ?3"),
+                params![reg_token, target_id, node_type.sql_variant()],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Changes the consistency state for the given targets to new individual values.
 ///
+/// Each actual change is recorded in the target state history.
+///
 /// # Return value
 /// Returns the number of affected entries.
 pub(crate) fn update_consistency_states(
@@ -106,13 +149,22 @@ pub(crate) fn update_consistency_states(
 
     let mut updated = 0;
     for e in changes {
-        updated += update.execute(params![e.0, node_type.sql_variant(), e.1.sql_variant()])?;
+        let affected = update.execute(params![e.0, node_type.sql_variant(), e.1.sql_variant()])?;
+        if affected > 0 {
+            target_state_history::insert(tx, e.0, node_type, e.1.user_str(), Duration::ZERO)?;
+        }
+
+        updated += affected;
     }
 
     Ok(updated)
 }
 
 /// Change the storage pool of the given targets IDs to a new one.
+///
+/// Fails if a target is part of a buddy group and its peer is not moved along, since both targets
+/// of a storage buddy group must always be in the same pool. If both are moved, the buddy group is
+/// moved as well.
 pub(crate) fn update_storage_pools(
     tx: &Transaction,
     new_pool_id: PoolId,
@@ -122,6 +174,31 @@ pub(crate) fn update_storage_pools(
 
     validate_ids(tx, target_ids, NodeTypeServer::Storage)?;
 
+    let split_groups: Vec<String> = tx.query_map_collect(
+        sql!(
+            "SELECT group_id, p_target_id, s_target_id FROM storage_buddy_groups
+            WHERE (p_target_id IN rarray(?1)) != (s_target_id IN rarray(?1))
+            ORDER BY group_id"
+        ),
+        [&rarray_param(target_ids.iter().copied())],
+        |row| {
+            Ok(format!(
+                "{} (targets {}, {})",
+                row.get::<_, BuddyGroupId>(0)?,
+                row.get::<_, TargetId>(1)?,
+                row.get::<_, TargetId>(2)?
+            ))
+        },
+    )?;
+
+    if !split_groups.is_empty() {
+        bail!(
+            "Targets can't be moved to storage pool {new_pool_id} without their buddy group peer. \
+             Conflicting buddy groups: {}",
+            split_groups.join(", ")
+        );
+    }
+
     tx.execute(
         sql!("UPDATE targets SET pool_id = ?1 WHERE target_id IN rarray(?2) AND node_type = ?3"),
         params![
@@ -131,6 +208,19 @@ pub(crate) fn update_storage_pools(
         ],
     )?;
 
+    // Both targets of affected groups have been moved (checked above), so move the group as well
+    tx.execute(
+        sql!(
+            "UPDATE buddy_groups SET pool_id = ?1
+            WHERE p_target_id IN rarray(?2) AND node_type = ?3"
+        ),
+        params![
+            new_pool_id,
+            &rarray_param(target_ids.iter().copied()),
+            NodeType::Storage.sql_variant()
+        ],
+    )?;
+
     Ok(())
 }
 
@@ -162,6 +252,7 @@ pub(crate) fn update_storage_node_mappings(
 /// Represents the storage capacities of a storage target.
 ///
 /// Values are `None` if there is no information available.
+#[derive(Clone, Debug)]
 pub(crate) struct TargetCapacities {
     pub total_space: Option<u64>,
     pub total_inodes: Option<u64>,
@@ -171,6 +262,8 @@ pub(crate) struct TargetCapacities {
 
 /// Retrieves the storage capacities for the given target IDs and updates them with new values.
 ///
+/// Also records the time of the update, which is reported as the capacity report age.
+///
 /// # Return value
 /// Vector of tuples containing the target ID and the pre-update capacity info.
 pub(crate) fn get_and_update_capacities(
@@ -186,7 +279,8 @@ pub(crate) fn get_and_update_capacities(
 
     let mut update = tx.prepare_cached(sql!(
         "UPDATE targets
-        SET total_space = ?1, total_inodes = ?2, free_space = ?3, free_inodes = ?4
+        SET total_space = ?1, total_inodes = ?2, free_space = ?3, free_inodes = ?4,
+            last_capacity_update = DATETIME('now')
         WHERE target_uid = (
             SELECT target_uid FROM targets WHERE target_id = ?5 AND node_type = ?6
         )"
@@ -224,14 +318,85 @@ pub(crate) fn get_and_update_capacities(
     Ok(old_values)
 }
 
-/// Deletes a storage target.
+/// Retrieves the free space and free inodes of all targets in the same storage pool as the given
+/// target (all meta targets for a meta target). These are the values the dynamic capacity pool
+/// limits are calculated from. Missing values are returned as 0.
+///
+/// # Return value
+/// Vector of (free space, free inodes) tuples.
+pub(crate) fn get_pool_free_capacities(
+    tx: &Transaction,
+    target_id: TargetId,
+    node_type: NodeTypeServer,
+) -> Result<Vec<(u64, u64)>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT COALESCE(t.free_space, 0), COALESCE(t.free_inodes, 0)
+            FROM targets AS t
+            INNER JOIN targets AS o ON o.node_type = t.node_type AND o.pool_id IS t.pool_id
+            WHERE o.target_id = ?1 AND o.node_type = ?2"
+        ),
+        params![target_id, node_type.sql_variant()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?)
+}
+
+/// Retrieves the capacity pool a target has last been classified into.
+///
+/// # Return value
+/// `None` if the target hasn't been classified yet.
+pub(crate) fn get_cap_pool(
+    tx: &Transaction,
+    target_id: TargetId,
+    node_type: NodeTypeServer,
+) -> Result<Option<CapacityPool>> {
+    let cap_pool = tx.query_row_cached(
+        sql!("SELECT cap_pool FROM targets WHERE target_id = ?1 AND node_type = ?2"),
+        params![target_id, node_type.sql_variant()],
+        |row| CapacityPool::from_row_opt(row, 0),
+    )?;
+
+    Ok(cap_pool)
+}
+
+/// Stores the capacity pool a target has been classified into.
+pub(crate) fn set_cap_pool(
+    tx: &Transaction,
+    target_id: TargetId,
+    node_type: NodeTypeServer,
+    cap_pool: CapacityPool,
+) -> Result<()> {
+    let affected = tx.execute_cached(
+        sql!("UPDATE targets SET cap_pool = ?1 WHERE target_id = ?2 AND node_type = ?3"),
+        params![cap_pool.sql_variant(), target_id, node_type.sql_variant()],
+    )?;
+
+    check_affected_rows(affected, [1])
+}
+
+/// Deletes a storage target and updates the exceeded quota of its pool.
 pub(crate) fn delete_storage(tx: &Transaction, target_id: TargetId) -> Result<()> {
+    let pool_id: Option<PoolId> = tx
+        .query_row_cached(
+            sql!("SELECT pool_id FROM targets WHERE target_id = ?1 AND node_type = ?2"),
+            params![target_id, NodeType::Storage.sql_variant()],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+
     let affected = tx.execute_cached(
         sql!("DELETE FROM targets WHERE target_id = ?1 AND node_type = ?2"),
         params![target_id, NodeType::Storage.sql_variant()],
     )?;
 
-    check_affected_rows(affected, [1])
+    check_affected_rows(affected, [1])?;
+
+    if let Some(pool_id) = pool_id {
+        quota::update_exceeded(tx, quota::ExceededScope::Pools(&[pool_id]))?;
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -266,4 +431,93 @@ mod test {
             assert!(targets.contains(&1000));
         })
     }
+
+    #[test]
+    fn update_storage_pools() {
+        with_test_data(|tx| {
+            let get_pool = |tx: &Transaction, target_id: TargetId| -> PoolId {
+                tx.query_row(
+                    sql!("SELECT pool_id FROM storage_targets WHERE target_id = ?1"),
+                    [target_id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            };
+
+            // Lone target
+            super::update_storage_pools(tx, 3, &[2]).unwrap();
+            assert_eq!(get_pool(tx, 2), 3);
+
+            // Grouped target without its peer
+            let err = super::update_storage_pools(tx, 3, &[1, 2]).unwrap_err();
+            assert!(err.to_string().contains("1 (targets 1, 5)"));
+            assert_eq!(get_pool(tx, 1), 1);
+
+            // Grouped target together with its peer
+            super::update_storage_pools(tx, 3, &[1, 5]).unwrap();
+            assert_eq!(get_pool(tx, 1), 3);
+            assert_eq!(get_pool(tx, 5), 3);
+            let group_pool: PoolId = tx
+                .query_row(
+                    sql!("SELECT pool_id FROM storage_buddy_groups WHERE group_id = 1"),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(group_pool, 3);
+        })
+    }
+
+    #[test]
+    fn check_and_record_reg_token() {
+        with_test_data(|tx| {
+            let get = |tx: &Transaction| -> Option<String> {
+                tx.query_row(
+                    sql!("SELECT reg_token FROM targets WHERE target_id = 1 AND node_type = 2"),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            };
+
+            // First registration records the token
+            super::check_and_record_reg_token(tx, 1, NodeTypeServer::Storage, "token").unwrap();
+            assert_eq!(get(tx).as_deref(), Some("token"));
+
+            // Same token is fine
+            super::check_and_record_reg_token(tx, 1, NodeTypeServer::Storage, "token").unwrap();
+
+            // Different token is rejected
+            super::check_and_record_reg_token(tx, 1, NodeTypeServer::Storage, "other").unwrap_err();
+
+            // Missing token is ignored
+            super::check_and_record_reg_token(tx, 1, NodeTypeServer::Storage, "").unwrap();
+            assert_eq!(get(tx).as_deref(), Some("token"));
+
+            // Tokens are per node type
+            super::check_and_record_reg_token(tx, 1, NodeTypeServer::Meta, "meta_token").unwrap();
+            assert_eq!(get(tx).as_deref(), Some("token"));
+        })
+    }
+
+    #[test]
+    fn delete_storage_updates_exceeded_quota() {
+        with_test_data(|tx| {
+            quota::update_exceeded(tx, quota::ExceededScope::All).unwrap();
+
+            let exceeded = |tx: &Transaction| -> usize {
+                tx.query_row(
+                    sql!("SELECT COUNT(*) FROM quota_exceeded WHERE pool_id = 2"),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            };
+            assert_eq!(exceeded(tx), 1);
+
+            // The usage of user 20 on pool 2 is only on target 2
+            super::delete_storage(tx, 2).unwrap();
+            assert_eq!(exceeded(tx), 0);
+        })
+    }
 }
@@ -0,0 +1,145 @@
+//! Functions for the target state history, recording consistency and reachability transitions
+
+use super::*;
+use std::time::Duration;
+
+/// The maximum number of history entries kept per target. When exceeded, the oldest entries are
+/// removed.
+pub(crate) const MAX_ENTRIES_PER_TARGET: usize = 100;
+
+/// Represents a target that transitioned its state frequently within the queried time window.
+#[derive(Clone, Debug)]
+pub(crate) struct FlappingTarget {
+    pub uid: Uid,
+    pub alias: String,
+    pub target_id: TargetId,
+    pub node_type: NodeTypeServer,
+    pub transitions: usize,
+    /// Unix timestamp in seconds of the most recent transition
+    pub last_transition: i64,
+}
+
+/// Records a state transition of the given target that happened `ago` before the current time.
+///
+/// `state` is the new state as user string (e.g. `needs_resync` or `online`). Drops the oldest
+/// entries of the target if it has more than [MAX_ENTRIES_PER_TARGET].
+pub(crate) fn insert(
+    tx: &Transaction,
+    target_id: TargetId,
+    node_type: NodeTypeServer,
+    state: &str,
+    ago: Duration,
+) -> Result<()> {
+    let target_uid: Uid = tx.query_row_cached(
+        sql!("SELECT target_uid FROM targets WHERE target_id = ?1 AND node_type = ?2"),
+        params![target_id, node_type.sql_variant()],
+        |row| row.get(0),
+    )?;
+
+    let affected = tx.execute_cached(
+        sql!(
+            "INSERT INTO target_state_history (target_uid, time, state)
+            VALUES (?1, UNIXEPOCH('now') - ?3, ?2)"
+        ),
+        params![target_uid, state, ago.as_secs()],
+    )?;
+
+    check_affected_rows(affected, [1])?;
+
+    tx.execute_cached(
+        sql!(
+            "DELETE FROM target_state_history
+            WHERE target_uid = ?1 AND id <= (
+                SELECT id FROM target_state_history WHERE target_uid = ?1
+                ORDER BY id DESC LIMIT 1 OFFSET ?2
+            )"
+        ),
+        params![target_uid, MAX_ENTRIES_PER_TARGET],
+    )?;
+
+    Ok(())
+}
+
+/// Retrieve all targets that recorded more than `threshold` transitions within the last
+/// `window_secs` seconds, ordered by the number of transitions (descending).
+pub(crate) fn get_flapping(
+    tx: &Transaction,
+    window_secs: u64,
+    threshold: usize,
+) -> Result<Vec<FlappingTarget>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT t.target_uid, t.alias, t.target_id, t.node_type, COUNT(*), MAX(h.time)
+            FROM target_state_history AS h
+            INNER JOIN targets_ext AS t USING(target_uid)
+            WHERE h.time >= UNIXEPOCH('now') - ?1
+            GROUP BY h.target_uid
+            HAVING COUNT(*) > ?2
+            ORDER BY COUNT(*) DESC, t.target_uid ASC"
+        ),
+        params![window_secs, threshold],
+        |row| {
+            Ok(FlappingTarget {
+                uid: row.get(0)?,
+                alias: row.get(1)?,
+                target_id: row.get(2)?,
+                node_type: NodeTypeServer::from_row(row, 3)?,
+                transitions: row.get(4)?,
+                last_transition: row.get(5)?,
+            })
+        },
+    )?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_flapping() {
+        with_test_data(|tx| {
+            for _ in 0..3 {
+                insert(
+                    tx,
+                    1,
+                    NodeTypeServer::Storage,
+                    "needs_resync",
+                    Duration::ZERO,
+                )
+                .unwrap();
+                insert(tx, 1, NodeTypeServer::Storage, "good", Duration::ZERO).unwrap();
+            }
+            insert(tx, 2, NodeTypeServer::Meta, "online", Duration::ZERO).unwrap();
+
+            let flapping = get_flapping(tx, 60, 5).unwrap();
+            assert_eq!(flapping.len(), 1);
+            assert_eq!(flapping[0].uid, 202001);
+            assert_eq!(flapping[0].node_type, NodeTypeServer::Storage);
+            assert_eq!(flapping[0].transitions, 6);
+
+            assert_eq!(get_flapping(tx, 60, 0).unwrap().len(), 2);
+            assert!(get_flapping(tx, 60, 6).unwrap().is_empty());
+
+            // Unknown target
+            insert(tx, 1234, NodeTypeServer::Storage, "good", Duration::ZERO).unwrap_err();
+        })
+    }
+
+    #[test]
+    fn insert_drops_oldest() {
+        with_test_data(|tx| {
+            for _ in 0..(MAX_ENTRIES_PER_TARGET + 10) {
+                insert(tx, 1, NodeTypeServer::Storage, "good", Duration::ZERO).unwrap();
+            }
+
+            let count: usize = tx
+                .query_row(
+                    "SELECT COUNT(*) FROM target_state_history WHERE target_uid = 202001",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, MAX_ENTRIES_PER_TARGET);
+        })
+    }
+}
@@ -0,0 +1,181 @@
+//! Removes stale and orphaned rows from the tables holding runtime data.
+//!
+//! Foreign key constraints usually prevent orphaned rows, but databases that have been imported,
+//! migrated or edited manually might still contain them. Clients are normally removed by the stale
+//! client deleter, this only acts as a backstop in case that didn't happen.
+
+use super::*;
+use std::time::Duration;
+
+/// The number of rows removed by [trim], per kind
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Trimmed {
+    /// Clients without contact for more than twice the auto remove timeout
+    pub stale_clients: usize,
+    /// Nics of nodes that don't exist
+    pub node_nics: usize,
+    /// Quota usage reported for targets that don't exist
+    pub quota_usage: usize,
+    /// State history of targets that don't exist
+    pub target_state_history: usize,
+}
+
+impl Trimmed {
+    pub fn total(&self) -> usize {
+        self.stale_clients + self.node_nics + self.quota_usage + self.target_state_history
+    }
+
+    /// Whether any kind hit the limit, meaning there might be more rows left to remove
+    pub fn limit_reached(&self, limit: usize) -> bool {
+        self.stale_clients >= limit
+            || self.node_nics >= limit
+            || self.quota_usage >= limit
+            || self.target_state_history >= limit
+    }
+}
+
+/// Removes stale clients and orphaned runtime data rows.
+///
+/// A client is only removed if its last contact is longer ago than twice `client_timeout`. At most
+/// `limit` rows are removed per kind. Entities that are not stale and rows belonging to existing
+/// entities are never touched.
+pub(crate) fn trim(tx: &Transaction, client_timeout: Duration, limit: usize) -> Result<Trimmed> {
+    let stale_clients = node::delete_stale_clients(tx, client_timeout.saturating_mul(2), limit)?;
+
+    let node_nics = tx.execute_cached(
+        sql!(
+            "DELETE FROM node_nics
+            WHERE rowid IN (
+                SELECT nn.rowid FROM node_nics AS nn
+                LEFT JOIN nodes AS n USING(node_uid)
+                WHERE n.node_uid IS NULL
+                LIMIT ?1
+            )"
+        ),
+        [limit],
+    )?;
+
+    let quota_usage = tx.execute_cached(
+        sql!(
+            "DELETE FROM quota_usage
+            WHERE (quota_id, id_type, quota_type, target_id) IN (
+                SELECT q.quota_id, q.id_type, q.quota_type, q.target_id
+                FROM quota_usage AS q
+                LEFT JOIN targets AS t USING(node_type, target_id)
+                WHERE t.target_uid IS NULL
+                LIMIT ?1
+            )"
+        ),
+        [limit],
+    )?;
+
+    let target_state_history = tx.execute_cached(
+        sql!(
+            "DELETE FROM target_state_history
+            WHERE id IN (
+                SELECT h.id FROM target_state_history AS h
+                LEFT JOIN targets AS t USING(target_uid)
+                WHERE t.target_uid IS NULL
+                LIMIT ?1
+            )"
+        ),
+        [limit],
+    )?;
+
+    Ok(Trimmed {
+        stale_clients,
+        node_nics,
+        quota_usage,
+        target_state_history,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::with_test_data;
+
+    #[test]
+    fn trim() {
+        with_test_data(|tx| {
+            let count = |sql: &str| -> usize { tx.query_row(sql, [], |row| row.get(0)).unwrap() };
+
+            let nodes = count("SELECT COUNT(*) FROM nodes");
+            let nics = count("SELECT COUNT(*) FROM node_nics");
+            let quota_usage = count("SELECT COUNT(*) FROM quota_usage");
+            let history = count("SELECT COUNT(*) FROM target_state_history");
+
+            // Nothing to trim in the test data
+            assert_eq!(
+                super::trim(tx, Duration::from_secs(60), 100).unwrap(),
+                Trimmed::default()
+            );
+
+            // Seed orphaned rows. Foreign key checks are deferred to the end of the transaction,
+            // so they must be trimmed before that.
+            tx.pragma_update(None, "defer_foreign_keys", true).unwrap();
+            tx.execute_batch(
+                "INSERT INTO node_nics (node_uid, nic_type, addr, name)
+                    VALUES (999999, 1, '1.2.3.4', 'orphan1'), (999999, 1, '1.2.3.5', 'orphan2');
+                INSERT INTO quota_usage (quota_id, id_type, quota_type, target_id, value)
+                    VALUES (1, 1, 1, 999, 10), (2, 1, 1, 999, 10), (3, 1, 1, 999, 10);
+                INSERT INTO target_state_history (target_uid, time, state)
+                    VALUES (999999, 0, 'offline');",
+            )
+            .unwrap();
+
+            // Client 103001 is stale for longer than the timeout, 103002 far beyond it. The
+            // others are live.
+            tx.execute_batch(
+                "UPDATE nodes SET last_contact = DATETIME('now', '-90 seconds')
+                    WHERE node_uid = 103001;
+                UPDATE nodes SET last_contact = DATETIME('now', '-1 day') WHERE node_uid = 103002;",
+            )
+            .unwrap();
+            let client_nics = count("SELECT COUNT(*) FROM node_nics WHERE node_uid = 103002");
+
+            // Removed in batches
+            let trimmed = super::trim(tx, Duration::from_secs(60), 2).unwrap();
+            assert_eq!(
+                trimmed,
+                Trimmed {
+                    stale_clients: 1,
+                    node_nics: 2,
+                    quota_usage: 2,
+                    target_state_history: 1,
+                }
+            );
+            assert!(trimmed.limit_reached(2));
+
+            let trimmed = super::trim(tx, Duration::from_secs(60), 2).unwrap();
+            assert_eq!(
+                trimmed,
+                Trimmed {
+                    quota_usage: 1,
+                    ..Default::default()
+                }
+            );
+            assert!(!trimmed.limit_reached(2));
+
+            assert_eq!(
+                super::trim(tx, Duration::from_secs(60), 2).unwrap().total(),
+                0
+            );
+
+            // Only the client far beyond the timeout is gone, the live and the recently stale
+            // ones as well as all the data of existing entities remain
+            assert_eq!(count("SELECT COUNT(*) FROM nodes"), nodes - 1);
+            assert_eq!(
+                count("SELECT COUNT(*) FROM nodes WHERE node_uid = 103002"),
+                0
+            );
+            assert_eq!(
+                count("SELECT COUNT(*) FROM nodes WHERE node_uid IN (103001, 103003, 103004)"),
+                3
+            );
+            assert_eq!(count("SELECT COUNT(*) FROM node_nics"), nics - client_nics);
+            assert_eq!(count("SELECT COUNT(*) FROM quota_usage"), quota_usage);
+            assert_eq!(count("SELECT COUNT(*) FROM target_state_history"), history);
+        })
+    }
+}
@@ -0,0 +1,98 @@
+//! Serializes canonical instances of BeeMsges for comparing their wire format against the C++
+//! implementation.
+//!
+//! Used by the hidden `--dump-msg` option.
+
+use anyhow::{Result, bail};
+use shared::bee_msg::buddy_group::SetMirrorBuddyGroup;
+use shared::bee_msg::node::{GetNodes, Nic, RegisterNode};
+use shared::bee_msg::{Msg, serialize};
+use shared::bee_serde::Serializable;
+use shared::types::{NicType, NodeType};
+use std::fmt::Write;
+use std::net::Ipv4Addr;
+
+/// The messages that can be dumped
+pub const MSG_NAMES: &[&str] = &["RegisterNode", "GetNodes", "SetMirrorBuddyGroup"];
+
+/// Large enough for all the canonical messages
+const BUF_LEN: usize = 64 * 1024;
+
+/// Serializes the canonical instance of the message `name` (including the header) and returns
+/// the message id, the length and the bytes as hex dump.
+pub fn dump_msg(name: &str) -> Result<String> {
+    match name {
+        "RegisterNode" => dump(&RegisterNode {
+            instance_version: 0,
+            nic_list_version: 0,
+            node_alias: b"node_1".to_vec(),
+            nics: vec![Nic {
+                addr: Ipv4Addr::new(192, 168, 0, 1).into(),
+                name: b"eth0".to_vec(),
+                nic_type: NicType::Tcp,
+            }],
+            node_type: NodeType::Storage,
+            node_id: 1,
+            root_num_id: 0,
+            is_root_mirrored: 0,
+            port: 8003,
+            port_tcp_unused: 8003,
+            machine_uuid: b"uuid".to_vec(),
+        }),
+        "GetNodes" => dump(&GetNodes {
+            node_type: NodeType::Storage,
+        }),
+        "SetMirrorBuddyGroup" => dump(&SetMirrorBuddyGroup {
+            node_type: NodeType::Storage,
+            primary_target_id: 1,
+            secondary_target_id: 2,
+            group_id: 1,
+            allow_update: 0,
+            ack_id: "ack".into(),
+        }),
+        _ => bail!(
+            "Unknown message {name}. Available messages: {}",
+            MSG_NAMES.join(", ")
+        ),
+    }
+}
+
+fn dump<M: Msg + Serializable>(msg: &M) -> Result<String> {
+    let mut buf = vec![0; BUF_LEN];
+    let len = serialize(msg, &mut buf)?;
+
+    let mut out = format!("{msg:?}\nID: {}\nLength: {len} bytes\n", M::ID);
+    for line in buf[..len].chunks(16) {
+        let hex: Vec<_> = line.iter().map(|b| format!("{b:02x}")).collect();
+        writeln!(out, "{}", hex.join(" "))?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dump_msg() {
+        for name in MSG_NAMES {
+            assert_eq!(
+                super::dump_msg(name).unwrap(),
+                super::dump_msg(name).unwrap()
+            );
+        }
+
+        assert_eq!(
+            super::dump_msg("GetNodes").unwrap(),
+            "GetNodes { node_type: Storage }\n\
+            ID: 1017\n\
+            Length: 44 bytes\n\
+            2c 00 00 00 00 00 00 00 00 00 00 00 53 46 47 42\n\
+            f9 03 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+            00 00 00 00 00 00 00 00 02 00 00 00\n"
+        );
+
+        super::dump_msg("Unknown").unwrap_err();
+    }
+}
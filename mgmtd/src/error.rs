@@ -15,6 +15,12 @@ pub enum TypedError {
     /// expected not to (e.g. for a new entry).
     #[error("{name} with value {value} already exists")]
     ValueExists { name: String, value: String },
+    /// A write was attempted while the management runs in read-only mode.
+    #[error("Management is in read-only mode")]
+    ReadOnly,
+    /// A licensed feature was requested but is not enabled by the installed license.
+    #[error("Feature {feature} requires a license: {reason}")]
+    LicenseDenied { feature: String, reason: String },
 }
 
 impl TypedError {
@@ -25,6 +31,13 @@ impl TypedError {
         }
     }
 
+    pub fn license_denied(feature: impl ToString, reason: impl ToString) -> Self {
+        Self::LicenseDenied {
+            feature: feature.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
     pub fn value_not_found(name: impl ToString, value: impl ToString) -> Self {
         Self::ValueNotFound {
             name: name.to_string(),
@@ -2,51 +2,77 @@
 
 use crate::app::*;
 use crate::db;
+use crate::error::TypedError;
 use crate::license::LicensedFeature;
-use crate::types::{ResolveEntityId, SqliteEnumExt};
+use crate::types::{ResolveEntityId, SqliteEnumExt, resolve_many};
 use anyhow::{Context as AContext, Result, anyhow, bail};
+use audit::Audit;
 use protobuf::{beegfs as pb, management as pm};
 use rusqlite::{OptionalExtension, Row, Transaction, TransactionBehavior, named_params, params};
 use shared::grpc::*;
 use shared::impl_grpc_handler;
-use shared::run_state::RunStateHandle;
+use shared::nic::select_bind_addr;
+use shared::run_state::{RunStateHandle, WeakRunStateHandle};
 use shared::types::*;
 use sqlite::{TransactionExt, check_affected_rows};
 use sqlite_check::sql;
 use std::fmt::Debug;
 use std::future::Future;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::pin::Pin;
+use tonic::codec::CompressionEncoding;
+use tonic::service::InterceptedService;
 use tonic::transport::{Identity, Server, ServerTlsConfig};
 use tonic::{Code, Request, Response, Status};
+use tonic_health::server::HealthReporter;
 
+mod audit;
+mod auth;
 mod common;
 
+mod abort_resync;
 mod assign_pool;
+mod check_consistency;
 mod create_buddy_group;
 mod create_pool;
 mod delete_buddy_group;
 mod delete_node;
 mod delete_pool;
 mod delete_target;
+mod ensure_buddy_group;
+mod evacuate_node;
+mod get_audit_log;
 mod get_buddy_groups;
+mod get_flapping_targets;
 mod get_license;
+mod get_local_nics;
+mod get_meta_root_status;
+mod get_msg_capture;
 mod get_nodes;
 mod get_pools;
 mod get_quota_limits;
 mod get_quota_usage;
+mod get_recent_logs;
+mod get_resync_status;
+mod get_server_info;
 mod get_targets;
+mod mirror_init;
 mod mirror_root_inode;
+mod ping;
 mod set_alias;
+mod set_aliases;
 mod set_default_quota_limits;
 mod set_quota_limits;
 mod set_target_state;
+mod start_msg_capture;
 mod start_resync;
+mod stream_nodes;
+mod subscribe_cap_pool_events;
 
 /// Management gRPC service implementation struct
 #[derive(Debug)]
-pub(crate) struct ManagementService {
-    pub app: RuntimeApp,
+pub(crate) struct ManagementService<A: App> {
+    pub app: A,
 }
 
 /// Implementation of the management gRPC service. Use the shared::impl_grpc_handler! macro to
@@ -54,7 +80,7 @@ pub(crate) struct ManagementService {
 ///
 /// However, if a function should be implemented manually using an async fn, re-add the
 /// #[tonic::async_trait] macro (or it will not work).
-impl pm::management_server::Management for ManagementService {
+impl<A: App + Sync> pm::management_server::Management for ManagementService<A> {
     // Example: Implement pm::management_server::Management::set_alias using the impl_grpc_handler
     // macro
     impl_grpc_handler! {
@@ -68,22 +94,43 @@ impl pm::management_server::Management for ManagementService {
         "Set alias"
     }
 
+    impl_grpc_handler! {
+        set_aliases,
+        pm::SetAliasesRequest => pm::SetAliasesResponse,
+        "Set aliases"
+    }
+
     impl_grpc_handler! {
         get_nodes,
-        pm::GetNodesRequest => pm::GetNodesResponse,
+        pm::GetNodesRequest => COMPRESSED(pm::GetNodesResponse),
         "Get nodes"
     }
+    impl_grpc_handler! {
+        stream_nodes,
+        pm::StreamNodesRequest => STREAM(StreamNodesStream, pm::StreamNodesResponse),
+        "Stream nodes"
+    }
     impl_grpc_handler! {
         delete_node,
         pm::DeleteNodeRequest => pm::DeleteNodeResponse,
         "Delete node"
     }
+    impl_grpc_handler! {
+        evacuate_node,
+        pm::EvacuateNodeRequest => pm::EvacuateNodeResponse,
+        "Evacuate node"
+    }
 
     impl_grpc_handler! {
         get_targets,
-        pm::GetTargetsRequest => pm::GetTargetsResponse,
+        pm::GetTargetsRequest => COMPRESSED(pm::GetTargetsResponse),
         "Get targets"
     }
+    impl_grpc_handler! {
+        get_flapping_targets,
+        pm::GetFlappingTargetsRequest => pm::GetFlappingTargetsResponse,
+        "Get flapping targets"
+    }
     impl_grpc_handler! {
         delete_target,
         pm::DeleteTargetRequest => pm::DeleteTargetResponse,
@@ -94,10 +141,16 @@ impl pm::management_server::Management for ManagementService {
         pm::SetTargetStateRequest => pm::SetTargetStateResponse,
         "Set target state"
     }
+    impl_grpc_handler! {
+        subscribe_cap_pool_events,
+        pm::SubscribeCapPoolEventsRequest
+            => STREAM(SubscribeCapPoolEventsStream, pm::SubscribeCapPoolEventsResponse),
+        "Subscribe capacity pool events"
+    }
 
     impl_grpc_handler! {
         get_pools,
-        pm::GetPoolsRequest => pm::GetPoolsResponse,
+        pm::GetPoolsRequest => COMPRESSED(pm::GetPoolsResponse),
         "Get pools"
     }
     impl_grpc_handler! {
@@ -118,7 +171,7 @@ impl pm::management_server::Management for ManagementService {
 
     impl_grpc_handler! {
         get_buddy_groups,
-        pm::GetBuddyGroupsRequest => pm::GetBuddyGroupsResponse,
+        pm::GetBuddyGroupsRequest => COMPRESSED(pm::GetBuddyGroupsResponse),
         "Get buddy groups"
     }
     impl_grpc_handler! {
@@ -126,6 +179,11 @@ impl pm::management_server::Management for ManagementService {
         pm::CreateBuddyGroupRequest => pm::CreateBuddyGroupResponse,
         "Create buddy group"
     }
+    impl_grpc_handler! {
+        ensure_buddy_group,
+        pm::EnsureBuddyGroupRequest => pm::EnsureBuddyGroupResponse,
+        "Ensure buddy group"
+    }
     impl_grpc_handler! {
         delete_buddy_group,
         pm::DeleteBuddyGroupRequest => pm::DeleteBuddyGroupResponse,
@@ -136,11 +194,31 @@ impl pm::management_server::Management for ManagementService {
         pm::MirrorRootInodeRequest => pm::MirrorRootInodeResponse,
         "Mirror root inode"
     }
+    impl_grpc_handler! {
+        get_meta_root_status,
+        pm::GetMetaRootStatusRequest => pm::GetMetaRootStatusResponse,
+        "Get meta root status"
+    }
+    impl_grpc_handler! {
+        mirror_init,
+        pm::MirrorInitRequest => pm::MirrorInitResponse,
+        "Mirror init"
+    }
     impl_grpc_handler! {
         start_resync,
         pm::StartResyncRequest => pm::StartResyncResponse,
         "Start resync"
     }
+    impl_grpc_handler! {
+        abort_resync,
+        pm::AbortResyncRequest => pm::AbortResyncResponse,
+        "Abort resync"
+    }
+    impl_grpc_handler! {
+        get_resync_status,
+        pm::GetResyncStatusRequest => pm::GetResyncStatusResponse,
+        "Get resync status"
+    }
 
     impl_grpc_handler! {
         set_default_quota_limits,
@@ -168,6 +246,117 @@ impl pm::management_server::Management for ManagementService {
         pm::GetLicenseRequest => pm::GetLicenseResponse,
         "Get license"
     }
+
+    impl_grpc_handler! {
+        get_server_info,
+        pm::GetServerInfoRequest => pm::GetServerInfoResponse,
+        "Get server info"
+    }
+
+    impl_grpc_handler! {
+        get_recent_logs,
+        pm::GetRecentLogsRequest => STREAM(GetRecentLogsStream, pm::GetRecentLogsResponse),
+        "Get recent logs"
+    }
+
+    impl_grpc_handler! {
+        get_local_nics,
+        pm::GetLocalNicsRequest => pm::GetLocalNicsResponse,
+        "Get local nics"
+    }
+
+    impl_grpc_handler! {
+        ping,
+        pm::PingRequest => pm::PingResponse,
+        "Ping"
+    }
+
+    impl_grpc_handler! {
+        check_consistency,
+        pm::CheckConsistencyRequest => pm::CheckConsistencyResponse,
+        "Check consistency"
+    }
+
+    impl_grpc_handler! {
+        get_audit_log,
+        pm::GetAuditLogRequest => STREAM(GetAuditLogStream, pm::GetAuditLogResponse),
+        "Get audit log"
+    }
+
+    impl_grpc_handler! {
+        start_msg_capture,
+        pm::StartMsgCaptureRequest => pm::StartMsgCaptureResponse,
+        "Start msg capture"
+    }
+
+    impl_grpc_handler! {
+        get_msg_capture,
+        pm::GetMsgCaptureRequest => STREAM(GetMsgCaptureStream, pm::GetMsgCaptureResponse),
+        "Get msg capture"
+    }
+}
+
+/// Checks that the request carries the required authentication secret. Failed attempts are logged
+/// with the source address of the request.
+fn check_auth_secret(req: &Request<()>, required_secret: AuthSecret) -> Result<(), Status> {
+    let check = || -> Result<()> {
+        let Some(request_secret) = req.metadata().get("auth-secret") else {
+            bail!("Request requires authentication but no secret was provided")
+        };
+
+        let request_secret = AuthSecret::try_from_bytes(request_secret.as_bytes())?;
+
+        if request_secret != required_secret {
+            bail!("Request requires authentication but provided secret doesn't match",);
+        }
+
+        Ok(())
+    };
+
+    check().map_err(|err| {
+        log::warn!("{}", auth_failure_log_msg(req.remote_addr(), &err));
+        Status::unauthenticated(err.to_string())
+    })
+}
+
+/// Builds the log message for a failed gRPC authentication attempt
+fn auth_failure_log_msg(remote_addr: Option<SocketAddr>, err: &anyhow::Error) -> String {
+    match remote_addr {
+        Some(addr) => format!("Authentication of gRPC request from {addr} failed: {err:#}"),
+        None => format!("Authentication of gRPC request from unknown address failed: {err:#}"),
+    }
+}
+
+/// The management gRPC service type, as registered with the health service
+type RuntimeManagementServer =
+    pm::management_server::ManagementServer<ManagementService<RuntimeApp>>;
+
+/// Keeps the gRPC health status of the management service and the server as a whole up to date.
+///
+/// Reports SERVING while running and NOT_SERVING as soon as pre shutdown is triggered.
+async fn report_health(reporter: HealthReporter, mut run_state: WeakRunStateHandle) {
+    reporter.set_serving::<RuntimeManagementServer>().await;
+
+    run_state.wait_for_pre_shutdown().await;
+
+    reporter.set_not_serving::<RuntimeManagementServer>().await;
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+}
+
+/// Builds the management gRPC service.
+///
+/// Gzip compression is accepted on requests and used for responses if the client announces
+/// support for it. Clients can therefore opt in per call, which is meant for the large streaming
+/// reads (e.g. quota usage and limits). Unary responses are only compressed for the methods
+/// returning large lists (marked with `COMPRESSED()` above), small replies stay uncompressed.
+fn management_server<A: App + Sync>(
+    app: A,
+) -> pm::management_server::ManagementServer<ManagementService<A>> {
+    pm::management_server::ManagementServer::new(ManagementService { app })
+        .accept_compressed(CompressionEncoding::Gzip)
+        .send_compressed(CompressionEncoding::Gzip)
 }
 
 /// Serve gRPC requests on the `grpc_port` extracted from the config
@@ -196,54 +385,47 @@ pub(crate) fn serve(app: RuntimeApp, mut shutdown: RunStateHandle) -> Result<()>
         builder
     };
 
-    let app2 = app.clone();
-    let service = pm::management_server::ManagementServer::with_interceptor(
-        ManagementService { app: app.clone() },
-        move |req: Request<()>| {
-            // If authentication is enabled, require the secret passed with every request
-            if let Some(required_secret) = app2.info.auth_secret {
-                let check = || -> Result<()> {
-                    let Some(request_secret) = req.metadata().get("auth-secret") else {
-                        bail!("Request requires authentication but no secret was provided")
-                    };
-
-                    let request_secret = AuthSecret::try_from_bytes(request_secret.as_bytes())?;
-
-                    if request_secret != required_secret {
-                        bail!("Request requires authentication but provided secret doesn't match",);
-                    }
-
-                    Ok(())
-                };
-
-                if let Err(err) = check() {
-                    return Err(Status::unauthenticated(err.to_string()));
-                }
-            }
+    // If authentication is enabled, require the secret passed with every request (or only with
+    // the mutating ones if unauthenticated reads are allowed)
+    let auth_secret = app.info.auth_secret;
+    let allow_unauthenticated_reads = app.info.user_config.auth_allow_unauthenticated_reads;
+    let service =
+        InterceptedService::new(management_server(app.clone()), move |req: Request<()>| {
+            auth::authenticate(req, auth_secret, allow_unauthenticated_reads)
+        });
 
-            Ok(req)
-        },
-    );
-
-    let serve_addr = SocketAddr::new(
-        if app.info.use_ipv6 {
-            Ipv6Addr::UNSPECIFIED.into()
-        } else {
-            Ipv4Addr::UNSPECIFIED.into()
-        },
+    let serve_addr = select_bind_addr(
+        app.info.user_config.bind_addr,
+        app.info.use_ipv6,
         app.info.user_config.grpc_port,
     );
 
+    // Standard gRPC health checking service (grpc.health.v1.Health), used e.g. by readiness
+    // probes
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(report_health(health_reporter, shutdown.clone_weak()));
+
     log::info!("Serving gRPC requests on {serve_addr}");
 
+    // Blocks the drain phase until the server has finished all outstanding requests
+    let drain = shutdown.clone_drain();
+    let mut drain_signal = shutdown.clone_weak();
+
     tokio::spawn(async move {
-        builder
+        let serve = builder
+            .add_service(health_service)
             .add_service(service)
-            // Provide our shutdown handle to automatically shutdown the server gracefully when
-            // requested
-            .serve_with_shutdown(serve_addr, shutdown.wait_for_shutdown())
-            .await
-            .ok();
+            // Stop accepting new requests when the drain phase starts and finish the
+            // outstanding ones
+            .serve_with_shutdown(serve_addr, drain_signal.wait_for_drain());
+
+        tokio::select! {
+            res = serve => { res.ok(); }
+            // Requests still running on shutdown (after the drain timeout) are cancelled
+            _ = shutdown.wait_for_shutdown() => {}
+        }
+
+        drop(drain);
     });
 
     Ok(())
@@ -258,8 +440,213 @@ fn fail_on_pre_shutdown(app: &impl App) -> Result<()> {
     Ok(())
 }
 
-/// Fails with "Unauthenticated" if the given license feature is not enabled
+/// Fails if the management runs in read-only mode
+fn fail_on_read_only(app: &impl App) -> Result<()> {
+    if app.static_info().user_config.read_only {
+        return Err(anyhow!(TypedError::ReadOnly))
+            .status_code_with_reason(Code::FailedPrecondition, REASON_READ_ONLY);
+    }
+
+    Ok(())
+}
+
+/// Fails with [TypedError::LicenseDenied] and "FailedPrecondition" if the given license feature is
+/// not enabled. The response carries the [REASON_LICENSE_DENIED] error reason, so clients can tell
+/// it apart from other failed preconditions (e.g. read-only mode).
 fn fail_on_missing_license(app: &impl App, feature: LicensedFeature) -> Result<()> {
-    app.verify_licensed_feature(feature)
-        .status_code(Code::Unauthenticated)
+    let name = format!("{feature:?}");
+    let Err(err) = app.verify_licensed_feature(feature) else {
+        return Ok(());
+    };
+
+    Err(anyhow!(TypedError::license_denied(
+        name,
+        format!("{err:#}")
+    )))
+    .status_code_with_reason(Code::FailedPrecondition, REASON_LICENSE_DENIED)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use tokio::net::TcpListener;
+    use tonic::metadata::MetadataValue;
+    use tonic::transport::server::{TcpConnectInfo, TcpIncoming};
+
+    #[test]
+    fn check_auth_secret() {
+        let secret = AuthSecret::hash_from_bytes("secret");
+        let addr: SocketAddr = "192.168.0.10:41000".parse().unwrap();
+
+        let mut req = Request::new(());
+        req.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some(addr),
+        });
+
+        // No secret
+        let status = super::check_auth_secret(&req, secret).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(req.remote_addr(), Some(addr));
+
+        let msg = auth_failure_log_msg(req.remote_addr(), &anyhow!(status.message().to_string()));
+        assert!(msg.contains("192.168.0.10:41000"), "{msg}");
+
+        // Wrong secret
+        req.metadata_mut()
+            .insert("auth-secret", MetadataValue::from_static("12345"));
+        let status = super::check_auth_secret(&req, secret).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        // Correct secret
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("auth-secret", "12345".parse::<MetadataValue<_>>().unwrap());
+        super::check_auth_secret(&req, "12345".parse().unwrap()).unwrap();
+
+        assert_eq!(
+            auth_failure_log_msg(None, &anyhow!("error")),
+            "Authentication of gRPC request from unknown address failed: error"
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_quota_stream() {
+        const ENTRIES: u32 = 20_000;
+
+        let app = TestApp::with_config(Config {
+            quota_enable: true,
+            ..Default::default()
+        })
+        .await;
+        app.write_tx(|tx| {
+            tx.execute(
+                sql!(
+                    "WITH RECURSIVE ids(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM ids WHERE i < ?1)
+                    INSERT INTO quota_usage (quota_id, id_type, quota_type, target_id, value)
+                    SELECT i, 1, 1, 1, i * 1000 FROM ids"
+                ),
+                [ENTRIES],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(management_server(app))
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+
+        let mut client = pm::management_client::ManagementClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap()
+            .accept_compressed(CompressionEncoding::Gzip);
+
+        let resp = client
+            .get_quota_usage(pm::GetQuotaUsageRequest {
+                user_id_min: Some(0),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.metadata()
+                .get("grpc-encoding")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "gzip"
+        );
+
+        let mut stream = resp.into_inner();
+        let mut count = 0;
+        while let Some(msg) = stream.message().await.unwrap() {
+            let entry = msg.entry.unwrap();
+            assert_eq!(
+                entry.space_used,
+                Some(entry.quota_id.unwrap() as i64 * 1000)
+            );
+            count += 1;
+        }
+        assert_eq!(count, ENTRIES);
+
+        // Small unary responses are sent uncompressed even if the client accepts compression
+        client.ping(pm::PingRequest::default()).await.unwrap();
+
+        // Calls that don't ask for compression are answered uncompressed
+        let mut client = pm::management_client::ManagementClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        let resp = client
+            .get_quota_usage(pm::GetQuotaUsageRequest {
+                user_id_min: Some(0),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(resp.metadata().get("grpc-encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn health_check() {
+        use tonic_health::pb::HealthCheckRequest;
+        use tonic_health::pb::health_check_response::ServingStatus;
+        use tonic_health::pb::health_client::HealthClient;
+
+        let (run_state, run_state_control) = shared::run_state::new();
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        let reporter_task = tokio::spawn(report_health(reporter, run_state.clone_weak()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_service)
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+
+        let client = HealthClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        let check = |service: &str| {
+            let mut client = client.clone();
+            let req = HealthCheckRequest {
+                service: service.to_string(),
+            };
+            async move {
+                client
+                    .check(req)
+                    .await
+                    .map(|resp| resp.into_inner().status())
+            }
+        };
+
+        let name = <RuntimeManagementServer as tonic::server::NamedService>::NAME;
+
+        // The reporter task sets the management service to SERVING on start
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !matches!(check(name).await, Ok(ServingStatus::Serving)) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(check("").await.unwrap(), ServingStatus::Serving);
+
+        let err = check("unknown.Service").await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        // Pre shutdown switches the management service and the server as a whole to NOT_SERVING
+        run_state_control.pre_shutdown();
+        reporter_task.await.unwrap();
+
+        assert_eq!(check(name).await.unwrap(), ServingStatus::NotServing);
+        assert_eq!(check("").await.unwrap(), ServingStatus::NotServing);
+    }
 }
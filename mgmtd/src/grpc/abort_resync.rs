@@ -0,0 +1,58 @@
+use super::start_resync::{RESYNC_REQUEST_TIMEOUT, override_last_buddy_comm, resync_targets};
+use super::*;
+use shared::bee_msg::buddy_group::{
+    BuddyResyncJobState, GetStorageResyncStats, GetStorageResyncStatsResp,
+};
+
+/// Aborts a running resync of a storage buddy group.
+///
+/// The last buddy communication timestamp on the source node is reset, so the next resync of the
+/// group will be a full resync.
+pub(crate) async fn abort_resync(
+    app: &impl App,
+    req: pm::AbortResyncRequest,
+) -> Result<pm::AbortResyncResponse> {
+    fail_on_missing_license(app, LicensedFeature::Mirroring)?;
+    fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
+
+    let buddy_group: EntityId = required_field(req.buddy_group)?.try_into()?;
+    let audit = Audit::new("Abort resync");
+
+    let (src_target_id, src_node_uid, group) = app
+        .read_tx(move |tx| {
+            let group = buddy_group.resolve(tx, EntityType::BuddyGroup)?;
+            if group.node_type() != NodeType::Storage {
+                bail!("Resync can only be aborted for storage buddy groups");
+            }
+
+            let (src_target_id, _, src_node_uid) = resync_targets(tx, group.uid)?;
+
+            Ok((src_target_id, src_node_uid, group))
+        })
+        .await?;
+
+    let resp: GetStorageResyncStatsResp = app
+        .request_with_timeout(
+            src_node_uid,
+            &GetStorageResyncStats {
+                target_id: src_target_id,
+            },
+            RESYNC_REQUEST_TIMEOUT,
+        )
+        .await?;
+
+    if resp.state != BuddyResyncJobState::Running {
+        bail!("No resync running on buddy group {group}");
+    }
+
+    override_last_buddy_comm(app, src_node_uid, src_target_id, &group, 0, true).await?;
+
+    let audit_group = group.clone();
+    app.write_tx(move |tx| audit.record(tx, &audit_group))
+        .await?;
+
+    log::info!("Resync aborted on buddy group {group}");
+
+    Ok(pm::AbortResyncResponse {})
+}
@@ -8,13 +8,16 @@ pub(crate) async fn assign_pool(
 ) -> Result<pm::AssignPoolResponse> {
     fail_on_missing_license(app, LicensedFeature::Storagepool)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let pool: EntityId = required_field(req.pool)?.try_into()?;
+    let audit = Audit::new("Assign pool");
 
     let pool = app
         .write_tx(move |tx| {
             let pool = pool.resolve(tx, EntityType::Pool)?;
             do_assign(tx, pool.num_id().try_into()?, req.targets, req.buddy_groups)?;
+            audit.record(tx, &pool)?;
             Ok(pool)
         })
         .await?;
@@ -23,7 +26,9 @@ pub(crate) async fn assign_pool(
 
     app.send_notifications(
         &[NodeType::Meta, NodeType::Storage],
-        &RefreshStoragePools { ack_id: "".into() },
+        &RefreshStoragePools {
+            ack_id: AckId::empty(),
+        },
     )
     .await;
 
@@ -51,16 +56,28 @@ pub(super) fn do_assign(
         "UPDATE targets SET pool_id = ?1 WHERE target_uid = ?2"
     ))?;
 
+    // The pools the targets are moved from and to, for updating the exceeded quota
+    let mut affected_pools = vec![pool_id];
+
+    let target_ids = targets
+        .into_iter()
+        .map(EntityId::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    let resolved_targets = resolve_many(tx, &target_ids, EntityType::Target)?;
+
     // Do the checks and assign for each target in the given list. This is expensive, but shouldn't
     // matter as this command should only be run occasionally and not with very large lists of
     // targets.
-    for t in targets {
-        let eid = EntityId::try_from(t)?;
-        let target = eid.resolve(tx, EntityType::Target)?;
+    for (eid, target) in target_ids.iter().zip(resolved_targets) {
         if check_group_membership.query_row([target.uid], |row| row.get::<_, i64>(0))? > 0 {
             bail!("Target {eid} can't be assigned directly as it's part of a buddy group");
         }
 
+        affected_pools.extend(tx.query_row_cached(
+            sql!("SELECT pool_id FROM targets WHERE target_uid = ?1"),
+            [target.uid],
+            |row| row.get::<_, Option<PoolId>>(0),
+        )?);
         assign_target.execute(params![pool_id, target.uid])?;
     }
 
@@ -80,14 +97,98 @@ pub(super) fn do_assign(
         WHERE target_uid IN (p_uid, s_uid)"
     ))?;
 
-    // Assign each group and their targets to the new pool
-    for g in groups {
-        let eid = EntityId::try_from(g)?;
-        let group = eid.resolve(tx, EntityType::BuddyGroup)?;
+    let group_ids = groups
+        .into_iter()
+        .map(EntityId::try_from)
+        .collect::<Result<Vec<_>>>()?;
 
+    // Assign each group and their targets to the new pool
+    for group in resolve_many(tx, &group_ids, EntityType::BuddyGroup)? {
+        affected_pools.extend(tx.query_row_cached(
+            sql!("SELECT pool_id FROM buddy_groups WHERE group_uid = ?1"),
+            [group.uid],
+            |row| row.get::<_, Option<PoolId>>(0),
+        )?);
         assign_group.execute(params![pool_id, group.uid])?;
         assign_grouped_targets.execute(params![pool_id, group.uid])?;
     }
 
+    affected_pools.sort_unstable();
+    affected_pools.dedup();
+    db::quota::update_exceeded(tx, db::quota::ExceededScope::Pools(&affected_pools))?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn assign_pool() {
+        let app = TestApp::new().await;
+
+        let req = |targets: &[Uid]| pm::AssignPoolRequest {
+            pool: Some(EntityId::Uid(401003).into()),
+            targets: targets.iter().map(|t| EntityId::Uid(*t).into()).collect(),
+            buddy_groups: vec![],
+        };
+
+        // One nonexisting target fails the whole batch
+        let err = super::assign_pool(&app, req(&[202002, 202006, 999999]))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("999999"), "{err:#}");
+
+        // One target being part of a buddy group fails the whole batch, even if the valid targets
+        // have been processed before
+        let err = super::assign_pool(&app, req(&[202002, 202006, 202001]))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("202001"), "{err:#}");
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE target_uid IN (202002, 202006) AND pool_id = 2",
+            [],
+            2
+        );
+        assert!(
+            !app.has_sent_notification::<RefreshStoragePools>(&[NodeType::Meta, NodeType::Storage])
+        );
+
+        // User 20 exceeds its limit on pool 2, which has no effect on pool 3
+        app.write_tx(|tx| db::quota::update_exceeded(tx, db::quota::ExceededScope::All))
+            .await
+            .unwrap();
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_exceeded WHERE quota_id = 20 AND pool_id = 2",
+            [],
+            1
+        );
+
+        // Success
+        super::assign_pool(&app, req(&[202002, 202006]))
+            .await
+            .unwrap();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_exceeded WHERE quota_id = 20",
+            [],
+            0
+        );
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets WHERE target_uid IN (202002, 202006) AND pool_id = 3",
+            [],
+            2
+        );
+        assert!(
+            app.has_sent_notification::<RefreshStoragePools>(&[NodeType::Meta, NodeType::Storage])
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use super::*;
+use std::fmt::Display;
+
+/// Records a mutating gRPC operation in the audit log.
+///
+/// Create it at the beginning of a handler, then call [Audit::record()] within the write
+/// transaction performing the change, so that the entry is only stored if the change is committed.
+#[derive(Debug, Clone)]
+pub(super) struct Audit {
+    operation: &'static str,
+    peer: Option<String>,
+}
+
+impl Audit {
+    /// Prepares an audit log entry for `operation`.
+    ///
+    /// Must be called within the handler task (not within a transaction closure) to pick up the
+    /// requests remote address.
+    pub(super) fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            peer: request_peer().map(|e| e.to_string()),
+        }
+    }
+
+    /// Writes the audit log entry for the operation on `entity`
+    pub(super) fn record(&self, tx: &Transaction, entity: impl Display) -> Result<()> {
+        db::audit_log::insert(
+            tx,
+            self.operation,
+            &entity.to_string(),
+            self.peer.as_deref(),
+        )
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+
+/// The methods that only read the management state.
+///
+/// If `auth-allow-unauthenticated-reads` is set, these can be called without the authentication
+/// secret. Every method not listed here is considered mutating and always requires authentication,
+/// so newly added methods are protected until they are explicitly classified as reading. Methods
+/// exposing sensitive contents, like the logs and the audit log, are not listed either.
+const READ_METHODS: &[&str] = &[
+    "get_nodes",
+    "stream_nodes",
+    "get_targets",
+    "get_flapping_targets",
+    "subscribe_cap_pool_events",
+    "get_pools",
+    "get_buddy_groups",
+    "get_meta_root_status",
+    "get_resync_status",
+    "get_quota_limits",
+    "get_quota_usage",
+    "get_license",
+    "get_server_info",
+    "get_local_nics",
+    "ping",
+    "check_consistency",
+];
+
+/// Whether `method` only reads the management state
+pub(super) fn is_read_method(method: &str) -> bool {
+    READ_METHODS.contains(&method)
+}
+
+/// Authenticates an incoming request. Meant to be called from the services interceptor.
+///
+/// If `allow_unauthenticated_reads` is set, requests without a secret are let through, but
+/// restricted to the [READ_METHODS]. Requests providing a wrong secret are always rejected.
+pub(super) fn authenticate(
+    mut req: Request<()>,
+    required_secret: Option<AuthSecret>,
+    allow_unauthenticated_reads: bool,
+) -> Result<Request<()>, Status> {
+    let Some(required_secret) = required_secret else {
+        return Ok(req);
+    };
+
+    if allow_unauthenticated_reads && req.metadata().get("auth-secret").is_none() {
+        req.extensions_mut().insert(AllowedMethods(is_read_method));
+        return Ok(req);
+    }
+
+    check_auth_secret(&req, required_secret)?;
+    Ok(req)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use tokio::net::TcpListener;
+    use tonic::metadata::MetadataValue;
+    use tonic::transport::server::TcpIncoming;
+
+    #[test]
+    fn classification() {
+        for m in ["get_nodes", "get_quota_usage", "ping"] {
+            assert!(is_read_method(m), "{m}");
+        }
+        for m in [
+            "set_alias",
+            "delete_node",
+            "create_pool",
+            "set_quota_limits",
+            "start_resync",
+            "mirror_root_inode",
+            "get_recent_logs",
+            "get_audit_log",
+            "unknown_method",
+        ] {
+            assert!(!is_read_method(m), "{m}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_reads() {
+        let secret: AuthSecret = "12345".parse().unwrap();
+
+        let start = async |allow_unauthenticated_reads| {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let service = InterceptedService::new(
+                management_server(TestApp::new().await),
+                move |req: Request<()>| {
+                    authenticate(req, Some(secret), allow_unauthenticated_reads)
+                },
+            );
+            tokio::spawn(
+                Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming(TcpIncoming::from(listener)),
+            );
+
+            pm::management_client::ManagementClient::connect(format!("http://{addr}"))
+                .await
+                .unwrap()
+        };
+
+        let set_alias = |secret: Option<&'static str>| {
+            let mut req = Request::new(pm::SetAliasRequest {
+                entity_id: Some(EntityId::Uid(101001).into()),
+                entity_type: pb::EntityType::Node.into(),
+                new_alias: "new_alias".to_string(),
+            });
+            if let Some(secret) = secret {
+                req.metadata_mut()
+                    .insert("auth-secret", MetadataValue::from_static(secret));
+            }
+            req
+        };
+
+        let mut client = start(true).await;
+
+        // Reading without secret is allowed
+        client
+            .ping(pm::PingRequest { nonce: Some(1) })
+            .await
+            .unwrap();
+
+        // Mutating without secret is not
+        let status = client.set_alias(set_alias(None)).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        // A wrong secret is always rejected
+        let status = client
+            .set_alias(set_alias(Some("wrong")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        client.set_alias(set_alias(Some("12345"))).await.unwrap();
+
+        // Without the option, reading requires the secret as well
+        let mut client = start(false).await;
+        let status = client
+            .ping(pm::PingRequest { nonce: Some(1) })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
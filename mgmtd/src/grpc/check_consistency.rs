@@ -0,0 +1,50 @@
+use super::*;
+use db::consistency::Severity;
+
+/// Runs the read-only integrity checks on the management database and reports the findings
+pub(crate) async fn check_consistency(
+    app: &impl App,
+    _req: pm::CheckConsistencyRequest,
+) -> Result<pm::CheckConsistencyResponse> {
+    let findings = app.read_tx(db::consistency::check).await?;
+
+    let findings = findings
+        .into_iter()
+        .map(|f| pm::check_consistency_response::Finding {
+            severity: match f.severity {
+                Severity::Warning => pm::check_consistency_response::Severity::Warning,
+                Severity::Error => pm::check_consistency_response::Severity::Error,
+            }
+            .into(),
+            description: f.description,
+        })
+        .collect();
+
+    Ok(pm::CheckConsistencyResponse { findings })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn check_consistency() {
+        let app = TestApp::new().await;
+
+        let resp = super::check_consistency(&app, pm::CheckConsistencyRequest {})
+            .await
+            .unwrap();
+
+        assert_eq!(resp.findings.len(), 1);
+        assert_eq!(
+            resp.findings[0].severity(),
+            pm::check_consistency_response::Severity::Warning
+        );
+        assert!(
+            resp.findings[0]
+                .description
+                .contains("storage_target_unmapped")
+        );
+    }
+}
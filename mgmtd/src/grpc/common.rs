@@ -11,3 +11,22 @@ pub(super) const QUOTA_STREAM_PAGE_LIMIT: usize = 1_000_000;
 // 60 bytes, so 100k (= 5-6MB) feels fine. And it is still big enough to give a significant
 // boost to throughput for big numbers.
 pub(super) const QUOTA_STREAM_BUF_SIZE: usize = 100_000;
+
+// Node entries are small and node lists rarely exceed a few thousand entries, so comparably
+// small pages are sufficient to keep the memory footprint of a single request low.
+pub(super) const NODES_STREAM_PAGE_LIMIT: usize = 1000;
+pub(super) const NODES_STREAM_BUF_SIZE: usize = 1000;
+
+// The number of kept log records is bounded by the configuration and usually small.
+pub(super) const RECENT_LOGS_STREAM_BUF_SIZE: usize = 1000;
+
+// The number of records per message if the compact encoding is requested for the recent log or
+// audit log streams. Records are usually below 200 bytes, keeping messages well below the gRPC
+// message size limit.
+pub(super) const COMPACT_RECORDS_BATCH_SIZE: usize = 1000;
+
+// The number of captured messages per message capture response. With the data included, each one
+// is up to about 3 KiB hex encoded, keeping messages below 1 MiB and thus well below the gRPC
+// message size limit.
+pub(super) const MSG_CAPTURE_BATCH_SIZE: usize = 256;
+pub(super) const MSG_CAPTURE_STREAM_BUF_SIZE: usize = 16;
@@ -2,58 +2,99 @@ use super::*;
 use shared::bee_msg::buddy_group::SetMirrorBuddyGroup;
 use shared::bee_msg::storage_pool::RefreshStoragePools;
 
-/// Creates a new buddy group
+/// Creates a new buddy group.
+///
+/// If `check_only` is set, only validates that the buddy group could be created and does not
+/// modify the database or notify nodes.
 pub(crate) async fn create_buddy_group(
     app: &impl App,
     req: pm::CreateBuddyGroupRequest,
 ) -> Result<pm::CreateBuddyGroupResponse> {
     fail_on_missing_license(app, LicensedFeature::Mirroring)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let node_type: NodeTypeServer = req.node_type().try_into()?;
     let alias: Alias = required_field(req.alias)?.try_into()?;
     let num_id: BuddyGroupId = req.num_id.unwrap_or_default().try_into()?;
     let p_target: EntityId = required_field(req.primary_target)?.try_into()?;
     let s_target: EntityId = required_field(req.secondary_target)?.try_into()?;
+    let check_only = req.check_only.unwrap_or_default();
+    let audit = Audit::new("Create buddy group");
 
     let (group, p_target, s_target) = app
-        .write_tx(move |tx| {
-            let p_target = p_target.resolve(tx, EntityType::Target)?;
-            let s_target = s_target.resolve(tx, EntityType::Target)?;
+        .db_conn(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let p_target = p_target.resolve(&tx, EntityType::Target)?;
+            let s_target = s_target.resolve(&tx, EntityType::Target)?;
 
             let (group_uid, group_id) = db::buddy_group::insert(
-                tx,
+                &tx,
                 num_id,
                 Some(alias.clone()),
                 node_type,
                 p_target.num_id().try_into()?,
                 s_target.num_id().try_into()?,
             )?;
-            Ok((
-                EntityIdSet {
-                    uid: group_uid,
-                    alias,
-                    legacy_id: LegacyId {
-                        node_type: node_type.into(),
-                        num_id: group_id.into(),
-                    },
+
+            let group = EntityIdSet {
+                uid: group_uid,
+                alias,
+                legacy_id: LegacyId {
+                    node_type: node_type.into(),
+                    num_id: group_id.into(),
                 },
-                p_target,
-                s_target,
-            ))
+            };
+
+            // In check only mode, the transaction is dropped and thus rolled back
+            if !check_only {
+                audit.record(&tx, &group)?;
+                tx.commit()?;
+            }
+
+            Ok((group, p_target, s_target))
         })
         .await?;
 
+    if check_only {
+        return Ok(pm::CreateBuddyGroupResponse {
+            group: Some(group.into()),
+        });
+    }
+
     log::info!("Buddy group created: {group}");
 
+    notify_created(
+        app,
+        node_type,
+        p_target.num_id().try_into()?,
+        s_target.num_id().try_into()?,
+        group.num_id().try_into()?,
+    )
+    .await;
+
+    Ok(pm::CreateBuddyGroupResponse {
+        group: Some(group.into()),
+    })
+}
+
+/// Notifies the nodes about a newly created buddy group
+pub(super) async fn notify_created(
+    app: &impl App,
+    node_type: NodeTypeServer,
+    p_target_id: TargetId,
+    s_target_id: TargetId,
+    group_id: BuddyGroupId,
+) {
     app.send_notifications(
         &[NodeType::Meta, NodeType::Storage, NodeType::Client],
         &SetMirrorBuddyGroup {
-            ack_id: "".into(),
+            ack_id: AckId::empty(),
             node_type: node_type.into(),
-            primary_target_id: p_target.num_id().try_into()?,
-            secondary_target_id: s_target.num_id().try_into()?,
-            group_id: group.num_id().try_into()?,
+            primary_target_id: p_target_id,
+            secondary_target_id: s_target_id,
+            group_id,
             allow_update: 0,
         },
     )
@@ -63,12 +104,201 @@ pub(crate) async fn create_buddy_group(
     if node_type == NodeTypeServer::Storage {
         app.send_notifications(
             &[NodeType::Meta, NodeType::Storage],
-            &RefreshStoragePools { ack_id: "".into() },
+            &RefreshStoragePools {
+                ack_id: AckId::empty(),
+            },
         )
         .await;
     }
+}
 
-    Ok(pm::CreateBuddyGroupResponse {
-        group: Some(group.into()),
-    })
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use crate::grpc::get_nodes::get_nodes;
+
+    #[tokio::test]
+    async fn create_buddy_group() {
+        let app = TestApp::new().await;
+
+        let res = super::create_buddy_group(
+            &app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Storage.into(),
+                alias: Some("new_group".to_string()),
+                num_id: Some(10),
+                primary_target: Some(EntityId::Uid(202002).into()),
+                secondary_target: Some(EntityId::Uid(202006).into()),
+                check_only: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.group.unwrap().alias.unwrap(), "new_group");
+        assert!(app.has_sent_notification::<SetMirrorBuddyGroup>(&[
+            NodeType::Meta,
+            NodeType::Storage,
+            NodeType::Client
+        ]));
+
+        // The operation must have been recorded in the audit log
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log
+            WHERE operation = 'Create buddy group' AND entity LIKE 'new_group%'",
+            [],
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn create_buddy_group_check_only() {
+        let app = TestApp::new().await;
+
+        // Targets in different storage pools
+        let err = super::create_buddy_group(
+            &app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Storage.into(),
+                alias: Some("new_group".to_string()),
+                num_id: Some(10),
+                primary_target: Some(EntityId::Uid(202002).into()),
+                secondary_target: Some(EntityId::Uid(202007).into()),
+                check_only: Some(true),
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Primary and secondary target are not assigned to the same storage pool"
+        );
+
+        // Valid pairing
+        let res = super::create_buddy_group(
+            &app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Storage.into(),
+                alias: Some("new_group".to_string()),
+                num_id: Some(10),
+                primary_target: Some(EntityId::Uid(202002).into()),
+                secondary_target: Some(EntityId::Uid(202006).into()),
+                check_only: Some(true),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.group.unwrap().alias.unwrap(), "new_group");
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 0);
+
+        // Nothing must have been created
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups WHERE group_id = 10",
+            [],
+            0
+        );
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log WHERE operation = 'Create buddy group'",
+            [],
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn create_buddy_group_read_only() {
+        let app = TestApp::with_config(Config {
+            read_only: true,
+            ..Default::default()
+        })
+        .await;
+
+        // Reading still works
+        get_nodes(
+            &app,
+            pm::GetNodesRequest {
+                include_nics: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let err = super::create_buddy_group(
+            &app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Storage.into(),
+                alias: Some("new_group".to_string()),
+                num_id: Some(10),
+                primary_target: Some(EntityId::Uid(202002).into()),
+                secondary_target: Some(EntityId::Uid(202006).into()),
+                check_only: None,
+            },
+        )
+        .await
+        .unwrap_err();
+
+        let status = process_grpc_handler_error(err);
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(status.message(), "Management is in read-only mode");
+        assert_eq!(
+            status.metadata().get(ERROR_REASON_KEY).unwrap(),
+            REASON_READ_ONLY
+        );
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 0);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups WHERE group_id = 10",
+            [],
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn create_buddy_group_license_denied() {
+        let app = TestApp::new().await;
+        app.deny_licensed_features();
+
+        let err = super::create_buddy_group(
+            &app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Storage.into(),
+                alias: Some("new_group".to_string()),
+                num_id: Some(10),
+                primary_target: Some(EntityId::Uid(202002).into()),
+                secondary_target: Some(EntityId::Uid(202006).into()),
+                check_only: None,
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            err.chain().find_map(|e| e.downcast_ref::<TypedError>()),
+            Some(TypedError::LicenseDenied { .. })
+        ));
+
+        let status = process_grpc_handler_error(err);
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.metadata().get(ERROR_REASON_KEY).unwrap(),
+            REASON_LICENSE_DENIED
+        );
+        assert!(
+            status
+                .message()
+                .starts_with("Feature Mirroring requires a license")
+        );
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups WHERE group_id = 10",
+            [],
+            0
+        );
+    }
 }
@@ -9,6 +9,7 @@ pub(crate) async fn create_pool(
 ) -> Result<pm::CreatePoolResponse> {
     fail_on_missing_license(app, LicensedFeature::Storagepool)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     if req.node_type() != pb::NodeType::Storage {
         bail!("node type must be storage");
@@ -16,11 +17,13 @@ pub(crate) async fn create_pool(
 
     let alias: Alias = required_field(req.alias)?.try_into()?;
     let num_id: PoolId = req.num_id.unwrap_or_default().try_into()?;
+    let audit = Audit::new("Create pool");
 
     let (pool_uid, alias, pool_id) = app
         .write_tx(move |tx| {
             let (pool_uid, pool_id) = db::storage_pool::insert(tx, num_id, &alias)?;
             do_assign(tx, pool_id, req.targets, req.buddy_groups)?;
+            audit.record(tx, format!("{alias} (storage:{pool_id})"))?;
             Ok((pool_uid, alias, pool_id))
         })
         .await?;
@@ -38,7 +41,9 @@ pub(crate) async fn create_pool(
 
     app.send_notifications(
         &[NodeType::Meta, NodeType::Storage],
-        &RefreshStoragePools { ack_id: "".into() },
+        &RefreshStoragePools {
+            ack_id: AckId::empty(),
+        },
     )
     .await;
 
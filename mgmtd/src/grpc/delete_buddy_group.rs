@@ -11,9 +11,11 @@ pub(crate) async fn delete_buddy_group(
 ) -> Result<pm::DeleteBuddyGroupResponse> {
     fail_on_missing_license(app, LicensedFeature::Mirroring)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let group: EntityId = required_field(req.group)?.try_into()?;
     let execute: bool = required_field(req.execute)?;
+    let audit = Audit::new("Delete buddy group");
 
     // 1. Check deletion is allowed
     let (group, p_node_uid, s_node_uid) = app
@@ -58,12 +60,14 @@ Primary result: {:?}, Secondary result: {:?}",
     }
 
     // 3. If the deletion request succeeded, remove the group from the database
+    let audit_group = group.clone();
     app.db_conn(move |conn| {
         let tx = conn.transaction()?;
 
         db::buddy_group::delete_storage(&tx, group_id)?;
 
         if execute {
+            audit.record(&tx, &audit_group)?;
             tx.commit()?;
         }
         Ok(())
@@ -76,7 +80,9 @@ Primary result: {:?}, Secondary result: {:?}",
         // Storage buddy groups alter pool membership, so trigger an immediate pool refresh
         app.send_notifications(
             &[NodeType::Meta, NodeType::Storage],
-            &RefreshStoragePools { ack_id: "".into() },
+            &RefreshStoragePools {
+                ack_id: AckId::empty(),
+            },
         )
         .await;
     }
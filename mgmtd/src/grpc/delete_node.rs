@@ -7,9 +7,11 @@ pub(crate) async fn delete_node(
     req: pm::DeleteNodeRequest,
 ) -> Result<pm::DeleteNodeResponse> {
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let node: EntityId = required_field(req.node)?.try_into()?;
     let execute: bool = required_field(req.execute)?;
+    let audit = Audit::new("Delete node");
 
     let node = app
         .db_conn(move |conn| {
@@ -66,6 +68,7 @@ pub(crate) async fn delete_node(
             db::node::delete(&tx, node.uid)?;
 
             if execute {
+                audit.record(&tx, &node)?;
                 tx.commit()?;
             }
             Ok(node)
@@ -84,7 +87,7 @@ pub(crate) async fn delete_node(
             &RemoveNode {
                 node_type: node.node_type(),
                 node_id: node.num_id(),
-                ack_id: "".into(),
+                ack_id: AckId::empty(),
             },
         )
         .await;
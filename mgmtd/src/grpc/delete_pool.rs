@@ -8,9 +8,11 @@ pub(crate) async fn delete_pool(
 ) -> Result<pm::DeletePoolResponse> {
     fail_on_missing_license(app, LicensedFeature::Storagepool)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let pool: EntityId = required_field(req.pool)?.try_into()?;
     let execute: bool = required_field(req.execute)?;
+    let audit = Audit::new("Delete pool");
 
     let pool = app
         .db_conn(move |conn| {
@@ -41,6 +43,7 @@ are still assigned to this pool"
             check_affected_rows(affected, [1])?;
 
             if execute {
+                audit.record(&tx, &pool)?;
                 tx.commit()?;
             }
             Ok(pool)
@@ -52,7 +55,9 @@ are still assigned to this pool"
 
         app.send_notifications(
             &[NodeType::Meta, NodeType::Storage],
-            &RefreshStoragePools { ack_id: "".into() },
+            &RefreshStoragePools {
+                ack_id: AckId::empty(),
+            },
         )
         .await;
     }
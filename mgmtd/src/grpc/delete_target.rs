@@ -8,9 +8,11 @@ pub(crate) async fn delete_target(
     req: pm::DeleteTargetRequest,
 ) -> Result<pm::DeleteTargetResponse> {
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let target: EntityId = required_field(req.target)?.try_into()?;
     let execute: bool = required_field(req.execute)?;
+    let audit = Audit::new("Delete target");
 
     let target = app
         .db_conn(move |conn| {
@@ -38,6 +40,7 @@ pub(crate) async fn delete_target(
             db::target::delete_storage(&tx, target.num_id().try_into()?)?;
 
             if execute {
+                audit.record(&tx, &target)?;
                 tx.commit()?;
             }
             Ok(target)
@@ -49,14 +52,18 @@ pub(crate) async fn delete_target(
 
         app.send_notifications(
             &[NodeType::Meta],
-            &RefreshCapacityPools { ack_id: "".into() },
+            &RefreshCapacityPools {
+                ack_id: AckId::empty(),
+            },
         )
         .await;
 
         // Storage targets deletion alter pool membership, so trigger an immediate pool refresh
         app.send_notifications(
             &[NodeType::Meta, NodeType::Storage],
-            &RefreshStoragePools { ack_id: "".into() },
+            &RefreshStoragePools {
+                ack_id: AckId::empty(),
+            },
         )
         .await;
     }
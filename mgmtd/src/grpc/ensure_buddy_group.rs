@@ -0,0 +1,208 @@
+use super::*;
+use create_buddy_group::notify_created;
+use itertools::Itertools;
+
+/// Makes sure the requested buddy group exists.
+///
+/// If a group consisting of the requested targets already exists and also matches the requested
+/// alias and numeric id (if given), it is returned without any changes. The order of primary and
+/// secondary doesn't matter since they are swapped on switchover. If no group overlaps with the
+/// request, a new one is created like `create_buddy_group` does. Any other overlap (e.g. the id or
+/// one of the targets being used by a different group) is a conflict and fails with
+/// `AlreadyExists`.
+pub(crate) async fn ensure_buddy_group(
+    app: &impl App,
+    req: pm::EnsureBuddyGroupRequest,
+) -> Result<pm::EnsureBuddyGroupResponse> {
+    fail_on_missing_license(app, LicensedFeature::Mirroring)?;
+    fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
+
+    let node_type: NodeTypeServer = req.node_type().try_into()?;
+    let alias: Alias = required_field(req.alias)?.try_into()?;
+    let num_id: BuddyGroupId = req.num_id.unwrap_or_default().try_into()?;
+    let p_target: EntityId = required_field(req.primary_target)?.try_into()?;
+    let s_target: EntityId = required_field(req.secondary_target)?.try_into()?;
+    let audit = Audit::new("Create buddy group");
+
+    let (group, created, p_target_id, s_target_id) = app
+        .write_tx(move |tx| {
+            let p_target_id: TargetId = p_target
+                .resolve(tx, EntityType::Target)?
+                .num_id()
+                .try_into()?;
+            let s_target_id: TargetId = s_target
+                .resolve(tx, EntityType::Target)?
+                .num_id()
+                .try_into()?;
+
+            let overlapping = db::buddy_group::get_overlapping(
+                tx,
+                node_type,
+                num_id,
+                alias.as_ref(),
+                p_target_id,
+                s_target_id,
+            )?;
+
+            let (group_uid, group_id, created) = match overlapping.as_slice() {
+                [] => {
+                    let (uid, id) = db::buddy_group::insert(
+                        tx,
+                        num_id,
+                        Some(alias.clone()),
+                        node_type,
+                        p_target_id,
+                        s_target_id,
+                    )?;
+                    (uid, id, true)
+                }
+                [g] if g.has_members(p_target_id, s_target_id)
+                    && g.alias == alias.as_ref()
+                    && (num_id == 0 || g.id == num_id) =>
+                {
+                    (g.uid, g.id, false)
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Requested buddy group {alias} (id {}, targets {p_target_id} and \
+                        {s_target_id}) conflicts with existing buddy group(s): {}",
+                        if num_id == 0 {
+                            "any".to_string()
+                        } else {
+                            num_id.to_string()
+                        },
+                        overlapping.iter().join(", ")
+                    ))
+                    .status_code(Code::AlreadyExists);
+                }
+            };
+
+            let group = EntityIdSet {
+                uid: group_uid,
+                alias,
+                legacy_id: LegacyId {
+                    node_type: node_type.into(),
+                    num_id: group_id.into(),
+                },
+            };
+
+            if created {
+                audit.record(tx, &group)?;
+            }
+
+            Ok((group, created, p_target_id, s_target_id))
+        })
+        .await?;
+
+    if created {
+        log::info!("Buddy group created: {group}");
+
+        notify_created(
+            app,
+            node_type,
+            p_target_id,
+            s_target_id,
+            group.num_id().try_into()?,
+        )
+        .await;
+    } else {
+        log::debug!("Buddy group already exists: {group}");
+    }
+
+    Ok(pm::EnsureBuddyGroupResponse {
+        group: Some(group.into()),
+        created,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::buddy_group::SetMirrorBuddyGroup;
+
+    fn req(alias: &str, num_id: Option<u32>, p: Uid, s: Uid) -> pm::EnsureBuddyGroupRequest {
+        pm::EnsureBuddyGroupRequest {
+            node_type: pb::NodeType::Storage.into(),
+            alias: Some(alias.to_string()),
+            num_id,
+            primary_target: Some(EntityId::Uid(p).into()),
+            secondary_target: Some(EntityId::Uid(s).into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_buddy_group() {
+        let app = TestApp::new().await;
+
+        // Create
+        let res = super::ensure_buddy_group(&app, req("new_group", Some(10), 202002, 202006))
+            .await
+            .unwrap();
+        assert!(res.created);
+        assert_eq!(res.group.unwrap().alias.unwrap(), "new_group");
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 1);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups
+            WHERE group_id = 10 AND node_type = 2 AND p_target_id = 2 AND s_target_id = 6",
+            [],
+            1
+        );
+
+        // Idempotent re-runs, with and without id and with swapped targets
+        for r in [
+            req("new_group", Some(10), 202002, 202006),
+            req("new_group", None, 202002, 202006),
+            req("new_group", Some(10), 202006, 202002),
+        ] {
+            let res = super::ensure_buddy_group(&app, r).await.unwrap();
+            assert!(!res.created);
+            let group = res.group.unwrap();
+            assert_eq!(group.alias.unwrap(), "new_group");
+            assert_eq!(group.legacy_id.unwrap().num_id, 10);
+        }
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 1);
+
+        // An existing group from the test data matches as well
+        let res =
+            super::ensure_buddy_group(&app, req("storage_buddy_group_1", None, 202005, 202001))
+                .await
+                .unwrap();
+        assert!(!res.created);
+
+        // Conflicts
+        for r in [
+            // Same id, different members
+            req("new_group", Some(10), 202002, 202007),
+            // Same id and members, different alias
+            req("other_group", Some(10), 202002, 202006),
+            // Same members and alias, different id
+            req("new_group", Some(11), 202002, 202006),
+            // Alias used by another group
+            req("storage_buddy_group_2", None, 202003, 202004),
+            // One of the targets is used by another group
+            req("other_group", None, 202002, 202004),
+        ] {
+            let err = super::ensure_buddy_group(&app, r).await.unwrap_err();
+            let msg = format!("{err:#}");
+            assert!(msg.contains("conflicts with existing buddy group"), "{msg}");
+            assert_eq!(process_grpc_handler_error(err).code(), Code::AlreadyExists);
+        }
+
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 1);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups WHERE node_type = 2",
+            [],
+            3
+        );
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log WHERE operation = 'Create buddy group'",
+            [],
+            1
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use super::*;
+use shared::bee_msg::OpsErr;
+use shared::bee_msg::buddy_group::{RemoveBuddyGroup, RemoveBuddyGroupResp};
+use shared::bee_msg::misc::RefreshCapacityPools;
+use shared::bee_msg::storage_pool::RefreshStoragePools;
+
+/// Removes everything from a storage node that prevents its deletion: Deletes the buddy groups its
+/// targets are part of and then deletes the targets themselves.
+///
+/// If `execute` is not set, only checks and reports what would be removed. Like deleting buddy
+/// groups, this is racy as it talks to other nodes in between, which is fine for an operation
+/// that is rarely used.
+pub(crate) async fn evacuate_node(
+    app: &impl App,
+    req: pm::EvacuateNodeRequest,
+) -> Result<pm::EvacuateNodeResponse> {
+    fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
+
+    let node: EntityId = required_field(req.node)?.try_into()?;
+    let execute: bool = required_field(req.execute)?;
+    let audit = Audit::new("Evacuate node");
+
+    // 1. Collect the affected buddy groups and targets and check they can be removed
+    let (node, groups, targets) = app
+        .read_tx(move |tx| {
+            let node = node.resolve(tx, EntityType::Node)?;
+
+            if node.node_type() != NodeType::Storage {
+                bail!("Only storage nodes can be evacuated");
+            }
+
+            db::node::ensure_no_clients(tx, "evacuate a node")?;
+
+            let storage_id_set = |uid, alias: String, num_id| -> Result<EntityIdSet> {
+                Ok(EntityIdSet {
+                    uid,
+                    alias: alias.try_into()?,
+                    legacy_id: LegacyId {
+                        node_type: NodeType::Storage,
+                        num_id,
+                    },
+                })
+            };
+
+            let groups: Vec<(Uid, String, u32, Uid, Uid)> = tx.query_map_collect(
+                sql!(
+                    "SELECT g.group_uid, g.alias, g.group_id, p_t.node_uid, s_t.node_uid
+                    FROM buddy_groups_ext AS g
+                    INNER JOIN targets_ext AS p_t ON p_t.target_uid = g.p_target_uid
+                    INNER JOIN targets_ext AS s_t ON s_t.target_uid = g.s_target_uid
+                    WHERE g.node_type = ?1 AND (p_t.node_uid = ?2 OR s_t.node_uid = ?2)
+                    ORDER BY g.group_id"
+                ),
+                params![NodeType::Storage.sql_variant(), node.uid],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )?;
+            let groups = groups
+                .into_iter()
+                .map(|(uid, alias, num_id, p_node_uid, s_node_uid)| {
+                    Ok((storage_id_set(uid, alias, num_id)?, p_node_uid, s_node_uid))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for (group, _, _) in &groups {
+                db::buddy_group::prepare_storage_deletion(tx, group.num_id().try_into()?)?;
+            }
+
+            let targets: Vec<(Uid, String, u32)> = tx.query_map_collect(
+                sql!(
+                    "SELECT target_uid, alias, target_id FROM targets_ext
+                    WHERE node_uid = ?1
+                    ORDER BY target_id"
+                ),
+                [node.uid],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            let targets = targets
+                .into_iter()
+                .map(|(uid, alias, num_id)| storage_id_set(uid, alias, num_id))
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok((node, groups, targets))
+        })
+        .await?;
+
+    if !groups.is_empty() {
+        fail_on_missing_license(app, LicensedFeature::Mirroring)?;
+    }
+
+    let resp = pm::EvacuateNodeResponse {
+        node: Some(node.clone().into()),
+        buddy_groups: groups.iter().map(|g| g.0.clone().into()).collect(),
+        targets: targets.iter().cloned().map(Into::into).collect(),
+    };
+
+    if !execute {
+        return Ok(resp);
+    }
+
+    // 2. Delete the buddy groups on the storage nodes and in the database, one by one
+    for (group, p_node_uid, s_node_uid) in groups {
+        let group_id: BuddyGroupId = group.num_id().try_into()?;
+        let remove_bee_msg = RemoveBuddyGroup {
+            node_type: NodeType::Storage,
+            group_id,
+            check_only: 0,
+            force: 0,
+        };
+
+        let p_res: RemoveBuddyGroupResp = app.request(p_node_uid, &remove_bee_msg).await?;
+        let s_res: RemoveBuddyGroupResp = app.request(s_node_uid, &remove_bee_msg).await?;
+
+        if p_res.result != OpsErr::SUCCESS || s_res.result != OpsErr::SUCCESS {
+            bail!(
+                "Removing storage buddy group {group} on primary and/or secondary storage node \
+failed. Primary result: {:?}, Secondary result: {:?}",
+                p_res.result,
+                s_res.result
+            );
+        }
+
+        let audit = audit.clone();
+        app.write_tx(move |tx| {
+            db::buddy_group::delete_storage(tx, group_id)?;
+            audit.record(tx, &group)
+        })
+        .await?;
+
+        log::info!("Buddy group deleted: {group}");
+    }
+
+    // 3. Delete the targets
+    app.write_tx(move |tx| {
+        for target in targets {
+            db::target::delete_storage(tx, target.num_id().try_into()?)?;
+            audit.record(tx, &target)?;
+        }
+        Ok(())
+    })
+    .await?;
+
+    log::info!("Node evacuated: {node}");
+
+    app.send_notifications(
+        &[NodeType::Meta],
+        &RefreshCapacityPools {
+            ack_id: AckId::empty(),
+        },
+    )
+    .await;
+
+    // Removing buddy groups and targets alters pool membership, so trigger an immediate refresh
+    app.send_notifications(
+        &[NodeType::Meta, NodeType::Storage],
+        &RefreshStoragePools {
+            ack_id: AckId::empty(),
+        },
+    )
+    .await;
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn req(execute: bool) -> pm::EvacuateNodeRequest {
+        pm::EvacuateNodeRequest {
+            node: Some(EntityId::Uid(102001).into()),
+            execute: Some(execute),
+        }
+    }
+
+    #[tokio::test]
+    async fn evacuate_node() {
+        let app = TestApp::new().await;
+
+        let removed_groups = Arc::new(AtomicUsize::new(0));
+        let removed_groups2 = removed_groups.clone();
+        app.set_request_handler(move |req| {
+            assert_eq!(
+                req.downcast_ref::<RemoveBuddyGroup>().unwrap().check_only,
+                0
+            );
+            removed_groups2.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(RemoveBuddyGroupResp {
+                result: OpsErr::SUCCESS,
+            }))
+        });
+
+        // Refuses while clients are mounted
+        super::evacuate_node(&app, req(false)).await.unwrap_err();
+
+        app.write_tx(|tx| {
+            tx.execute(
+                sql!("DELETE FROM nodes WHERE node_type = ?1"),
+                [NodeType::Client.sql_variant()],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        // Check only reports the group and the targets of storage node 1, but doesn't change
+        // anything
+        let resp = super::evacuate_node(&app, req(false)).await.unwrap();
+        let uids = |ids: &[pb::EntityIdSet]| -> Vec<i64> { ids.iter().map(|e| e.uid()).collect() };
+        assert_eq!(uids(&resp.buddy_groups), [302001]);
+        assert_eq!(uids(&resp.targets), [202001, 202002, 202003, 202004]);
+        assert_eq!(removed_groups.load(Ordering::SeqCst), 0);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets_ext WHERE node_uid = ?1",
+            [102001],
+            4
+        );
+        assert!(
+            !app.has_sent_notification::<RefreshStoragePools>(&[NodeType::Meta, NodeType::Storage])
+        );
+
+        // Execute removes the group on both nodes and then the targets from the database
+        super::evacuate_node(&app, req(true)).await.unwrap();
+        assert_eq!(removed_groups.load(Ordering::SeqCst), 2);
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM targets_ext WHERE node_uid = ?1",
+            [102001],
+            0
+        );
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM buddy_groups WHERE group_uid = ?1",
+            [302001],
+            0
+        );
+        assert!(
+            app.has_sent_notification::<RefreshStoragePools>(&[NodeType::Meta, NodeType::Storage])
+        );
+
+        // Nothing left to evacuate
+        let resp = super::evacuate_node(&app, req(true)).await.unwrap();
+        assert!(resp.buddy_groups.is_empty());
+        assert!(resp.targets.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use super::common::COMPACT_RECORDS_BATCH_SIZE;
+use super::*;
+use shared::compact_records::{AuditRecord, encode_batch};
+
+const PAGE_LIMIT: usize = 10_000;
+const BUF_SIZE: usize = 10_000;
+
+/// Delivers the audit log entries within the requested time range as a stream
+///
+/// If `compact` is set, the entries are delivered in batches using the BeeSerde encoding from
+/// [shared::compact_records] instead of one message per entry.
+pub(crate) async fn get_audit_log(
+    app: &impl App,
+    req: pm::GetAuditLogRequest,
+) -> Result<RespStream<pm::GetAuditLogResponse>> {
+    let from = req.from_secs.unwrap_or(0);
+    let to = req.to_secs.unwrap_or(i64::MAX);
+    let compact = req.compact;
+
+    if from > to {
+        bail!("Invalid time range: from ({from}) is later than to ({to})");
+    }
+
+    let app = app.clone();
+    let stream = resp_stream(BUF_SIZE, async move |stream| {
+        let mut offset = 0;
+
+        loop {
+            let entries = app
+                .read_tx(move |tx| db::audit_log::get_range(tx, from, to, offset, PAGE_LIMIT))
+                .await?;
+
+            let len = entries.len();
+
+            if compact {
+                let records: Vec<_> = entries
+                    .into_iter()
+                    .map(|e| AuditRecord {
+                        time_secs: e.time,
+                        operation: e.operation,
+                        entity: e.entity,
+                        peer: e.peer,
+                    })
+                    .collect();
+
+                for batch in records.chunks(COMPACT_RECORDS_BATCH_SIZE) {
+                    stream
+                        .send(pm::GetAuditLogResponse {
+                            entry: None,
+                            compact_records: encode_batch(batch)?,
+                        })
+                        .await?;
+                }
+            } else {
+                for e in entries {
+                    stream
+                        .send(pm::GetAuditLogResponse {
+                            entry: Some(pm::AuditLogEntry {
+                                time_secs: e.time,
+                                operation: e.operation,
+                                entity: e.entity,
+                                peer: e.peer,
+                            }),
+                            compact_records: vec![],
+                        })
+                        .await?;
+                }
+            }
+
+            // This was the last page? Then we are done
+            if len < PAGE_LIMIT {
+                return Ok(());
+            }
+
+            offset += PAGE_LIMIT;
+        }
+    });
+
+    Ok(stream)
+}
@@ -1,31 +1,63 @@
+use super::get_resync_status::{fetch_resync_stats, resync_progress};
 use super::*;
+use tokio::task::JoinSet;
 
-/// Delivers the list of buddy groups
+/// Delivers the list of buddy groups.
+///
+/// The result can optionally be filtered by node type and storage pool and paged using `limit`
+/// and `offset`. Groups are ordered by node type and numeric id.
+///
+/// If `include_resync` is set, the resync state and progress of all groups with a secondary
+/// needing a resync are requested concurrently from their source (primary) nodes. If a source
+/// can not be reached, the state is reported as unspecified (unknown).
 pub(crate) async fn get_buddy_groups(
     app: &impl App,
-    _req: pm::GetBuddyGroupsRequest,
+    req: pm::GetBuddyGroupsRequest,
 ) -> Result<pm::GetBuddyGroupsResponse> {
-    let buddy_groups = app
-        .read_tx(|tx| {
+    let node_type: Option<NodeTypeServer> = match req.node_type {
+        Some(_) => Some(req.node_type().try_into()?),
+        None => None,
+    };
+    let pool: Option<EntityId> = req.pool.map(TryInto::try_into).transpose()?;
+    // A negative LIMIT means no limit in SQLite
+    let limit = req.limit.map(i64::from).unwrap_or(-1);
+    let offset = req.offset.map(i64::from).unwrap_or(0);
+
+    let groups: Vec<(
+        pm::get_buddy_groups_response::BuddyGroup,
+        NodeType,
+        TargetId,
+        Uid,
+    )> = app
+        .read_tx(move |tx| {
+            let pool_uid = pool
+                .map(|p| p.resolve(tx, EntityType::Pool))
+                .transpose()?
+                .map(|p| p.uid);
+
             Ok(tx.query_map_collect(
                 sql!(
                     "SELECT group_uid, group_id, bg.alias, bg.node_type,
                         p_target_uid, p_t.target_id, p_t.alias,
                         s_target_uid, s_t.target_id, s_t.alias,
                         p.pool_uid, bg.pool_id, p.alias,
-                        p_t.consistency, s_t.consistency
+                        p_t.consistency, s_t.consistency, p_t.node_uid
                     FROM buddy_groups_ext AS bg
                     INNER JOIN targets_ext AS p_t ON p_t.target_uid = p_target_uid
                     INNER JOIN targets_ext AS s_t ON s_t.target_uid = s_target_uid
-                    LEFT JOIN pools_ext AS p USING(node_type, pool_id)"
+                    LEFT JOIN pools_ext AS p USING(node_type, pool_id)
+                    WHERE (?1 IS NULL OR bg.node_type = ?1) AND (?2 IS NULL OR p.pool_uid = ?2)
+                    ORDER BY bg.node_type, group_id
+                    LIMIT ?3 OFFSET ?4"
                 ),
-                [],
+                params![node_type.map(|t| t.sql_variant()), pool_uid, limit, offset],
                 |row| {
-                    let node_type = NodeType::from_row(row, 3)?.into_proto_i32();
+                    let raw_node_type = NodeType::from_row(row, 3)?;
+                    let node_type = raw_node_type.into_proto_i32();
                     let p_con_state = TargetConsistencyState::from_row(row, 13)?.into_proto_i32();
                     let s_con_state = TargetConsistencyState::from_row(row, 14)?.into_proto_i32();
 
-                    Ok(pm::get_buddy_groups_response::BuddyGroup {
+                    let group = pm::get_buddy_groups_response::BuddyGroup {
                         id: Some(pb::EntityIdSet {
                             uid: row.get(0)?,
                             legacy_id: Some(pb::LegacyId {
@@ -65,11 +97,233 @@ pub(crate) async fn get_buddy_groups(
                         },
                         primary_consistency_state: p_con_state,
                         secondary_consistency_state: s_con_state,
-                    })
+                        resync_state: None,
+                        resync_progress: None,
+                    };
+
+                    Ok((group, raw_node_type, row.get(5)?, row.get(15)?))
                 },
             )?)
         })
         .await?;
 
+    let mut tasks = JoinSet::new();
+    let mut buddy_groups = Vec::with_capacity(groups.len());
+
+    for (i, (group, node_type, src_target_id, src_node_uid)) in groups.into_iter().enumerate() {
+        if req.include_resync.unwrap_or_default()
+            && group.secondary_consistency_state
+                == TargetConsistencyState::NeedsResync.into_proto_i32()
+        {
+            let id = group.id.clone().unwrap_or_default();
+            let group_id = EntityIdSet {
+                uid: id.uid.unwrap_or_default(),
+                alias: id.alias.unwrap_or_default().try_into()?,
+                legacy_id: LegacyId {
+                    node_type,
+                    num_id: id.legacy_id.unwrap_or_default().num_id,
+                },
+            };
+
+            let app = app.clone();
+            tasks.spawn(async move {
+                let res = fetch_resync_stats(&app, &group_id, src_target_id, src_node_uid).await;
+                (i, group_id, res)
+            });
+        }
+
+        buddy_groups.push(group);
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        let (i, group_id, res) = res?;
+
+        match res {
+            Ok(resync) => {
+                buddy_groups[i].resync_progress = resync_progress(&resync);
+                buddy_groups[i].resync_state = Some(resync.state);
+            }
+            Err(err) => {
+                log::warn!("Fetching resync stats for buddy group {group_id} failed: {err:#}");
+                buddy_groups[i].resync_state = Some(pb::ResyncState::Unspecified.into());
+            }
+        }
+    }
+
     Ok(pm::GetBuddyGroupsResponse { buddy_groups })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    async fn get(app: &TestApp, req: pm::GetBuddyGroupsRequest) -> Vec<i64> {
+        super::get_buddy_groups(app, req)
+            .await
+            .unwrap()
+            .buddy_groups
+            .into_iter()
+            .map(|g| g.id.unwrap().uid.unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn get_buddy_groups() {
+        let app = TestApp::new().await;
+
+        // Add another storage group in pool 2 (storage targets 2 and 6 are in pool 2)
+        app.write_tx(|tx| {
+            db::buddy_group::insert(tx, 3, None, NodeTypeServer::Storage, 2, 6)?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let all = get(&app, pm::GetBuddyGroupsRequest::default()).await;
+        assert_eq!(all.len(), 4);
+        assert_eq!(&all[0..3], &[301001, 302001, 302002]);
+
+        let storage = get(
+            &app,
+            pm::GetBuddyGroupsRequest {
+                node_type: Some(pb::NodeType::Storage.into()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(storage.len(), 3);
+        assert!(!storage.contains(&301001));
+
+        let pool_2 = get(
+            &app,
+            pm::GetBuddyGroupsRequest {
+                pool: Some(
+                    EntityId::LegacyID(LegacyId {
+                        node_type: NodeType::Storage,
+                        num_id: 2,
+                    })
+                    .into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(pool_2, &all[3..4]);
+
+        let page = get(
+            &app,
+            pm::GetBuddyGroupsRequest {
+                limit: Some(2),
+                offset: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(page, &all[1..3]);
+
+        let page = get(
+            &app,
+            pm::GetBuddyGroupsRequest {
+                node_type: Some(pb::NodeType::Storage.into()),
+                limit: Some(2),
+                offset: Some(2),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(page, &all[3..4]);
+    }
+
+    #[tokio::test]
+    async fn get_buddy_groups_resync() {
+        use shared::bee_msg::buddy_group::*;
+        use shared::bee_msg::target::SetTargetConsistencyStatesResp;
+
+        let app = TestApp::new().await;
+        app.set_request_handler(|req| {
+            if req.is::<GetStorageResyncStats>() {
+                return Ok(Box::new(GetStorageResyncStatsResp {
+                    state: BuddyResyncJobState::Running,
+                    discovered_files: 80,
+                    discovered_dirs: 20,
+                    synced_files: 20,
+                    synced_dirs: 5,
+                    ..Default::default()
+                }));
+            }
+
+            if req.is::<GetMetaResyncStats>() {
+                bail!("Meta node unreachable");
+            }
+
+            Ok(Box::new(SetTargetConsistencyStatesResp {
+                result: shared::bee_msg::OpsErr::SUCCESS,
+            }))
+        });
+
+        let groups = |include_resync| {
+            let app = app.clone();
+            async move {
+                super::get_buddy_groups(
+                    &app,
+                    pm::GetBuddyGroupsRequest {
+                        include_resync: Some(include_resync),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap()
+                .buddy_groups
+            }
+        };
+
+        // No resync needed, nothing is requested
+        assert!(groups(true).await.iter().all(|g| g.resync_state.is_none()));
+
+        super::super::start_resync::start_resync(
+            &app,
+            pm::StartResyncRequest {
+                buddy_group: Some(EntityId::Uid(302001).into()),
+                timestamp: Some(-1),
+                restart: Some(false),
+            },
+        )
+        .await
+        .unwrap();
+
+        app.write_tx(|tx| {
+            db::target::update_consistency_states(
+                tx,
+                [(2, TargetConsistencyState::NeedsResync)],
+                NodeTypeServer::Meta,
+            )
+        })
+        .await
+        .unwrap();
+
+        // Not requested
+        assert!(groups(false).await.iter().all(|g| g.resync_state.is_none()));
+
+        let groups = groups(true).await;
+        let find = |uid| {
+            groups
+                .iter()
+                .find(|g| g.id.as_ref().unwrap().uid == Some(uid))
+                .unwrap()
+        };
+
+        // The storage group reports the progress of the running resync
+        let storage = find(302001);
+        assert_eq!(storage.resync_state(), pb::ResyncState::Running);
+        assert_eq!(storage.resync_progress, Some(25.0));
+
+        // The meta source is unreachable
+        let meta = find(301001);
+        assert_eq!(meta.resync_state(), pb::ResyncState::Unspecified);
+        assert_eq!(meta.resync_progress, None);
+
+        // Groups not needing a resync are not requested
+        assert!(find(302002).resync_state.is_none());
+    }
+}
@@ -0,0 +1,96 @@
+use super::*;
+
+/// The default time window to count transitions in
+const DEFAULT_WINDOW_SECS: u64 = 3600;
+/// The default number of transitions within the window a target must exceed to be reported
+const DEFAULT_THRESHOLD: u32 = 3;
+
+/// Delivers the targets that changed their consistency or reachability state more often than the
+/// requested threshold within the requested time window
+pub(crate) async fn get_flapping_targets(
+    app: &impl App,
+    req: pm::GetFlappingTargetsRequest,
+) -> Result<pm::GetFlappingTargetsResponse> {
+    let window_secs = req.window_secs.unwrap_or(DEFAULT_WINDOW_SECS);
+    let threshold = req.threshold.unwrap_or(DEFAULT_THRESHOLD).try_into()?;
+
+    let flapping = app
+        .read_tx(move |tx| db::target_state_history::get_flapping(tx, window_secs, threshold))
+        .await?;
+
+    let targets = flapping
+        .into_iter()
+        .map(|t| {
+            Ok(pm::get_flapping_targets_response::Target {
+                id: Some(pb::EntityIdSet {
+                    uid: Some(t.uid),
+                    legacy_id: Some(pb::LegacyId {
+                        num_id: t.target_id.into(),
+                        node_type: NodeType::from(t.node_type).into_proto_i32(),
+                    }),
+                    alias: Some(t.alias),
+                }),
+                transitions: t.transitions.try_into()?,
+                last_transition_secs: t.last_transition,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(pm::GetFlappingTargetsResponse { targets })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn get_flapping_targets() {
+        let app = TestApp::new().await;
+
+        // Drive storage target 1 through several consistency transitions
+        for _ in 0..3 {
+            for state in [
+                TargetConsistencyState::NeedsResync,
+                TargetConsistencyState::Good,
+            ] {
+                app.write_tx(move |tx| {
+                    db::target::update_consistency_states(tx, [(1, state)], NodeTypeServer::Storage)
+                })
+                .await
+                .unwrap();
+            }
+        }
+
+        // Setting the same state again is not a transition
+        app.write_tx(|tx| {
+            db::target::update_consistency_states(
+                tx,
+                [(2, TargetConsistencyState::Good)],
+                NodeTypeServer::Storage,
+            )
+        })
+        .await
+        .unwrap();
+
+        let resp = super::get_flapping_targets(&app, pm::GetFlappingTargetsRequest::default())
+            .await
+            .unwrap();
+
+        assert_eq!(resp.targets.len(), 1);
+        assert_eq!(resp.targets[0].id.as_ref().unwrap().uid, Some(202001));
+        assert_eq!(resp.targets[0].transitions, 6);
+
+        let resp = super::get_flapping_targets(
+            &app,
+            pm::GetFlappingTargetsRequest {
+                window_secs: Some(3600),
+                threshold: Some(6),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(resp.targets.is_empty());
+    }
+}
@@ -23,6 +23,7 @@ pub(crate) async fn get_license(
         if let Some(d) = app.get_license_cert_data()?.data
             && d.r#type() == CertType::Trial
             && let None = prev_trial_serial
+            && !app.static_info().user_config.read_only
         {
             app.write_tx(|tx| db::config::set(tx, Config::TrialSerial, serial))
                 .await?;
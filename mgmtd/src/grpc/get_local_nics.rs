@@ -0,0 +1,50 @@
+use super::*;
+
+/// Delivers the local nics the management advertises to other nodes.
+///
+/// This is the list resulting from applying the `interfaces` filter on startup, in the order the
+/// other nodes receive it (e.g. in response to a `HeartbeatRequest`).
+pub(crate) async fn get_local_nics(
+    app: &impl App,
+    _req: pm::GetLocalNicsRequest,
+) -> Result<pm::GetLocalNicsResponse> {
+    let nics = app
+        .static_info()
+        .network_addrs
+        .iter()
+        .map(|nic| {
+            Ok(pm::get_local_nics_response::Nic {
+                name: nic.name.clone(),
+                addr: nic.address.to_string(),
+                nic_type: nic.nic_type.into_proto_i32(),
+                priority: nic.priority().try_into()?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(pm::GetLocalNicsResponse { nics })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn get_local_nics() {
+        // The test app filters for the IPv4 loopback address
+        let app = TestApp::new().await;
+
+        let resp = super::get_local_nics(&app, pm::GetLocalNicsRequest {})
+            .await
+            .unwrap();
+
+        assert_eq!(resp.nics.len(), app.static_info().network_addrs.len());
+        assert!(!resp.nics.is_empty());
+        for nic in resp.nics {
+            assert_eq!(nic.addr, "127.0.0.1");
+            assert_eq!(nic.nic_type, pb::NicType::Tcp as i32);
+            assert_eq!(nic.priority, 0);
+        }
+    }
+}
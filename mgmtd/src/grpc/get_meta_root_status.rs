@@ -0,0 +1,107 @@
+use super::*;
+use db::misc::MetaRoot;
+
+/// Delivers the state of the root inode and the meta target or buddy group owning it
+pub(crate) async fn get_meta_root_status(
+    app: &impl App,
+    _req: pm::GetMetaRootStatusRequest,
+) -> Result<pm::GetMetaRootStatusResponse> {
+    app.read_tx(|tx| {
+        let resp = match db::misc::get_meta_root(tx)? {
+            MetaRoot::Unknown => pm::GetMetaRootStatusResponse {
+                status: pm::MetaRootStatus::Unknown.into(),
+                ..Default::default()
+            },
+            MetaRoot::Normal(_, node_uid) => {
+                let target_id: TargetId =
+                    tx.query_row(sql!("SELECT target_id FROM root_inode"), [], |row| {
+                        row.get(0)
+                    })?;
+
+                let target = EntityId::LegacyID(LegacyId {
+                    node_type: NodeType::Meta,
+                    num_id: target_id.into(),
+                })
+                .resolve(tx, EntityType::Target)?;
+                let node = EntityId::Uid(node_uid).resolve(tx, EntityType::Node)?;
+
+                pm::GetMetaRootStatusResponse {
+                    status: pm::MetaRootStatus::Normal.into(),
+                    target: Some(target.into()),
+                    node: Some(node.into()),
+                    buddy_group: None,
+                }
+            }
+            MetaRoot::Mirrored(group_id) => {
+                let group = EntityId::LegacyID(LegacyId {
+                    node_type: NodeType::Meta,
+                    num_id: group_id.into(),
+                })
+                .resolve(tx, EntityType::BuddyGroup)?;
+
+                pm::GetMetaRootStatusResponse {
+                    status: pm::MetaRootStatus::Mirrored.into(),
+                    buddy_group: Some(group.into()),
+                    ..Default::default()
+                }
+            }
+        };
+
+        Ok(resp)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    async fn status(app: &TestApp) -> pm::GetMetaRootStatusResponse {
+        get_meta_root_status(app, pm::GetMetaRootStatusRequest {})
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn normal() {
+        let app = TestApp::new().await;
+
+        let resp = status(&app).await;
+        assert_eq!(resp.status(), pm::MetaRootStatus::Normal);
+        assert_eq!(resp.target.unwrap().legacy_id.unwrap().num_id, 1);
+        assert_eq!(resp.node.unwrap().uid, Some(101001));
+        assert!(resp.buddy_group.is_none());
+    }
+
+    #[tokio::test]
+    async fn mirrored() {
+        let app = TestApp::new().await;
+        app.write_tx(db::misc::enable_metadata_mirroring)
+            .await
+            .unwrap();
+
+        let resp = status(&app).await;
+        assert_eq!(resp.status(), pm::MetaRootStatus::Mirrored);
+        assert_eq!(resp.buddy_group.unwrap().legacy_id.unwrap().num_id, 1);
+        assert!(resp.target.is_none());
+        assert!(resp.node.is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown() {
+        let app = TestApp::new().await;
+        app.write_tx(|tx| {
+            tx.execute(sql!("DELETE FROM root_inode"), [])?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let resp = status(&app).await;
+        assert_eq!(resp.status(), pm::MetaRootStatus::Unknown);
+        assert!(resp.target.is_none());
+        assert!(resp.node.is_none());
+        assert!(resp.buddy_group.is_none());
+    }
+}
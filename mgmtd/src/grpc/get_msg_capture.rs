@@ -0,0 +1,152 @@
+use super::common::{MSG_CAPTURE_BATCH_SIZE, MSG_CAPTURE_STREAM_BUF_SIZE};
+use super::*;
+use shared::conn::msg_capture::{Direction, MSG_CAPTURE, MsgCapture};
+use std::time::UNIX_EPOCH;
+
+/// Delivers the BeeMsges recorded by the current or the most recent capture, oldest first.
+///
+/// The messages are delivered in batches of [MSG_CAPTURE_BATCH_SIZE], since a full capture can
+/// be far bigger than the gRPC message size limit.
+pub(crate) async fn get_msg_capture(
+    _app: &impl App,
+    _req: pm::GetMsgCaptureRequest,
+) -> Result<RespStream<pm::GetMsgCaptureResponse>> {
+    capture_stream(&MSG_CAPTURE)
+}
+
+/// Builds the response stream from the messages recorded by `capture`
+fn capture_stream(capture: &MsgCapture) -> Result<RespStream<pm::GetMsgCaptureResponse>> {
+    let active = capture.is_active();
+
+    let msgs = capture
+        .msgs()
+        .into_iter()
+        .map(|m| {
+            Ok(pm::get_msg_capture_response::Msg {
+                time_secs: m
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default(),
+                direction: match m.direction {
+                    Direction::Incoming => pm::get_msg_capture_response::Direction::Incoming,
+                    Direction::Outgoing => pm::get_msg_capture_response::Direction::Outgoing,
+                }
+                .into(),
+                peer: m.peer.to_string(),
+                msg_id: m.msg_id.into(),
+                len: m.len.try_into()?,
+                data_hex: m.data_hex(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let stream = resp_stream(MSG_CAPTURE_STREAM_BUF_SIZE, async move |stream| {
+        // Always send at least one response, so the requester learns whether the capture is active
+        if msgs.is_empty() {
+            stream
+                .send(pm::GetMsgCaptureResponse {
+                    active,
+                    msgs: vec![],
+                })
+                .await?;
+        }
+
+        for batch in msgs.chunks(MSG_CAPTURE_BATCH_SIZE) {
+            stream
+                .send(pm::GetMsgCaptureResponse {
+                    active,
+                    msgs: batch.to_vec(),
+                })
+                .await?;
+        }
+
+        Ok(())
+    });
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grpc::start_msg_capture::start_capture;
+    use shared::bee_msg::Msg;
+    use shared::bee_msg::node::GetNodes;
+    use shared::bee_msg::serialize;
+    use std::net::SocketAddr;
+    use tokio_stream::StreamExt;
+
+    async fn get(capture: &MsgCapture) -> Vec<pm::GetMsgCaptureResponse> {
+        capture_stream(capture)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn msg_capture() {
+        // Use a separate capture, the global one is recorded into by other tests
+        let capture = MsgCapture::new();
+
+        for (duration_secs, max_msgs) in [(0, None), (601, None), (10, Some(0))] {
+            start_capture(
+                &capture,
+                pm::StartMsgCaptureRequest {
+                    duration_secs,
+                    max_msgs,
+                    include_data: false,
+                },
+            )
+            .unwrap_err();
+        }
+
+        // Nothing captured yet, but the state is still reported
+        let resps = get(&capture).await;
+        assert_eq!(resps.len(), 1);
+        assert!(!resps[0].active);
+        assert!(resps[0].msgs.is_empty());
+
+        start_capture(
+            &capture,
+            pm::StartMsgCaptureRequest {
+                duration_secs: 60,
+                max_msgs: Some(1000),
+                include_data: true,
+            },
+        )
+        .unwrap();
+
+        // Simulate received messages - the connection code records the real ones
+        let mut buf = vec![0; 1024];
+        let len = serialize(
+            &GetNodes {
+                node_type: NodeType::Meta,
+            },
+            &mut buf,
+        )
+        .unwrap();
+        let peer: SocketAddr = "192.0.2.1:8003".parse().unwrap();
+        for _ in 0..MSG_CAPTURE_BATCH_SIZE + 1 {
+            capture.record(Direction::Incoming, peer, &buf[..len]);
+        }
+
+        // The messages are split into batches
+        let resps = get(&capture).await;
+        assert_eq!(resps.len(), 2);
+        assert!(resps.iter().all(|r| r.active));
+        assert_eq!(resps[0].msgs.len(), MSG_CAPTURE_BATCH_SIZE);
+        assert_eq!(resps[1].msgs.len(), 1);
+
+        let msg = &resps[1].msgs[0];
+        assert_eq!(msg.peer, "192.0.2.1:8003");
+        assert_eq!(msg.msg_id, u32::from(GetNodes::ID));
+        assert_eq!(
+            msg.direction,
+            i32::from(pm::get_msg_capture_response::Direction::Incoming)
+        );
+        assert_eq!(msg.len, len as u64);
+        assert!(msg.data_hex.as_ref().unwrap().len() >= len * 2);
+    }
+}
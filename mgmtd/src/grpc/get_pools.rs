@@ -25,7 +25,7 @@ pub(crate) async fn get_pools(
                 tx.query_map_collect(
                     sql!(
                         "SELECT p.pool_uid, p.pool_id, alias,
-                            qus.value, qui.value, qgs.value, qgi.value
+                            qus.value, qui.value, qgs.value, qgi.value, qps.value, qpi.value
                         FROM storage_pools AS p
                         INNER JOIN entities ON uid = pool_uid
                         LEFT JOIN quota_default_limits AS qus ON qus.pool_id = p.pool_id
@@ -35,11 +35,16 @@ pub(crate) async fn get_pools(
                         LEFT JOIN quota_default_limits AS qgs ON qgs.pool_id = p.pool_id
                             AND qgs.id_type = :group AND qgs.quota_type = :space
                         LEFT JOIN quota_default_limits AS qgi ON qgi.pool_id = p.pool_id
-                            AND qgi.id_type = :group AND qgi.quota_type = :inode"
+                            AND qgi.id_type = :group AND qgi.quota_type = :inode
+                        LEFT JOIN quota_default_limits AS qps ON qps.pool_id = p.pool_id
+                            AND qps.id_type = :project AND qps.quota_type = :space
+                        LEFT JOIN quota_default_limits AS qpi ON qpi.pool_id = p.pool_id
+                            AND qpi.id_type = :project AND qpi.quota_type = :inode"
                     ),
                     named_params![
                         ":user": QuotaIdType::User.sql_variant(),
                         ":group": QuotaIdType::Group.sql_variant(),
+                        ":project": QuotaIdType::Project.sql_variant(),
                         ":space": QuotaType::Space.sql_variant(),
                         ":inode": QuotaType::Inode.sql_variant()
                     ],
@@ -49,6 +54,8 @@ pub(crate) async fn get_pools(
                         sp.user_inode_limit = row.get::<_, Option<i64>>(4)?.or(Some(-1));
                         sp.group_space_limit = row.get::<_, Option<i64>>(5)?.or(Some(-1));
                         sp.group_inode_limit = row.get::<_, Option<i64>>(6)?.or(Some(-1));
+                        sp.project_space_limit = row.get::<_, Option<i64>>(7)?.or(Some(-1));
+                        sp.project_inode_limit = row.get::<_, Option<i64>>(8)?.or(Some(-1));
                         Ok(sp)
                     },
                 )?
@@ -164,6 +171,8 @@ mod test {
         assert_eq!(default_pool.user_inode_limit.unwrap(), 1000);
         assert_eq!(default_pool.group_space_limit.unwrap(), 1000);
         assert_eq!(default_pool.group_inode_limit.unwrap(), 1000);
+        assert_eq!(default_pool.project_space_limit.unwrap(), -1);
+        assert_eq!(default_pool.project_inode_limit.unwrap(), -1);
 
         let other_pool = resp
             .pools
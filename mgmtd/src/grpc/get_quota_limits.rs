@@ -3,6 +3,10 @@ use super::*;
 use itertools::Itertools;
 use std::fmt::Write;
 
+/// Delivers the quota limits matching the requested IDs.
+///
+/// If no pool is requested, the system wide default limits of the requested ID types are delivered
+/// first. These entries have neither a pool nor a quota ID set.
 pub(crate) async fn get_quota_limits(
     app: &impl App,
     req: pm::GetQuotaLimitsRequest,
@@ -25,10 +29,12 @@ pub(crate) async fn get_quota_limits(
     };
 
     let mut r#where = "FALSE ".to_string();
+    let mut id_types = vec![];
 
     let mut filter =
         |min: Option<u32>, max: Option<u32>, list: &[u32], typ: QuotaIdType| -> Result<()> {
             if min.is_some() || max.is_some() || !list.is_empty() {
+                id_types.push(typ);
                 write!(r#where, "OR (l.id_type = {} ", typ.sql_variant())?;
 
                 if min.is_some() || max.is_some() {
@@ -66,6 +72,13 @@ pub(crate) async fn get_quota_limits(
         QuotaIdType::Group,
     )?;
 
+    filter(
+        req.project_id_min,
+        req.project_id_max,
+        &req.project_id_list,
+        QuotaIdType::Project,
+    )?;
+
     let sql = format!(
         "SELECT l.quota_id, l.id_type, l.pool_id, sp.alias, sp.pool_uid,
             MAX(CASE WHEN l.quota_type = {space} THEN l.value END) AS space_limit,
@@ -79,8 +92,23 @@ pub(crate) async fn get_quota_limits(
         inode = QuotaType::Inode.sql_variant()
     );
 
+    let system_defaults = if pool_id.is_none() {
+        app.read_tx(move |tx| system_default_limits(tx, &id_types))
+            .await?
+    } else {
+        vec![]
+    };
+
     let app = app.clone();
     let stream = resp_stream(QUOTA_STREAM_BUF_SIZE, async move |stream| {
+        for entry in system_defaults {
+            stream
+                .send(pm::GetQuotaLimitsResponse {
+                    limits: Some(entry),
+                })
+                .await?;
+        }
+
         let mut offset = 0;
 
         loop {
@@ -132,3 +160,40 @@ pub(crate) async fn get_quota_limits(
 
     Ok(stream)
 }
+
+/// Builds an entry containing the system wide default limits for each of the given ID types that
+/// has at least one of them set.
+fn system_default_limits(tx: &Transaction, id_types: &[QuotaIdType]) -> Result<Vec<pm::QuotaInfo>> {
+    let mut entries = vec![];
+
+    for &id_type in id_types {
+        let (space_limit, inode_limit): (Option<i64>, Option<i64>) = tx.query_row_cached(
+            sql!(
+                "SELECT MAX(CASE WHEN quota_type = ?2 THEN value END),
+                    MAX(CASE WHEN quota_type = ?3 THEN value END)
+                FROM quota_system_default_limits
+                WHERE id_type = ?1"
+            ),
+            params![
+                id_type.sql_variant(),
+                QuotaType::Space.sql_variant(),
+                QuotaType::Inode.sql_variant()
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        if space_limit.is_some() || inode_limit.is_some() {
+            entries.push(pm::QuotaInfo {
+                pool: None,
+                id_type: id_type.into_proto_i32(),
+                quota_id: None,
+                space_limit,
+                inode_limit,
+                space_used: None,
+                inode_used: None,
+            });
+        }
+    }
+
+    Ok(entries)
+}
@@ -52,6 +52,13 @@ pub(crate) async fn get_quota_usage(
         QuotaIdType::Group,
     )?;
 
+    filter(
+        req.project_id_min,
+        req.project_id_max,
+        &req.project_id_list,
+        QuotaIdType::Project,
+    )?;
+
     let mut having = "TRUE ".to_string();
 
     if let Some(pool) = req.pool {
@@ -73,28 +80,7 @@ pub(crate) async fn get_quota_usage(
         }
     }
 
-    let sql = format!(
-        "SELECT u.quota_id, u.id_type, sp.pool_id, sp.alias, sp.pool_uid,
-            MAX(CASE WHEN u.quota_type = {space} THEN
-                COALESCE(l.value, d.value, -1)
-            END) AS space_limit,
-            MAX(CASE WHEN u.quota_type = {inode} THEN
-                COALESCE(l.value, d.value, -1)
-            END) AS inode_limit,
-            SUM(CASE WHEN u.quota_type = {space} THEN u.value END) AS space_used,
-            SUM(CASE WHEN u.quota_type = {inode} THEN u.value END) AS inode_used
-        FROM quota_usage AS u
-        INNER JOIN targets AS st USING(node_type, target_id)
-        INNER JOIN pools_ext AS sp USING(node_type, pool_id)
-        LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
-        LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
-        WHERE {where}
-        GROUP BY u.quota_id, u.id_type, st.pool_id
-        HAVING {having}
-        LIMIT ?1, ?2",
-        space = QuotaType::Space.sql_variant(),
-        inode = QuotaType::Inode.sql_variant()
-    );
+    let sql = usage_query(&r#where, &having, req.exceeded == Some(true));
 
     let app = app.clone();
     let stream = resp_stream(QUOTA_STREAM_BUF_SIZE, async move |stream| {
@@ -137,12 +123,7 @@ pub(crate) async fn get_quota_usage(
                 stream
                     .send(pm::GetQuotaUsageResponse {
                         entry: Some(entry),
-                        refresh_period_s: Some(
-                            app.static_info()
-                                .user_config
-                                .quota_update_interval
-                                .as_secs(),
-                        ),
+                        refresh_period_s: Some(app.dynamic_info().quota_update_interval.as_secs()),
                     })
                     .await?;
             }
@@ -168,3 +149,188 @@ pub(crate) async fn get_quota_usage(
 
     Ok(stream)
 }
+
+/// Builds the paged quota usage query.
+///
+/// If `exceeded_only` is set, the query is driven by the `quota_exceeded` table instead of
+/// scanning all usage entries. Only the usage entries of the exceeded IDs are then looked up using
+/// the primary key. The `having` filter is still applied, so the result is exact even if
+/// `quota_exceeded` contains outdated entries.
+fn usage_query(r#where: &str, having: &str, exceeded_only: bool) -> String {
+    let (from, restrict) = if exceeded_only {
+        // CROSS JOIN forces SQLite to use the exceeded IDs as the outer loop
+        (
+            "(SELECT DISTINCT quota_id AS x_quota_id, id_type AS x_id_type, pool_id AS x_pool_id
+                FROM quota_exceeded) AS x
+            CROSS JOIN quota_usage AS u
+                ON u.quota_id = x.x_quota_id AND u.id_type = x.x_id_type",
+            "AND st.pool_id = x.x_pool_id",
+        )
+    } else {
+        ("quota_usage AS u", "")
+    };
+
+    format!(
+        "SELECT u.quota_id, u.id_type, sp.pool_id, sp.alias, sp.pool_uid,
+            MAX(CASE WHEN u.quota_type = {space} THEN
+                COALESCE(l.value, d.value, s.value, -1)
+            END) AS space_limit,
+            MAX(CASE WHEN u.quota_type = {inode} THEN
+                COALESCE(l.value, d.value, s.value, -1)
+            END) AS inode_limit,
+            SUM(CASE WHEN u.quota_type = {space} THEN u.value END) AS space_used,
+            SUM(CASE WHEN u.quota_type = {inode} THEN u.value END) AS inode_used
+        FROM {from}
+        INNER JOIN targets AS st USING(node_type, target_id)
+        INNER JOIN pools_ext AS sp USING(node_type, pool_id)
+        LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+        LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
+        LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
+        WHERE ({where}) {restrict}
+        GROUP BY u.quota_id, u.id_type, st.pool_id
+        HAVING {having}
+        LIMIT ?1, ?2",
+        space = QuotaType::Space.sql_variant(),
+        inode = QuotaType::Inode.sql_variant()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use crate::db::test::with_test_data;
+    use tokio_stream::StreamExt;
+
+    const WHERE: &str = "FALSE OR (u.id_type = 1 AND u.quota_id BETWEEN 0 AND 4294967295)";
+    const HAVING: &str = "TRUE AND (space_used > space_limit AND space_limit > -1
+        OR inode_used > inode_limit AND inode_limit > -1)";
+
+    #[test]
+    fn exceeded_query_plan() {
+        with_test_data(|tx| {
+            // Many IDs, none of them exceeding a limit
+            tx.execute(
+                sql!(
+                    "WITH RECURSIVE ids(i) AS (SELECT 100 UNION ALL SELECT i + 1 FROM ids WHERE i < ?1)
+                    INSERT INTO quota_usage (quota_id, id_type, quota_type, target_id, value)
+                    SELECT i, 1, 1, 1, 1 FROM ids"
+                ),
+                [100_000],
+            )
+            .unwrap();
+            db::quota::update_exceeded(tx, db::quota::ExceededScope::All).unwrap();
+            tx.execute_batch("ANALYZE").unwrap();
+
+            let plan = |exceeded_only| -> Vec<String> {
+                tx.query_map_collect(
+                    &format!(
+                        "EXPLAIN QUERY PLAN {}",
+                        usage_query(WHERE, HAVING, exceeded_only)
+                    ),
+                    [0, 1000],
+                    |row| row.get(3),
+                )
+                .unwrap()
+            };
+
+            // The exceeded path only looks up the usage entries of the exceeded IDs
+            let exceeded_plan = plan(true);
+            assert!(
+                exceeded_plan
+                    .iter()
+                    .any(|e| e.starts_with("SEARCH u USING PRIMARY KEY")),
+                "{exceeded_plan:#?}"
+            );
+            assert!(
+                !exceeded_plan.iter().any(|e| e.starts_with("SCAN u")),
+                "{exceeded_plan:#?}"
+            );
+
+            // The general path has to scan all usage entries
+            let plan = plan(false);
+            assert!(plan.iter().any(|e| e == "SCAN u"), "{plan:#?}");
+
+            // Both paths deliver the same result
+            let query = |exceeded_only| -> Vec<(QuotaId, PoolId)> {
+                tx.query_map_collect(
+                    &usage_query(WHERE, HAVING, exceeded_only),
+                    [0, 1000],
+                    |row| Ok((row.get(0)?, row.get(2)?)),
+                )
+                .unwrap()
+            };
+            let mut exceeded = query(true);
+            exceeded.sort();
+            assert_eq!(exceeded, [(2, 1), (4, 1), (10, 1), (12, 1), (20, 2)]);
+            let mut all = query(false);
+            all.sort();
+            assert_eq!(exceeded, all);
+        })
+    }
+
+    #[tokio::test]
+    async fn get_exceeded() {
+        let app = TestApp::with_config(Config {
+            quota_enable: true,
+            ..Default::default()
+        })
+        .await;
+        app.write_tx(|tx| db::quota::update_exceeded(tx, db::quota::ExceededScope::All))
+            .await
+            .unwrap();
+
+        let get = async |exceeded| -> Vec<(u32, u32)> {
+            let mut res: Vec<_> = get_quota_usage(
+                &app,
+                pm::GetQuotaUsageRequest {
+                    group_id_min: Some(0),
+                    exceeded,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap().entry.unwrap();
+                (
+                    e.quota_id.unwrap(),
+                    e.pool.unwrap().legacy_id.unwrap().num_id,
+                )
+            })
+            .collect()
+            .await;
+            res.sort();
+            res
+        };
+
+        assert_eq!(get(Some(true)).await, [(2, 1), (4, 1), (11, 1), (13, 1)]);
+        assert_eq!(get(Some(false)).await, [(1, 1), (3, 1), (10, 2)]);
+
+        // Raising a limit is reflected immediately
+        crate::grpc::set_quota_limits::set_quota_limits(
+            &app,
+            pm::SetQuotaLimitsRequest {
+                limits: vec![pm::QuotaInfo {
+                    pool: Some(pb::EntityIdSet {
+                        uid: None,
+                        legacy_id: Some(pb::LegacyId {
+                            num_id: 1,
+                            node_type: pb::NodeType::Storage.into(),
+                        }),
+                        alias: None,
+                    }),
+                    id_type: pb::QuotaIdType::Group.into(),
+                    quota_id: Some(2),
+                    space_limit: Some(5000),
+                    inode_limit: Some(5000),
+                    ..Default::default()
+                }],
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(get(Some(true)).await, [(4, 1), (11, 1), (13, 1)]);
+    }
+}
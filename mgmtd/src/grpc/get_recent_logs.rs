@@ -0,0 +1,138 @@
+use super::common::{COMPACT_RECORDS_BATCH_SIZE, RECENT_LOGS_STREAM_BUF_SIZE};
+use super::*;
+use crate::RECENT_LOGS;
+use shared::compact_records::{LogRecord, encode_batch};
+use std::time::UNIX_EPOCH;
+
+/// Delivers the log records kept in memory, oldest first.
+///
+/// If `limit` is given, only the most recent `limit` records are delivered. If `compact` is set,
+/// the records are delivered in batches using the BeeSerde encoding from
+/// [shared::compact_records] instead of one message per record.
+pub(crate) async fn get_recent_logs(
+    _app: &impl App,
+    req: pm::GetRecentLogsRequest,
+) -> Result<RespStream<pm::GetRecentLogsResponse>> {
+    let mut entries = RECENT_LOGS.entries();
+
+    if let Some(limit) = req.limit {
+        let excess = entries.len().saturating_sub(limit.try_into()?);
+        entries.drain(..excess);
+    }
+
+    let compact = req.compact;
+
+    let records: Vec<_> = entries
+        .into_iter()
+        .map(|entry| LogRecord {
+            time_secs: entry
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+            level: entry.level.to_string(),
+            module: entry.target,
+            message: entry.message,
+        })
+        .collect();
+
+    let stream = resp_stream(RECENT_LOGS_STREAM_BUF_SIZE, async move |stream| {
+        if compact {
+            for batch in records.chunks(COMPACT_RECORDS_BATCH_SIZE) {
+                stream
+                    .send(pm::GetRecentLogsResponse {
+                        compact_records: encode_batch(batch)?,
+                        ..Default::default()
+                    })
+                    .await?;
+            }
+        } else {
+            for r in records {
+                stream
+                    .send(pm::GetRecentLogsResponse {
+                        time_secs: r.time_secs,
+                        level: r.level,
+                        module: r.module,
+                        message: r.message,
+                        compact_records: vec![],
+                    })
+                    .await?;
+            }
+        }
+
+        Ok(())
+    });
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use log::{Level, LevelFilter, Record};
+    use tokio_stream::StreamExt;
+
+    async fn get(app: &TestApp, limit: Option<u32>) -> Vec<String> {
+        super::get_recent_logs(
+            app,
+            pm::GetRecentLogsRequest {
+                limit,
+                compact: false,
+            },
+        )
+        .await
+        .unwrap()
+        .map(|e| e.unwrap().message)
+        .collect()
+        .await
+    }
+
+    #[tokio::test]
+    async fn get_recent_logs() {
+        let app = TestApp::new().await;
+
+        RECENT_LOGS.configure(5, LevelFilter::Info);
+        for i in 0..8 {
+            RECENT_LOGS.push(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target("mgmtd::test")
+                    .args(format_args!("msg {i}"))
+                    .build(),
+            );
+        }
+
+        assert_eq!(
+            get(&app, None).await,
+            ["msg 3", "msg 4", "msg 5", "msg 6", "msg 7"]
+        );
+        assert_eq!(get(&app, Some(2)).await, ["msg 6", "msg 7"]);
+        assert_eq!(get(&app, Some(100)).await.len(), 5);
+
+        // Compact encoding delivers all records in one batch
+        let batches: Vec<_> = super::get_recent_logs(
+            &app,
+            pm::GetRecentLogsRequest {
+                limit: Some(2),
+                compact: true,
+            },
+        )
+        .await
+        .unwrap()
+        .map(|e| e.unwrap().compact_records)
+        .collect()
+        .await;
+        assert_eq!(batches.len(), 1);
+
+        let records: Vec<LogRecord> = shared::compact_records::decode_batch(&batches[0]).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>(),
+            ["msg 6", "msg 7"]
+        );
+        assert_eq!(records[0].module, "mgmtd::test");
+    }
+}
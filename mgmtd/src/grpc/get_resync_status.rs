@@ -0,0 +1,288 @@
+use super::start_resync::RESYNC_REQUEST_TIMEOUT;
+use super::*;
+use pm::get_resync_status_response::Resync;
+use shared::bee_msg::buddy_group::{
+    BuddyResyncJobState, GetMetaResyncStats, GetMetaResyncStatsResp, GetStorageResyncStats,
+    GetStorageResyncStatsResp,
+};
+
+/// Delivers the resync state and progress of all buddy groups as reported by their source
+/// (primary) nodes
+pub(crate) async fn get_resync_status(
+    app: &impl App,
+    _req: pm::GetResyncStatusRequest,
+) -> Result<pm::GetResyncStatusResponse> {
+    fail_on_missing_license(app, LicensedFeature::Mirroring)?;
+
+    let groups: Vec<(EntityIdSet, TargetId, Uid)> = app
+        .read_tx(|tx| {
+            let groups: Vec<(Uid, String, NodeType, u32, TargetId, Uid)> = tx.query_map_collect(
+                sql!(
+                    "SELECT g.group_uid, g.alias, g.node_type, g.group_id, g.p_target_id,
+                        src_t.node_uid
+                    FROM buddy_groups_ext AS g
+                    INNER JOIN targets_ext AS src_t ON src_t.target_uid = g.p_target_uid"
+                ),
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        NodeType::from_row(row, 2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )?;
+
+            groups
+                .into_iter()
+                .map(
+                    |(uid, alias, node_type, num_id, src_target_id, src_node_uid)| {
+                        Ok((
+                            EntityIdSet {
+                                uid,
+                                alias: alias.try_into()?,
+                                legacy_id: LegacyId { node_type, num_id },
+                            },
+                            src_target_id,
+                            src_node_uid,
+                        ))
+                    },
+                )
+                .collect()
+        })
+        .await?;
+
+    let mut resyncs = Vec::with_capacity(groups.len());
+    for (group, src_target_id, src_node_uid) in groups {
+        let resync = match fetch_resync_stats(app, &group, src_target_id, src_node_uid).await {
+            Ok(resync) => resync,
+            Err(err) => {
+                log::warn!("Fetching resync stats for buddy group {group} failed: {err:#}");
+                Resync {
+                    group: Some(group.into()),
+                    ..Default::default()
+                }
+            }
+        };
+
+        resyncs.push(resync);
+    }
+
+    Ok(pm::GetResyncStatusResponse { resyncs })
+}
+
+/// Requests the resync stats from the source node of a buddy group
+pub(super) async fn fetch_resync_stats(
+    app: &impl App,
+    group: &EntityIdSet,
+    src_target_id: TargetId,
+    src_node_uid: Uid,
+) -> Result<Resync> {
+    let resync = match group.node_type() {
+        NodeType::Meta => {
+            let resp: GetMetaResyncStatsResp = app
+                .request_with_timeout(
+                    src_node_uid,
+                    &GetMetaResyncStats {
+                        target_id: src_target_id,
+                    },
+                    RESYNC_REQUEST_TIMEOUT,
+                )
+                .await?;
+
+            Resync {
+                group: Some(group.clone().into()),
+                state: resync_state(resp.state).into(),
+                start_time: Some(resp.start_time),
+                end_time: Some(resp.end_time),
+                discovered_files: None,
+                discovered_dirs: Some(resp.discovered_dirs),
+                synced_files: Some(resp.synced_files),
+                synced_dirs: Some(resp.synced_dirs),
+                error_files: Some(resp.error_files),
+                error_dirs: Some(resp.error_dirs),
+            }
+        }
+        NodeType::Storage => {
+            let resp: GetStorageResyncStatsResp = app
+                .request_with_timeout(
+                    src_node_uid,
+                    &GetStorageResyncStats {
+                        target_id: src_target_id,
+                    },
+                    RESYNC_REQUEST_TIMEOUT,
+                )
+                .await?;
+
+            Resync {
+                group: Some(group.clone().into()),
+                state: resync_state(resp.state).into(),
+                start_time: Some(resp.start_time),
+                end_time: Some(resp.end_time),
+                discovered_files: Some(resp.discovered_files),
+                discovered_dirs: Some(resp.discovered_dirs),
+                synced_files: Some(resp.synced_files),
+                synced_dirs: Some(resp.synced_dirs),
+                error_files: Some(resp.error_files),
+                error_dirs: Some(resp.error_dirs),
+            }
+        }
+        t => bail!("Invalid buddy group node type {t}"),
+    };
+
+    Ok(resync)
+}
+
+/// Calculates the progress of a resync in percent from the reported stats.
+///
+/// Meta resyncs only report discovered directories, so only these are taken into account there.
+/// Since discovery runs alongside syncing, the value might go down while a resync is running.
+/// Returns `None` if nothing has been discovered yet.
+pub(super) fn resync_progress(resync: &Resync) -> Option<f64> {
+    let (done, total) = match resync.discovered_files {
+        Some(discovered_files) => (
+            resync.synced_files()
+                + resync.error_files()
+                + resync.synced_dirs()
+                + resync.error_dirs(),
+            discovered_files + resync.discovered_dirs(),
+        ),
+        None => (
+            resync.synced_dirs() + resync.error_dirs(),
+            resync.discovered_dirs(),
+        ),
+    };
+
+    if total == 0 {
+        return None;
+    }
+
+    Some((done as f64 / total as f64 * 100.0).min(100.0))
+}
+
+fn resync_state(state: BuddyResyncJobState) -> pb::ResyncState {
+    match state {
+        BuddyResyncJobState::NotStarted => pb::ResyncState::NotStarted,
+        BuddyResyncJobState::Running => pb::ResyncState::Running,
+        BuddyResyncJobState::Success => pb::ResyncState::Success,
+        BuddyResyncJobState::Interrupted => pb::ResyncState::Interrupted,
+        BuddyResyncJobState::Failure => pb::ResyncState::Failure,
+        BuddyResyncJobState::Errors => pb::ResyncState::Errors,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::OpsErr;
+    use shared::bee_msg::buddy_group::SetLastBuddyCommOverride;
+    use shared::bee_msg::target::SetTargetConsistencyStatesResp;
+    use std::sync::{Arc, Mutex};
+
+    /// Fetches the resync state of buddy group 1 from the status list
+    async fn storage_group_1_state(app: &TestApp) -> pb::ResyncState {
+        let resp = get_resync_status(app, pm::GetResyncStatusRequest {})
+            .await
+            .unwrap();
+
+        resp.resyncs
+            .into_iter()
+            .find(|r| r.group.as_ref().unwrap().uid == Some(302001))
+            .unwrap()
+            .state()
+    }
+
+    #[tokio::test]
+    async fn start_and_abort_resync() {
+        let app = TestApp::new().await;
+
+        // Simulates the resync job on the storage node
+        let job_state = Arc::new(Mutex::new(BuddyResyncJobState::NotStarted));
+        let handler_job_state = job_state.clone();
+        app.set_request_handler(move |req| {
+            if req.is::<GetStorageResyncStats>() {
+                return Ok(Box::new(GetStorageResyncStatsResp {
+                    state: *handler_job_state.lock().unwrap(),
+                    ..Default::default()
+                }));
+            }
+
+            if let Some(msg) = req.downcast_ref::<SetLastBuddyCommOverride>() {
+                if msg.abort_resync > 0 {
+                    *handler_job_state.lock().unwrap() = BuddyResyncJobState::Interrupted;
+                }
+
+                return Ok(Box::new(SetTargetConsistencyStatesResp {
+                    result: OpsErr::SUCCESS,
+                }));
+            }
+
+            Ok(Box::new(GetMetaResyncStatsResp::default()))
+        });
+
+        assert_eq!(
+            storage_group_1_state(&app).await,
+            pb::ResyncState::NotStarted
+        );
+
+        // Aborting without a running resync fails
+        super::super::abort_resync::abort_resync(
+            &app,
+            pm::AbortResyncRequest {
+                buddy_group: Some(EntityId::Uid(302001).into()),
+            },
+        )
+        .await
+        .unwrap_err();
+
+        super::super::start_resync::start_resync(
+            &app,
+            pm::StartResyncRequest {
+                buddy_group: Some(EntityId::Uid(302001).into()),
+                timestamp: Some(-1),
+                restart: Some(false),
+            },
+        )
+        .await
+        .unwrap();
+
+        // The storage node picks up the resync
+        *job_state.lock().unwrap() = BuddyResyncJobState::Running;
+        assert_eq!(storage_group_1_state(&app).await, pb::ResyncState::Running);
+
+        super::super::abort_resync::abort_resync(
+            &app,
+            pm::AbortResyncRequest {
+                buddy_group: Some(EntityId::Uid(302001).into()),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            storage_group_1_state(&app).await,
+            pb::ResyncState::Interrupted
+        );
+
+        // Meta buddy groups can't be aborted
+        super::super::abort_resync::abort_resync(
+            &app,
+            pm::AbortResyncRequest {
+                buddy_group: Some(EntityId::Uid(301001).into()),
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log WHERE operation = 'Abort resync'",
+            [],
+            1
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use super::*;
+use crate::db::config::Config;
+use crate::license::days_remaining;
+use protobuf::license::VerifyResult;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Delivers general information about the running management service
+pub(crate) async fn get_server_info(
+    app: &impl App,
+    _req: pm::GetServerInfoRequest,
+) -> Result<pm::GetServerInfoResponse> {
+    let (fs_uuid, schema_version): (Option<String>, u32) = app
+        .read_tx(|tx| {
+            Ok((
+                db::config::get(tx, Config::FsUuid)?,
+                tx.query_row("PRAGMA user_version", [], |row| row.get(0))?,
+            ))
+        })
+        .await?;
+
+    let uptime = app.static_info().start_time.elapsed();
+    let start_time = SystemTime::now()
+        .checked_sub(uptime)
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    let license_valid = app
+        .get_license_cert_data()
+        .is_ok_and(|r| r.result() == VerifyResult::VerifyValid);
+    let license_days_remaining = app
+        .get_license_expiry()?
+        .map(|expiry| days_remaining(expiry, SystemTime::now()));
+
+    let user_config = &app.static_info().user_config;
+
+    Ok(pm::GetServerInfoResponse {
+        version: Some(crate::version_str().to_string()),
+        start_time_secs: Some(start_time.as_secs().try_into()?),
+        uptime_secs: Some(uptime.as_secs()),
+        fs_uuid,
+        schema_version: Some(schema_version),
+        quota_enabled: Some(user_config.quota_enable),
+        quota_enforced: Some(user_config.quota_enforce),
+        license_valid: Some(license_valid),
+        license_days_remaining,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn get_server_info() {
+        let app = TestApp::new().await;
+
+        let resp = super::get_server_info(&app, pm::GetServerInfoRequest {})
+            .await
+            .unwrap();
+
+        assert!(!resp.version.unwrap().is_empty());
+        assert_eq!(
+            resp.schema_version.unwrap(),
+            db::MIGRATIONS.last().unwrap().version
+        );
+        assert!(!resp.quota_enabled.unwrap());
+        assert_eq!(resp.license_days_remaining, None);
+    }
+}
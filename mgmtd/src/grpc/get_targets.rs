@@ -17,7 +17,8 @@ pub(crate) async fn get_targets(
     app: &impl App,
     _req: pm::GetTargetsRequest,
 ) -> Result<pm::GetTargetsResponse> {
-    let node_offline_timeout = app.static_info().user_config.node_offline_timeout;
+    let node_offline_timeout = app.dynamic_info().node_offline_timeout;
+    let node_offline_grace = app.dynamic_info().node_offline_grace;
     let pre_shutdown = app.is_pre_shutdown();
 
     let fetch_op = move |tx: &Transaction| {
@@ -27,7 +28,8 @@ pub(crate) async fn get_targets(
                 p.pool_uid, p.alias, p.pool_id,
                 t.consistency, (UNIXEPOCH('now') - UNIXEPOCH(t.last_update)),
                 t.free_space, t.free_inodes, t.total_space, t.total_inodes,
-                gp.p_target_id, gs.s_target_id
+                gp.p_target_id, gs.s_target_id, t.cap_pool,
+                (UNIXEPOCH('now') - UNIXEPOCH(t.last_capacity_update))
             FROM targets_ext AS t
             LEFT JOIN nodes_ext AS n USING(node_uid)
             LEFT JOIN pools_ext AS p USING(node_type, pool_id)
@@ -81,7 +83,7 @@ pub(crate) async fn get_targets(
                 },
 
                 reachability_state: if !pre_shutdown || is_secondary {
-                    if !is_primary && age > node_offline_timeout {
+                    if !is_primary && age > node_offline_timeout + node_offline_grace {
                         pb::ReachabilityState::Offline
                     } else if age > node_offline_timeout / 2 {
                         pb::ReachabilityState::Poffline
@@ -96,9 +98,15 @@ pub(crate) async fn get_targets(
                 last_contact_s: row.get(11)?,
                 free_space_bytes: row.get(12)?,
                 free_inodes: row.get(13)?,
-                cap_pool: pb::CapacityPool::Unspecified.into(),
+                // The last stored classification, used as the previous pool when determining
+                // the current one below
+                cap_pool: CapacityPool::from_row_opt(row, 18)?
+                    .map(CapacityPool::into_proto_i32)
+                    .unwrap_or(pb::CapacityPool::Unspecified.into()),
                 total_space_bytes: row.get(14)?,
                 total_inodes: row.get(15)?,
+                // None if the target never reported its capacity
+                last_capacity_report_s: row.get(19)?,
             })
         };
 
@@ -131,7 +139,10 @@ pub(crate) async fn get_targets(
         if let Some(fs) = t.free_space_bytes
             && let Some(fi) = t.free_inodes
         {
-            t.cap_pool = pb::CapacityPool::from(cap_pool_meta_calc.cap_pool(fs, fi)).into();
+            let previous = CapacityPool::try_from(t.cap_pool()).ok();
+            t.cap_pool =
+                pb::CapacityPool::from(cap_pool_meta_calc.cap_pool_with_previous(fs, fi, previous))
+                    .into();
         }
     }
 
@@ -160,10 +171,60 @@ pub(crate) async fn get_targets(
             if let Some(fs) = t.free_space_bytes
                 && let Some(fi) = t.free_inodes
             {
-                t.cap_pool = pb::CapacityPool::from(cap_pool_storage_calc.cap_pool(fs, fi)).into();
+                let previous = CapacityPool::try_from(t.cap_pool()).ok();
+                t.cap_pool = pb::CapacityPool::from(
+                    cap_pool_storage_calc.cap_pool_with_previous(fs, fi, previous),
+                )
+                .into();
             }
         }
     }
 
     Ok(pm::GetTargetsResponse { targets })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn last_capacity_report() {
+        let app = TestApp::new().await;
+
+        app.write_tx(|tx| {
+            tx.execute(
+                sql!(
+                    "UPDATE targets SET last_capacity_update = DATETIME('now', '-1 hour')
+                    WHERE target_uid = 202001"
+                ),
+                [],
+            )?;
+            tx.execute(
+                sql!(
+                    "UPDATE targets SET last_capacity_update = DATETIME('now')
+                    WHERE target_uid = 202002"
+                ),
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let resp = get_targets(&app, pm::GetTargetsRequest {}).await.unwrap();
+        let age = |uid: Uid| {
+            resp.targets
+                .iter()
+                .find(|t| t.id.as_ref().unwrap().uid == Some(uid))
+                .unwrap()
+                .last_capacity_report_s
+        };
+
+        // Allow for a slow test run
+        assert!((3600..3610).contains(&age(202001).unwrap()));
+        assert!(age(202002).unwrap() < 10);
+        // Never reported
+        assert_eq!(age(202003), None);
+    }
+}
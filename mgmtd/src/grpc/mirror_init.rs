@@ -0,0 +1,281 @@
+use super::*;
+use db::misc::MetaRoot;
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+/// The interval in which the primary targets state is checked while waiting for it to come online
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sets up metadata mirroring for the root directory in one go.
+///
+/// Creates the meta buddy group containing the root inode target as primary (unless it already
+/// exists), waits for the primary target to be reported as online and then mirrors the root inode.
+/// Mirroring the root inode before the root meta node has seen the new group and reported back
+/// would fail, so the ordering matters.
+pub(crate) async fn mirror_init(
+    app: &impl App,
+    req: pm::MirrorInitRequest,
+) -> Result<pm::MirrorInitResponse> {
+    fail_on_missing_license(app, LicensedFeature::Mirroring)?;
+    fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
+
+    let offline_timeout = app.dynamic_info().node_offline_timeout;
+    let wait_timeout = req
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(offline_timeout);
+
+    let (root_target_uid, root_target_id, existing_group) = app
+        .read_tx(|tx| {
+            if let MetaRoot::Mirrored(_) = db::misc::get_meta_root(tx)? {
+                return Err(anyhow!("Root inode is already mirrored"))
+                    .status_code(Code::AlreadyExists);
+            }
+
+            let (target_uid, target_id): (Uid, TargetId) = tx
+                .query_row(
+                    sql!(
+                        "SELECT t.target_uid, t.target_id FROM root_inode AS ri
+                        INNER JOIN targets AS t USING(node_type, target_id)"
+                    ),
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?
+                .ok_or_else(|| anyhow!("Root inode unknown"))?;
+
+            let is_secondary = tx.query_row(
+                sql!("SELECT COUNT(*) FROM buddy_groups WHERE node_type = ?1 AND s_target_id = ?2"),
+                params![NodeType::Meta.sql_variant(), target_id],
+                |row| row.get::<_, i64>(0),
+            )? > 0;
+
+            if is_secondary {
+                bail!(
+                    "The meta target {target_id} holding the root inode is the secondary target of \
+a buddy group. It must be the primary target"
+                );
+            }
+
+            let existing_group: Option<(Uid, String, BuddyGroupId)> = tx
+                .query_row(
+                    sql!(
+                        "SELECT g.group_uid, e.alias, g.group_id FROM buddy_groups AS g
+                        INNER JOIN entities AS e ON e.uid = g.group_uid
+                        WHERE g.node_type = ?1 AND g.p_target_id = ?2"
+                    ),
+                    params![NodeType::Meta.sql_variant(), target_id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?;
+
+            Ok((target_uid, target_id, existing_group))
+        })
+        .await?;
+
+    // Create the root meta buddy group first, if it doesn't exist yet
+    let group: pb::EntityIdSet = match existing_group {
+        Some((uid, alias, group_id)) => {
+            log::info!("Using existing meta buddy group {group_id} for mirroring the root inode");
+
+            EntityIdSet {
+                uid,
+                alias: alias.try_into()?,
+                legacy_id: LegacyId {
+                    node_type: NodeType::Meta,
+                    num_id: group_id.into(),
+                },
+            }
+            .into()
+        }
+        None => super::create_buddy_group::create_buddy_group(
+            app,
+            pm::CreateBuddyGroupRequest {
+                node_type: pb::NodeType::Meta.into(),
+                alias: Some(required_field(req.alias)?),
+                num_id: req.num_id,
+                primary_target: Some(EntityId::Uid(root_target_uid).into()),
+                secondary_target: Some(required_field(req.secondary_target)?),
+                check_only: None,
+            },
+        )
+        .await?
+        .group
+        .ok_or_else(|| anyhow!("Creating the root meta buddy group returned no group"))?,
+    };
+
+    // Wait for the primary target to leave the probably offline state
+    let start = Instant::now();
+    loop {
+        let age: u64 = app
+            .read_tx(move |tx| {
+                tx.query_row(
+                    sql!(
+                        "SELECT UNIXEPOCH('now') - UNIXEPOCH(last_update) FROM targets
+                        WHERE node_type = ?1 AND target_id = ?2"
+                    ),
+                    params![NodeType::Meta.sql_variant(), root_target_id],
+                    |row| row.get(0),
+                )
+                .map_err(Into::into)
+            })
+            .await?;
+
+        if Duration::from_secs(age) <= offline_timeout / 2 {
+            break;
+        }
+
+        if start.elapsed() >= wait_timeout {
+            bail!(
+                "The primary meta target {root_target_id} did not come online within {}s. Make \
+sure the root meta node is running and try again",
+                wait_timeout.as_secs()
+            );
+        }
+
+        sleep(POLL_INTERVAL).await;
+    }
+
+    super::mirror_root_inode::mirror_root_inode(app, pm::MirrorRootInodeRequest {}).await?;
+
+    Ok(pm::MirrorInitResponse { group: Some(group) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::OpsErr;
+    use shared::bee_msg::buddy_group::{SetMetadataMirroringResp, SetMirrorBuddyGroup};
+    use std::any::Any;
+
+    /// Removes the clients and makes all nodes except the root meta node look offline, as
+    /// required by mirroring the root inode.
+    async fn prepare(app: &TestApp) {
+        app.write_tx(|tx| {
+            tx.execute(
+                "DELETE FROM nodes WHERE node_type = ?1",
+                [NodeType::Client.sql_variant()],
+            )?;
+            tx.execute(
+                "UPDATE nodes SET last_contact = DATETIME(0) WHERE node_uid != 101001",
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        app.set_request_handler(|_: &dyn Any| {
+            Ok(Box::new(SetMetadataMirroringResp {
+                result: OpsErr::SUCCESS,
+            }))
+        });
+    }
+
+    #[tokio::test]
+    async fn mirror_init_with_other_groups() {
+        let app = TestApp::new().await;
+        prepare(&app).await;
+
+        // Replace the existing root meta group by an unrelated one
+        app.write_tx(|tx| {
+            tx.execute("DELETE FROM buddy_groups WHERE group_uid = 301001", [])?;
+            db::buddy_group::insert(tx, 2, None, NodeTypeServer::Meta, 3, 4)?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let resp = super::mirror_init(
+            &app,
+            pm::MirrorInitRequest {
+                alias: Some("root_group".to_string()),
+                num_id: None,
+                secondary_target: Some(EntityId::Uid(201002).into()),
+                timeout_secs: Some(0),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(resp.group.unwrap().alias.unwrap(), "root_group");
+        assert!(app.has_sent_notification::<SetMirrorBuddyGroup>(&[
+            NodeType::Meta,
+            NodeType::Storage,
+            NodeType::Client
+        ]));
+        assert_eq_db!(
+            app,
+            "SELECT g.s_target_id FROM root_inode AS ri
+            INNER JOIN buddy_groups AS g USING(node_type, group_id)",
+            [],
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn mirror_init_existing_group() {
+        let app = TestApp::new().await;
+        prepare(&app).await;
+
+        let resp = super::mirror_init(
+            &app,
+            pm::MirrorInitRequest {
+                alias: None,
+                num_id: None,
+                secondary_target: None,
+                timeout_secs: Some(0),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(resp.group.unwrap().uid.unwrap(), 301001);
+        assert_eq!(app.sent_notifications::<SetMirrorBuddyGroup>(), 0);
+        assert_eq_db!(app, "SELECT group_id FROM root_inode", [], 1);
+
+        // Already mirrored
+        let err = super::mirror_init(
+            &app,
+            pm::MirrorInitRequest {
+                alias: None,
+                num_id: None,
+                secondary_target: None,
+                timeout_secs: Some(0),
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(process_grpc_handler_error(err).code(), Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn mirror_init_primary_offline() {
+        let app = TestApp::new().await;
+        prepare(&app).await;
+
+        app.write_tx(|tx| {
+            tx.execute("UPDATE targets SET last_update = DATETIME(0)", [])?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let err = super::mirror_init(
+            &app,
+            pm::MirrorInitRequest {
+                alias: None,
+                num_id: None,
+                secondary_target: None,
+                timeout_secs: Some(0),
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.to_string().contains("did not come online"));
+        assert_eq_db!(app, "SELECT group_id FROM root_inode", [], None::<i64>);
+    }
+}
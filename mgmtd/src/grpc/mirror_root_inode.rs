@@ -10,14 +10,21 @@ pub(crate) async fn mirror_root_inode(
 ) -> Result<pm::MirrorRootInodeResponse> {
     fail_on_missing_license(app, LicensedFeature::Mirroring)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
-    let offline_timeout = app.static_info().user_config.node_offline_timeout.as_secs();
+    let audit = Audit::new("Mirror root inode");
+    let offline_timeout = app.dynamic_info().node_offline_timeout.as_secs();
     let meta_root = app
         .read_tx(move |tx| {
             let node_uid = match db::misc::get_meta_root(tx)? {
                 MetaRoot::Normal(_, node_uid) => node_uid,
-                MetaRoot::Mirrored(_) => bail!("Root inode is already mirrored"),
-                MetaRoot::Unknown => bail!("Root inode unknown"),
+                MetaRoot::Mirrored(_) => {
+                    return Err(anyhow!("Root inode is already mirrored"))
+                        .status_code(Code::AlreadyExists);
+                }
+                MetaRoot::Unknown => {
+                    return Err(anyhow!("Root inode unknown")).status_code(Code::NotFound);
+                }
             };
 
             let count = tx.query_row(
@@ -31,7 +38,10 @@ pub(crate) async fn mirror_root_inode(
             )?;
 
             if count < 1 {
-                bail!("The meta target holding the root inode is not part of a buddy group.");
+                return Err(anyhow!(
+                    "The meta target holding the root inode is not part of a buddy group."
+                ))
+                .status_code(Code::FailedPrecondition);
             }
 
             // Check that no clients are connected to prevent data corruption. Note that there is
@@ -43,10 +53,11 @@ pub(crate) async fn mirror_root_inode(
             })?;
 
             if clients > 0 {
-                bail!(
+                return Err(anyhow!(
                     "This operation requires that all clients are disconnected/unmounted. \
 {clients} clients are still mounted."
-                );
+                ))
+                .status_code(Code::FailedPrecondition);
             }
 
             let mut server_stmt = tx.prepare(sql!(
@@ -65,11 +76,12 @@ pub(crate) async fn mirror_root_inode(
             )?;
 
             if metas > 0 || storages > 0 {
-                bail!(
+                return Err(anyhow!(
                     "This operation requires that all nodes except the root meta node are shut \
 down. {metas} meta nodes (excluding the root meta node) and {storages} storage nodes have \
 communicated during the last {offline_timeout}s."
-                );
+                ))
+                .status_code(Code::FailedPrecondition);
             }
 
             Ok(node_uid)
@@ -79,7 +91,13 @@ communicated during the last {offline_timeout}s."
     let resp: SetMetadataMirroringResp = app.request(meta_root, &SetMetadataMirroring {}).await?;
 
     match resp.result {
-        OpsErr::SUCCESS => app.write_tx(db::misc::enable_metadata_mirroring).await?,
+        OpsErr::SUCCESS => {
+            app.write_tx(move |tx| {
+                db::misc::enable_metadata_mirroring(tx)?;
+                audit.record(tx, "root inode")
+            })
+            .await?
+        }
         _ => bail!(
             "The root meta server failed to mirror the root inode: {:?}",
             resp.result
@@ -89,3 +107,53 @@ communicated during the last {offline_timeout}s."
     log::info!("Root inode has been mirrored");
     Ok(pm::MirrorRootInodeResponse {})
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    async fn mirror(app: &TestApp) -> Status {
+        let err = mirror_root_inode(app, pm::MirrorRootInodeRequest {})
+            .await
+            .unwrap_err();
+        process_grpc_handler_error(err)
+    }
+
+    #[tokio::test]
+    async fn preconditions() {
+        let app = TestApp::new().await;
+
+        // Root meta target not in a buddy group
+        app.write_tx(|tx| {
+            tx.execute(sql!("UPDATE root_inode SET target_id = 3"), [])?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let status = mirror(&app).await;
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.message().contains("not part of a buddy group"));
+
+        // Clients are still mounted
+        app.write_tx(|tx| {
+            tx.execute(sql!("UPDATE root_inode SET target_id = 1"), [])?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let status = mirror(&app).await;
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.message().contains("4 clients are still mounted"));
+
+        // Already mirrored
+        app.write_tx(db::misc::enable_metadata_mirroring)
+            .await
+            .unwrap();
+
+        let status = mirror(&app).await;
+        assert_eq!(status.code(), Code::AlreadyExists);
+    }
+}
@@ -0,0 +1,37 @@
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Echoes the nonce sent by the client together with the current server time.
+///
+/// Meant for checking responsiveness and measuring the round-trip latency. Deliberately does not
+/// fail on pre shutdown, so it keeps answering while the management is draining.
+pub(crate) async fn ping(_app: &impl App, req: pm::PingRequest) -> Result<pm::PingResponse> {
+    let server_time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+    Ok(pm::PingResponse {
+        nonce: req.nonce,
+        server_time_unix_nanos: Some(server_time.as_nanos().try_into()?),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn ping() {
+        let app = TestApp::new().await;
+
+        let resp = super::ping(
+            &app,
+            pm::PingRequest {
+                nonce: Some(0xdead_beef),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.nonce, Some(0xdead_beef));
+        assert!(resp.server_time_unix_nanos.unwrap() > 0);
+    }
+}
@@ -2,89 +2,127 @@ use super::*;
 use db::node_nic::map_bee_msg_nics;
 use shared::bee_msg::node::Heartbeat;
 
-/// Sets the entity alias for any entity
+/// Sets the entity alias for any entity.
+///
+/// The new alias must be valid and not in use by any other entity. Fails with `InvalidArgument` or
+/// `AlreadyExists` otherwise.
 pub(crate) async fn set_alias(
     app: &impl App,
     req: pm::SetAliasRequest,
 ) -> Result<pm::SetAliasResponse> {
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     // Parse proto msg
     let entity_type: EntityType = req.entity_type().try_into()?;
     let entity_id: EntityId = required_field(req.entity_id)?.try_into()?;
-    let new_alias: Alias = req.new_alias.try_into()?;
+    let new_alias: Alias = req
+        .new_alias
+        .try_into()
+        .status_code(Code::InvalidArgument)?;
+    let audit = Audit::new("Set alias");
+
+    let renamed_node = app
+        .write_tx(move |tx| {
+            let entity = update_alias(tx, entity_type, &entity_id, &new_alias, &audit)?;
+            renamed_node(tx, entity_type, entity, &new_alias)
+        })
+        .await?;
+
+    if let Some(node) = renamed_node {
+        notify_renamed_node(app, node).await;
+    }
 
-    let update_alias_fn = move |tx: &Transaction, new_alias: &Alias| -> Result<EntityIdSet> {
-        let entity = entity_id.resolve(tx, entity_type)?;
+    Ok(pm::SetAliasResponse {})
+}
 
-        if entity.node_type() == NodeType::Client {
-            bail!("Client updates are not supported")
-        }
+/// A node whose alias has been changed, with the info needed to notify the other nodes
+#[derive(Debug)]
+pub(super) struct RenamedNode {
+    entity: EntityIdSet,
+    node: db::node::Node,
+    nic_list: Vec<db::node_nic::NodeNic>,
+}
 
-        // Check that the alias is not in use yet
-        let et: Option<EntityType> = tx
-            .query_row_cached(
-                sql!("SELECT entity_type FROM entities WHERE alias = ?1"),
-                [new_alias.as_ref()],
-                |row| EntityType::from_row(row, 0),
-            )
-            .optional()?;
+/// Sets the alias of an entity within a transaction.
+///
+/// The new alias must not be in use by any other entity. Fails with `AlreadyExists` otherwise.
+pub(super) fn update_alias(
+    tx: &Transaction,
+    entity_type: EntityType,
+    entity_id: &EntityId,
+    new_alias: &Alias,
+    audit: &Audit,
+) -> Result<EntityIdSet> {
+    let entity = entity_id.resolve(tx, entity_type)?;
+
+    if entity.node_type() == NodeType::Client {
+        bail!("Client updates are not supported")
+    }
 
-        if let Some(et) = et {
-            bail!(
-                "Alias {} is already in use by a {}",
-                new_alias,
-                et.user_str()
-            );
-        }
+    // Check that the alias is not in use yet. This happens within the same transaction as the
+    // update, so no other request can take the alias in between.
+    if db::entity::get_uid(tx, new_alias.as_ref())?.is_some() {
+        return Err(anyhow!(TypedError::value_exists("Alias", new_alias)))
+            .status_code(Code::AlreadyExists);
+    }
 
-        tx.execute_cached(
-            sql!("UPDATE entities SET alias = ?1 WHERE uid = ?2"),
-            params![new_alias.as_ref(), entity.uid],
-        )?;
-
-        Ok(entity)
-    };
-
-    // If the entity is a node, notify all nodes about the changed alias
-    if entity_type == EntityType::Node {
-        let (entity, node, nic_list) = app
-            .write_tx(move |tx| {
-                let entity = update_alias_fn(tx, &new_alias)?;
-
-                let node = db::node::get_by_alias(tx, new_alias.as_ref())?;
-                let nic_list = db::node_nic::get_with_node(tx, entity.uid)?;
-
-                Ok((entity, node, nic_list))
-            })
-            .await?;
-
-        app.send_notifications(
-            &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-            &Heartbeat {
-                instance_version: 0,
-                nic_list_version: 0,
-                node_type: entity.node_type(),
-                node_alias: node.alias.into_bytes(),
-                ack_id: "".into(),
-                node_num_id: entity.num_id(),
-                root_num_id: 0,
-                is_root_mirrored: 0,
-                port: node.port,
-                port_tcp_unused: node.port,
-                nic_list: map_bee_msg_nics(nic_list).collect(),
-                machine_uuid: vec![],
-            },
-        )
-        .await;
+    let affected = tx.execute_cached(
+        sql!("UPDATE entities SET alias = ?1 WHERE uid = ?2"),
+        params![new_alias.as_ref(), entity.uid],
+    )?;
 
-    // If not a node, just update the alias
-    } else {
-        app.write_tx(move |tx| update_alias_fn(tx, &new_alias))
-            .await?;
+    check_affected_rows(affected, [1])?;
+
+    audit.record(tx, format!("{entity} -> {new_alias}"))?;
+
+    Ok(entity)
+}
+
+/// Fetches the info for notifying the other nodes about the changed alias, if `entity` is a node
+pub(super) fn renamed_node(
+    tx: &Transaction,
+    entity_type: EntityType,
+    entity: EntityIdSet,
+    new_alias: &Alias,
+) -> Result<Option<RenamedNode>> {
+    if entity_type != EntityType::Node {
+        return Ok(None);
     }
 
-    Ok(pm::SetAliasResponse {})
+    Ok(Some(RenamedNode {
+        node: db::node::get_by_alias(tx, new_alias.as_ref())?,
+        nic_list: db::node_nic::get_with_node(tx, entity.uid)?,
+        entity,
+    }))
+}
+
+/// Notifies all nodes about the changed alias of a node
+pub(super) async fn notify_renamed_node(app: &impl App, renamed: RenamedNode) {
+    let RenamedNode {
+        entity,
+        node,
+        nic_list,
+    } = renamed;
+
+    app.send_notifications(
+        &[NodeType::Meta, NodeType::Storage, NodeType::Client],
+        &Heartbeat {
+            instance_version: 0,
+            nic_list_version: 0,
+            node_type: entity.node_type(),
+            node_alias: node.alias.into_bytes(),
+            ack_id: AckId::empty(),
+            node_num_id: entity.num_id(),
+            root_num_id: 0,
+            is_root_mirrored: 0,
+            port: node.port,
+            port_tcp_unused: node.port,
+            nic_list: map_bee_msg_nics(nic_list).collect(),
+            machine_uuid: vec![],
+        },
+    )
+    .await;
 }
 
 #[cfg(test)]
@@ -121,7 +159,7 @@ mod test {
         .unwrap_err();
 
         // Alias already in use
-        super::set_alias(
+        let err = super::set_alias(
             &app,
             pm::SetAliasRequest {
                 entity_id: Some(EntityId::Alias("meta_node_1".try_into().unwrap()).into()),
@@ -131,6 +169,32 @@ mod test {
         )
         .await
         .unwrap_err();
+        assert_eq!(process_grpc_handler_error(err).code(), Code::AlreadyExists);
+
+        // Invalid alias
+        for new_alias in ["", "1starts_with_digit", "contains space", "in/valid"] {
+            let err = super::set_alias(
+                &app,
+                pm::SetAliasRequest {
+                    entity_id: Some(EntityId::Uid(101001).into()),
+                    entity_type: pb::EntityType::Node.into(),
+                    new_alias: new_alias.to_string(),
+                },
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(
+                process_grpc_handler_error(err).code(),
+                Code::InvalidArgument
+            );
+        }
+
+        assert_eq_db!(
+            app,
+            "SELECT alias FROM entities WHERE uid = ?1",
+            [101001],
+            "meta_node_1"
+        );
 
         // Deny setting client aliases
         super::set_alias(
@@ -0,0 +1,154 @@
+use super::*;
+use set_alias::{notify_renamed_node, renamed_node, update_alias};
+
+/// Sets the aliases of multiple entities at once.
+///
+/// All aliases are set within a single transaction. If any of the entries is invalid (e.g. the
+/// entity doesn't exist or the alias is invalid or already in use), none of the aliases is changed
+/// and the error names the offending entry. Nodes are notified only after all aliases have been set.
+pub(crate) async fn set_aliases(
+    app: &impl App,
+    req: pm::SetAliasesRequest,
+) -> Result<pm::SetAliasesResponse> {
+    fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
+
+    let entries = req
+        .aliases
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| parse_entry(entry).with_context(|| format!("Invalid entry {i}")))
+        .collect::<Result<Vec<_>>>()?;
+    let audit = Audit::new("Set alias");
+
+    let renamed_nodes = app
+        .write_tx(move |tx| {
+            let mut renamed_nodes = vec![];
+
+            for (entity_type, entity_id, new_alias) in entries {
+                let entity = update_alias(tx, entity_type, &entity_id, &new_alias, &audit)
+                    .with_context(|| {
+                        format!("Setting alias of {entity_id} to {new_alias} failed")
+                    })?;
+                renamed_nodes.extend(renamed_node(tx, entity_type, entity, &new_alias)?);
+            }
+
+            Ok(renamed_nodes)
+        })
+        .await?;
+
+    for node in renamed_nodes {
+        notify_renamed_node(app, node).await;
+    }
+
+    Ok(pm::SetAliasesResponse {})
+}
+
+/// Parses one entry of a [pm::SetAliasesRequest]
+fn parse_entry(entry: pm::SetAliasRequest) -> Result<(EntityType, EntityId, Alias)> {
+    let entity_type: EntityType = entry.entity_type().try_into()?;
+    let entity_id: EntityId = required_field(entry.entity_id)?.try_into()?;
+    let new_alias: Alias = entry
+        .new_alias
+        .try_into()
+        .status_code(Code::InvalidArgument)?;
+
+    Ok((entity_type, entity_id, new_alias))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use shared::bee_msg::node::Heartbeat;
+
+    fn entry(uid: Uid, entity_type: pb::EntityType, new_alias: &str) -> pm::SetAliasRequest {
+        pm::SetAliasRequest {
+            entity_id: Some(EntityId::Uid(uid).into()),
+            entity_type: entity_type.into(),
+            new_alias: new_alias.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn set_aliases() {
+        let app = TestApp::new().await;
+
+        let aliases = |app: &TestApp| {
+            let app = app.clone();
+            async move {
+                app.read_tx(|tx| {
+                    Ok(tx.query_map_collect(
+                        sql!(
+                            "SELECT alias FROM entities WHERE uid IN (101001, 202001, 202002)
+                            ORDER BY uid"
+                        ),
+                        [],
+                        |row| row.get::<_, String>(0),
+                    )?)
+                })
+                .await
+                .unwrap()
+            }
+        };
+        let before = aliases(&app).await;
+
+        // One invalid entry fails the whole batch and is named in the error
+        for (invalid, name) in [
+            (
+                entry(999999, pb::EntityType::Target, "new_target_3"),
+                "999999",
+            ),
+            (
+                entry(202003, pb::EntityType::Target, "storage_target_1"),
+                "storage_target_1",
+            ),
+            (entry(202003, pb::EntityType::Target, "in valid"), "entry 2"),
+        ] {
+            let err = super::set_aliases(
+                &app,
+                pm::SetAliasesRequest {
+                    aliases: vec![
+                        entry(202001, pb::EntityType::Target, "new_target_1"),
+                        entry(101001, pb::EntityType::Node, "new_node_1"),
+                        invalid,
+                    ],
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(format!("{err:#}").contains(name), "{err:#}");
+        }
+
+        assert_eq!(aliases(&app).await, before);
+        assert!(!app.has_sent_notification::<Heartbeat>(&[
+            NodeType::Meta,
+            NodeType::Storage,
+            NodeType::Client,
+        ]));
+
+        // Success
+        super::set_aliases(
+            &app,
+            pm::SetAliasesRequest {
+                aliases: vec![
+                    entry(202001, pb::EntityType::Target, "new_target_1"),
+                    entry(202002, pb::EntityType::Target, "new_target_2"),
+                    entry(101001, pb::EntityType::Node, "new_node_1"),
+                ],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            aliases(&app).await,
+            ["new_node_1", "new_target_1", "new_target_2"]
+        );
+        assert!(app.has_sent_notification::<Heartbeat>(&[
+            NodeType::Meta,
+            NodeType::Storage,
+            NodeType::Client,
+        ]));
+    }
+}
@@ -2,29 +2,41 @@ use super::common::QUOTA_NOT_ENABLED_STR;
 use super::*;
 use std::cmp::Ordering;
 
+/// Sets the default quota limits of a pool or, if `system_wide` is set instead of a pool, the
+/// system wide ones.
+///
+/// A limit is resolved in the order specific limit, pool default limit, system default limit. If
+/// none of them is set, there is no limit.
 pub(crate) async fn set_default_quota_limits(
     app: &impl App,
     req: pm::SetDefaultQuotaLimitsRequest,
 ) -> Result<pm::SetDefaultQuotaLimitsResponse> {
     fail_on_missing_license(app, LicensedFeature::Quota)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     if !app.static_info().user_config.quota_enable {
         bail!(QUOTA_NOT_ENABLED_STR);
     }
 
-    let pool: EntityId = required_field(req.pool)?.try_into()?;
+    let pool: Option<EntityId> = match (req.pool, req.system_wide.unwrap_or_default()) {
+        (Some(pool), false) => Some(pool.try_into()?),
+        (None, true) => None,
+        (Some(_), true) => bail!("A pool can't be given when setting the system wide defaults"),
+        (None, false) => bail!("Either a pool or system_wide must be given"),
+    };
+    let audit = Audit::new("Set default quota limits");
 
     fn update(
         tx: &Transaction,
         limit: i64,
-        pool_id: PoolId,
+        pool_id: Option<PoolId>,
         id_type: QuotaIdType,
         quota_type: QuotaType,
     ) -> Result<()> {
-        match limit.cmp(&-1) {
-            Ordering::Less => bail!("invalid {id_type} {quota_type} limit {limit}"),
-            Ordering::Equal => {
+        match (limit.cmp(&-1), pool_id) {
+            (Ordering::Less, _) => bail!("invalid {id_type} {quota_type} limit {limit}"),
+            (Ordering::Equal, Some(pool_id)) => {
                 tx.execute_cached(
                     sql!(
                         "DELETE FROM quota_default_limits
@@ -33,7 +45,16 @@ pub(crate) async fn set_default_quota_limits(
                     params![pool_id, id_type.sql_variant(), quota_type.sql_variant()],
                 )?;
             }
-            Ordering::Greater => {
+            (Ordering::Equal, None) => {
+                tx.execute_cached(
+                    sql!(
+                        "DELETE FROM quota_system_default_limits
+                        WHERE id_type = ?1 AND quota_type = ?2"
+                    ),
+                    params![id_type.sql_variant(), quota_type.sql_variant()],
+                )?;
+            }
+            (Ordering::Greater, Some(pool_id)) => {
                 tx.execute_cached(
                     sql!(
                         "REPLACE INTO quota_default_limits (pool_id, id_type, quota_type, value)
@@ -47,14 +68,23 @@ pub(crate) async fn set_default_quota_limits(
                     ],
                 )?;
             }
+            (Ordering::Greater, None) => {
+                tx.execute_cached(
+                    sql!(
+                        "REPLACE INTO quota_system_default_limits (id_type, quota_type, value)
+                        VALUES(?1, ?2, ?3)"
+                    ),
+                    params![id_type.sql_variant(), quota_type.sql_variant(), limit],
+                )?;
+            }
         }
 
         Ok(())
     }
 
     app.write_tx(move |tx| {
-        let pool = pool.resolve(tx, EntityType::Pool)?;
-        let pool_id: PoolId = pool.num_id().try_into()?;
+        let pool = pool.map(|p| p.resolve(tx, EntityType::Pool)).transpose()?;
+        let pool_id: Option<PoolId> = pool.as_ref().map(|p| p.num_id().try_into()).transpose()?;
 
         if let Some(l) = req.user_space_limit {
             update(tx, l, pool_id, QuotaIdType::User, QuotaType::Space)?;
@@ -68,6 +98,24 @@ pub(crate) async fn set_default_quota_limits(
         if let Some(l) = req.group_inode_limit {
             update(tx, l, pool_id, QuotaIdType::Group, QuotaType::Inode)?;
         }
+        if let Some(l) = req.project_space_limit {
+            update(tx, l, pool_id, QuotaIdType::Project, QuotaType::Space)?;
+        }
+        if let Some(l) = req.project_inode_limit {
+            update(tx, l, pool_id, QuotaIdType::Project, QuotaType::Inode)?;
+        }
+
+        match &pool {
+            Some(pool) => audit.record(tx, pool)?,
+            None => audit.record(tx, "system")?,
+        }
+
+        match pool_id {
+            Some(pool_id) => {
+                db::quota::update_exceeded(tx, db::quota::ExceededScope::Pools(&[pool_id]))?
+            }
+            None => db::quota::update_exceeded(tx, db::quota::ExceededScope::All)?,
+        };
 
         Ok(())
     })
@@ -75,3 +123,198 @@ pub(crate) async fn set_default_quota_limits(
 
     Ok(pm::SetDefaultQuotaLimitsResponse {})
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use crate::grpc::get_quota_limits::get_quota_limits;
+    use crate::grpc::get_quota_usage::get_quota_usage;
+    use std::collections::HashMap;
+    use tokio_stream::StreamExt;
+
+    /// Returns the effective (space, inode) limits per (quota_id, pool_id)
+    async fn limits(
+        app: &TestApp,
+        req: pm::GetQuotaUsageRequest,
+    ) -> HashMap<(u32, u32), (Option<i64>, Option<i64>)> {
+        get_quota_usage(app, req)
+            .await
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap().entry.unwrap();
+                (
+                    (
+                        e.quota_id.unwrap(),
+                        e.pool.unwrap().legacy_id.unwrap().num_id,
+                    ),
+                    (e.space_limit, e.inode_limit),
+                )
+            })
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn system_default_limits() {
+        let app = TestApp::with_config(Config {
+            quota_enable: true,
+            ..Default::default()
+        })
+        .await;
+
+        // Neither or both of pool and system wide are rejected
+        for (pool, system_wide) in [(None, None), (Some(pb::EntityIdSet::default()), Some(true))] {
+            set_default_quota_limits(
+                &app,
+                pm::SetDefaultQuotaLimitsRequest {
+                    pool,
+                    system_wide,
+                    user_space_limit: Some(5000),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+        }
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_system_default_limits",
+            [],
+            0
+        );
+
+        // System wide defaults for users
+        set_default_quota_limits(
+            &app,
+            pm::SetDefaultQuotaLimitsRequest {
+                pool: None,
+                system_wide: Some(true),
+                user_space_limit: Some(5000),
+                user_inode_limit: Some(7000),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // The system wide defaults are delivered by GetQuotaLimits if no pool is requested
+        let system_defaults = |pool| {
+            let app = app.clone();
+            async move {
+                get_quota_limits(
+                    &app,
+                    pm::GetQuotaLimitsRequest {
+                        pool,
+                        user_id_min: Some(0),
+                        group_id_min: Some(0),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap()
+                .map(|e| e.unwrap().limits.unwrap())
+                .filter(|e| e.pool.is_none())
+                .collect::<Vec<_>>()
+                .await
+            }
+        };
+
+        let defaults = system_defaults(None).await;
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].id_type, QuotaIdType::User.into_proto_i32());
+        assert_eq!(defaults[0].quota_id, None);
+        assert_eq!(defaults[0].space_limit, Some(5000));
+        assert_eq!(defaults[0].inode_limit, Some(7000));
+
+        assert!(
+            system_defaults(Some(pb::EntityIdSet {
+                uid: None,
+                legacy_id: Some(pb::LegacyId {
+                    num_id: 1,
+                    node_type: pb::NodeType::Storage.into(),
+                }),
+                alias: None,
+            }))
+            .await
+            .is_empty()
+        );
+
+        // Remove the pool 1 default user inode limit, so the system default applies
+        set_default_quota_limits(
+            &app,
+            pm::SetDefaultQuotaLimitsRequest {
+                pool: Some(pb::EntityIdSet {
+                    uid: None,
+                    legacy_id: Some(pb::LegacyId {
+                        num_id: 1,
+                        node_type: pb::NodeType::Storage.into(),
+                    }),
+                    alias: None,
+                }),
+                user_inode_limit: Some(-1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let users = limits(
+            &app,
+            pm::GetQuotaUsageRequest {
+                user_id_min: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Specific limits take precedence
+        assert_eq!(users[&(1, 1)], (Some(10000), Some(10000)));
+        // Pool default before system default
+        assert_eq!(users[&(3, 1)], (Some(1000), Some(7000)));
+        // Pool 2 has no defaults, so the system defaults apply
+        assert_eq!(users[&(10, 2)], (Some(5000), Some(7000)));
+        assert_eq!(users[&(20, 2)].0, Some(100));
+
+        // There are no group defaults for pool 2, neither pool nor system wide
+        let groups = limits(
+            &app,
+            pm::GetQuotaUsageRequest {
+                group_id_min: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(groups[&(10, 2)], (Some(-1), Some(-1)));
+
+        // Removing the system default restores unlimited
+        set_default_quota_limits(
+            &app,
+            pm::SetDefaultQuotaLimitsRequest {
+                pool: None,
+                system_wide: Some(true),
+                user_space_limit: Some(-1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_system_default_limits",
+            [],
+            1
+        );
+
+        let users = limits(
+            &app,
+            pm::GetQuotaUsageRequest {
+                user_id_min: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(users[&(10, 2)], (Some(-1), Some(7000)));
+    }
+}
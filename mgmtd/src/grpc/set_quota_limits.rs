@@ -7,72 +7,131 @@ pub(crate) async fn set_quota_limits(
 ) -> Result<pm::SetQuotaLimitsResponse> {
     fail_on_missing_license(app, LicensedFeature::Quota)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     if !app.static_info().user_config.quota_enable {
         bail!(QUOTA_NOT_ENABLED_STR);
     }
 
-    app.write_tx(|tx| {
-        let mut insert_stmt = tx.prepare_cached(sql!(
-            "REPLACE INTO quota_limits
-                (quota_id, id_type, quota_type, pool_id, value)
-                VALUES (?1, ?2, ?3, ?4, ?5)"
-        ))?;
+    let audit = Audit::new("Set quota limits");
 
-        let mut delete_stmt = tx.prepare_cached(sql!(
-            "DELETE FROM quota_limits
-                WHERE quota_id = ?1 AND id_type = ?2 AND quota_type = ?3 AND pool_id = ?4"
-        ))?;
+    app.write_tx(move |tx| {
+        let mut changed = Vec::with_capacity(req.limits.len());
 
         for lim in req.limits {
             let id_type: QuotaIdType = lim.id_type().try_into()?;
             let quota_id = required_field(lim.quota_id)?;
 
             let pool: EntityId = required_field(lim.pool)?.try_into()?;
-            let pool_id = pool.resolve(tx, EntityType::Pool)?.num_id();
+            let pool_id = pool.resolve(tx, EntityType::Pool)?.num_id().try_into()?;
+            changed.push((quota_id, id_type, pool_id));
 
             if let Some(l) = lim.space_limit {
-                if l > -1 {
-                    insert_stmt.execute(params![
-                        quota_id,
-                        id_type.sql_variant(),
-                        QuotaType::Space.sql_variant(),
-                        pool_id,
-                        l
-                    ])?
-                } else {
-                    delete_stmt.execute(params![
-                        quota_id,
-                        id_type.sql_variant(),
-                        QuotaType::Space.sql_variant(),
-                        pool_id,
-                    ])?
-                };
+                db::quota::set_limit(tx, quota_id, id_type, QuotaType::Space, pool_id, l)?;
             }
 
             if let Some(l) = lim.inode_limit {
-                if l > -1 {
-                    insert_stmt.execute(params![
-                        quota_id,
-                        id_type.sql_variant(),
-                        QuotaType::Inode.sql_variant(),
-                        pool_id,
-                        l
-                    ])?
-                } else {
-                    delete_stmt.execute(params![
-                        quota_id,
-                        id_type.sql_variant(),
-                        QuotaType::Inode.sql_variant(),
-                        pool_id,
-                    ])?
-                };
+                db::quota::set_limit(tx, quota_id, id_type, QuotaType::Inode, pool_id, l)?;
             }
+
+            audit.record(tx, format!("{id_type} {quota_id} on pool {pool}"))?;
         }
 
+        db::quota::update_exceeded(tx, db::quota::ExceededScope::Ids(&changed))?;
+
         Ok(())
     })
     .await?;
 
     Ok(pm::SetQuotaLimitsResponse {})
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use crate::grpc::get_quota_limits::get_quota_limits;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn set_quota_limits() {
+        let app = TestApp::with_config(Config {
+            quota_enable: true,
+            ..Default::default()
+        })
+        .await;
+
+        let limit = |id_type: pb::QuotaIdType, space_limit| pm::QuotaInfo {
+            pool: Some(
+                EntityId::LegacyID(LegacyId {
+                    node_type: NodeType::Storage,
+                    num_id: 1,
+                })
+                .into(),
+            ),
+            id_type: id_type.into(),
+            quota_id: Some(100),
+            space_limit: Some(space_limit),
+            inode_limit: Some(-1),
+            space_used: None,
+            inode_used: None,
+        };
+
+        super::set_quota_limits(
+            &app,
+            pm::SetQuotaLimitsRequest {
+                limits: vec![
+                    limit(pb::QuotaIdType::User, 1000),
+                    limit(pb::QuotaIdType::Project, 2000),
+                ],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_limits WHERE quota_id = 100",
+            [],
+            2
+        );
+
+        // Read back the project limit only
+        let limits: Vec<_> = get_quota_limits(
+            &app,
+            pm::GetQuotaLimitsRequest {
+                project_id_list: vec![100],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .map(|e| e.unwrap().limits.unwrap())
+        .collect()
+        .await;
+
+        assert_eq!(limits.len(), 1);
+        assert_eq!(limits[0].id_type(), pb::QuotaIdType::Project);
+        assert_eq!(limits[0].quota_id, Some(100));
+        assert_eq!(limits[0].space_limit, Some(2000));
+        assert_eq!(limits[0].inode_limit, None);
+
+        // Removing the limit again
+        super::set_quota_limits(
+            &app,
+            pm::SetQuotaLimitsRequest {
+                limits: vec![limit(pb::QuotaIdType::Project, -1)],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM quota_limits WHERE quota_id = 100 AND id_type = ?1",
+            [QuotaIdType::Project.sql_variant()],
+            0
+        );
+    }
+}
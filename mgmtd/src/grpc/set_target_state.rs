@@ -1,8 +1,6 @@
 use super::*;
 use shared::bee_msg::OpsErr;
-use shared::bee_msg::target::{
-    RefreshTargetStates, SetTargetConsistencyStates, SetTargetConsistencyStatesResp,
-};
+use shared::bee_msg::target::{SetTargetConsistencyStates, SetTargetConsistencyStatesResp};
 
 /// Set consistency state for a target
 pub(crate) async fn set_target_state(
@@ -10,11 +8,13 @@ pub(crate) async fn set_target_state(
     req: pm::SetTargetStateRequest,
 ) -> Result<pm::SetTargetStateResponse> {
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let state: TargetConsistencyState = req.consistency_state().try_into()?;
     let target: EntityId = required_field(req.target)?.try_into()?;
+    let audit = Audit::new("Set target state");
 
-    let (target, node_uid) = app
+    let (target, node_uid, changes) = app
         .write_tx(move |tx| {
             let target = target.resolve(tx, EntityType::Target)?;
 
@@ -24,13 +24,18 @@ pub(crate) async fn set_target_state(
                 |row| row.get(0),
             )?;
 
-            db::target::update_consistency_states(
-                tx,
-                [(target.num_id().try_into()?, state)],
-                NodeTypeServer::try_from(target.node_type())?,
-            )?;
+            let changes = TargetStateChanges {
+                consistency: db::target::update_consistency_states(
+                    tx,
+                    [(target.num_id().try_into()?, state)],
+                    NodeTypeServer::try_from(target.node_type())?,
+                )?,
+                reachability: 0,
+            };
+
+            audit.record(tx, format!("{target} -> {state}"))?;
 
-            Ok((target, node))
+            Ok((target, node, changes))
         })
         .await?;
 
@@ -41,7 +46,7 @@ pub(crate) async fn set_target_state(
                 node_type: target.node_type(),
                 target_ids: vec![target.num_id().try_into().unwrap()],
                 states: vec![state],
-                ack_id: "".into(),
+                ack_id: AckId::empty(),
                 set_online: 0,
             },
         )
@@ -53,11 +58,7 @@ pub(crate) async fn set_target_state(
         );
     }
 
-    app.send_notifications(
-        &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-        &RefreshTargetStates { ack_id: "".into() },
-    )
-    .await;
+    changes.notify(app).await;
 
     Ok(pm::SetTargetStateResponse {})
 }
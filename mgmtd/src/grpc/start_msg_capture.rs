@@ -0,0 +1,124 @@
+use super::*;
+use shared::conn::msg_capture::{MSG_CAPTURE, MsgCapture};
+use std::time::Duration;
+
+/// The maximum duration of a capture
+const MAX_DURATION_SECS: u64 = 600;
+/// The default number of messages to keep
+const DEFAULT_MAX_MSGS: u32 = 1000;
+/// The maximum number of messages to keep
+const MAX_MAX_MSGS: u32 = 100_000;
+
+/// Starts capturing the incoming and outgoing BeeMsges for the requested duration.
+///
+/// Replaces a running or previous capture. The captured messages can be retrieved using
+/// `get_msg_capture`. Doesn't modify the management state, so it is allowed in read-only mode. The
+/// operation is recorded in the audit log unless running read-only.
+pub(crate) async fn start_msg_capture(
+    app: &impl App,
+    req: pm::StartMsgCaptureRequest,
+) -> Result<pm::StartMsgCaptureResponse> {
+    let audit = Audit::new("Start message capture");
+    let entity = format!(
+        "{}s{}",
+        req.duration_secs,
+        if req.include_data {
+            " including message data"
+        } else {
+            ""
+        }
+    );
+
+    start_capture(&MSG_CAPTURE, req)?;
+
+    if !app.static_info().user_config.read_only {
+        app.write_tx(move |tx| audit.record(tx, entity)).await?;
+    }
+
+    Ok(pm::StartMsgCaptureResponse {})
+}
+
+/// Validates the request and starts `capture` accordingly
+pub(super) fn start_capture(capture: &MsgCapture, req: pm::StartMsgCaptureRequest) -> Result<()> {
+    if req.duration_secs == 0 || req.duration_secs > MAX_DURATION_SECS {
+        return Err(anyhow!(
+            "Capture duration must be between 1 and {MAX_DURATION_SECS} seconds"
+        ))
+        .status_code(Code::InvalidArgument);
+    }
+
+    let max_msgs = req.max_msgs.unwrap_or(DEFAULT_MAX_MSGS);
+    if max_msgs == 0 || max_msgs > MAX_MAX_MSGS {
+        return Err(anyhow!(
+            "Maximum number of captured messages must be between 1 and {MAX_MAX_MSGS}"
+        ))
+        .status_code(Code::InvalidArgument);
+    }
+
+    capture.start(
+        Duration::from_secs(req.duration_secs),
+        max_msgs.try_into()?,
+        req.include_data,
+    );
+
+    log::warn!(
+        "Started capturing up to {max_msgs} BeeMsges for {}s{}",
+        req.duration_secs,
+        if req.include_data {
+            " including message data"
+        } else {
+            ""
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+
+    #[tokio::test]
+    async fn start_msg_capture_audit() {
+        let app = TestApp::new().await;
+
+        // Invalid requests don't start a capture and are not recorded
+        super::start_msg_capture(
+            &app,
+            pm::StartMsgCaptureRequest {
+                duration_secs: 0,
+                max_msgs: None,
+                include_data: false,
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log WHERE operation = 'Start message capture'",
+            [],
+            0
+        );
+
+        super::start_msg_capture(
+            &app,
+            pm::StartMsgCaptureRequest {
+                duration_secs: 1,
+                max_msgs: Some(10),
+                include_data: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq_db!(
+            app,
+            "SELECT COUNT(*) FROM audit_log
+            WHERE operation = 'Start message capture' AND entity = '1s including message data'",
+            [],
+            1
+        );
+    }
+}
@@ -5,9 +5,28 @@ use shared::bee_msg::buddy_group::{
     GetStorageResyncStatsResp, SetLastBuddyCommOverride,
 };
 use shared::bee_msg::target::{RefreshTargetStates, SetTargetConsistencyStatesResp};
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 use tokio::time::sleep;
 
+/// Maximum time to wait for a node to respond to a resync related request. Prevents a hanging
+/// node from blocking the gRPC request forever.
+pub(super) const RESYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval for checking whether a resync slot has become free if `max_concurrent_resyncs` is
+/// reached
+const RESYNC_SLOT_POLL_INTERVAL: Duration = if cfg!(test) {
+    Duration::from_millis(50)
+} else {
+    Duration::from_secs(2)
+};
+
+/// Serializes taking a free resync slot and marking the resync as needed, so concurrent requests
+/// can't take the same slot. Not held while polling the nodes.
+static RESYNC_SLOT: Mutex<()> = Mutex::const_new(());
+
 /// Starts a resync of a storage or metadata target from its buddy target
 pub(crate) async fn start_resync(
     app: &impl App,
@@ -15,10 +34,12 @@ pub(crate) async fn start_resync(
 ) -> Result<pm::StartResyncResponse> {
     fail_on_missing_license(app, LicensedFeature::Mirroring)?;
     fail_on_pre_shutdown(app)?;
+    fail_on_read_only(app)?;
 
     let buddy_group: EntityId = required_field(req.buddy_group)?.try_into()?;
     let timestamp: i64 = required_field(req.timestamp)?;
     let restart: bool = required_field(req.restart)?;
+    let audit = Audit::new("Start resync");
 
     // For resync source is always primary target and destination is secondary target
     let (src_target_id, dest_target_id, src_node_uid, node_type, group) = app
@@ -26,18 +47,7 @@ pub(crate) async fn start_resync(
             let group = buddy_group.resolve(tx, EntityType::BuddyGroup)?;
             let node_type: NodeTypeServer = group.node_type().try_into()?;
 
-            let (src_target_id, dest_target_id, src_node_uid): (TargetId, TargetId, Uid) = tx
-                .query_row_cached(
-                    sql!(
-                        "SELECT g.p_target_id, g.s_target_id, src_t.node_uid
-                        FROM buddy_groups AS g
-                        INNER JOIN targets_ext AS src_t
-                            ON src_t.target_id = g.p_target_id AND src_t.node_type = g.node_type
-                        WHERE group_uid = ?1"
-                    ),
-                    [group.uid],
-                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-                )?;
+            let (src_target_id, dest_target_id, src_node_uid) = resync_targets(tx, group.uid)?;
 
             Ok((
                 src_target_id,
@@ -64,11 +74,12 @@ not supported."
             }
 
             let resp: GetMetaResyncStatsResp = app
-                .request(
+                .request_with_timeout(
                     src_node_uid,
                     &GetMetaResyncStats {
                         target_id: src_target_id,
                     },
+                    RESYNC_REQUEST_TIMEOUT,
                 )
                 .await?;
 
@@ -79,11 +90,12 @@ not supported."
         NodeTypeServer::Storage => {
             if !restart {
                 let resp: GetStorageResyncStatsResp = app
-                    .request(
+                    .request_with_timeout(
                         src_node_uid,
                         &GetStorageResyncStats {
                             target_id: src_target_id,
                         },
+                        RESYNC_REQUEST_TIMEOUT,
                     )
                     .await?;
 
@@ -92,16 +104,30 @@ not supported."
                 }
 
                 if timestamp > -1 {
-                    override_last_buddy_comm(app, src_node_uid, src_target_id, &group, timestamp)
-                        .await?;
+                    override_last_buddy_comm(
+                        app,
+                        src_node_uid,
+                        src_target_id,
+                        &group,
+                        timestamp,
+                        false,
+                    )
+                    .await?;
                 }
             } else {
                 if timestamp < 0 {
                     bail!("Resync for storage targets can only be restarted with timestamp.");
                 }
 
-                override_last_buddy_comm(app, src_node_uid, src_target_id, &group, timestamp)
-                    .await?;
+                override_last_buddy_comm(
+                    app,
+                    src_node_uid,
+                    src_target_id,
+                    &group,
+                    timestamp,
+                    false,
+                )
+                .await?;
 
                 log::info!("Waiting for the already running resync operations to abort.");
 
@@ -114,11 +140,12 @@ not supported."
                 // current system, still unreliable.
                 loop {
                     let resp: GetStorageResyncStatsResp = app
-                        .request(
+                        .request_with_timeout(
                             src_node_uid,
                             &GetStorageResyncStats {
                                 target_id: src_target_id,
                             },
+                            RESYNC_REQUEST_TIMEOUT,
                         )
                         .await?;
 
@@ -136,6 +163,38 @@ not supported."
         }
     }
 
+    // If the number of concurrent resyncs is limited, wait for a free slot. The nodes are polled
+    // without holding the lock. The lock is then held from counting the active resyncs until the
+    // resync has been marked as needed, so it counts as active for the next waiting request.
+    let max_resyncs = app.static_info().user_config.max_concurrent_resyncs;
+    let _slot = if max_resyncs > 0 {
+        let mut logged = false;
+        loop {
+            fail_on_pre_shutdown(app)?;
+
+            let polled = poll_resync_states(app, group.uid).await?;
+
+            let slot = RESYNC_SLOT.lock().await;
+            let active = count_active_resyncs(app, group.uid, &polled).await?;
+            if active < max_resyncs {
+                break Some(slot);
+            }
+            drop(slot);
+
+            if !logged {
+                log::info!(
+                    "{active} resyncs are active, waiting for one to finish before starting the \
+resync of buddy group {group}"
+                );
+                logged = true;
+            }
+
+            sleep(RESYNC_SLOT_POLL_INTERVAL).await;
+        }
+    } else {
+        None
+    };
+
     // set destination target state as needs-resync in mgmtd database
     app.write_tx(move |tx| {
         db::target::update_consistency_states(
@@ -143,6 +202,7 @@ not supported."
             [(dest_target_id, TargetConsistencyState::NeedsResync)],
             node_type,
         )?;
+        audit.record(tx, &group)?;
         Ok(())
     })
     .await?;
@@ -157,40 +217,316 @@ not supported."
     // so we omit it.
     app.send_notifications(
         &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-        &RefreshTargetStates { ack_id: "".into() },
+        &RefreshTargetStates {
+            ack_id: AckId::empty(),
+        },
     )
     .await;
 
-    return Ok(pm::StartResyncResponse {});
-
-    /// Override last buddy communication timestamp on source storage node
-    /// Note that this might be overwritten again on the storage server between
-    async fn override_last_buddy_comm(
-        app: &impl App,
-        src_node_uid: Uid,
-        src_target_id: TargetId,
-        group: &EntityIdSet,
-        timestamp: i64,
-    ) -> Result<()> {
-        let resp: SetTargetConsistencyStatesResp = app
-            .request(
-                src_node_uid,
-                &SetLastBuddyCommOverride {
-                    target_id: src_target_id,
-                    timestamp,
-                    abort_resync: 0,
-                },
-            )
-            .await?;
+    Ok(pm::StartResyncResponse {})
+}
+
+/// Fetches the buddy groups whose secondary needs a resync, excluding `exclude_group_uid`. Returns
+/// the group uid, the node type, the source target id and the source node uid.
+fn needs_resync_groups(
+    tx: &Transaction,
+    exclude_group_uid: Uid,
+) -> Result<Vec<(Uid, NodeType, TargetId, Uid)>> {
+    Ok(tx.query_map_collect(
+        sql!(
+            "SELECT g.group_uid, g.node_type, g.p_target_id, src_t.node_uid
+            FROM buddy_groups AS g
+            INNER JOIN targets AS dest_t
+                ON dest_t.target_id = g.s_target_id AND dest_t.node_type = g.node_type
+            INNER JOIN targets_ext AS src_t
+                ON src_t.target_id = g.p_target_id AND src_t.node_type = g.node_type
+            WHERE dest_t.consistency = ?1 AND g.group_uid != ?2"
+        ),
+        params![
+            TargetConsistencyState::NeedsResync.sql_variant(),
+            exclude_group_uid
+        ],
+        |row| {
+            Ok((
+                row.get(0)?,
+                NodeType::from_row(row, 1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        },
+    )?)
+}
+
+/// Polls the source nodes of all buddy groups needing a resync (excluding `exclude_group_uid`)
+/// concurrently for the resync state. Returns whether the resync is active by group uid.
+///
+/// A resync is active if the source node reports it as running or not yet started. Resyncs whose
+/// source node can't be reached are not active.
+async fn poll_resync_states(app: &impl App, exclude_group_uid: Uid) -> Result<HashMap<Uid, bool>> {
+    let groups = app
+        .read_tx(move |tx| needs_resync_groups(tx, exclude_group_uid))
+        .await?;
+
+    let mut tasks = JoinSet::new();
+    for (group_uid, node_type, src_target_id, src_node_uid) in groups {
+        let app = app.clone();
+        tasks.spawn(async move {
+            let state = match node_type {
+                NodeType::Meta => app
+                    .request_with_timeout::<_, GetMetaResyncStatsResp>(
+                        src_node_uid,
+                        &GetMetaResyncStats {
+                            target_id: src_target_id,
+                        },
+                        RESYNC_REQUEST_TIMEOUT,
+                    )
+                    .await
+                    .map(|resp| resp.state),
+                _ => app
+                    .request_with_timeout::<_, GetStorageResyncStatsResp>(
+                        src_node_uid,
+                        &GetStorageResyncStats {
+                            target_id: src_target_id,
+                        },
+                        RESYNC_REQUEST_TIMEOUT,
+                    )
+                    .await
+                    .map(|resp| resp.state),
+            };
+
+            let active = match state {
+                Ok(BuddyResyncJobState::Running | BuddyResyncJobState::NotStarted) => true,
+                Ok(_) => false,
+                Err(err) => {
+                    log::debug!(
+                        "Fetching resync state of target {src_target_id} from node \
+{src_node_uid} failed: {err:#}"
+                    );
+                    false
+                }
+            };
 
-        if resp.result != OpsErr::SUCCESS {
-            bail!(
-                "Could not override last buddy communication timestamp on primary of buddy group {group}. \
+            (group_uid, active)
+        });
+    }
+
+    let mut states = HashMap::new();
+    while let Some(res) = tasks.join_next().await {
+        let (group_uid, active) = res?;
+        states.insert(group_uid, active);
+    }
+
+    Ok(states)
+}
+
+/// Counts the active resyncs, excluding the one of the buddy group `exclude_group_uid`.
+///
+/// Uses the states from [poll_resync_states()]. Resyncs that have been marked as needed after the
+/// poll are counted as active.
+async fn count_active_resyncs(
+    app: &impl App,
+    exclude_group_uid: Uid,
+    polled: &HashMap<Uid, bool>,
+) -> Result<usize> {
+    let groups = app
+        .read_tx(move |tx| needs_resync_groups(tx, exclude_group_uid))
+        .await?;
+
+    Ok(groups
+        .iter()
+        .filter(|(group_uid, ..)| polled.get(group_uid).copied().unwrap_or(true))
+        .count())
+}
+
+/// Fetches the source (primary) target id, the destination (secondary) target id and the source
+/// node uid of a buddy group
+pub(super) fn resync_targets(
+    tx: &Transaction,
+    group_uid: Uid,
+) -> Result<(TargetId, TargetId, Uid)> {
+    Ok(tx.query_row_cached(
+        sql!(
+            "SELECT g.p_target_id, g.s_target_id, src_t.node_uid
+            FROM buddy_groups AS g
+            INNER JOIN targets_ext AS src_t
+                ON src_t.target_id = g.p_target_id AND src_t.node_type = g.node_type
+            WHERE group_uid = ?1"
+        ),
+        [group_uid],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?)
+}
+
+/// Override last buddy communication timestamp on source storage node. If `abort_resync` is set,
+/// a running resync on the source node is aborted as well.
+/// Note that this might be overwritten again on the storage server between
+pub(super) async fn override_last_buddy_comm(
+    app: &impl App,
+    src_node_uid: Uid,
+    src_target_id: TargetId,
+    group: &EntityIdSet,
+    timestamp: i64,
+    abort_resync: bool,
+) -> Result<()> {
+    let resp: SetTargetConsistencyStatesResp = app
+        .request_with_timeout(
+            src_node_uid,
+            &SetLastBuddyCommOverride {
+                target_id: src_target_id,
+                timestamp,
+                abort_resync: abort_resync.into(),
+            },
+            RESYNC_REQUEST_TIMEOUT,
+        )
+        .await?;
+
+    if resp.result != OpsErr::SUCCESS {
+        bail!(
+            "Could not override last buddy communication timestamp on primary of buddy group {group}. \
 Failed with resp {:?}",
-                resp.result
-            );
-        }
+            resp.result
+        );
+    }
+
+    Ok(())
+}
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use crate::config::Config;
+    use std::sync::{Arc, Mutex};
+
+    async fn start(app: TestApp, group_uid: Uid) -> Result<()> {
+        start_resync(
+            &app,
+            pm::StartResyncRequest {
+                buddy_group: Some(EntityId::Uid(group_uid).into()),
+                timestamp: Some(-1),
+                restart: Some(false),
+            },
+        )
+        .await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn max_concurrent_resyncs() {
+        let app = TestApp::with_config(Config {
+            max_concurrent_resyncs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        // Simulates the resync jobs on the storage nodes, by source target
+        let job_states: Arc<Mutex<HashMap<TargetId, BuddyResyncJobState>>> = Default::default();
+        let handler_job_states = job_states.clone();
+        app.set_request_handler(move |req| {
+            let state = req
+                .downcast_ref::<GetStorageResyncStats>()
+                .and_then(|msg| {
+                    handler_job_states
+                        .lock()
+                        .unwrap()
+                        .get(&msg.target_id)
+                        .copied()
+                })
+                .unwrap_or_default();
+
+            Ok(Box::new(GetStorageResyncStatsResp {
+                state,
+                ..Default::default()
+            }))
+        });
+
+        // Storage buddy group 1 (targets 1 and 5) gets the only slot
+        start(app.clone(), 302001).await.unwrap();
+
+        // Storage buddy group 2 (targets 9 and 13) has to wait
+        let queued = tokio::spawn(start(app.clone(), 302002));
+        sleep(RESYNC_SLOT_POLL_INTERVAL * 4).await;
+        assert!(!queued.is_finished());
+        assert_eq_db!(
+            app,
+            "SELECT consistency FROM targets WHERE node_type = ?1 AND target_id = 13",
+            [NodeType::Storage.sql_variant()],
+            TargetConsistencyState::Good.sql_variant()
+        );
+
+        // Still running
+        job_states
+            .lock()
+            .unwrap()
+            .insert(1, BuddyResyncJobState::Running);
+        sleep(RESYNC_SLOT_POLL_INTERVAL * 4).await;
+        assert!(!queued.is_finished());
+
+        // The first resync finishes
+        job_states
+            .lock()
+            .unwrap()
+            .insert(1, BuddyResyncJobState::Success);
+        app.write_tx(|tx| {
+            db::target::update_consistency_states(
+                tx,
+                [(5, TargetConsistencyState::Good)],
+                NodeTypeServer::Storage,
+            )
+        })
+        .await
+        .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), queued)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq_db!(
+            app,
+            "SELECT consistency FROM targets WHERE node_type = ?1 AND target_id = 13",
+            [NodeType::Storage.sql_variant()],
+            TargetConsistencyState::NeedsResync.sql_variant()
+        );
+    }
+
+    #[tokio::test]
+    async fn resyncs_marked_after_poll_are_active() {
+        let app = TestApp::new().await;
+
+        let polled = poll_resync_states(&app, 0).await.unwrap();
+        assert!(polled.is_empty());
+
+        app.write_tx(|tx| {
+            db::target::update_consistency_states(
+                tx,
+                [(5, TargetConsistencyState::NeedsResync)],
+                NodeTypeServer::Storage,
+            )
+        })
+        .await
+        .unwrap();
+
+        // Marked by another request after the poll
+        assert_eq!(count_active_resyncs(&app, 0, &polled).await.unwrap(), 1);
+
+        // Reported as finished by the source node
+        app.set_request_handler(|_| {
+            Ok(Box::new(GetStorageResyncStatsResp {
+                state: BuddyResyncJobState::Success,
+                ..Default::default()
+            }))
+        });
+        let polled = poll_resync_states(&app, 0).await.unwrap();
+        assert_eq!(polled, HashMap::from([(302001, false)]));
+        assert_eq!(count_active_resyncs(&app, 0, &polled).await.unwrap(), 0);
+
+        // The requesting group is excluded
+        assert_eq!(
+            count_active_resyncs(&app, 302001, &HashMap::new())
+                .await
+                .unwrap(),
+            0
+        );
+    }
 }
@@ -0,0 +1,127 @@
+use super::common::{NODES_STREAM_BUF_SIZE, NODES_STREAM_PAGE_LIMIT};
+use super::*;
+
+/// Delivers the node list as a stream, fetching it page by page from the database.
+///
+/// Meant for big systems where the complete list would result in a huge single message. Unlike
+/// `get_nodes`, this does not provide the meta root and file system information.
+pub(crate) async fn stream_nodes(
+    app: &impl App,
+    req: pm::StreamNodesRequest,
+) -> Result<RespStream<pm::StreamNodesResponse>> {
+    let app = app.clone();
+    let stream = resp_stream(NODES_STREAM_BUF_SIZE, async move |stream| {
+        let mut offset = 0;
+
+        loop {
+            let (nodes, mut nics) = app
+                .read_tx(move |tx| {
+                    let nodes = db::node::get_page(tx, offset, NODES_STREAM_PAGE_LIMIT)?;
+
+                    // Fetching the nic list is optional as it causes additional load
+                    let nics: Vec<(Uid, pm::get_nodes_response::node::Nic)> = if req.include_nics {
+                        tx.query_map_collect(
+                            sql!(
+                                "SELECT node_uid, addr, nic_type, name FROM node_nics
+                                WHERE node_uid IN rarray(?1)
+                                ORDER BY node_uid ASC"
+                            ),
+                            [sqlite::rarray_param(nodes.iter().map(|n| n.uid))],
+                            |row| {
+                                Ok((
+                                    row.get(0)?,
+                                    pm::get_nodes_response::node::Nic {
+                                        addr: row.get(1)?,
+                                        nic_type: NicType::from_row(row, 2)?.into_proto_i32(),
+                                        name: row.get(3)?,
+                                    },
+                                ))
+                            },
+                        )?
+                    } else {
+                        vec![]
+                    };
+
+                    Ok((nodes, nics))
+                })
+                .await?;
+
+            let len = nodes.len();
+
+            for node in nodes {
+                let node_type = node.node_type.into_proto_i32();
+
+                let nics = nics
+                    .extract_if(.., |(uid, _)| *uid == node.uid)
+                    .map(|(_, mut nic)| {
+                        nic.addr = nic
+                            .addr
+                            .parse::<std::net::IpAddr>()
+                            .map(|ip| SocketAddr::new(ip, node.port).to_string())
+                            .unwrap_or(nic.addr);
+                        nic
+                    })
+                    .collect();
+
+                stream
+                    .send(pm::StreamNodesResponse {
+                        node: Some(pm::get_nodes_response::Node {
+                            id: Some(pb::EntityIdSet {
+                                uid: Some(node.uid),
+                                legacy_id: Some(pb::LegacyId {
+                                    num_id: node.id,
+                                    node_type,
+                                }),
+                                alias: Some(node.alias),
+                            }),
+                            node_type,
+                            port: node.port.into(),
+                            nics,
+                        }),
+                    })
+                    .await?;
+            }
+
+            // This was the last page? Then we are done
+            if len < NODES_STREAM_PAGE_LIMIT {
+                return Ok(());
+            }
+
+            offset += NODES_STREAM_PAGE_LIMIT;
+        }
+    });
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::test::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn stream_nodes() {
+        let app = TestApp::new().await;
+
+        let nodes: Vec<_> =
+            super::stream_nodes(&app, pm::StreamNodesRequest { include_nics: true })
+                .await
+                .unwrap()
+                .collect::<std::result::Result<_, _>>()
+                .await
+                .unwrap();
+
+        assert_eq!(nodes.len(), 14);
+        assert_eq!(
+            nodes
+                .iter()
+                .filter_map(|e| e.node.as_ref())
+                .find(|e| e.id.as_ref().unwrap().uid() == 101001)
+                .unwrap()
+                .nics
+                .len(),
+            4
+        );
+    }
+}
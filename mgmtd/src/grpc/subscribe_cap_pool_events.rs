@@ -0,0 +1,61 @@
+use super::*;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const BUF_SIZE: usize = 128;
+/// How often the stream checks for pre shutdown while waiting for events
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Streams targets moving into or out of the emergency capacity pool as they happen.
+///
+/// The stream stays open until the client disconnects or the management shuts down.
+pub(crate) async fn subscribe_cap_pool_events(
+    app: &impl App,
+    _req: pm::SubscribeCapPoolEventsRequest,
+) -> Result<RespStream<pm::SubscribeCapPoolEventsResponse>> {
+    fail_on_pre_shutdown(app)?;
+
+    let app = app.clone();
+    let mut events = app.subscribe_cap_pool_events();
+
+    let stream = resp_stream(BUF_SIZE, async move |stream| {
+        let mut shutdown_check = tokio::time::interval(SHUTDOWN_CHECK_INTERVAL);
+
+        loop {
+            let event = tokio::select! {
+                res = events.recv() => match res {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        log::warn!("Capacity pool event subscriber lagged, skipped {n} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = stream.closed() => return Ok(()),
+                _ = shutdown_check.tick() => {
+                    if app.is_pre_shutdown() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            stream
+                .send(pm::SubscribeCapPoolEventsResponse {
+                    target: Some(pb::EntityIdSet {
+                        uid: Some(event.target_uid),
+                        legacy_id: Some(pb::LegacyId {
+                            num_id: event.target_id.into(),
+                            node_type: pb::NodeType::from(event.node_type).into(),
+                        }),
+                        alias: Some(event.alias),
+                    }),
+                    previous_cap_pool: pb::CapacityPool::from(event.previous).into(),
+                    current_cap_pool: pb::CapacityPool::from(event.current).into(),
+                })
+                .await?;
+        }
+    });
+
+    Ok(stream)
+}
@@ -5,38 +5,46 @@ mod bee_msg;
 mod cap_pool;
 pub mod config;
 pub mod db;
+pub mod dump_msg;
 mod error;
 mod grpc;
 pub mod license;
+mod metrics;
 mod quota;
 mod timer;
 mod types;
 
 use crate::app::RuntimeApp;
 use crate::config::Config;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use app::App;
 use db::config::Config as dbConfig;
 use db::node_nic::ReplaceNic;
 use license::LicenseVerifier;
+use log::LevelFilter;
 use protobuf::license::CertType;
 use shared::bee_msg::target::RefreshTargetStates;
 use shared::conn::incoming;
 use shared::conn::outgoing::Pool;
-use shared::nic::Nic;
+use shared::log_ring::LogRing;
+use shared::nic::{Nic, select_bind_addr};
 use shared::run_state::{self, RunStateControl};
-use shared::types::{AuthSecret, MGMTD_UID, NicType, NodeId, NodeType};
+use shared::types::{AckId, AuthSecret, MGMTD_UID, NicType, NodeId, NodeType};
 use sqlite::TransactionExt;
 use sqlite_check::sql;
 use std::collections::HashSet;
 use std::future::Future;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::time::Instant;
 use types::SqliteEnumExt;
 
+/// The most recent log records, delivered by the `GetRecentLogs` gRPC call. Configured and filled
+/// by the logger set up on startup.
+pub static RECENT_LOGS: LogRing = LogRing::new();
+
 /// Contains information that is obtained at the start of the app and then never changes again.
 #[derive(Debug)]
 pub struct StaticInfo {
@@ -44,6 +52,42 @@ pub struct StaticInfo {
     pub auth_secret: Option<AuthSecret>,
     pub network_addrs: Vec<Nic>,
     pub use_ipv6: bool,
+    /// The point in time the process was started, used to determine the uptime
+    pub start_time: std::time::Instant,
+}
+
+/// Contains the settings that can be changed at runtime by reloading the configuration.
+///
+/// The values here take precedence over the ones in [StaticInfo::user_config], which always
+/// contains the configuration loaded on startup.
+#[derive(Debug, Clone)]
+pub struct DynamicInfo {
+    pub node_offline_timeout: Duration,
+    pub node_offline_grace: Duration,
+    pub client_auto_remove_timeout: Duration,
+    pub quota_update_interval: Duration,
+    pub log_level: LevelFilter,
+}
+
+impl DynamicInfo {
+    /// The names of the [Config] settings that can be changed at runtime
+    const SETTINGS: &[&str] = &[
+        "node_offline_timeout",
+        "node_offline_grace",
+        "client_auto_remove_timeout",
+        "quota_update_interval",
+        "log_level",
+    ];
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            node_offline_timeout: config.node_offline_timeout,
+            node_offline_grace: config.node_offline_grace,
+            client_auto_remove_timeout: config.client_auto_remove_timeout,
+            quota_update_interval: config.quota_update_interval,
+            log_level: config.log_level.clone().into(),
+        }
+    }
 }
 
 /// Starts the management service.
@@ -64,33 +108,56 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
     // Static configuration which doesn't change at runtime
     let info = Box::leak(Box::new(info));
 
-    let beemsg_serve_addr = SocketAddr::new(
-        if info.use_ipv6 {
-            Ipv6Addr::UNSPECIFIED.into()
-        } else {
-            Ipv4Addr::UNSPECIFIED.into()
-        },
+    let beemsg_serve_addr = select_bind_addr(
+        info.user_config.bind_addr,
+        info.use_ipv6,
         info.user_config.beemsg_port,
     );
 
-    // UDP socket for in- and outgoing messages
-    let udp_socket = Arc::new(UdpSocket::bind(beemsg_serve_addr).await?);
+    // UDP sockets for in- and outgoing messages
+    let udp_sockets = incoming::bind_udp(beemsg_serve_addr, info.user_config.udp_sockets)?;
+    log::info!(
+        "Bound {} UDP socket(s) to {beemsg_serve_addr}",
+        udp_sockets.len()
+    );
 
     // Node address store and connection pool
     let conn_pool = Pool::new(
-        udp_socket.clone(),
+        udp_sockets.clone(),
         info.user_config.connection_limit,
         info.auth_secret,
         info.use_ipv6,
     );
 
-    let db = sqlite::Connections::new(info.user_config.db_file.as_path());
+    let read_only = info.user_config.read_only;
+
+    let db = if read_only {
+        sqlite::Connections::new_read_only(
+            info.user_config.db_file.as_path(),
+            info.user_config.db_busy_timeout,
+        )
+    } else {
+        sqlite::Connections::new_with_read_connections(
+            info.user_config.db_file.as_path(),
+            info.user_config.db_busy_timeout,
+            info.user_config.db_durability.clone().into(),
+            info.user_config.db_read_connections,
+        )
+    };
 
     let need_migration = db
         .read_tx(|tx| Ok(sqlite::check_schema(tx, db::MIGRATIONS)))
         .await??;
 
     if need_migration {
+        if read_only {
+            bail!(
+                "The database needs to be migrated to schema version {}, which is not possible \
+in read-only mode. Start the management without read-only mode once to migrate it",
+                db::MIGRATIONS.last().map(|m| m.version).unwrap_or_default()
+            );
+        }
+
         migrate_db_schema(&db).await?;
     }
 
@@ -99,22 +166,26 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
         info.user_config.db_file.as_path()
     );
 
-    db.write_tx(|tx| {
-        // Update management node entry in db
-        db::node::update(tx, MGMTD_UID, info.user_config.beemsg_port, None)?;
-
-        // Update management nics entry in db
-        db::node_nic::replace(
-            tx,
-            MGMTD_UID,
-            info.network_addrs.iter().map(|e| ReplaceNic {
-                nic_type: NicType::Tcp,
-                addr: &e.address,
-                name: e.name.as_str().into(),
-            }),
-        )
-    })
-    .await?;
+    if read_only {
+        log::warn!("Running in read-only mode. Requests modifying the system will be denied");
+    } else {
+        db.write_tx(|tx| {
+            // Update management node entry in db
+            db::node::update(tx, MGMTD_UID, info.user_config.beemsg_port, None)?;
+
+            // Update management nics entry in db
+            db::node_nic::replace(
+                tx,
+                MGMTD_UID,
+                info.network_addrs.iter().map(|e| ReplaceNic {
+                    nic_type: NicType::Tcp,
+                    addr: &e.address,
+                    name: e.name.as_str().into(),
+                }),
+            )
+        })
+        .await?;
+    }
 
     let prev_trial_serial: Option<String> = db
         .read_tx(|tx| db::config::get(tx, db::config::Config::TrialSerial))
@@ -134,15 +205,29 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
                 .data
                 .is_some_and(|d| d.r#type() == CertType::Trial)
                 && prev_trial_serial.is_none()
+                && !read_only
             {
                 db.write_tx(|tx| db::config::set(tx, dbConfig::TrialSerial, serial))
                     .await?;
             }
         }
-        Err(err) => log::warn!(
-            "Loading and verifying license certificate failed. \
+        Err(err) => {
+            log::warn!(
+                "Loading and verifying license certificate failed. \
                 Licensed features will be unavailable: {err}"
-        ),
+            );
+
+            if license
+                .get_license_cert_data()
+                .is_ok_and(|c| license::cert_missing(&c))
+            {
+                log::warn!(
+                    "No license certificate loaded. Server node registration is limited to {} \
+                    machine(s).",
+                    license::UNLICENSED_MACHINES
+                );
+            }
+        }
     };
 
     // Fill node addrs store from db
@@ -160,6 +245,7 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
         db,
         license,
         info,
+        DynamicInfo::from_config(&info.user_config),
         run_state.clone_weak(),
         shutdown_client_tx,
     );
@@ -168,6 +254,8 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
     // Fall back to ipv4 socket if ipv6 is not available
     incoming::listen_tcp(
         beemsg_serve_addr,
+        info.user_config.tcp_listen_backlog,
+        info.user_config.tcp_accept_tasks,
         app.clone(),
         info.auth_secret.is_some(),
         run_state.clone(),
@@ -175,11 +263,20 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
     .await?;
 
     // Recv UDP datagrams
-    incoming::recv_udp(udp_socket, app.clone(), run_state.clone())?;
+    incoming::recv_udp(
+        udp_sockets,
+        app.clone(),
+        info.user_config.udp_handler_concurrency,
+        info.user_config.udp_dedup_window,
+        run_state.clone(),
+    )?;
 
     // Run the timers
     timer::start_tasks(app.clone(), run_state.clone());
 
+    // Start the metrics endpoint, if enabled
+    metrics::serve(app.clone(), run_state.clone()).await?;
+
     // Start gRPC service
     grpc::serve(app.clone(), run_state)?;
 
@@ -194,12 +291,27 @@ pub async fn start(info: StaticInfo, license: LicenseVerifier) -> Result<RunCont
 async fn migrate_db_schema(db: &sqlite::Connections) -> Result<()> {
     log::warn!("The database needs to be migrated. Applying migrations...");
 
-    db.conn(|conn| {
-        let backup_file = sqlite::backup_db(conn)?;
-        log::warn!("Old database backed up to {backup_file:?}");
-        Ok(())
-    })
-    .await?;
+    // Allow aborting a long running backup using Ctrl-C
+    let cancel = Arc::new(AtomicBool::new(false));
+    let ctrl_c = tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    let res = db
+        .conn(move |conn| {
+            let backup_file = sqlite::backup_db(conn, || cancel.load(Ordering::Relaxed))?;
+            log::warn!("Old database backed up to {backup_file:?}");
+            Ok(())
+        })
+        .await;
+
+    ctrl_c.abort();
+    res?;
 
     let version = db
         .write_tx(|tx| {
@@ -225,6 +337,13 @@ pub struct RunControl {
 pub type ClientPulledStateNotification = (NodeType, NodeId);
 
 impl RunControl {
+    /// Returns a handle for applying a reloaded configuration to the running app.
+    pub fn config_reloader(&self) -> ConfigReloader {
+        ConfigReloader {
+            app: self.app.clone(),
+        }
+    }
+
     /// Waits for the provided future to complete before initiating shutdown. Completes after
     /// shutdown is done.
     pub async fn wait_for_shutdown<F, R>(mut self, shutdown_signal: F)
@@ -277,7 +396,7 @@ impl RunControl {
             log::warn!(
                 "Buddy groups are in use and clients are registered - \
                 waiting for all clients to pull state (timeout after {:?}) ...",
-                self.app.info.user_config.node_offline_timeout
+                self.app.dynamic_info().node_offline_timeout
             );
 
             // Let the nodes pull the new states as soon as possible
@@ -285,7 +404,7 @@ impl RunControl {
                 .send_notifications(
                     &[NodeType::Client, NodeType::Meta, NodeType::Storage],
                     &RefreshTargetStates {
-                        ack_id: b"".to_vec(),
+                        ack_id: AckId::empty(),
                     },
                 )
                 .await;
@@ -298,6 +417,22 @@ impl RunControl {
             }
         }
 
+        let drain_timeout = self.app.info.user_config.shutdown_drain_timeout;
+        log::warn!(
+            "Waiting for outstanding requests to complete (timeout after {drain_timeout:?}) ..."
+        );
+
+        tokio::select! {
+            // Stop accepting new connections and requests and let the outstanding ones finish
+            drained = self.run_state_control.drain(drain_timeout) => {
+                if !drained {
+                    log::warn!("Drain timeout hit, cancelling outstanding requests");
+                }
+            }
+            // or wait for another shutdown signal
+            _ = shutdown_signal() => {}
+        }
+
         log::warn!("Waiting for all tasks to complete ... ");
 
         tokio::select! {
@@ -314,7 +449,7 @@ impl RunControl {
 
     /// Waits until every client in `client_list` has been received to the `self.shutdown_client_`
     async fn wait_for_clients(&mut self, mut client_list: HashSet<ClientPulledStateNotification>) {
-        let deadline = Instant::now() + self.app.info.user_config.node_offline_timeout;
+        let deadline = Instant::now() + self.app.dynamic_info().node_offline_timeout;
 
         let receive_client_ids = async {
             while let Some(client_id) = self.shutdown_client_rx.recv().await {
@@ -340,6 +475,35 @@ impl RunControl {
     }
 }
 
+/// Applies a reloaded configuration to the running app.
+#[derive(Debug, Clone)]
+pub struct ConfigReloader {
+    app: RuntimeApp,
+}
+
+impl ConfigReloader {
+    /// Applies the settings from `config` that can be changed at runtime (see [DynamicInfo]).
+    ///
+    /// For all other settings that differ from the configuration loaded on startup, a warning is
+    /// logged as they require a restart to take effect.
+    pub fn reload(&self, config: &Config) {
+        for setting in self.app.info.user_config.changed_settings(config) {
+            if !DynamicInfo::SETTINGS.contains(&setting) {
+                log::warn!(
+                    "Setting {setting} can not be changed at runtime, restart the management \
+to apply it"
+                );
+            }
+        }
+
+        let new = DynamicInfo::from_config(config);
+        log::set_max_level(new.log_level);
+        log::info!("Applied reloaded runtime settings: {new:?}");
+
+        self.app.set_dynamic_info(new);
+    }
+}
+
 /// Constructs a version str from the `VERSION` environment variable at compile time
 pub const fn version_str() -> &'static str {
     match option_env!("VERSION") {
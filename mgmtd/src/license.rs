@@ -4,6 +4,7 @@ use prost::Message;
 use protobuf::license::*;
 use std::ffi::{CStr, CString};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum LicensedFeature {
@@ -35,6 +36,72 @@ impl LicensedFeature {
 const NUM_MACHINES_PREFIX: &str = "io.beegfs.numservers.";
 const NUM_MACHINES_UNLIMITED: &str = "unlimited";
 
+/// The number of server machines allowed while the library is loaded but no license certificate
+/// is. Enough for evaluating BeeGFS on a single machine.
+pub const UNLICENSED_MACHINES: u32 = 1;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Determines the log level for a license expiry warning.
+///
+/// The level escalates as the expiry date approaches: info below 30 days, warn below 7 days and
+/// error below 1 day (including already expired certificates). Returns `None` if no warning is
+/// due yet.
+pub(crate) fn expiry_warning_level(expiry: SystemTime, now: SystemTime) -> Option<log::Level> {
+    let remaining = expiry.duration_since(now).unwrap_or_default();
+
+    if remaining <= DAY {
+        Some(log::Level::Error)
+    } else if remaining <= DAY * 7 {
+        Some(log::Level::Warn)
+    } else if remaining <= DAY * 30 {
+        Some(log::Level::Info)
+    } else {
+        None
+    }
+}
+
+/// Calculates the number of whole days left until expiry. Negative if already expired.
+pub(crate) fn days_remaining(expiry: SystemTime, now: SystemTime) -> i64 {
+    match expiry.duration_since(now) {
+        Ok(d) => (d.as_secs() / DAY.as_secs()) as i64,
+        Err(err) => -(err.duration().as_secs().div_ceil(DAY.as_secs()) as i64),
+    }
+}
+
+/// Whether no license certificate is loaded, according to the data returned by the library.
+///
+/// This is different from a loaded, but invalid (e.g. expired) or unverifiable certificate.
+pub(crate) fn cert_missing(cert: &GetCertDataResult) -> bool {
+    cert.data.is_none()
+}
+
+/// Determines the number of licensed machines from the data of the loaded certificate.
+///
+/// Returns [UNLICENSED_MACHINES] if no certificate is loaded. Fails if the loaded certificate
+/// couldn't be verified or doesn't contain the required information.
+fn licensed_machines(cert: &GetCertDataResult) -> Result<u32> {
+    if cert_missing(cert) {
+        return Ok(UNLICENSED_MACHINES);
+    }
+
+    if cert.result() == VerifyResult::VerifyError {
+        bail!("Error during license verification: {}", cert.message);
+    }
+
+    for name in cert.data.iter().flat_map(|d| &d.dns_names) {
+        if let Some(suffix) = name.strip_prefix(NUM_MACHINES_PREFIX) {
+            if suffix == NUM_MACHINES_UNLIMITED {
+                return Ok(u32::MAX);
+            } else {
+                return Ok(suffix.parse::<u32>()?);
+            }
+        }
+    }
+
+    bail!("Number of licensed machines not specified in certificate")
+}
+
 /// Encapsulates a C string buffer and provides methods for easy access to the data inside the
 /// buffer and automatic deallocation.
 struct ExternalBuf {
@@ -75,8 +142,10 @@ impl Drop for ExternalBuf {
 /// * `free_return_buffer` must free C-Strings (`*mut c_char`) returned by the other functions.
 #[derive(Debug)]
 struct LoadedLibrary {
+    /// Keeps the library loaded as long as the function pointers are used. Only `None` for fake
+    /// libraries in tests.
     #[allow(dead_code)]
-    library: ::libloading::Library,
+    library: Option<::libloading::Library>,
 
     init_cert_store: unsafe extern "C" fn() -> c_uchar,
     verify_pem: unsafe extern "C" fn(pem: *mut c_char, len: c_uint) -> *mut c_char,
@@ -104,7 +173,7 @@ impl LoadedLibrary {
                 verify_feature: *library.get(b"VerifyFeature\0")?,
                 free_returned_buffer: *library.get(b"FreeReturnedBuffer\0")?,
 
-                library,
+                library: Some(library),
             })
         }
     }
@@ -266,26 +335,29 @@ impl LicenseVerifier {
         Ok(cert)
     }
 
-    /// Fetches the number of machines the license is valid for. Defaults to maximum if library is
-    /// not loaded or certificate is not loaded or certificate doesn't contain the required
-    /// information.
-    pub fn get_licensed_machines(&self) -> Result<u32> {
-        let cert_data = self
-            .get_license_cert_data()?
-            .data
-            .ok_or_else(|| anyhow!("No certificate loaded"))?;
-
-        for name in cert_data.dns_names {
-            if let Some(suffix) = name.strip_prefix(NUM_MACHINES_PREFIX) {
-                if suffix == NUM_MACHINES_UNLIMITED {
-                    return Ok(u32::MAX);
-                } else {
-                    return Ok(suffix.parse::<u32>()?);
-                }
-            }
+    /// Fetches the expiry date (`not_after`) of the certificate that was last verified
+    ///
+    /// Returns `None` if the library is not loaded or no certificate with an expiry date is loaded.
+    pub fn get_license_expiry(&self) -> Result<Option<SystemTime>> {
+        if self.0.is_none() {
+            return Ok(None);
         }
 
-        bail!("Number of licensed machines not specified in certificate")
+        let Some(data) = self.get_license_cert_data()?.data else {
+            return Ok(None);
+        };
+
+        Ok(data
+            .valid_until
+            .map(|t| UNIX_EPOCH + Duration::from_secs(t.seconds.try_into().unwrap_or_default())))
+    }
+
+    /// Fetches the number of machines the license is valid for.
+    ///
+    /// If the library is loaded but no certificate is, [UNLICENSED_MACHINES] is returned. Fails if
+    /// the library is not loaded or the certificate doesn't contain the required information.
+    pub fn get_licensed_machines(&self) -> Result<u32> {
+        licensed_machines(&self.get_license_cert_data()?)
     }
 
     /// Verifies a specific licensed feature
@@ -297,6 +369,11 @@ impl LicenseVerifier {
             bail!("License verification library not loaded. Feature {feature:?} unavailable.");
         };
 
+        // Don't rely on the library to deny features if there is no certificate at all
+        if cert_missing(&self.get_license_cert_data()?) {
+            bail!("No license certificate loaded. Feature {feature:?} unavailable.");
+        }
+
         let res = VerifyFeatureResult::decode(library.verify_feature(feature).as_ref())?;
         let result = res.result();
         let message = res.message;
@@ -311,3 +388,192 @@ impl LicenseVerifier {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expiry_warning_level() {
+        let now = SystemTime::now();
+
+        assert_eq!(super::expiry_warning_level(now + DAY * 60, now), None);
+        assert_eq!(super::expiry_warning_level(now + DAY * 31, now), None);
+        assert_eq!(
+            super::expiry_warning_level(now + DAY * 30, now),
+            Some(log::Level::Info)
+        );
+        assert_eq!(
+            super::expiry_warning_level(now + DAY * 8, now),
+            Some(log::Level::Info)
+        );
+        assert_eq!(
+            super::expiry_warning_level(now + DAY * 7, now),
+            Some(log::Level::Warn)
+        );
+        assert_eq!(
+            super::expiry_warning_level(now + DAY * 2, now),
+            Some(log::Level::Warn)
+        );
+        assert_eq!(
+            super::expiry_warning_level(now + Duration::from_secs(3600), now),
+            Some(log::Level::Error)
+        );
+        assert_eq!(
+            super::expiry_warning_level(now - DAY, now),
+            Some(log::Level::Error)
+        );
+    }
+
+    #[test]
+    fn days_remaining() {
+        let now = SystemTime::now();
+
+        assert_eq!(super::days_remaining(now + DAY * 10, now), 10);
+        assert_eq!(super::days_remaining(now + DAY / 2, now), 0);
+        assert_eq!(super::days_remaining(now - DAY / 2, now), -1);
+        assert_eq!(super::days_remaining(now - DAY * 3, now), -3);
+    }
+
+    #[test]
+    fn no_lib_expiry() {
+        let license = LicenseVerifier::with_no_lib();
+        assert_eq!(license.get_license_expiry().unwrap(), None);
+    }
+
+    #[test]
+    fn no_cert() {
+        let no_cert = GetCertDataResult {
+            result: VerifyResult::VerifyError.into(),
+            message: "no certificate loaded".into(),
+            data: None,
+        };
+        assert!(cert_missing(&no_cert));
+        assert!(cert_missing(&GetCertDataResult::default()));
+
+        let machines = super::licensed_machines(&no_cert).unwrap();
+        assert_eq!(machines, UNLICENSED_MACHINES);
+        assert_ne!(machines, u32::MAX);
+
+        // Without a library, features (including mirroring) are denied and the machine count is
+        // unknown
+        let license = LicenseVerifier::with_no_lib();
+        license
+            .verify_licensed_feature(LicensedFeature::Mirroring)
+            .unwrap_err();
+        license.get_licensed_machines().unwrap_err();
+    }
+
+    /// Returns `msg` encoded as a C string allocated by Rust, like the library does
+    fn fake_buf(msg: impl Message) -> *mut c_char {
+        CString::new(msg.encode_to_vec()).unwrap().into_raw()
+    }
+
+    extern "C" fn fake_init_cert_store() -> c_uchar {
+        0
+    }
+
+    extern "C" fn fake_verify_pem(_pem: *mut c_char, _len: c_uint) -> *mut c_char {
+        fake_buf(VerifyCertResult::default())
+    }
+
+    extern "C" fn fake_verify_file(_path: *mut c_char) -> *mut c_char {
+        fake_buf(VerifyCertResult::default())
+    }
+
+    /// Behaves like the library without a loaded certificate
+    extern "C" fn fake_get_loaded_cert_data() -> *mut c_char {
+        fake_buf(GetCertDataResult {
+            result: VerifyResult::VerifyError.into(),
+            message: "no certificate loaded".into(),
+            data: None,
+        })
+    }
+
+    /// Allows every feature, so denials must come from the verifier itself
+    extern "C" fn fake_verify_feature(_feature: *mut c_char) -> *mut c_char {
+        fake_buf(VerifyFeatureResult {
+            result: VerifyResult::VerifyValid.into(),
+            ..Default::default()
+        })
+    }
+
+    extern "C" fn fake_free_returned_buffer(ptr: *mut c_char) {
+        // SAFETY: All fake functions return buffers allocated by CString::into_raw()
+        drop(unsafe { CString::from_raw(ptr) });
+    }
+
+    #[test]
+    fn lib_without_cert() {
+        let license = LicenseVerifier(Some(LoadedLibrary {
+            library: None,
+            init_cert_store: fake_init_cert_store,
+            verify_pem: fake_verify_pem,
+            verify_file: fake_verify_file,
+            get_loaded_cert_data: fake_get_loaded_cert_data,
+            verify_feature: fake_verify_feature,
+            free_returned_buffer: fake_free_returned_buffer,
+        }));
+
+        assert!(cert_missing(&license.get_license_cert_data().unwrap()));
+        assert_eq!(
+            license.get_licensed_machines().unwrap(),
+            UNLICENSED_MACHINES
+        );
+        assert_eq!(license.get_license_expiry().unwrap(), None);
+
+        let err = license
+            .verify_licensed_feature(LicensedFeature::Mirroring)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("No license certificate"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn licensed_machines() {
+        let cert = |result: VerifyResult, dns_names: &[&str]| GetCertDataResult {
+            result: result.into(),
+            data: Some(CertData {
+                dns_names: dns_names.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        for (result, dns_names, expected) in [
+            (
+                VerifyResult::VerifyValid,
+                &["io.beegfs.numservers.10"][..],
+                10,
+            ),
+            (
+                VerifyResult::VerifyValid,
+                &["other", "io.beegfs.numservers.unlimited"],
+                u32::MAX,
+            ),
+            // An expired certificate still limits to its own number
+            (VerifyResult::VerifyInvalid, &["io.beegfs.numservers.4"], 4),
+        ] {
+            assert_eq!(
+                super::licensed_machines(&cert(result, dns_names)).unwrap(),
+                expected
+            );
+        }
+
+        super::licensed_machines(&cert(VerifyResult::VerifyValid, &["other"])).unwrap_err();
+        // A certificate that couldn't be verified is not treated as missing
+        let unverifiable = cert(
+            VerifyResult::VerifyError,
+            &["io.beegfs.numservers.unlimited"],
+        );
+        assert!(!cert_missing(&unverifiable));
+        super::licensed_machines(&unverifiable).unwrap_err();
+        super::licensed_machines(&cert(
+            VerifyResult::VerifyValid,
+            &["io.beegfs.numservers.x"],
+        ))
+        .unwrap_err();
+    }
+}
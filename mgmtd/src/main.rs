@@ -1,15 +1,21 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use log::LevelFilter;
-use mgmtd::config::LogTarget;
+use mgmtd::config::{LogFormat, LogTarget};
 use mgmtd::db::{self};
 use mgmtd::license::LicenseVerifier;
 use mgmtd::{StaticInfo, start};
-use shared::journald_logger;
+use shared::log_ring::RingLogger;
 use shared::nic::check_ipv6;
-use shared::types::AuthSecret;
+use shared::parser::quota_limits;
+use shared::quota_export;
+use shared::types::NicType;
+use shared::{journald_logger, json_logger};
 use std::backtrace::{Backtrace, BacktraceStatus};
 use std::fmt::Write;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use std::{fs, panic};
 use tokio::signal::unix::{SignalKind, signal};
 use uuid::Uuid;
@@ -28,19 +34,62 @@ fn main() -> Result<(), i32> {
 ///
 /// The binary related setup is made here, before execution is passed to the actual app.
 fn inner_main() -> Result<()> {
+    let start_time = Instant::now();
+
     panic::set_hook(Box::new(panic_handler));
 
     let (user_config, info_log) = mgmtd::config::load_and_parse()?;
 
-    if user_config.init || user_config.import_from_v7.is_some() {
+    // The config has already been checked for validity by `load_and_parse()`, so all that's left
+    // to do is printing it
+    if user_config.check_config {
+        print!(
+            "{}",
+            mgmtd::config::check_config_report(&user_config, &info_log)
+        );
+        return Ok(());
+    }
+
+    if let Some(ref name) = user_config.dump_msg {
+        print!("{}", mgmtd::dump_msg::dump_msg(name)?);
+        return Ok(());
+    }
+
+    if user_config.init || user_config.import_from_v7.is_some() || user_config.import.is_some() {
         init_db(
             &user_config.db_file,
             user_config.import_from_v7.as_deref(),
+            user_config.import.as_deref(),
             user_config.fs_uuid,
+            user_config.aliases.as_deref(),
+        )?;
+        return Ok(());
+    }
+
+    if let Some(ref export_path) = user_config.export {
+        export_db(&user_config.db_file, export_path)?;
+        return Ok(());
+    }
+
+    if let Some(ref export_path) = user_config.export_quota_usage {
+        export_quota_usage(
+            &user_config.db_file,
+            export_path,
+            user_config.quota_update_interval,
         )?;
         return Ok(());
     }
 
+    if let Some(ref limits_path) = user_config.import_quota_limits {
+        import_quota_limits(&user_config.db_file, limits_path)?;
+        return Ok(());
+    }
+
+    if user_config.upgrade && user_config.dry_run {
+        upgrade_dry_run(&user_config.db_file)?;
+        return Ok(());
+    }
+
     if user_config.upgrade {
         println!(
             "--upgrade is deprecated. Upgrading the database now happens automatically \
@@ -49,11 +98,15 @@ on running the management. Exiting."
         return Ok(());
     }
 
+    // Load the secret before opening any other files or sockets (including daemonization and the
+    // logger). An inherited secret file descriptor can't be confused with one of ours that way.
+    let auth_secret = user_config.load_auth_secret()?;
+
     // Daemonization
     // It has to happen as early as possible to make sure all the logs go into the redirected
     // stderr file. This also means there is no success or failure indication, except for the
     // daemonization itself.
-    if user_config.daemonize {
+    if user_config.daemonize && !user_config.foreground {
         std::fs::create_dir_all(
             user_config
                 .daemonize_pid_file
@@ -65,21 +118,52 @@ on running the management. Exiting."
     }
 
     // Initialize logging
-    match user_config.log_target {
-        LogTarget::Stderr => env_logger::Builder::from_env(
-            env_logger::Env::default()
-                .default_filter_or(LevelFilter::from(user_config.log_level.clone()).as_str()),
-        )
-        .format_target(false)
-        .try_init()
-        .context("Env logger initialization failed")?,
+    let logger: Box<dyn log::Log> = match user_config.log_target {
+        LogTarget::Stderr => {
+            // The logger itself lets everything pass, the level is controlled by
+            // log::set_max_level() instead, so it can be changed when reloading the config. Only
+            // filters set with RUST_LOG are applied by the logger itself.
+            let mut builder = env_logger::Builder::new();
+            builder
+                .filter_level(LevelFilter::Trace)
+                .parse_env(env_logger::Env::default());
+
+            match user_config.log_format {
+                LogFormat::Text => builder.format_target(false),
+                LogFormat::Json => builder.format(|buf, record| {
+                    let timestamp = buf.timestamp_millis();
+                    json_logger::write_record(buf, timestamp, record)
+                }),
+            };
+
+            let logger = builder.build();
+
+            if std::env::var_os(env_logger::DEFAULT_FILTER_ENV).is_some() {
+                log::set_max_level(logger.filter());
+            } else {
+                log::set_max_level(user_config.log_level.clone().into());
+            }
+            Box::new(logger)
+        }
 
-        LogTarget::Journald => journald_logger::init(user_config.log_level.clone().into())
-            .context(
+        LogTarget::Journald => {
+            let logger = journald_logger::JournaldLogger::new().context(
                 "Journald logger initialization failed. Check that systemd is available \
                 and running or choose a different log target",
-            )?,
-    }
+            )?;
+
+            log::set_max_level(user_config.log_level.clone().into());
+            Box::new(logger)
+        }
+    };
+
+    // Additionally keep the most recent log records in memory for remote access
+    mgmtd::RECENT_LOGS.configure(
+        user_config.log_recent_records,
+        user_config.log_recent_level.clone().into(),
+    );
+    log::set_boxed_logger(Box::new(RingLogger::new(logger, &mgmtd::RECENT_LOGS)))
+        .context("Logger initialization failed")?;
 
     log::info!("BeeGFS version: {}", mgmtd::version_str());
 
@@ -96,25 +180,40 @@ If you want to initialize a new system, refer to --help or doc.beegfs.io.",
         );
     }
 
-    let auth_secret = if !user_config.auth_disable {
-        let secret = std::fs::read(&user_config.auth_file).with_context(|| {
-            format!(
-                "Could not open authentication file {:?}",
-                user_config.auth_file
-            )
-        })?;
-        Some(AuthSecret::hash_from_bytes(secret))
-    } else {
-        None
-    };
+    if let Err(err) = shared::nic::check_filter_names(&user_config.interfaces) {
+        if user_config.strict_interfaces {
+            return Err(err);
+        }
+        log::warn!("{err:#}");
+    }
 
-    let use_ipv6 = check_ipv6(user_config.beemsg_port, !user_config.ipv6_disable);
-    let network_addrs = shared::nic::query_nics(&user_config.interfaces, use_ipv6)?;
+    // An IPv4 bind address can't handle IPv6 traffic
+    let use_ipv6 = check_ipv6(
+        user_config.beemsg_port,
+        !user_config.ipv6_disable && user_config.bind_addr.is_none_or(|a| a.is_ipv6()),
+    );
+    // The management only accepts TCP / UDP connections, so its own nics are always advertised as
+    // TCP, even if they belong to an RDMA device
+    let network_addrs = shared::nic::query_nics(
+        &user_config.interfaces,
+        use_ipv6,
+        user_config.interfaces_strict_order,
+        Some(NicType::Tcp),
+    )?;
+    // Other nodes can only reach the management on the addresses it listens on
+    let nics_before = network_addrs.len();
+    let network_addrs = shared::nic::restrict_to_bind_addr(network_addrs, user_config.bind_addr);
+    if nics_before > 0 && network_addrs.is_empty() {
+        bail!(
+            "bind-addr {:?} doesn't match any of the interfaces selected by the interfaces filter",
+            user_config.bind_addr
+        );
+    }
 
     // Configure the tokio runtime
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
-        .thread_stack_size(16 * 1024 * 1024)
+        .thread_stack_size(user_config.worker_stack_size.try_into()?)
         .max_blocking_threads(user_config.max_blocking_threads)
         .build()?;
 
@@ -137,6 +236,7 @@ If you want to initialize a new system, refer to --help or doc.beegfs.io.",
                 user_config,
                 auth_secret,
                 network_addrs,
+                start_time,
             },
             license,
         )
@@ -146,6 +246,21 @@ If you want to initialize a new system, refer to --help or doc.beegfs.io.",
         // notification that the service has completed startup and is ready for serving
         let _ = sd_notify::notify(&[sd_notify::NotifyState::Ready]);
 
+        // Reload the runtime changeable settings on SIGHUP
+        let reloader = run.config_reloader();
+        let mut sig_hup =
+            signal(SignalKind::hangup()).context("Failed to install signal handler")?;
+        tokio::spawn(async move {
+            while sig_hup.recv().await.is_some() {
+                log::info!("Received SIGHUP, reloading configuration");
+
+                match mgmtd::config::load_and_parse() {
+                    Ok((config, _)) => reloader.reload(&config),
+                    Err(err) => log::error!("Reloading configuration failed: {err:#}"),
+                }
+            }
+        });
+
         run.wait_for_shutdown(wait_for_shutdown_signal).await;
 
         Ok(())
@@ -154,10 +269,27 @@ If you want to initialize a new system, refer to --help or doc.beegfs.io.",
 
 /// Create and initialize a new database.
 ///
-/// Optionally import v7 data from the given path. Optionally the FsUUID can be specified otherwise
-/// it will be autogenerated. The database file is only written to disk if initialization succeeds.
-/// This is called before the logger is initialized, so logging from here will do nothing.
-fn init_db(db_file: &Path, v7_path: Option<&Path>, fs_uuid: Option<Uuid>) -> Result<()> {
+/// Optionally import v7 data or a previous export from the given path. Optionally the FsUUID can be
+/// specified otherwise it will be autogenerated (or taken from the export). Optionally, aliases for
+/// the initial entities are read from the given file. The database file is
+/// only written to disk if initialization succeeds. This is called before the logger is
+/// initialized, so logging from here will do nothing.
+fn init_db(
+    db_file: &Path,
+    v7_path: Option<&Path>,
+    export_path: Option<&Path>,
+    fs_uuid: Option<Uuid>,
+    aliases_path: Option<&Path>,
+) -> Result<()> {
+    let aliases = match aliases_path {
+        Some(path) => db::InitialAliases::parse(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Reading aliases file {path:?} failed"))?,
+        )
+        .with_context(|| format!("Parsing aliases file {path:?} failed"))?,
+        None => Default::default(),
+    };
+
     let mut conn = sqlite::open_in_memory()?;
 
     // Create db in memory
@@ -166,7 +298,14 @@ fn init_db(db_file: &Path, v7_path: Option<&Path>, fs_uuid: Option<Uuid>) -> Res
 
         let version =
             sqlite::migrate_schema(&tx, db::MIGRATIONS).context("Creating schema failed")?;
-        db::initial_entries(&tx, fs_uuid).context("Creating initial entries failed")?;
+        if let Some(export_path) = export_path {
+            let data = fs::read(export_path)
+                .with_context(|| format!("Reading export file {export_path:?} failed"))?;
+            db::export::import(&tx, &data).context("Management data import failed")?;
+        } else {
+            db::initial_entries(&tx, fs_uuid, &aliases)
+                .context("Creating initial entries failed")?;
+        }
 
         if let Some(v7_path) = v7_path {
             db::import_v7(&tx, v7_path).context("v7 management data import failed")?;
@@ -185,12 +324,23 @@ fn init_db(db_file: &Path, v7_path: Option<&Path>, fs_uuid: Option<Uuid>) -> Res
                 .ok_or_else(|| anyhow!("File does not have a parent folder"))?,
         )?;
         std::fs::File::create_new(db_file)?;
-        conn.backup(rusqlite::MAIN_DB, db_file, None)
-            .inspect_err(|_| {
-                if let Err(err) = std::fs::remove_file(db_file) {
-                    println!("Cleaning up {db_file:?} failed: {err}");
-                }
-            })?;
+        sqlite::backup_to_file(
+            &conn,
+            db_file,
+            sqlite::BACKUP_PAGES_PER_STEP,
+            |p| {
+                println!(
+                    "Writing database: {}% completed",
+                    sqlite::backup_percentage(p)
+                )
+            },
+            || false,
+        )
+        .inspect_err(|_| {
+            if let Err(err) = std::fs::remove_file(db_file) {
+                println!("Cleaning up {db_file:?} failed: {err}");
+            }
+        })?;
 
         Ok(())
     })()
@@ -205,6 +355,8 @@ IMPORTANT: The import only contains managements data store, no configuration fro
 beegfs-mgmtd.conf. Before starting the management, you must MANUALLY transfer your old settings \
 (if they still apply) to the new config file (/etc/beegfs/beegfs-mgmtd.toml by default)."
         );
+    } else if let Some(export_path) = export_path {
+        println!(" Successfully imported management data from {export_path:?}.");
     } else {
         println!();
     }
@@ -212,6 +364,130 @@ beegfs-mgmtd.conf. Before starting the management, you must MANUALLY transfer yo
     Ok(())
 }
 
+/// Exports the management state from the database into a new file.
+///
+/// The database must be at the current schema version. This is called before the logger is
+/// initialized, so logging from here will do nothing.
+fn export_db(db_file: &Path, export_path: &Path) -> Result<()> {
+    let mut conn = sqlite::open_read_only(db_file)
+        .with_context(|| format!("Opening database file {db_file:?} failed"))?;
+    let tx = conn.transaction()?;
+
+    if sqlite::check_schema(&tx, db::MIGRATIONS)? {
+        anyhow::bail!(
+            "The database needs to be migrated before exporting. Start the management once to \
+migrate it automatically."
+        );
+    }
+
+    let data = db::export::export(&tx).context("Exporting management data failed")?;
+
+    fs::File::create_new(export_path)
+        .and_then(|mut f| std::io::Write::write_all(&mut f, &data))
+        .with_context(|| format!("Writing export file {export_path:?} failed"))?;
+
+    println!("Exported management data from {db_file:?} to {export_path:?}.");
+
+    Ok(())
+}
+
+/// Exports the quota usage and the effective limits from the database into a new gzip compressed
+/// file.
+///
+/// The database must be at the current schema version. This is called before the logger is
+/// initialized, so logging from here will do nothing.
+fn export_quota_usage(db_file: &Path, export_path: &Path, refresh_period: Duration) -> Result<()> {
+    let mut conn = sqlite::open_read_only(db_file)
+        .with_context(|| format!("Opening database file {db_file:?} failed"))?;
+    let tx = conn.transaction()?;
+
+    if sqlite::check_schema(&tx, db::MIGRATIONS)? {
+        anyhow::bail!(
+            "The database needs to be migrated before exporting. Start the management once to \
+migrate it automatically."
+        );
+    }
+
+    let file = fs::File::create_new(export_path)
+        .with_context(|| format!("Creating export file {export_path:?} failed"))?;
+
+    let mut writer = quota_export::Writer::new(
+        GzEncoder::new(std::io::BufWriter::new(file), Compression::default()),
+        &quota_export::Header::new(Some(refresh_period.as_secs())),
+    )?;
+    let count =
+        db::export::export_quota_usage(&tx, &mut writer).context("Exporting quota usage failed")?;
+    (|| -> Result<_> {
+        let mut out = writer.finish()?.finish()?;
+        std::io::Write::flush(&mut out)?;
+        Ok(())
+    })()
+    .with_context(|| format!("Writing export file {export_path:?} failed"))?;
+
+    println!("Exported {count} quota usage entries from {db_file:?} to {export_path:?}.");
+
+    Ok(())
+}
+
+/// Imports quota limits from a quota limit file into the database.
+///
+/// The database must be at the current schema version. Nothing is imported if the file contains
+/// an invalid line. This is called before the logger is initialized, so logging from here will do
+/// nothing.
+fn import_quota_limits(db_file: &Path, limits_path: &Path) -> Result<()> {
+    let input = fs::read_to_string(limits_path)
+        .with_context(|| format!("Reading quota limit file {limits_path:?} failed"))?;
+    let (entries, _) = quota_limits::parse(&input, true)
+        .with_context(|| format!("Parsing quota limit file {limits_path:?} failed"))?;
+
+    let mut conn = sqlite::open(db_file)
+        .with_context(|| format!("Opening database file {db_file:?} failed"))?;
+    let tx = conn.transaction()?;
+
+    if sqlite::check_schema(&tx, db::MIGRATIONS)? {
+        anyhow::bail!(
+            "The database needs to be migrated before importing. Start the management once to \
+migrate it automatically."
+        );
+    }
+
+    db::quota::import_limits(&tx, &entries).context("Importing quota limits failed")?;
+    tx.commit()?;
+
+    println!(
+        "Imported {} quota limit entries from {limits_path:?} into {db_file:?}.",
+        entries.len()
+    );
+
+    Ok(())
+}
+
+/// Reports the migrations that would be applied to the database without modifying it.
+///
+/// This is called before the logger is initialized, so logging from here will do nothing.
+fn upgrade_dry_run(db_file: &Path) -> Result<()> {
+    let mut conn = sqlite::open_read_only(db_file)
+        .with_context(|| format!("Opening database file {db_file:?} failed"))?;
+    let tx = conn.transaction()?;
+
+    let pending = sqlite::pending_migrations(&tx, db::MIGRATIONS)?;
+
+    println!("Database file: {db_file:?}");
+    println!("Current schema version: {}", pending.current);
+    println!("Latest schema version: {}", pending.latest);
+
+    if pending.migrations.is_empty() {
+        println!("The database is up to date, no migrations would be applied.");
+    } else {
+        println!("Migrations that would be applied:");
+        for m in pending.migrations {
+            println!("  {}.sql", m.version);
+        }
+    }
+
+    Ok(())
+}
+
 fn panic_handler(info: &std::panic::PanicHookInfo) {
     let backtrace = Backtrace::capture();
 
@@ -0,0 +1,243 @@
+//! Metrics collected by the management and their export via HTTP in the Prometheus text format
+//!
+//! The endpoint is only started when `metrics-port` is configured. It serves `GET /metrics` and
+//! nothing else.
+
+use crate::app::RuntimeApp;
+use anyhow::{Context, Result, bail};
+use shared::conn::StoreStats;
+use shared::grpc::GRPC_REQUESTS;
+use shared::metrics::{Counter, DURATION_BUCKETS, Gauge, Histogram, encode};
+use shared::nic::select_bind_addr;
+use shared::run_state::RunStateHandle;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// Counts the handled BeeMsgs by message type
+pub(crate) static BEEMSG_MESSAGES: Counter = Counter::new(
+    "beegfs_mgmtd_beemsg_messages_total",
+    "Number of handled BeeMsgs by message type",
+    &["msg_type"],
+);
+
+/// Tracks the duration of database operations, including waiting for a connection
+pub(crate) static DB_OP_DURATION: Histogram = Histogram::new(
+    "beegfs_mgmtd_db_op_duration_seconds",
+    "Duration of database operations by operation type",
+    &["op"],
+    DURATION_BUCKETS,
+);
+
+/// Tracks the duration of a complete quota update cycle
+pub(crate) static QUOTA_UPDATE_DURATION: Histogram = Histogram::new(
+    "beegfs_mgmtd_quota_update_duration_seconds",
+    "Duration of a complete quota update cycle",
+    &[],
+    DURATION_BUCKETS,
+);
+
+/// The outgoing connection pool usage. Updated on scrape.
+static CONN_POOL_STREAMS: Gauge = Gauge::new(
+    "beegfs_mgmtd_conn_pool_streams",
+    "Number of outgoing streams in the connection pool by state",
+    &["state"],
+);
+
+/// The number of message buffers stored in the connection pool. Updated on scrape.
+static CONN_POOL_BUFS: Gauge = Gauge::new(
+    "beegfs_mgmtd_conn_pool_buffers",
+    "Number of message buffers stored in the connection pool",
+    &[],
+);
+
+/// Maximum accepted size of a HTTP request header
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+/// Time a client has to send its request before the connection is closed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Awaits a database operation and records its duration under the given operation name
+pub(crate) async fn time_db_op<R>(op: &str, fut: impl Future<Output = R>) -> R {
+    let start = Instant::now();
+    let res = fut.await;
+    DB_OP_DURATION.observe_duration(&[op], start.elapsed());
+    res
+}
+
+/// Renders all metrics in the Prometheus text format
+fn render(pool_stats: StoreStats) -> String {
+    CONN_POOL_STREAMS.set(&["open"], pool_stats.open_streams as f64);
+    CONN_POOL_STREAMS.set(&["idle"], pool_stats.idle_streams as f64);
+    CONN_POOL_BUFS.set(&[], pool_stats.bufs as f64);
+
+    encode(&[
+        &GRPC_REQUESTS,
+        &BEEMSG_MESSAGES,
+        &DB_OP_DURATION,
+        &QUOTA_UPDATE_DURATION,
+        &CONN_POOL_STREAMS,
+        &CONN_POOL_BUFS,
+    ])
+}
+
+/// Starts the metrics HTTP endpoint if `metrics_port` is configured.
+///
+/// Returns after the listener has been bound. The endpoint is stopped on shutdown.
+pub(crate) async fn serve(app: RuntimeApp, run_state: RunStateHandle) -> Result<()> {
+    let Some(port) = app.info.user_config.metrics_port else {
+        return Ok(());
+    };
+
+    let serve_addr = select_bind_addr(app.info.user_config.bind_addr, app.info.use_ipv6, port);
+
+    let listener = TcpListener::bind(serve_addr)
+        .await
+        .with_context(|| format!("Binding metrics endpoint to {serve_addr} failed"))?;
+
+    log::info!("Serving metrics on http://{serve_addr}/metrics");
+
+    serve_listener(listener, move || render(app.conn.stats()), run_state);
+    Ok(())
+}
+
+/// Accepts HTTP connections on `listener` until shutdown and answers them using `render`.
+fn serve_listener(
+    listener: TcpListener,
+    render: impl Fn() -> String + Clone + Send + Sync + 'static,
+    mut run_state: RunStateHandle,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                res = listener.accept() => {
+                    match res {
+                        Ok((stream, _)) => {
+                            let render = render.clone();
+                            tokio::spawn(async move {
+                                if let Err(err) = handle_connection(stream, render).await {
+                                    log::debug!("Handling metrics request failed: {err:#}");
+                                }
+                            });
+                        }
+                        Err(err) => log::error!("Accepting metrics connection failed: {err:#}"),
+                    }
+                }
+                _ = run_state.wait_for_shutdown() => { break; }
+            }
+        }
+
+        log::debug!("Metrics endpoint has been shut down");
+    });
+}
+
+/// Reads a single HTTP request from the stream, answers it and closes the connection.
+///
+/// Only the request line is evaluated, headers and body are ignored.
+async fn handle_connection(mut stream: TcpStream, render: impl Fn() -> String) -> Result<()> {
+    let mut buf = vec![0; MAX_REQUEST_LEN];
+    let mut len = 0;
+
+    // Read until the end of the request header
+    while !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        if len == buf.len() {
+            bail!("Request header exceeds {MAX_REQUEST_LEN} bytes");
+        }
+
+        let n = timeout(REQUEST_TIMEOUT, stream.read(&mut buf[len..]))
+            .await
+            .context("Timed out reading request")??;
+        if n == 0 {
+            bail!("Connection closed before request was complete");
+        }
+
+        len += n;
+    }
+
+    let request_line = buf[..len].split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(request_line)
+        .context("Request line is not valid UTF-8")?
+        .split(' ');
+
+    let (status, body) = match (parts.next(), parts.next().map(|p| p.split('?').next())) {
+        (Some("GET"), Some(Some("/metrics"))) => ("200 OK", render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+
+    let resp = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared::run_state;
+    use std::net::SocketAddr;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    #[tokio::test]
+    async fn scrape() {
+        let (run_state, _run_state_control) = run_state::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        serve_listener(
+            listener,
+            || {
+                render(StoreStats {
+                    open_streams: 3,
+                    idle_streams: 2,
+                    bufs: 1,
+                })
+            },
+            run_state,
+        );
+
+        // Record some values the way the request handling code does
+        GRPC_REQUESTS.inc(&["get_nodes", "ok"]);
+        BEEMSG_MESSAGES.inc(&["Heartbeat"]);
+        time_db_op("read_tx", async {}).await;
+        QUOTA_UPDATE_DURATION.observe_duration(&[], Duration::from_millis(5));
+
+        let resp = get(addr, "/metrics").await;
+
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        for name in [
+            "beegfs_grpc_requests_total{method=\"get_nodes\",result=\"ok\"}",
+            "beegfs_mgmtd_beemsg_messages_total{msg_type=\"Heartbeat\"}",
+            "beegfs_mgmtd_db_op_duration_seconds_count{op=\"read_tx\"}",
+            "beegfs_mgmtd_quota_update_duration_seconds_count",
+            "beegfs_mgmtd_conn_pool_streams{state=\"open\"} 3",
+            "beegfs_mgmtd_conn_pool_streams{state=\"idle\"} 2",
+            "beegfs_mgmtd_conn_pool_buffers 1",
+        ] {
+            assert!(resp.contains(name), "{name} missing in response:\n{resp}");
+        }
+
+        let resp = get(addr, "/other").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
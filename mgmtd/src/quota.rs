@@ -3,19 +3,28 @@
 mod system_id;
 
 use crate::app::*;
+use crate::config::Config;
+use crate::db;
 use crate::license::LicensedFeature;
 use crate::types::SqliteEnumExt;
 use anyhow::{Context as AnyhowContext, Result};
 use rusqlite::params;
 use shared::bee_msg::OpsErr;
 use shared::bee_msg::quota::{
-    GetQuotaInfo, GetQuotaInfoResp, SetExceededQuota, SetExceededQuotaResp,
+    GetQuotaInfo, GetQuotaInfoResp, QuotaEntry, SetExceededQuota, SetExceededQuotaResp,
 };
 use shared::types::{NodeType, PoolId, QuotaId, QuotaIdType, QuotaType, TargetId, Uid};
 use sqlite::TransactionExt;
 use sqlite_check::sql;
 use std::collections::HashSet;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Maximum time to wait for a storage node to deliver the quota information of a target. Large
+/// id sets can take a while to be collected, but a hanging node must not stall the update cycle.
+const QUOTA_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Fetches quota information for all storage targets and updates the quota usage database
 pub(crate) async fn fetch_and_update(app: &impl App) -> Result<()> {
@@ -50,156 +59,213 @@ pub(crate) async fn fetch_and_update(app: &impl App) -> Result<()> {
         targets.len()
     );
 
-    // The to-be-queried IDs
-    let (mut user_ids, mut group_ids) = (HashSet::new(), HashSet::new());
-
-    // If configured, add system User IDS
-    let user_ids_min = app.static_info().user_config.quota_user_system_ids_min;
+    // The to-be-queried IDs. All configured sources of a type are combined.
+    let config = &app.static_info().user_config;
 
-    if let Some(user_ids_min) = user_ids_min {
-        system_id::user_ids()
-            .await
-            .filter(|e| e >= &user_ids_min)
-            .for_each(|e| {
-                user_ids.insert(e);
-            });
-    }
+    // If configured, use the system user and group IDs above the given minimum
+    let system_user_ids: Vec<_> = match config.quota_user_system_ids_min {
+        Some(min) => system_id::user_ids().await.filter(|e| *e >= min).collect(),
+        None => vec![],
+    };
+    let system_group_ids: Vec<_> = match config.quota_group_system_ids_min {
+        Some(min) => system_id::group_ids().await.filter(|e| *e >= min).collect(),
+        None => vec![],
+    };
 
-    // If configured, add system Group IDS
-    let group_ids_min = app.static_info().user_config.quota_group_system_ids_min;
+    let user_ids = combine_quota_ids(
+        QuotaIdType::User,
+        system_user_ids,
+        config.quota_user_ids_file.as_deref(),
+        config.quota_user_ids_range.as_ref(),
+    )?;
+    let group_ids = combine_quota_ids(
+        QuotaIdType::Group,
+        system_group_ids,
+        config.quota_group_ids_file.as_deref(),
+        config.quota_group_ids_range.as_ref(),
+    )?;
+    let project_ids = combine_quota_ids(
+        QuotaIdType::Project,
+        vec![],
+        config.quota_project_ids_file.as_deref(),
+        config.quota_project_ids_range.as_ref(),
+    )?;
+
+    // Sends one request per target to the respective owner node. Requesting is done concurrently,
+    // but limited to the configured number of targets at a time. The results are processed as
+    // they arrive.
+    let concurrency = app.static_info().user_config.quota_fetch_concurrency;
+    let mut targets = targets.into_iter();
+    let mut tasks = JoinSet::new();
+
+    loop {
+        while tasks.len() < concurrency
+            && let Some((target_id, pool_id, node_uid)) = targets.next()
+        {
+            tasks.spawn(fetch_target(
+                app.clone(),
+                target_id,
+                pool_id,
+                node_uid,
+                user_ids.clone(),
+                group_ids.clone(),
+                project_ids.clone(),
+            ));
+        }
 
-    if let Some(group_ids_min) = group_ids_min {
-        system_id::group_ids()
-            .await
-            .filter(|e| e >= &group_ids_min)
-            .for_each(|e| {
-                group_ids.insert(e);
-            });
-    }
+        let Some(res) = tasks.join_next().await else {
+            break;
+        };
 
-    // If configured, add user IDs from file
-    if let Some(ref path) = app.static_info().user_config.quota_user_ids_file {
-        try_read_quota_ids(path, &mut user_ids)?;
+        // Only process that target if there were not errors when fetching for this target. Failed
+        // targets are just skipped and fetched again in the next cycle.
+        match res {
+            Ok((target_id, Some(entries))) => {
+                if let Err(err) = update_target(app, target_id, entries).await {
+                    log::error!(
+                        "Updating quota usage for storage target {target_id} failed: {err:#}"
+                    );
+                }
+            }
+            Ok((_, None)) => {}
+            Err(err) => log::error!("Quota fetch task failed: {err}"),
+        }
     }
 
-    // If configured, add group IDs from file
-    if let Some(ref path) = app.static_info().user_config.quota_group_ids_file {
-        try_read_quota_ids(path, &mut group_ids)?;
-    }
+    let exceeded = app
+        .write_tx(|tx| db::quota::update_exceeded(tx, db::quota::ExceededScope::All))
+        .await?;
+    log::debug!("{exceeded} quota ID, type and pool combinations exceed their limits");
 
-    // If configured, add range based user IDs
-    if let Some(range) = &app.static_info().user_config.quota_user_ids_range {
-        user_ids.extend(range.clone());
-    }
+    Ok(())
+}
 
-    // If configured, add range based group IDs
-    if let Some(range) = &app.static_info().user_config.quota_group_ids_range {
-        group_ids.extend(range.clone());
-    }
+/// Fetches the quota information for the given user, group and project IDs from a storage target.
+///
+/// Project quota is only requested if there are project IDs to query, so servers not supporting
+/// it are never asked for it unless it has been configured.
+///
+/// # Return value
+/// Returns the target id and the received entries. If fetching failed, the error is logged and
+/// `None` is returned instead of the entries.
+async fn fetch_target(
+    app: impl App,
+    target_id: TargetId,
+    pool_id: PoolId,
+    node_uid: Uid,
+    user_ids: HashSet<QuotaId>,
+    group_ids: HashSet<QuotaId>,
+    project_ids: HashSet<QuotaId>,
+) -> (TargetId, Option<Vec<QuotaEntry>>) {
+    let resp_users: Result<GetQuotaInfoResp> = app
+        .request_with_timeout(
+            node_uid,
+            &GetQuotaInfo::with_user_ids(user_ids, target_id, pool_id),
+            QUOTA_REQUEST_TIMEOUT,
+        )
+        .await;
 
-    let mut tasks = vec![];
-    // Sends one request per target to the respective owner node
-    // Requesting is done concurrently.
-    for (target_id, pool_id, node_uid) in targets {
-        let app2 = app.clone();
-        let user_ids2 = user_ids.clone();
-        let group_ids2 = group_ids.clone();
-
-        tasks.push(tokio::spawn(async move {
-            let resp_users: Result<GetQuotaInfoResp> = app2
-                .request(
-                    node_uid,
-                    &GetQuotaInfo::with_user_ids(user_ids2, target_id, pool_id),
-                )
-                .await;
+    let resp_groups: Result<GetQuotaInfoResp> = app
+        .request_with_timeout(
+            node_uid,
+            &GetQuotaInfo::with_group_ids(group_ids, target_id, pool_id),
+            QUOTA_REQUEST_TIMEOUT,
+        )
+        .await;
 
-            let resp_groups: Result<GetQuotaInfoResp> = app2
-                .request(
-                    node_uid,
-                    &GetQuotaInfo::with_group_ids(group_ids2, target_id, pool_id),
-                )
-                .await;
+    let resp_projects: Result<GetQuotaInfoResp> = if project_ids.is_empty() {
+        Ok(GetQuotaInfoResp::default())
+    } else {
+        app.request_with_timeout(
+            node_uid,
+            &GetQuotaInfo::with_project_ids(project_ids, target_id, pool_id),
+            QUOTA_REQUEST_TIMEOUT,
+        )
+        .await
+    };
 
-            match (resp_users, resp_groups) {
-                (Ok(u), Ok(mut g)) => {
-                    let mut entries = u.quota_entry;
-                    entries.append(&mut g.quota_entry);
+    match (resp_users, resp_groups, resp_projects) {
+        (Ok(u), Ok(mut g), Ok(mut p)) => {
+            let mut entries = u.quota_entry;
+            entries.append(&mut g.quota_entry);
+            entries.append(&mut p.quota_entry);
 
-                    (target_id, Some(entries))
-                }
-                (u, g) => {
-                    let log_u = u
-                        .err()
-                        .map(|err| format!("\nUsers: {err:#}"))
-                        .unwrap_or_else(|| "".into());
-                    let log_g = g
-                        .err()
-                        .map(|err| format!("\nGroups: {err:#}"))
-                        .unwrap_or_else(|| "".into());
+            (target_id, Some(entries))
+        }
+        (u, g, p) => {
+            let log_u = u
+                .err()
+                .map(|err| format!("\nUsers: {err:#}"))
+                .unwrap_or_else(|| "".into());
+            let log_g = g
+                .err()
+                .map(|err| format!("\nGroups: {err:#}"))
+                .unwrap_or_else(|| "".into());
+            let log_p = p
+                .err()
+                .map(|err| format!("\nProjects: {err:#}"))
+                .unwrap_or_else(|| "".into());
 
-                    log::error!(
-                        "Fetching quota info for storage target {target_id} from node with uid \
-{node_uid} failed.{log_u}{log_g}"
-                    );
+            log::error!(
+                "Fetching quota info for storage target {target_id} from node with uid \
+{node_uid} failed.{log_u}{log_g}{log_p}"
+            );
 
-                    (target_id, None)
-                }
-            }
-        }));
+            (target_id, None)
+        }
     }
+}
 
-    // Await all the responses
-    for t in tasks {
-        let (target_id, entries) = t.await?;
-
-        // Only process that target if there were not errors when fetching for this target
-        if let Some(entries) = entries {
-            app.write_tx(move |tx| {
-                // Always delete all the old entries for that target to make sure entries for no
-                // longer queried ids are removed. We always get the complete list from the
-                // storages and we only update if there was no fetch error.
-                tx.execute_cached(
-                    sql!("DELETE FROM quota_usage WHERE target_id = ?1"),
-                    [target_id],
-                )?;
-
-                let mut insert_stmt = tx.prepare_cached(sql!(
-                    "INSERT INTO quota_usage (quota_id, id_type, quota_type, target_id, value)
-                    VALUES (?1, ?2, ?3 ,?4 ,?5)"
-                ))?;
-
-                log::debug!(
-                    "Setting {} quota usage entries for target {target_id}",
-                    entries.len()
-                );
-
-                for e in entries {
-                    if e.space > 0 {
-                        insert_stmt.execute(params![
-                            e.id,
-                            e.id_type.sql_variant(),
-                            QuotaType::Space.sql_variant(),
-                            target_id,
-                            e.space
-                        ])?;
-                    }
+/// Replaces the quota usage entries of a storage target in the database.
+async fn update_target(
+    app: &impl App,
+    target_id: TargetId,
+    entries: Vec<QuotaEntry>,
+) -> Result<()> {
+    app.write_tx(move |tx| {
+        // Always delete all the old entries for that target to make sure entries for no
+        // longer queried ids are removed. We always get the complete list from the
+        // storages and we only update if there was no fetch error.
+        tx.execute_cached(
+            sql!("DELETE FROM quota_usage WHERE target_id = ?1"),
+            [target_id],
+        )?;
+
+        let mut insert_stmt = tx.prepare_cached(sql!(
+            "INSERT INTO quota_usage (quota_id, id_type, quota_type, target_id, value)
+            VALUES (?1, ?2, ?3 ,?4 ,?5)"
+        ))?;
+
+        log::debug!(
+            "Setting {} quota usage entries for target {target_id}",
+            entries.len()
+        );
 
-                    if e.inodes > 0 {
-                        insert_stmt.execute(params![
-                            e.id,
-                            e.id_type.sql_variant(),
-                            QuotaType::Inode.sql_variant(),
-                            target_id,
-                            e.inodes
-                        ])?;
-                    }
-                }
+        for e in entries {
+            if e.space > 0 {
+                insert_stmt.execute(params![
+                    e.id,
+                    e.id_type.sql_variant(),
+                    QuotaType::Space.sql_variant(),
+                    target_id,
+                    e.space
+                ])?;
+            }
 
-                Ok(())
-            })
-            .await?;
+            if e.inodes > 0 {
+                insert_stmt.execute(params![
+                    e.id,
+                    e.id_type.sql_variant(),
+                    QuotaType::Inode.sql_variant(),
+                    target_id,
+                    e.inodes
+                ])?;
+            }
         }
-    }
+
+        Ok(())
+    })
+    .await?;
 
     Ok(())
 }
@@ -213,6 +279,13 @@ pub(crate) async fn distribute_exceeded(app: &impl App) -> Result<()> {
 
     let quota_licensed = app.verify_licensed_feature(LicensedFeature::Quota).is_ok();
 
+    // Project quota is only pushed if project IDs are configured to not confuse servers that
+    // don't support it
+    let mut id_types = vec![QuotaIdType::User, QuotaIdType::Group];
+    if project_quota_configured(&app.static_info().user_config) {
+        id_types.push(QuotaIdType::Project);
+    }
+
     let (msges, nodes) = app
         .read_tx(move |tx| {
             let pools: Vec<_> =
@@ -223,7 +296,7 @@ pub(crate) async fn distribute_exceeded(app: &impl App) -> Result<()> {
             // previously existing exceeded ids on the servers.
             let mut msges: Vec<SetExceededQuota> = vec![];
             for pool_id in pools {
-                for id_type in [QuotaIdType::User, QuotaIdType::Group] {
+                for id_type in id_types.iter().copied() {
                     for quota_type in [QuotaType::Space, QuotaType::Inode] {
                         msges.push(SetExceededQuota {
                             pool_id,
@@ -242,9 +315,10 @@ pub(crate) async fn distribute_exceeded(app: &impl App) -> Result<()> {
                     FROM quota_usage AS e
                     INNER JOIN targets AS st USING(node_type, target_id)
                     LEFT JOIN quota_default_limits AS d USING(id_type, quota_type, pool_id)
+                    LEFT JOIN quota_system_default_limits AS s USING(id_type, quota_type)
                     LEFT JOIN quota_limits AS l USING(quota_id, id_type, quota_type, pool_id)
                     GROUP BY e.quota_id, e.id_type, e.quota_type, st.pool_id
-                    HAVING SUM(e.value) > COALESCE(l.value, d.value)"
+                    HAVING SUM(e.value) > COALESCE(l.value, d.value, s.value)"
                 ))?;
                 let mut rows = stmt.query([])?;
                 while let Some(row) = rows.next()? {
@@ -315,16 +389,60 @@ pub(crate) async fn distribute_exceeded(app: &impl App) -> Result<()> {
     Ok(())
 }
 
-/// Tries to read quota IDs (users, groups) from a file
+/// Returns whether project IDs to be quota queried and enforced are configured
+fn project_quota_configured(config: &Config) -> bool {
+    config.quota_project_ids_file.is_some() || config.quota_project_ids_range.is_some()
+}
+
+/// Combines the quota IDs of one type from the system, a file and a range into one set.
+///
+/// The sources are additive - an ID is queried if it is contained in any of them. Logs the
+/// resulting number of IDs if any source is given.
+fn combine_quota_ids(
+    id_type: QuotaIdType,
+    system_ids: Vec<QuotaId>,
+    file: Option<&Path>,
+    range: Option<&RangeInclusive<QuotaId>>,
+) -> Result<HashSet<QuotaId>> {
+    let mut ids = HashSet::new();
+    let system_count = system_ids.len();
+    ids.extend(system_ids);
+
+    let mut file_count = 0;
+    if let Some(path) = file {
+        file_count = try_read_quota_ids(path, &mut ids)?;
+    }
+
+    if let Some(range) = range {
+        ids.extend(range.clone());
+    }
+
+    if system_count > 0 || file.is_some() || range.is_some() {
+        log::info!(
+            "Querying quota for {} {id_type} IDs ({system_count} from the system, {file_count} \
+            from file, {} from range)",
+            ids.len(),
+            range.map_or(0, |e| e.clone().count())
+        );
+    }
+
+    Ok(ids)
+}
+
+/// Tries to read quota IDs (users, groups, projects) from a file
 ///
-/// IDs must be in numerical form and separated by any whitespace.
-fn try_read_quota_ids(path: &Path, read_into: &mut HashSet<QuotaId>) -> Result<()> {
-    let data = std::fs::read_to_string(path)?;
+/// IDs must be in numerical form and separated by any whitespace. Returns the number of IDs read.
+fn try_read_quota_ids(path: &Path, read_into: &mut HashSet<QuotaId>) -> Result<usize> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading quota file {path:?} failed"))?;
+
+    let mut count = 0;
     for id in data.split_whitespace().map(|e| e.parse()) {
-        read_into.insert(id.context("Invalid syntax in quota file {path}")?);
+        read_into.insert(id.with_context(|| format!("Invalid syntax in quota file {path:?}"))?);
+        count += 1;
     }
 
-    Ok(())
+    Ok(count)
 }
 
 #[cfg(test)]
@@ -338,6 +456,10 @@ mod test {
         SetExceededQuotaResp,
     };
     use shared::types::{QuotaIdType, QuotaType};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
     #[tokio::test]
     async fn update() {
@@ -499,6 +621,48 @@ mod test {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn fetch_concurrency() {
+        let app = TestApp::with_config(Config {
+            quota_enable: true,
+            quota_user_ids_range: Some(0..=9),
+            quota_group_ids_range: Some(0..=9),
+            quota_fetch_concurrency: 2,
+            ..Default::default()
+        })
+        .await;
+
+        let targets: usize = app
+            .db
+            .read_tx(|tx| {
+                tx.query_row(
+                    "SELECT COUNT(*) FROM storage_targets WHERE node_id IS NOT NULL",
+                    [],
+                    |row| row.get(0),
+                )
+                .map_err(Into::into)
+            })
+            .await
+            .unwrap();
+        assert!(targets > 2);
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        app.set_request_handler({
+            let requests = requests.clone();
+            move |_| {
+                requests.fetch_add(1, Ordering::Relaxed);
+                Ok(Box::new(GetQuotaInfoResp::default()))
+            }
+        });
+        app.set_request_delay(Duration::from_millis(20));
+
+        super::fetch_and_update(&app).await.unwrap();
+
+        // One user and one group request per target, but never more than two at a time
+        assert_eq!(requests.load(Ordering::Relaxed), targets * 2);
+        assert_eq!(app.max_requests_in_flight(), 2);
+    }
+
     #[tokio::test]
     async fn distribute_exceeded() {
         // This fn doesn't need special config
@@ -535,4 +699,39 @@ mod test {
 
         super::distribute_exceeded(&app).await.unwrap();
     }
+
+    #[test]
+    fn combine_quota_ids() {
+        let path = std::env::temp_dir().join(format!("mgmtd-quota-ids-{}", std::process::id()));
+        std::fs::write(&path, "1001 2000\n 2001\t3000\n").unwrap();
+
+        // All sources are combined, overlapping IDs are only contained once
+        let ids = super::combine_quota_ids(
+            QuotaIdType::User,
+            vec![5, 1000, 1001],
+            Some(path.as_path()),
+            Some(&(2000..=2002)),
+        )
+        .unwrap();
+        assert_eq!(ids, HashSet::from([5, 1000, 1001, 2000, 2001, 2002, 3000]));
+
+        // Each source alone
+        let ids = super::combine_quota_ids(QuotaIdType::Group, vec![5], None, None).unwrap();
+        assert_eq!(ids, HashSet::from([5]));
+        let ids = super::combine_quota_ids(QuotaIdType::Group, vec![], Some(path.as_path()), None)
+            .unwrap();
+        assert_eq!(ids, HashSet::from([1001, 2000, 2001, 3000]));
+        let ids =
+            super::combine_quota_ids(QuotaIdType::Group, vec![], None, Some(&(1..=2))).unwrap();
+        assert_eq!(ids, HashSet::from([1, 2]));
+        let ids = super::combine_quota_ids(QuotaIdType::Project, vec![], None, None).unwrap();
+        assert!(ids.is_empty());
+
+        // Invalid file content
+        std::fs::write(&path, "1001 abc").unwrap();
+        super::combine_quota_ids(QuotaIdType::User, vec![], Some(path.as_path()), None)
+            .unwrap_err();
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -3,32 +3,378 @@
 use crate::App;
 use crate::app::RuntimeApp;
 use crate::db::{self};
+use crate::license::{days_remaining, expiry_warning_level};
+use crate::metrics::QUOTA_UPDATE_DURATION;
 use crate::quota::{distribute_exceeded, fetch_and_update};
 use shared::bee_msg::target::RefreshTargetStates;
 use shared::run_state::RunStateHandle;
-use shared::types::NodeType;
-use tokio::time::{MissedTickBehavior, sleep};
+use shared::types::{AckId, NodeType};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior, sleep};
 
-/// Starts the timed tasks.
+/// The delay between two stale client removal batches if more clients are left to be removed
+const CLIENT_REMOVE_BATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// The interval in which the license certificate expiry date is checked
+const LICENSE_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// The interval in which the watchdog checks the timed tasks
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The number of its intervals a timed task may miss before the watchdog reports it as stuck
+const WATCHDOG_INTERVAL_FACTOR: u32 = 3;
+/// Additional time a timed task may take before the watchdog reports it as stuck. Covers the
+/// actual work done, which can take a while (e.g. fetching quota information).
+const WATCHDOG_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Starts the timed tasks and the watchdog monitoring them.
 pub(crate) fn start_tasks(app: RuntimeApp, run_state: RunStateHandle) {
     // TODO send out timer based RefreshTargetStates notification if a reachability
     // state changed ?
 
-    tokio::spawn(delete_stale_clients(app.clone(), run_state.clone()));
-    tokio::spawn(switchover(app.clone(), run_state.clone()));
+    let mut watchdog = Watchdog::new(app.info.user_config.restart_stuck_timer_tasks);
+
+    watchdog.watch(
+        "check_license_expiry",
+        spawner(&app, &run_state, check_license_expiry),
+    );
+
+    // All other tasks write to the database
+    if app.info.user_config.read_only {
+        log::info!("Read-only mode: Stale client removal, switchover and quota updates disabled");
+    } else {
+        watchdog.watch(
+            "delete_stale_clients",
+            spawner(&app, &run_state, delete_stale_clients),
+        );
+        watchdog.watch("switchover", spawner(&app, &run_state, switchover));
+
+        if !app.info.user_config.db_trim_interval.is_zero() {
+            watchdog.watch("trim_db", spawner(&app, &run_state, trim_db));
+        }
+
+        if app.info.user_config.quota_enable {
+            watchdog.watch("update_quota", spawner(&app, &run_state, update_quota));
+        }
+    }
+
+    let healthy = Arc::new(AtomicBool::new(true));
+
+    // Tell systemd that the process is alive as long as the timed tasks are healthy, if its
+    // watchdog is enabled for the service
+    if let Some(interval) = systemd_watchdog_interval(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    ) {
+        log::info!(
+            "Systemd watchdog enabled, notifying every {}ms",
+            interval.as_millis()
+        );
+
+        tokio::spawn(run_systemd_watchdog(
+            interval,
+            healthy.clone(),
+            || {
+                let _ = sd_notify::notify(&[sd_notify::NotifyState::Watchdog]);
+            },
+            run_state.clone(),
+        ));
+    }
+
+    tokio::spawn(run_watchdog(watchdog, healthy, run_state));
+}
+
+/// Builds a function spawning the given timed task, meant to be passed to [Watchdog::watch]
+fn spawner<F: Future<Output = ()> + Send + 'static>(
+    app: &RuntimeApp,
+    run_state: &RunStateHandle,
+    task: impl Fn(RuntimeApp, RunStateHandle, Heartbeat) -> F + Send + 'static,
+) -> impl Fn(Heartbeat) -> JoinHandle<()> + Send + 'static {
+    let (app, run_state) = (app.clone(), run_state.clone());
+    move |heartbeat| tokio::spawn(task(app.clone(), run_state.clone(), heartbeat))
+}
+
+/// Liveness signal of a timed task, checked by the [Watchdog]
+#[derive(Debug, Clone)]
+struct Heartbeat {
+    /// The time until which the task is expected to have signaled again
+    deadline: Arc<Mutex<Instant>>,
+    /// Whether the task has signaled at least once
+    beaten: Arc<AtomicBool>,
+}
+
+impl Heartbeat {
+    fn new(now: Instant) -> Self {
+        Self {
+            deadline: Arc::new(Mutex::new(now + WATCHDOG_GRACE)),
+            beaten: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Signals that the task is alive and expects to run again after `interval`
+    fn beat(&self, interval: Duration) {
+        *self.deadline.lock().unwrap() =
+            Instant::now() + interval * WATCHDOG_INTERVAL_FACTOR + WATCHDOG_GRACE;
+        self.beaten.store(true, Ordering::Relaxed);
+    }
+
+    fn deadline(&self) -> Instant {
+        *self.deadline.lock().unwrap()
+    }
+
+    fn has_beaten(&self) -> bool {
+        self.beaten.load(Ordering::Relaxed)
+    }
+}
+
+/// A timed task monitored by the [Watchdog]
+struct WatchedTask {
+    name: &'static str,
+    spawn: Box<dyn Fn(Heartbeat) -> JoinHandle<()> + Send>,
+    handle: JoinHandle<()>,
+    heartbeat: Heartbeat,
+    /// Whether the current problem has already been logged
+    reported: bool,
+}
+
+/// Monitors the timed tasks, reporting the ones that exited or stopped running and optionally
+/// restarting them.
+struct Watchdog {
+    tasks: Vec<WatchedTask>,
+    restart: bool,
+    /// Whether the systemd watchdog should be notified according to the last check
+    notify_systemd: bool,
+}
+
+impl Watchdog {
+    fn new(restart: bool) -> Self {
+        Self {
+            tasks: vec![],
+            restart,
+            notify_systemd: true,
+        }
+    }
+
+    /// Starts a timed task using `spawn` and adds it to the monitored ones. The task is expected
+    /// to call [Heartbeat::beat] on the passed handle every time it runs, before doing its work.
+    fn watch(
+        &mut self,
+        name: &'static str,
+        spawn: impl Fn(Heartbeat) -> JoinHandle<()> + Send + 'static,
+    ) {
+        let heartbeat = Heartbeat::new(Instant::now());
+        let handle = spawn(heartbeat.clone());
+
+        self.tasks.push(WatchedTask {
+            name,
+            spawn: Box::new(spawn),
+            handle,
+            heartbeat,
+            reported: false,
+        });
+    }
+
+    /// Checks the monitored tasks for having exited or missed their deadline at `now`. Logs an
+    /// error once per problem and restarts the affected task if enabled.
+    ///
+    /// Also determines whether the systemd watchdog should still be notified: Not if a task exited
+    /// or a task that has signaled before got stuck, unless tasks are restarted. A task that got
+    /// stuck before ever signaling (e.g. during a long first run) is only reported.
+    ///
+    /// # Return value
+    /// The names of the tasks that have been found unhealthy.
+    fn check(&mut self, now: Instant) -> Vec<&'static str> {
+        let mut unhealthy = vec![];
+        self.notify_systemd = true;
+
+        for task in &mut self.tasks {
+            let problem = if task.handle.is_finished() {
+                self.notify_systemd &= self.restart;
+                "exited unexpectedly".to_string()
+            } else if let Some(overdue) = now.checked_duration_since(task.heartbeat.deadline()) {
+                self.notify_systemd &= self.restart || !task.heartbeat.has_beaten();
+                format!("is stuck, it is overdue by {}s", overdue.as_secs())
+            } else {
+                task.reported = false;
+                continue;
+            };
+
+            unhealthy.push(task.name);
+
+            if self.restart {
+                // A task failing again right after being restarted is only logged once
+                if task.reported {
+                    log::debug!("Timed task {} {problem}, restarting it", task.name);
+                } else {
+                    log::error!("Timed task {} {problem}, restarting it", task.name);
+                    task.reported = true;
+                }
+
+                task.handle.abort();
+                task.heartbeat = Heartbeat::new(now);
+                task.handle = (task.spawn)(task.heartbeat.clone());
+            } else if !task.reported {
+                log::error!("Timed task {} {problem}", task.name);
+                task.reported = true;
+            }
+        }
+
+        unhealthy
+    }
+}
+
+/// Periodically checks the timed tasks monitored by `watchdog` until shutdown.
+///
+/// `healthy` is cleared while the systemd watchdog shall not be notified, see [Watchdog::check].
+async fn run_watchdog(
+    mut watchdog: Watchdog,
+    healthy: Arc<AtomicBool>,
+    mut run_state: RunStateHandle,
+) {
+    loop {
+        tokio::select! {
+            _ = sleep(WATCHDOG_CHECK_INTERVAL) => {}
+            _ = run_state.wait_for_pre_shutdown() => { break; }
+        }
+
+        // The timed tasks exit on pre shutdown, which must not be reported
+        if run_state.pre_shutdown() {
+            break;
+        }
 
-    if app.info.user_config.quota_enable {
-        tokio::spawn(update_quota(app, run_state));
+        watchdog.check(Instant::now());
+        healthy.store(watchdog.notify_systemd, Ordering::Relaxed);
     }
+
+    log::debug!("Timed task watchdog exited");
+}
+
+/// Determines the interval for sending systemd watchdog notifications from the values of the
+/// `WATCHDOG_USEC` and `WATCHDOG_PID` environment variables.
+///
+/// Returns `None` if the watchdog is not enabled or meant for another process. As recommended by
+/// systemd, the interval is half the watchdog timeout.
+fn systemd_watchdog_interval(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<Duration> {
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok() != Some(own_pid)
+    {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Calls `notify` every `interval` while `healthy` is set, until shutdown.
+///
+/// Since this runs on the same runtime as everything else, the notifications also stop if the
+/// runtime is wedged, letting systemd restart the process.
+async fn run_systemd_watchdog(
+    interval: Duration,
+    healthy: Arc<AtomicBool>,
+    notify: impl Fn(),
+    mut run_state: RunStateHandle,
+) {
+    let mut timer = tokio::time::interval(interval);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut warned = false;
+
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {}
+            _ = run_state.wait_for_shutdown() => { break; }
+        }
+
+        if healthy.load(Ordering::Relaxed) {
+            notify();
+            warned = false;
+        } else if !warned {
+            log::error!("Timed tasks are unhealthy, stopped notifying the systemd watchdog");
+            warned = true;
+        }
+    }
+
+    log::debug!("Systemd watchdog notifier exited");
+}
+
+/// Periodically removes stale clients and orphaned runtime data from the database.
+///
+/// If a run hits the batch limit, the next one happens after `CLIENT_REMOVE_BATCH_INTERVAL`
+/// instead of the full interval.
+async fn trim_db(app: RuntimeApp, mut run_state: RunStateHandle, heartbeat: Heartbeat) {
+    let interval = app.info.user_config.db_trim_interval;
+    let batch = app.info.user_config.db_trim_batch;
+    let mut backlog = false;
+
+    loop {
+        let wait = if backlog {
+            CLIENT_REMOVE_BATCH_INTERVAL
+        } else {
+            interval
+        };
+
+        heartbeat.beat(wait);
+
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = run_state.wait_for_pre_shutdown() => { break; }
+        }
+
+        log::debug!("Running database trim");
+
+        let client_timeout = app.dynamic_info().client_auto_remove_timeout;
+        match app
+            .db
+            .write_tx(move |tx| db::trim::trim(tx, client_timeout, batch))
+            .await
+        {
+            Ok(trimmed) => {
+                if trimmed.total() > 0 {
+                    log::info!("Trimmed stale and orphaned database rows: {trimmed:?}");
+                }
+                backlog = trimmed.limit_reached(batch);
+            }
+            Err(err) => {
+                log::error!("Trimming the database failed: {err:#}");
+                backlog = false;
+            }
+        }
+    }
+
+    log::debug!("Timed task trim_db exited");
 }
 
 /// Deletes client nodes from the database which haven't responded for the configured time.
-async fn delete_stale_clients(app: RuntimeApp, mut run_state: RunStateHandle) {
-    let timeout = app.info.user_config.client_auto_remove_timeout;
+///
+/// At most `client_auto_remove_batch` clients are deleted per run. If the limit is hit, the next
+/// run happens after `CLIENT_REMOVE_BATCH_INTERVAL` instead of the full timeout.
+async fn delete_stale_clients(
+    app: RuntimeApp,
+    mut run_state: RunStateHandle,
+    heartbeat: Heartbeat,
+) {
+    let batch = app.info.user_config.client_auto_remove_batch;
+    let mut backlog = false;
 
     loop {
+        let timeout = app.dynamic_info().client_auto_remove_timeout;
+        let wait = if backlog {
+            CLIENT_REMOVE_BATCH_INTERVAL
+        } else {
+            timeout
+        };
+
+        heartbeat.beat(wait);
+
         tokio::select! {
-            _ = sleep(timeout) => {}
+            _ = sleep(wait) => {}
             _ = run_state.wait_for_pre_shutdown() => { break; }
         }
 
@@ -36,15 +382,19 @@ async fn delete_stale_clients(app: RuntimeApp, mut run_state: RunStateHandle) {
 
         match app
             .db
-            .write_tx(move |tx| db::node::delete_stale_clients(tx, timeout))
+            .write_tx(move |tx| db::node::delete_stale_clients(tx, timeout, batch))
             .await
         {
             Ok(affected) => {
                 if affected > 0 {
                     log::info!("Deleted {affected} stale clients");
                 }
+                backlog = affected >= batch;
+            }
+            Err(err) => {
+                log::error!("Deleting stale clients failed: {err:#}");
+                backlog = false;
             }
-            Err(err) => log::error!("Deleting stale clients failed: {err:#}"),
         }
     }
 
@@ -52,19 +402,25 @@ async fn delete_stale_clients(app: RuntimeApp, mut run_state: RunStateHandle) {
 }
 
 /// Fetches quota information for all storage targets, calculates exceeded IDs and distributes them.
-async fn update_quota(app: RuntimeApp, mut run_state: RunStateHandle) {
+async fn update_quota(app: RuntimeApp, mut run_state: RunStateHandle, heartbeat: Heartbeat) {
     loop {
+        // Signal before doing the work, which can take a while
+        let interval = app.dynamic_info().quota_update_interval;
+        heartbeat.beat(interval);
+
         log::debug!("Running quota update");
 
+        let start = Instant::now();
         if let Err(e) = fetch_and_update(&app).await {
             log::error!("Updating quota failed: {e:#}");
         }
         if let Err(e) = distribute_exceeded(&app).await {
             log::error!("Distributing exceeded quota failed: {e:#}");
         }
+        QUOTA_UPDATE_DURATION.observe_duration(&[], start.elapsed());
 
         tokio::select! {
-            _ = sleep(app.info.user_config.quota_update_interval) => {}
+            _ = sleep(interval) => {}
             _ = run_state.wait_for_pre_shutdown() => { break; }
         }
     }
@@ -72,8 +428,46 @@ async fn update_quota(app: RuntimeApp, mut run_state: RunStateHandle) {
     log::debug!("Timed task update_quota exited");
 }
 
+/// Logs escalating warnings when the license certificate approaches its expiry date.
+///
+/// Does nothing if no license library or certificate is loaded.
+async fn check_license_expiry(
+    app: RuntimeApp,
+    mut run_state: RunStateHandle,
+    heartbeat: Heartbeat,
+) {
+    loop {
+        heartbeat.beat(LICENSE_EXPIRY_CHECK_INTERVAL);
+
+        log::debug!("Running license expiry check");
+
+        match app.get_license_expiry() {
+            Ok(Some(expiry)) => {
+                let now = SystemTime::now();
+                if let Some(level) = expiry_warning_level(expiry, now) {
+                    let days = days_remaining(expiry, now);
+                    if days < 0 {
+                        log::log!(level, "License certificate expired {} days ago", -days);
+                    } else {
+                        log::log!(level, "License certificate expires in {days} days");
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => log::debug!("Checking license expiry failed: {err:#}"),
+        }
+
+        tokio::select! {
+            _ = sleep(LICENSE_EXPIRY_CHECK_INTERVAL) => {}
+            _ = run_state.wait_for_pre_shutdown() => { break; }
+        }
+    }
+
+    log::debug!("Timed task check_license_expiry exited");
+}
+
 /// Finds buddy groups with switchover condition, swaps them and notifies nodes.
-async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle) {
+async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle, heartbeat: Heartbeat) {
     // On the other nodes / old management, the interval in which the switchover checks are done
     // is determined by "1/6 sysTargetOfflineTimeoutSecs".
     // This is also the interval the target states are being pushed to management. To avoid an
@@ -81,14 +475,13 @@ async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle) {
     // up-and-running primary doesn't because of their timing, this value should be the same as on
     // the nodes. If we delay the initial check by that time, then a running primary has enough time
     // to report in and update the last contact time before the check happens.
-    let interval = app.info.user_config.node_offline_timeout / 6;
-    let mut timer = tokio::time::interval(interval);
+    let mut interval = app.dynamic_info().node_offline_timeout / 6;
+    let mut timer = tokio::time::interval_at(Instant::now() + interval, interval);
     timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
-    // First call of tick completes immediately
-    timer.tick().await;
-
     loop {
+        heartbeat.beat(interval);
+
         tokio::select! {
             _ = timer.tick() => {}
             _ = run_state.wait_for_pre_shutdown() => { break; }
@@ -96,11 +489,31 @@ async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle) {
 
         log::debug!("Running switchover check");
 
-        let timeout = app.info.user_config.node_offline_timeout;
+        let timeout = app.dynamic_info().node_offline_timeout;
+        let grace = app.dynamic_info().node_offline_grace;
+
+        // The offline timeout might have been changed by a config reload, so the interval needs
+        // to be adjusted
+        if timeout / 6 != interval {
+            interval = timeout / 6;
+            timer = tokio::time::interval_at(Instant::now() + interval, interval);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        }
+
+        let divisor = app.info.user_config.switchover_secondary_divisor;
+        let on_needs_resync = app.info.user_config.switchover_on_primary_needs_resync;
 
         match app
             .db
-            .write_tx(move |tx| db::buddy_group::check_and_swap_buddies(tx, timeout))
+            .write_tx(move |tx| {
+                db::buddy_group::check_and_swap_buddies(
+                    tx,
+                    timeout,
+                    grace,
+                    divisor,
+                    on_needs_resync,
+                )
+            })
             .await
         {
             Ok(swapped) => {
@@ -111,7 +524,9 @@ async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle) {
 
                     app.send_notifications(
                         &[NodeType::Meta, NodeType::Storage, NodeType::Client],
-                        &RefreshTargetStates { ack_id: "".into() },
+                        &RefreshTargetStates {
+                            ack_id: AckId::empty(),
+                        },
                     )
                     .await;
                 }
@@ -122,3 +537,208 @@ async fn switchover(app: RuntimeApp, mut run_state: RunStateHandle) {
 
     log::debug!("Timed task check_for_switchover exited");
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Builds a task that runs once and then hangs forever, counting how often it was spawned
+    fn stalled_task(spawned: Arc<AtomicUsize>) -> impl Fn(Heartbeat) -> JoinHandle<()> + Send {
+        move |heartbeat| {
+            spawned.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                heartbeat.beat(Duration::from_secs(1));
+                std::future::pending::<()>().await;
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn watchdog() {
+        let mut watchdog = Watchdog::new(false);
+
+        watchdog.watch("healthy", |heartbeat| {
+            tokio::spawn(async move {
+                loop {
+                    heartbeat.beat(Duration::from_secs(3600));
+                    sleep(Duration::from_millis(10)).await;
+                }
+            })
+        });
+        watchdog.watch("stalled", stalled_task(Arc::default()));
+        watchdog.watch("panicked", |_| {
+            tokio::spawn(async { panic!("timed task failure") })
+        });
+
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(watchdog.check(Instant::now()), ["panicked"]);
+
+        // The stalled task missed its deadline, the healthy one is still within its own
+        let later = Instant::now()
+            + Duration::from_secs(1) * WATCHDOG_INTERVAL_FACTOR
+            + WATCHDOG_GRACE
+            + Duration::from_secs(1);
+        assert_eq!(watchdog.check(later), ["stalled", "panicked"]);
+    }
+
+    #[tokio::test]
+    async fn watchdog_notify_systemd() {
+        let later = || {
+            Instant::now()
+                + Duration::from_secs(1) * WATCHDOG_INTERVAL_FACTOR
+                + WATCHDOG_GRACE
+                + Duration::from_secs(1)
+        };
+
+        // A task stuck in its first run before signaling is reported, but doesn't stop the
+        // systemd notifications
+        let mut watchdog = Watchdog::new(false);
+        watchdog.watch("first_run", |_| tokio::spawn(std::future::pending::<()>()));
+        assert_eq!(watchdog.check(later()), ["first_run"]);
+        assert!(watchdog.notify_systemd);
+
+        // A task that has signaled before and got stuck does
+        watchdog.watch("stalled", stalled_task(Arc::default()));
+        wait_for_beat(&watchdog.tasks[1].heartbeat).await;
+        assert_eq!(watchdog.check(later()), ["first_run", "stalled"]);
+        assert!(!watchdog.notify_systemd);
+
+        // So does an exited task
+        let mut watchdog = Watchdog::new(false);
+        watchdog.watch("exited", |_| tokio::spawn(async {}));
+        while !watchdog.tasks[0].handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(watchdog.check(Instant::now()), ["exited"]);
+        assert!(!watchdog.notify_systemd);
+    }
+
+    /// Waits until the task owning `heartbeat` has signaled for the first time
+    async fn wait_for_beat(heartbeat: &Heartbeat) {
+        while !heartbeat.has_beaten() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn watchdog_restart() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+
+        let mut watchdog = Watchdog::new(true);
+        watchdog.watch("stalled", stalled_task(spawned.clone()));
+
+        sleep(Duration::from_millis(10)).await;
+        assert!(watchdog.check(Instant::now()).is_empty());
+
+        let later = Instant::now()
+            + Duration::from_secs(1) * WATCHDOG_INTERVAL_FACTOR
+            + WATCHDOG_GRACE
+            + Duration::from_secs(1);
+        assert_eq!(watchdog.check(later), ["stalled"]);
+        assert_eq!(spawned.load(Ordering::SeqCst), 2);
+
+        // The restarted task runs and signals again
+        sleep(Duration::from_millis(10)).await;
+        assert!(watchdog.check(Instant::now()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn watchdog_restart_reports_once() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+
+        let mut watchdog = Watchdog::new(true);
+        watchdog.watch("panicked", {
+            let spawned = spawned.clone();
+            move |_| {
+                spawned.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async { panic!("timed task failure") })
+            }
+        });
+
+        for i in 1..=3 {
+            while !watchdog.tasks[0].handle.is_finished() {
+                tokio::task::yield_now().await;
+            }
+
+            // Restarted every time, but only reported on the first failure
+            assert_eq!(watchdog.check(Instant::now()), ["panicked"]);
+            assert!(watchdog.tasks[0].reported);
+            assert_eq!(spawned.load(Ordering::SeqCst), i + 1);
+        }
+    }
+
+    /// Lets the spawned tasks handle the timers that fired after advancing the paused clock
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn systemd_watchdog() {
+        let pid = std::process::id();
+
+        assert_eq!(systemd_watchdog_interval(None, None, pid), None);
+        assert_eq!(systemd_watchdog_interval(Some("0"), None, pid), None);
+        assert_eq!(systemd_watchdog_interval(Some("abc"), None, pid), None);
+        assert_eq!(
+            systemd_watchdog_interval(Some("30000000"), None, pid),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            systemd_watchdog_interval(Some("30000000"), Some(&pid.to_string()), pid),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            systemd_watchdog_interval(Some("30000000"), Some(&(pid + 1).to_string()), pid),
+            None
+        );
+
+        // A fake watchdog timeout of 100ms results in notifications every 50ms
+        let interval = systemd_watchdog_interval(Some("100000"), None, pid).unwrap();
+        assert_eq!(interval, Duration::from_millis(50));
+
+        let (run_state, run_state_control) = shared::run_state::new();
+        let healthy = Arc::new(AtomicBool::new(true));
+        let notified = Arc::new(AtomicUsize::new(0));
+
+        let task = tokio::spawn(run_systemd_watchdog(
+            interval,
+            healthy.clone(),
+            {
+                let notified = notified.clone();
+                move || {
+                    notified.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+            run_state,
+        ));
+
+        // The first notification is sent immediately, then one every interval
+        settle().await;
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+
+        for count in 2..=4 {
+            tokio::time::advance(interval / 2).await;
+            settle().await;
+            assert_eq!(notified.load(Ordering::SeqCst), count - 1);
+
+            tokio::time::advance(interval / 2).await;
+            settle().await;
+            assert_eq!(notified.load(Ordering::SeqCst), count);
+        }
+
+        // No notifications while unhealthy
+        healthy.store(false, Ordering::Relaxed);
+        for _ in 0..3 {
+            tokio::time::advance(interval).await;
+            settle().await;
+        }
+        assert_eq!(notified.load(Ordering::SeqCst), 4);
+
+        run_state_control.shutdown().await;
+        task.await.unwrap();
+    }
+}
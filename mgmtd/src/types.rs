@@ -19,6 +19,16 @@ pub(crate) trait SqliteEnumExt {
     {
         Self::from_sql_variant(row.get_ref(idx)?.as_i64()?)
     }
+
+    fn from_row_opt(row: &Row, idx: usize) -> rusqlite::Result<Option<Self>>
+    where
+        Self: Sized,
+    {
+        row.get_ref(idx)?
+            .as_i64_or_null()?
+            .map(Self::from_sql_variant)
+            .transpose()
+    }
 }
 
 /// Implements SqliteStr for an enum
@@ -78,9 +88,16 @@ impl_enum_sqlite! {TargetConsistencyState,
     TargetConsistencyState::Bad => 3,
 }
 
+impl_enum_sqlite! {CapacityPool,
+    CapacityPool::Normal => 1,
+    CapacityPool::Low => 2,
+    CapacityPool::Emergency => 3,
+}
+
 impl_enum_sqlite! {QuotaIdType,
     QuotaIdType::User => 1,
     QuotaIdType::Group => 2,
+    QuotaIdType::Project => 3,
 }
 
 impl_enum_sqlite! {QuotaType,
@@ -88,6 +88,85 @@ impl ResolveEntityId for Alias {
     }
 }
 
+/// Resolves a batch of entity ids using a single query.
+///
+/// The returned list has the same order as the given ids. Fails if any of the ids can't be
+/// resolved.
+pub(crate) fn resolve_many(
+    tx: &Transaction,
+    ids: &[EntityId],
+    entity_type: EntityType,
+) -> Result<Vec<EntityIdSet>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut uids = vec![];
+    let mut aliases = vec![];
+    let mut legacy_ids = vec![];
+    for id in ids {
+        match id {
+            EntityId::Uid(uid) => uids.push(*uid),
+            EntityId::Alias(alias) => aliases.push(alias.to_string()),
+            // Legacy ids consist of two columns, so they are combined into one value for matching
+            EntityId::LegacyID(legacy_id) => {
+                legacy_ids.push((legacy_id.node_type.sql_variant() << 32) + legacy_id.num_id as i64)
+            }
+        }
+    }
+
+    let sql = resolve_sql_from(entity_type);
+    let sql = format!(
+        "{sql} WHERE uid IN rarray(?1) OR alias IN rarray(?2)
+        OR (node_type << 32) + id IN rarray(?3)"
+    );
+
+    let mut stmt = tx.prepare_cached(&sql)?;
+    let resolved = stmt
+        .query_map(
+            params![
+                sqlite::rarray_param(uids),
+                sqlite::rarray_param(aliases),
+                sqlite::rarray_param(legacy_ids)
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, Uid>(0)?,
+                    row.get::<_, String>(1)?,
+                    NodeType::from_row(row, 2)?,
+                    row.get::<_, u32>(3)?,
+                ))
+            },
+        )?
+        .map(|res| {
+            let res = res?;
+            Ok(EntityIdSet {
+                uid: res.0,
+                alias: res.1.try_into()?,
+                legacy_id: LegacyId {
+                    node_type: res.2,
+                    num_id: res.3,
+                },
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    ids.iter()
+        .map(|id| {
+            let found = resolved.iter().find(|e| match id {
+                EntityId::Uid(uid) => e.uid == *uid,
+                EntityId::Alias(alias) => e.alias == *alias,
+                EntityId::LegacyID(legacy_id) => e.legacy_id == *legacy_id,
+            });
+
+            match found {
+                Some(e) => Ok(e.clone()),
+                None => bail!("{} {} does not exist", entity_type, id),
+            }
+        })
+        .collect()
+}
+
 fn resolve_sql_from(entity_type: EntityType) -> &'static str {
     match entity_type {
         EntityType::Node => {
@@ -130,3 +209,67 @@ fn resolve_query(tx: &Transaction, sql: &str, params: impl Params) -> Result<Opt
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::with_test_data;
+
+    #[test]
+    fn resolve_many() {
+        with_test_data(|tx| {
+            let ids = [
+                EntityId::Alias("storage_target_3".try_into().unwrap()),
+                EntityId::Uid(202001),
+                EntityId::LegacyID(LegacyId {
+                    node_type: NodeType::Storage,
+                    num_id: 2,
+                }),
+                EntityId::Uid(202001),
+            ];
+
+            let resolved = super::resolve_many(tx, &ids, EntityType::Target).unwrap();
+            assert_eq!(
+                resolved.iter().map(|e| e.uid).collect::<Vec<_>>(),
+                [202003, 202001, 202002, 202001]
+            );
+            assert_eq!(
+                resolved
+                    .iter()
+                    .map(|e| e.legacy_id.num_id)
+                    .collect::<Vec<_>>(),
+                [3, 1, 2, 1]
+            );
+
+            // Meta target with the same numeric id must not match a storage target
+            let resolved = super::resolve_many(
+                tx,
+                &[EntityId::LegacyID(LegacyId {
+                    node_type: NodeType::Meta,
+                    num_id: 2,
+                })],
+                EntityType::Target,
+            )
+            .unwrap();
+            assert_eq!(resolved[0].uid, 201002);
+
+            // Wrong entity type
+            super::resolve_many(tx, &[EntityId::Uid(202001)], EntityType::Node).unwrap_err();
+
+            // One id missing
+            let err = super::resolve_many(
+                tx,
+                &[EntityId::Uid(202001), EntityId::Uid(999999)],
+                EntityType::Target,
+            )
+            .unwrap_err();
+            assert_eq!(err.to_string(), "target uid:999999 does not exist");
+
+            assert!(
+                super::resolve_many(tx, &[], EntityType::Target)
+                    .unwrap()
+                    .is_empty()
+            );
+        })
+    }
+}
@@ -2,7 +2,7 @@
 
 use crate::bee_serde::*;
 use crate::types::*;
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use bee_serde_derive::BeeSerde;
 use std::any::Any;
 use std::collections::{HashMap, HashSet};
@@ -88,6 +88,23 @@ impl Header {
     pub fn msg_id(&self) -> MsgId {
         self.msg_id
     }
+
+    /// Checks that the declared message length is at least the header length and does not exceed
+    /// `max_len`.
+    ///
+    /// Must be called before reading the message body into a buffer of length `max_len`, so a
+    /// peer declaring a bogus length is rejected instead of causing an out of bounds access.
+    pub fn check_msg_len(&self, max_len: usize) -> Result<()> {
+        if self.msg_len() < Self::LEN || self.msg_len() > max_len {
+            bail!(
+                "Invalid BeeMsg length {}: Must be between {} and {max_len} bytes",
+                self.msg_len(),
+                Self::LEN
+            );
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for Header {
@@ -1,4 +1,4 @@
-use super::target::TargetReachabilityState;
+use super::target::{COMPAT_FLAG_MGMTD_PRE_SHUTDOWN, TargetReachabilityState};
 use super::*;
 
 /// Fetch buddy groups of the given node type
@@ -63,6 +63,9 @@ pub struct GetStatesAndBuddyGroupsResp {
     pub groups: HashMap<BuddyGroupId, BuddyGroup>,
     #[bee_serde(as = Map<false, _, _>)]
     pub states: HashMap<TargetId, CombinedTargetState>,
+    /// The management is in pre-shutdown. Transferred in the header.
+    #[bee_serde(as = CompatFlag<COMPAT_FLAG_MGMTD_PRE_SHUTDOWN>)]
+    pub pre_shutdown: bool,
 }
 
 impl Msg for GetStatesAndBuddyGroupsResp {
@@ -128,7 +131,7 @@ pub struct SetMirrorBuddyGroup {
     /// This probably shall allow a group to be updated
     pub allow_update: u8,
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for SetMirrorBuddyGroup {
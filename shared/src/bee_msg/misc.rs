@@ -25,7 +25,7 @@ impl Msg for GenericResponse {
 #[derive(Clone, Debug, Default, PartialEq, Eq, BeeSerde)]
 pub struct Ack {
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for Ack {
@@ -75,7 +75,7 @@ impl Msg for SetChannelDirect {
 #[derive(Clone, Debug, Default, PartialEq, Eq, BeeSerde)]
 pub struct RefreshCapacityPools {
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for RefreshCapacityPools {
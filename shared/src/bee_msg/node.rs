@@ -142,7 +142,7 @@ pub struct Heartbeat {
     #[bee_serde(as = CStr<0>)]
     pub node_alias: Vec<u8>,
     #[bee_serde(as = CStr<4>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
     pub node_num_id: NodeId,
     // The root info is only relevant when sent from meta nodes. There it must contain the meta
     // root nodes ID, but on other nodes it is just irrelevant.
@@ -216,7 +216,7 @@ pub struct RemoveNode {
     pub node_type: NodeType,
     pub node_id: NodeId,
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for RemoveNode {
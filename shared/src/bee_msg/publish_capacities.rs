@@ -7,7 +7,7 @@ use super::*;
 #[derive(Clone, Debug, Default, PartialEq, Eq, BeeSerde)]
 pub struct PublishCapacities {
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for PublishCapacities {
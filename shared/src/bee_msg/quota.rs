@@ -55,6 +55,23 @@ impl GetQuotaInfo {
             pool_id,
         }
     }
+
+    pub fn with_project_ids(
+        mut project_ids: HashSet<QuotaId>,
+        target_id: TargetId,
+        pool_id: PoolId,
+    ) -> Self {
+        Self {
+            query_type: QuotaQueryType::List,
+            id_type: QuotaIdType::Project,
+            id_range_start: 0,
+            id_range_end: 0,
+            id_list: project_ids.drain().collect(),
+            transfer_method: GetQuotaInfoTransferMethod::AllTargetsOneRequestPerTarget,
+            target_id,
+            pool_id,
+        }
+    }
 }
 
 impl Msg for GetQuotaInfo {
@@ -113,7 +113,7 @@ impl Deserializable for BuddyGroupCapacityPools {
 #[derive(Clone, Debug, Default, PartialEq, Eq, BeeSerde)]
 pub struct RefreshStoragePools {
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for RefreshStoragePools {
@@ -43,6 +43,16 @@ impl Msg for GetTargetStates {
     const ID: MsgId = 1049;
 }
 
+/// Compat feature flag set on target state responses ([GetTargetStatesResp],
+/// [GetStatesAndBuddyGroupsResp](super::buddy_group::GetStatesAndBuddyGroupsResp)) while the
+/// management is shutting down.
+///
+/// Tells nodes understanding it to avoid starting new operations that depend on the management.
+/// Ignored by older nodes. The C++ nodes don't use bit `1` of the compat flags for the response to
+/// [GetTargetStates] (msg ID 1049) nor for the states and buddy groups response, so it can't be
+/// misinterpreted.
+pub const COMPAT_FLAG_MGMTD_PRE_SHUTDOWN: u8 = 1;
+
 /// Contains three Vecs containing the requested mapping
 ///
 /// The elements in the same position in the Vecs / sequences belong together.
@@ -54,6 +64,9 @@ pub struct GetTargetStatesResp {
     pub reachability_states: Vec<TargetReachabilityState>,
     #[bee_serde(as = Seq<true, _>)]
     pub consistency_states: Vec<TargetConsistencyState>,
+    /// The management is in pre-shutdown. Transferred in the header.
+    #[bee_serde(as = CompatFlag<COMPAT_FLAG_MGMTD_PRE_SHUTDOWN>)]
+    pub pre_shutdown: bool,
 }
 
 impl Msg for GetTargetStatesResp {
@@ -120,7 +133,7 @@ pub struct MapTargets {
     pub target_ids: HashMap<TargetId, PoolId>,
     pub node_id: NodeId,
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for MapTargets {
@@ -155,7 +168,7 @@ pub struct ChangeTargetConsistencyStates {
     #[bee_serde(as = Seq<true, _>)]
     pub new_states: Vec<TargetConsistencyState>,
     #[bee_serde(as = CStr<4>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for ChangeTargetConsistencyStates {
@@ -186,7 +199,7 @@ pub struct SetTargetConsistencyStates {
     #[bee_serde(as = Seq<true, _>)]
     pub states: Vec<TargetConsistencyState>,
     #[bee_serde(as = CStr<4>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
     pub set_online: u8,
 }
 
@@ -248,7 +261,7 @@ pub struct TargetInfo {
 #[derive(Clone, Debug, Default, PartialEq, Eq, BeeSerde)]
 pub struct RefreshTargetStates {
     #[bee_serde(as = CStr<0>)]
-    pub ack_id: Vec<u8>,
+    pub ack_id: AckId,
 }
 
 impl Msg for RefreshTargetStates {
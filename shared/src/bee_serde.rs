@@ -215,6 +215,30 @@ impl<'a> Serializer<'a> {
     pub fn bytes_written(&self) -> usize {
         self.write_pos
     }
+
+    /// Saves the current write position to be restored later by [Serializer::rewind()].
+    ///
+    /// Allows speculative or conditional serialization in hand written [Serializable] impls, e.g.
+    /// trying to serialize an optional section and backing out on error.
+    pub fn checkpoint(&self) -> usize {
+        self.write_pos
+    }
+
+    /// Discards everything written after `checkpoint`, which must have been obtained from
+    /// [Serializer::checkpoint()] on this serializer.
+    ///
+    /// The discarded bytes are zeroed and the write position is reset, so the next write continues
+    /// at `checkpoint`.
+    pub fn rewind(&mut self, checkpoint: usize) {
+        assert!(
+            checkpoint <= self.write_pos,
+            "Checkpoint {checkpoint} is beyond the write position {}",
+            self.write_pos
+        );
+
+        self.target_buf[checkpoint..self.write_pos].fill(0);
+        self.write_pos = checkpoint;
+    }
 }
 
 // DESERIALIZATION
@@ -233,6 +257,10 @@ pub struct Deserializer<'a> {
     /// BeeMsg header, used for conditional deserialization by certain messages. Can be
     /// accessed from the deserialization definition.
     pub header: Cow<'a, Header>,
+    /// The current nesting depth of sequences and maps
+    depth: usize,
+    /// The maximum allowed nesting depth of sequences and maps
+    max_depth: usize,
 }
 
 macro_rules! fn_deserialize_primitive {
@@ -244,13 +272,28 @@ macro_rules! fn_deserialize_primitive {
     };
 }
 
+macro_rules! fn_peek_primitive {
+    ($name:ident, $P:ident) => {
+        /// Reads the next value without advancing the deserializer
+        pub fn $name(&self) -> Result<$P> {
+            let b = self.peek(size_of::<$P>())?;
+            Ok($P::from_le_bytes(b.try_into()?))
+        }
+    };
+}
+
 impl<'a> Deserializer<'a> {
+    /// The default maximum nesting depth of sequences and maps. Real messages are far below this.
+    pub const DEFAULT_MAX_DEPTH: usize = 32;
+
     /// Creates a new Deserializer object with the given header used as metadata. Meant for BeeMsg -
     /// they sometimes do conditional deserialization based on these fields.
     pub fn with_header(buf: &'a [u8], header: &'a Header) -> Self {
         Self {
             source_buf: buf,
             header: Cow::Borrowed(header),
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
         }
     }
 
@@ -261,9 +304,18 @@ impl<'a> Deserializer<'a> {
         Self {
             source_buf: buf,
             header: Cow::Owned(Header::default()),
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
         }
     }
 
+    /// Sets the maximum nesting depth of sequences and maps. Deserializing data nested deeper
+    /// fails with an error instead of recursing further.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
     /// Checks that the whole buffer has been consumed - meant to be called after deserialization
     /// as a sanity check.
     pub fn finish(&self) -> Result<()> {
@@ -286,6 +338,9 @@ impl<'a> Deserializer<'a> {
     fn_deserialize_primitive!(u128);
     fn_deserialize_primitive!(i128);
 
+    fn_peek_primitive!(peek_u8, u8);
+    fn_peek_primitive!(peek_u32, u32);
+
     /// Deserialize a block of bytes as expected by BeeGFS
     pub fn bytes(&mut self, len: usize) -> Result<Vec<u8>> {
         Ok(self.take(len)?.to_owned())
@@ -333,20 +388,22 @@ impl<'a> Deserializer<'a> {
         include_total_size: bool,
         f: impl Fn(&mut Self) -> Result<T>,
     ) -> Result<Vec<T>> {
-        if include_total_size {
-            self.skip(size_of::<u32>())?;
-        }
+        self.nested(|des| {
+            if include_total_size {
+                des.skip(size_of::<u32>())?;
+            }
 
-        let len = self.u32()? as usize;
+            let len = des.u32()? as usize;
 
-        let mut v = Vec::new();
-        v.try_reserve_exact(len)?;
+            let mut v = Vec::new();
+            v.try_reserve_exact(len)?;
 
-        for _ in 0..len {
-            v.push(f(self)?);
-        }
+            for _ in 0..len {
+                v.push(f(des)?);
+            }
 
-        Ok(v)
+            Ok(v)
+        })
     }
 
     /// Deserialized a BeeGFS serialized map
@@ -368,20 +425,35 @@ impl<'a> Deserializer<'a> {
         // Unlike in serialization we do not forward deserialization to self.seq() to avoid double
         // allocation of Vec and Hashmap
 
-        if include_total_size {
-            self.skip(size_of::<u32>())?;
-        }
+        self.nested(|des| {
+            if include_total_size {
+                des.skip(size_of::<u32>())?;
+            }
 
-        let len = self.u32()? as usize;
+            let len = des.u32()? as usize;
 
-        let mut v = HashMap::new();
-        v.try_reserve(len)?;
+            let mut v = HashMap::new();
+            v.try_reserve(len)?;
+
+            for _ in 0..len {
+                v.insert(f_key(des)?, f_value(des)?);
+            }
+
+            Ok(v)
+        })
+    }
 
-        for _ in 0..len {
-            v.insert(f_key(self)?, f_value(self)?);
+    /// Runs `f` one nesting level deeper, failing if that exceeds the maximum depth.
+    fn nested<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        if self.depth >= self.max_depth {
+            bail!("Maximum nesting depth of {} exceeded", self.max_depth);
         }
 
-        Ok(v)
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+
+        res
     }
 
     /// Skips `n` bytes
@@ -393,16 +465,22 @@ impl<'a> Deserializer<'a> {
     }
 
     /// Takes the next n bytes from the source buffer, checking that there are enough left.
-    fn take(&mut self, n: usize) -> Result<&[u8]> {
-        match self.source_buf.split_at_checked(n) {
-            Some((taken, rest)) => {
-                self.source_buf = rest;
-                Ok(taken)
-            }
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let taken = self.peek(n)?;
+        self.source_buf = &self.source_buf[n..];
+        Ok(taken)
+    }
+
+    /// Returns the next n bytes from the source buffer without consuming them, checking that there
+    /// are enough left.
+    fn peek(&self, n: usize) -> Result<&'a [u8]> {
+        let source_buf: &'a [u8] = self.source_buf;
+        match source_buf.get(..n) {
+            Some(peeked) => Ok(peeked),
             None => {
                 bail!(
                     "Unexpected end of source buffer. Needed at least {n}, got {}",
-                    self.source_buf.len()
+                    source_buf.len()
                 );
             }
         }
@@ -532,6 +610,28 @@ where
     }
 }
 
+/// Serialize a bool as a flag in the BeeMsg headers `msg_compat_feature_flags`
+///
+/// Nothing is written to the message body. `FLAG` is the bit to set if the value is true. Since
+/// receivers ignore compat flags they don't know, this allows adding information to a message
+/// without breaking older peers.
+pub struct CompatFlag<const FLAG: u8>;
+
+impl<const FLAG: u8> BeeSerdeHelper<bool> for CompatFlag<FLAG> {
+    fn serialize_as(data: &bool, ser: &mut Serializer<'_>) -> Result<()> {
+        if *data {
+            ser.header.msg_compat_feature_flags |= FLAG;
+        } else {
+            ser.header.msg_compat_feature_flags &= !FLAG;
+        }
+        Ok(())
+    }
+
+    fn deserialize_as(des: &mut Deserializer<'_>) -> Result<bool> {
+        Ok(des.header.msg_compat_feature_flags & FLAG != 0)
+    }
+}
+
 // Implement BeeSerde for all integer primitives including conversion into bool
 macro_rules! impl_traits_for_primitive {
     ($t:ident) => {
@@ -590,6 +690,31 @@ mod test {
         des.finish().unwrap();
     }
 
+    #[test]
+    fn peek() {
+        let mut buf = vec![0; 1 + 4 + 2];
+
+        let mut ser = Serializer::new(&mut buf);
+        ser.u8(123).unwrap();
+        ser.u32(0x11223344).unwrap();
+        ser.u16(22222).unwrap();
+
+        let mut des = Deserializer::new(&buf);
+        assert_eq!(123, des.peek_u8().unwrap());
+        assert_eq!(123, des.peek_u8().unwrap());
+        assert_eq!(123, des.u8().unwrap());
+
+        assert_eq!(0x11223344, des.peek_u32().unwrap());
+        assert_eq!(0x11223344, des.u32().unwrap());
+
+        // Not enough bytes left for a u32, but peeking doesn't consume the remaining ones
+        assert!(des.peek_u32().is_err());
+        assert_eq!(22222, des.u16().unwrap());
+
+        assert!(des.peek_u8().is_err());
+        des.finish().unwrap();
+    }
+
     #[test]
     fn bytes() {
         let bytes = vec![0, 1, 2, 3, 4, 5];
@@ -606,6 +731,37 @@ mod test {
         des.finish().unwrap();
     }
 
+    #[test]
+    fn checkpoint_rewind() {
+        let mut buf = vec![0; 16];
+
+        let mut ser = Serializer::new(&mut buf);
+        ser.u32(0x11223344).unwrap();
+        let checkpoint = ser.checkpoint();
+        assert_eq!(checkpoint, 4);
+
+        // Speculatively write a sequence that doesn't fit and back out
+        let res = ser.seq([1u64, 2, 3], false, |ser, e| ser.u64(e));
+        assert!(res.is_err());
+        assert!(ser.bytes_written() > checkpoint);
+
+        ser.rewind(checkpoint);
+        assert_eq!(ser.bytes_written(), checkpoint);
+
+        // Writing continues at the checkpoint
+        ser.u16(22222).unwrap();
+        assert_eq!(ser.bytes_written(), 6);
+
+        // Rewinding to the current position is a no-op
+        ser.rewind(ser.checkpoint());
+        assert_eq!(ser.bytes_written(), 6);
+
+        let mut expected = vec![0; 16];
+        expected[0..4].copy_from_slice(&0x11223344u32.to_le_bytes());
+        expected[4..6].copy_from_slice(&22222u16.to_le_bytes());
+        assert_eq!(buf, expected);
+    }
+
     #[test]
     fn cstr() {
         let str: Vec<u8> = "text".into();
@@ -743,4 +899,46 @@ mod test {
         // Complete buffer consumed
         des.finish().unwrap();
     }
+
+    #[test]
+    fn max_depth() {
+        /// Deserializes a recursively nested sequence, returning its depth
+        fn recursive(des: &mut Deserializer) -> Result<usize> {
+            Ok(des
+                .seq(false, recursive)?
+                .into_iter()
+                .max()
+                .map_or(1, |e| e + 1))
+        }
+
+        // Each level declares a sequence containing one element, the innermost one is empty
+        let nested_buf = |depth: usize| -> Vec<u8> {
+            let mut buf = vec![0; depth * 4];
+            let mut ser = Serializer::new(&mut buf);
+            for _ in 0..depth - 1 {
+                ser.u32(1).unwrap();
+            }
+            ser.u32(0).unwrap();
+            buf
+        };
+
+        let buf = nested_buf(Deserializer::DEFAULT_MAX_DEPTH);
+        let mut des = Deserializer::new(&buf);
+        assert_eq!(
+            recursive(&mut des).unwrap(),
+            Deserializer::DEFAULT_MAX_DEPTH
+        );
+        des.finish().unwrap();
+
+        let buf = nested_buf(100_000);
+        let mut des = Deserializer::new(&buf);
+        let err = recursive(&mut des).unwrap_err();
+        assert!(err.to_string().contains("nesting depth"));
+
+        let buf = nested_buf(5);
+        let mut des = Deserializer::new(&buf).with_max_depth(4);
+        recursive(&mut des).unwrap_err();
+        let mut des = Deserializer::new(&buf).with_max_depth(5);
+        recursive(&mut des).unwrap();
+    }
 }
@@ -0,0 +1,186 @@
+//! Compact BeeSerde encoding for log and audit records.
+//!
+//! The recent log and audit log streams can optionally deliver their records as batches encoded
+//! this way instead of one protobuf message per record. This saves the per message overhead on
+//! large queries at the cost of not being self describing.
+//!
+//! A batch is a BeeSerde sequence (without total size) of records. Strings are encoded as
+//! unaligned c strings.
+
+use crate::bee_serde::{Deserializable, Deserializer, Serializable, Serializer};
+use anyhow::Result;
+use std::mem::size_of;
+
+/// A log record as delivered by the recent log stream
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogRecord {
+    pub time_secs: i64,
+    pub level: String,
+    pub module: String,
+    pub message: String,
+}
+
+/// An audit log record as delivered by the audit log stream
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub time_secs: i64,
+    pub operation: String,
+    pub entity: String,
+    pub peer: Option<String>,
+}
+
+/// A record that can be encoded in a batch
+pub trait Record: Serializable + Deserializable {
+    /// The number of bytes the encoded record takes
+    fn encoded_len(&self) -> usize;
+}
+
+/// The number of bytes a string takes when encoded as c string
+fn cstr_len(s: &str) -> usize {
+    size_of::<u32>() + s.len() + 1
+}
+
+fn deserialize_string(des: &mut Deserializer<'_>) -> Result<String> {
+    Ok(String::from_utf8(des.cstr(0)?)?)
+}
+
+impl Serializable for LogRecord {
+    fn serialize(&self, ser: &mut Serializer<'_>) -> Result<()> {
+        ser.i64(self.time_secs)?;
+        ser.cstr(self.level.as_bytes(), 0)?;
+        ser.cstr(self.module.as_bytes(), 0)?;
+        ser.cstr(self.message.as_bytes(), 0)
+    }
+}
+
+impl Deserializable for LogRecord {
+    fn deserialize(des: &mut Deserializer<'_>) -> Result<Self> {
+        Ok(Self {
+            time_secs: des.i64()?,
+            level: deserialize_string(des)?,
+            module: deserialize_string(des)?,
+            message: deserialize_string(des)?,
+        })
+    }
+}
+
+impl Record for LogRecord {
+    fn encoded_len(&self) -> usize {
+        size_of::<i64>() + cstr_len(&self.level) + cstr_len(&self.module) + cstr_len(&self.message)
+    }
+}
+
+impl Serializable for AuditRecord {
+    fn serialize(&self, ser: &mut Serializer<'_>) -> Result<()> {
+        ser.i64(self.time_secs)?;
+        ser.cstr(self.operation.as_bytes(), 0)?;
+        ser.cstr(self.entity.as_bytes(), 0)?;
+        // The peer is optional, so it is prefixed with a flag whether it is set
+        match &self.peer {
+            Some(peer) => {
+                ser.u8(1)?;
+                ser.cstr(peer.as_bytes(), 0)
+            }
+            None => ser.u8(0),
+        }
+    }
+}
+
+impl Deserializable for AuditRecord {
+    fn deserialize(des: &mut Deserializer<'_>) -> Result<Self> {
+        Ok(Self {
+            time_secs: des.i64()?,
+            operation: deserialize_string(des)?,
+            entity: deserialize_string(des)?,
+            peer: match des.u8()? {
+                0 => None,
+                _ => Some(deserialize_string(des)?),
+            },
+        })
+    }
+}
+
+impl Record for AuditRecord {
+    fn encoded_len(&self) -> usize {
+        size_of::<i64>()
+            + cstr_len(&self.operation)
+            + cstr_len(&self.entity)
+            + 1
+            + self.peer.as_deref().map_or(0, cstr_len)
+    }
+}
+
+/// Encodes a batch of records
+pub fn encode_batch<R: Record>(records: &[R]) -> Result<Vec<u8>> {
+    let len = size_of::<u32>() + records.iter().map(R::encoded_len).sum::<usize>();
+    let mut buf = vec![0; len];
+
+    let mut ser = Serializer::new(&mut buf);
+    ser.seq(records, false, |ser, r| r.serialize(ser))?;
+    let written = ser.bytes_written();
+
+    buf.truncate(written);
+    Ok(buf)
+}
+
+/// Decodes a batch of records encoded by [encode_batch]
+pub fn decode_batch<R: Record>(buf: &[u8]) -> Result<Vec<R>> {
+    let mut des = Deserializer::new(buf);
+    let records = des.seq(false, R::deserialize)?;
+    des.finish()?;
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let logs: Vec<_> = (0..100)
+            .map(|i| LogRecord {
+                time_secs: 1_700_000_000 + i,
+                level: "WARN".into(),
+                module: "mgmtd::test".into(),
+                message: format!("message {i} with ünïcödé"),
+            })
+            .collect();
+
+        let buf = encode_batch(&logs).unwrap();
+        assert_eq!(
+            buf.len(),
+            4 + logs.iter().map(|e| e.encoded_len()).sum::<usize>()
+        );
+        assert_eq!(decode_batch::<LogRecord>(&buf).unwrap(), logs);
+
+        let audits = vec![
+            AuditRecord {
+                time_secs: 1,
+                operation: "Set alias".into(),
+                entity: "node_1 -> node_2".into(),
+                peer: Some("127.0.0.1:12345".into()),
+            },
+            AuditRecord {
+                time_secs: 2,
+                operation: "Delete node".into(),
+                entity: "".into(),
+                peer: None,
+            },
+        ];
+
+        let buf = encode_batch(&audits).unwrap();
+        assert_eq!(decode_batch::<AuditRecord>(&buf).unwrap(), audits);
+
+        // Empty batch
+        let buf = encode_batch::<AuditRecord>(&[]).unwrap();
+        assert!(decode_batch::<AuditRecord>(&buf).unwrap().is_empty());
+
+        // Truncated and trailing data is rejected
+        let buf = encode_batch(&audits).unwrap();
+        decode_batch::<AuditRecord>(&buf[..buf.len() - 1]).unwrap_err();
+        let mut buf = buf;
+        buf.push(0);
+        decode_batch::<AuditRecord>(&buf).unwrap_err();
+    }
+}
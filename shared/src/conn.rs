@@ -2,16 +2,30 @@
 
 mod async_queue;
 pub mod incoming;
+pub mod msg_capture;
 pub mod msg_dispatch;
 pub mod outgoing;
+mod static_resolver;
 mod store;
 mod stream;
+#[cfg(test)]
+pub(crate) mod test;
+
+pub use static_resolver::StaticAddrResolver;
+pub use store::StoreStats;
 
 /// Fixed length of the stream / TCP message buffers.
 /// Must match the `WORKER_BUF(IN|OUT)_SIZE` value in `Worker.h` in the C++
 /// codebase.
 const TCP_BUF_LEN: usize = 4 * 1024 * 1024;
 
+/// Maximum length a BeeMsg received via a stream may declare in its header.
+///
+/// Messages are read into the fixed size stream buffers without any further allocation, so this
+/// is bound to TCP_BUF_LEN. As this matches the C++ worker buffers, no BeeGFS node sends longer
+/// messages. A larger declared length is rejected before reading the body.
+const MAX_MSG_LEN: usize = TCP_BUF_LEN;
+
 /// Fixed length of the datagram / UDP message buffers.
 /// Must match the `DGRAMMR_(RECV|SEND)BUF_SIZE` value in `DatagramListener.*` in the C/C++
 /// codebase. Must be smaller than TCP_BUF_LEN;
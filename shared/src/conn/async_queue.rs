@@ -26,6 +26,16 @@ impl<T> AsyncQueue<T> {
         self.notification.notify_one();
     }
 
+    /// The number of items currently in the queue
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Whether the queue is currently empty
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
     /// Try to pop an item from the queue
     ///
     /// Returns immediately with `None` if the queue is empty.
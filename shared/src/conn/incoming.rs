@@ -1,18 +1,40 @@
 //! Handle incoming TCP and UDP connections and BeeMsgs.
 
-use super::msg_dispatch::{DispatchRequest, SocketRequest, StreamRequest};
+use super::msg_capture::{Direction, MSG_CAPTURE};
+use super::msg_dispatch::{DispatchRequest, Request, SocketRequest, StreamRequest};
 use super::stream::Stream;
 use super::*;
 use crate::bee_msg::misc::AuthenticateChannel;
-use crate::bee_msg::{Header, Msg, deserialize_header};
-use crate::run_state::RunStateHandle;
-use anyhow::{Context, Result, bail};
+use crate::bee_msg::{Header, Msg, MsgId, deserialize_header};
+use crate::run_state::{DrainHandle, RunStateHandle};
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::future::{Future, poll_fn};
+use std::hash::{Hash, Hasher};
 use std::io::{self, ErrorKind};
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::{TcpListener, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpSocket, UdpSocket};
+use tokio::sync::Semaphore;
 
-/// Spawns a new task that listens for incoming TCP connections. The task accepts all connection
+/// Maximum number of datagrams remembered for detecting duplicates
+const DEDUP_MAX_ENTRIES: usize = 4096;
+/// Maximum length of a reply remembered for resending it to a duplicate datagram. Covers Acks and
+/// other small replies.
+const DEDUP_MAX_REPLY_LEN: usize = 1024;
+/// Minimum time between two warnings about rejected unauthenticated streams
+const UNAUTHENTICATED_WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps track of streams rejected for not being authenticated. Shared by all listeners.
+static UNAUTHENTICATED_STREAMS: Mutex<RejectedStreams> = Mutex::new(RejectedStreams::new());
+
+/// Spawns new tasks that listen for incoming TCP connections. The tasks accept all connection
 /// requests and spawns a new receiver task for each of them, handling receiving BeeMsges and
 /// forwarding them to the provided dispatcher. This is probably what you want to call if you want
 /// to receive and process BeeMsgs.
@@ -25,55 +47,104 @@ use tokio::net::{TcpListener, UdpSocket};
 /// set the flag while handling this message.
 ///
 /// The [`Shutdown`] handle is used to shutdown all running tasks gracefully (e.g. finishing running
-/// operations)
+/// operations). When the drain phase starts, no new connections are accepted and existing
+/// connections are closed after finishing the request currently being processed.
+///
+/// `backlog` sets the size of the kernels queue for connections not accepted yet (see
+/// [bind_tcp()]) and `accept_tasks` the number of tasks accepting connections from it
+/// concurrently. Both help with connection storms, e.g. when the whole cluster restarts.
 ///
 /// There is no connection limit on incoming connections.
 ///
 /// # Return behavior
-/// Returns immediately after the task has been started.
+/// Returns immediately after the tasks have been started.
 pub async fn listen_tcp(
     listen_addr: SocketAddr,
+    backlog: u32,
+    accept_tasks: usize,
     dispatch: impl DispatchRequest,
     stream_authentication_required: bool,
-    mut run_state: RunStateHandle,
+    run_state: RunStateHandle,
 ) -> Result<()> {
-    let listener = TcpListener::bind(listen_addr).await?;
+    if accept_tasks == 0 {
+        bail!("At least one TCP accept task is required");
+    }
+
+    let listener = Arc::new(bind_tcp(listen_addr, backlog)?);
     log::info!("Listening for BeeGFS connections on {listen_addr}");
 
-    tokio::spawn(async move {
-        // Listen-loop
-        loop {
-            tokio::select! {
-                res = listener.accept() => {
-                    let (stream, _) = match res {
-                        Ok(res) => res,
-                        Err(err) => {
-                            log::error!("Accepting TCP connection failed: {err:#}");
-                            continue;
-                        }
-                    };
-
-                    // BeeGFS streams follow a "request-response" schema: A request is made using one
-                    // stream and the following response comes back using the same stream. The stream
-                    // is blocked during that and not used for anything else. Therefore, we just handle
-                    // reading from each stream in a separate task that is also used for
-                    // (de-)serializing, processing the request and sending the response.
-                    tokio::spawn(stream_loop(
-                        stream.into(),
-                        dispatch.clone(),
-                        stream_authentication_required,
-                        run_state.clone(),
-                    ));
-                }
+    for _ in 0..accept_tasks {
+        tokio::spawn(accept_loop(
+            listener.clone(),
+            dispatch.clone(),
+            stream_authentication_required,
+            run_state.clone(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Binds a TCP listener to `addr` with a listen queue for `backlog` connections.
+///
+/// Connections exceeding the queue while the listener doesn't accept them quick enough might be
+/// dropped. The kernel caps the value to `net.core.somaxconn`. Must be called within a tokio
+/// runtime.
+pub fn bind_tcp(addr: SocketAddr, backlog: u32) -> Result<TcpListener> {
+    if backlog == 0 {
+        bail!("TCP listen backlog must be at least 1");
+    }
+
+    let sock = if addr.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+
+    // Allows rebinding the port immediately after a restart, like TcpListener::bind() does
+    sock.set_reuseaddr(true)?;
+    sock.bind(addr)
+        .with_context(|| format!("Binding TCP socket to {addr} failed"))?;
+
+    Ok(sock.listen(backlog)?)
+}
 
-                _ = run_state.wait_for_shutdown() =>{ break; }
+/// Contains the accept loop of a TCP listener
+async fn accept_loop(
+    listener: Arc<TcpListener>,
+    dispatch: impl DispatchRequest,
+    stream_authentication_required: bool,
+    mut run_state: RunStateHandle,
+) {
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                let (stream, _) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::error!("Accepting TCP connection failed: {err:#}");
+                        continue;
+                    }
+                };
+
+                // BeeGFS streams follow a "request-response" schema: A request is made using one
+                // stream and the following response comes back using the same stream. The stream
+                // is blocked during that and not used for anything else. Therefore, we just handle
+                // reading from each stream in a separate task that is also used for
+                // (de-)serializing, processing the request and sending the response.
+                tokio::spawn(stream_loop(
+                    stream.into(),
+                    dispatch.clone(),
+                    stream_authentication_required,
+                    run_state.clone_drain(),
+                ));
             }
-        }
 
-        log::debug!("TCP listener task has been shut down: {listener:?}")
-    });
+            _ = run_state.wait_for_drain() =>{ break; }
+        }
+    }
 
-    Ok(())
+    log::debug!("TCP accept task has been shut down: {listener:?}")
 }
 
 /// Contains the stream reading loop
@@ -81,7 +152,7 @@ async fn stream_loop(
     mut stream: Stream,
     dispatch: impl DispatchRequest,
     stream_authentication_required: bool,
-    mut run_state: RunStateHandle,
+    mut run_state: DrainHandle,
 ) {
     log::debug!("Accepted incoming stream from {:?}", stream.addr());
 
@@ -89,17 +160,19 @@ async fn stream_loop(
     let mut buf = vec![0; TCP_BUF_LEN];
 
     loop {
-        // Wait for available data or shutdown signal
+        // Wait for available data or drain signal. A request is always processed completely
+        // before checking again.
         tokio::select! {
+            biased;
+            _ = run_state.wait_for_drain() => {
+                return;
+            }
             res = stream.readable() => {
                 if let Err(err) = res {
                     log::debug!("Closed stream from {:?}: {err:#}", stream.addr());
                     return;
                 }
             }
-            _ = run_state.wait_for_shutdown() => {
-                return;
-            }
         }
 
         if let Err(err) = read_stream(
@@ -118,6 +191,12 @@ async fn stream_loop(
                 return;
             }
 
+            // Rejected unauthenticated streams have already been warned about (rate limited)
+            if err.is::<UnauthenticatedStream>() {
+                log::debug!("Closed stream from {:?}: {err:#}", stream.addr());
+                return;
+            }
+
             log::error!(
                 "Error while handling stream from {:?}: {err:#}",
                 stream.addr()
@@ -148,56 +227,219 @@ async fn read_stream(
         && !stream.authenticated
         && header.msg_id() != AuthenticateChannel::ID
     {
-        bail!(
-            "Stream is not authenticated and received message with id {}",
-            header.msg_id()
-        );
+        if let Some(suppressed) = UNAUTHENTICATED_STREAMS
+            .lock()
+            .unwrap()
+            .record(Instant::now())
+        {
+            log::warn!(
+                "Rejected unauthenticated stream from {:?} which sent message with id {} \
+({suppressed} more rejections suppressed since the last warning)",
+                stream.addr(),
+                header.msg_id()
+            );
+        }
+
+        return Err(UnauthenticatedStream {
+            msg_id: header.msg_id(),
+        }
+        .into());
     }
 
+    // Reject messages that don't fit into the buffer before reading the body
+    header.check_msg_len(MAX_MSG_LEN.min(buf.len()))?;
+
     // Read body
     stream
         .read_exact(&mut buf[Header::LEN..header.msg_len()])
         .await?;
 
+    MSG_CAPTURE.record(
+        Direction::Incoming,
+        stream.addr(),
+        &buf[0..header.msg_len()],
+    );
+
     // Forward to the dispatcher. The dispatcher is responsible for deserializing, dispatching to
     // msg handlers and sending a response using the [`StreamRequest`] handle.
-    dispatch
-        .dispatch_request(StreamRequest {
+    dispatch_catching_panics(
+        dispatch,
+        StreamRequest {
             stream,
             buf,
             header: &header,
-        })
-        .await
-        .context("Stream msg dispatch failed")?;
+        },
+        header.msg_id(),
+    )
+    .await
+    .context("Stream msg dispatch failed")?;
 
     Ok(())
 }
 
-/// Spawns a new task that receives datagrams from a UDP socket and forwards them to the
-/// dispatcher. This is probably what you want to call if you want to receive and process BeeMsgs
-/// via UDP.
+/// Binds `count` UDP sockets to `addr`.
+///
+/// If `count` is greater than 1, the sockets are bound using `SO_REUSEPORT`, so the kernel spreads
+/// incoming datagrams over them (by hashing the peers address). If the port in `addr` is 0, all
+/// sockets are bound to the port picked for the first one. Must be called within a tokio runtime.
+pub fn bind_udp(addr: SocketAddr, count: usize) -> Result<Vec<Arc<UdpSocket>>> {
+    if count == 0 {
+        bail!("At least one UDP socket is required");
+    }
+
+    if count == 1 {
+        let sock = std::net::UdpSocket::bind(addr)
+            .with_context(|| format!("Binding UDP socket to {addr} failed"))?;
+        sock.set_nonblocking(true)?;
+        return Ok(vec![Arc::new(UdpSocket::from_std(sock)?)]);
+    }
+
+    let mut addr = addr;
+    let mut socks = Vec::with_capacity(count);
+    for _ in 0..count {
+        let sock = bind_udp_reuseport(addr)
+            .with_context(|| format!("Binding UDP socket to {addr} using SO_REUSEPORT failed"))?;
+        addr = sock.local_addr()?;
+        socks.push(Arc::new(UdpSocket::from_std(sock)?));
+    }
+
+    Ok(socks)
+}
+
+/// Creates a non-blocking UDP socket with `SO_REUSEPORT` set and binds it to `addr`
+fn bind_udp_reuseport(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let domain = if addr.is_ipv6() {
+        libc::AF_INET6
+    } else {
+        libc::AF_INET
+    };
+
+    // SAFETY: The created socket is owned by an OwnedFd and thus closed on error. The address
+    // structs are local and passed together with their actual size.
+    unsafe {
+        let fd = libc::socket(
+            domain,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+
+        let enable: libc::c_int = 1;
+        let res = libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &enable as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        );
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let res = match addr {
+            SocketAddr::V4(addr) => {
+                let addr_in = libc::sockaddr_in {
+                    sin_family: libc::AF_INET as libc::sa_family_t,
+                    sin_port: libc::htons(addr.port()),
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                    },
+                    sin_zero: [0; 8],
+                };
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr_in as *const _ as *const libc::sockaddr,
+                    size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+            SocketAddr::V6(addr) => {
+                let addr_in6 = libc::sockaddr_in6 {
+                    sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                    sin6_port: libc::htons(addr.port()),
+                    sin6_flowinfo: addr.flowinfo(),
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: addr.ip().octets(),
+                    },
+                    sin6_scope_id: addr.scope_id(),
+                };
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr_in6 as *const _ as *const libc::sockaddr,
+                    size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(fd.into())
+    }
+}
+
+/// Spawns a task for each of the given UDP sockets that receives datagrams and forwards them to
+/// the dispatcher. This is probably what you want to call if you want to receive and process
+/// BeeMsgs via UDP.
 ///
 /// The `dispatch` argument expects an implementation of [`DispatchRequest`] and is called whenever
 /// a BeeMsg is received.
 ///
+/// `max_concurrent_handlers` limits the number of datagrams being handled at the same time over all
+/// sockets.
+/// Datagrams received while the limit is reached are dropped. This is fine since BeeGFS datagrams
+/// are either resent or idempotent.
+///
+/// If `dedup_window` is non-zero, a datagram that exactly matches one received from the same peer
+/// within that window is dropped without being dispatched. A zero window disables this.
+///
 /// The [`Shutdown`] handle is used to shutdown all running tasks gracefully (e.g. finishing running
 /// operations)
 ///
 /// # Return behavior
 /// Returns immediately after the task has been started.
 pub fn recv_udp(
-    sock: Arc<UdpSocket>,
+    socks: impl IntoIterator<Item = Arc<UdpSocket>>,
     dispatch: impl DispatchRequest,
-    mut run_state: RunStateHandle,
+    max_concurrent_handlers: usize,
+    dedup_window: Duration,
+    run_state: RunStateHandle,
 ) -> Result<()> {
-    log::info!("Receiving BeeGFS datagrams on {}", sock.local_addr()?);
+    let handler_permits = Arc::new(Semaphore::new(max_concurrent_handlers));
+    let recent =
+        (!dedup_window.is_zero()).then(|| Arc::new(Mutex::new(RecentDatagrams::new(dedup_window))));
 
+    for sock in socks {
+        log::info!("Receiving BeeGFS datagrams on {}", sock.local_addr()?);
+
+        recv_udp_loop(
+            sock,
+            dispatch.clone(),
+            handler_permits.clone(),
+            recent.clone(),
+            run_state.clone(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Spawns the receive loop for a single UDP socket
+fn recv_udp_loop(
+    sock: Arc<UdpSocket>,
+    dispatch: impl DispatchRequest,
+    handler_permits: Arc<Semaphore>,
+    recent: Option<Arc<Mutex<RecentDatagrams>>>,
+    mut run_state: RunStateHandle,
+) {
     tokio::spawn(async move {
         // Receive loop
         loop {
             tokio::select! {
                 // Do the actual work
-                res = recv_datagram(sock.clone(), dispatch.clone()) => {
+                res = recv_datagram(sock.clone(), dispatch.clone(), handler_permits.clone(), recent.clone()) => {
                     if let Err(err) = res {
                         log::error!("Error on receiving datagram using UDP socket {:?}: {err:#}", sock.local_addr());
                     }
@@ -209,46 +451,820 @@ pub fn recv_udp(
 
         log::debug!("UDP receiver task has been shut down: {sock:?}")
     });
-
-    Ok(())
 }
 
 /// Receives a datagram from the given socket into and forwards it to the dispatcher.
 ///
 /// The dispatcher is responsible for deserializing the message, dispatching it to the correct
 /// handler and sending back a message using the [`SocketRequest`] handle.
-async fn recv_datagram(sock: Arc<UdpSocket>, msg_handler: impl DispatchRequest) -> Result<()> {
+///
+/// If no permit is available from `handler_permits`, the datagram is dropped. If the datagram is a
+/// duplicate according to `recent`, the reply sent to the original one is sent again instead of
+/// handling it. This makes sure a sender retrying because of a lost Ack gets it eventually. A
+/// duplicate of a datagram that hasn't been replied to (yet) is dropped.
+async fn recv_datagram(
+    sock: Arc<UdpSocket>,
+    msg_handler: impl DispatchRequest,
+    handler_permits: Arc<Semaphore>,
+    recent: Option<Arc<Mutex<RecentDatagrams>>>,
+) -> Result<()> {
     // We use a new buffer for each incoming datagram. This is not ideal, but since each incoming
     // message spawns a new task (below) and we don't know how long the processing takes, we cannot
     // reuse Buffers like the TCP reader does.
     // A separate buffer pool could potentially be used to avoid allocating new buffers every time.
     let mut buf = vec![0; UDP_BUF_LEN];
 
-    let (_, peer_addr) = sock.recv_from(&mut buf).await?;
+    let (len, peer_addr) = sock.recv_from(&mut buf).await?;
+
+    let Ok(permit) = handler_permits.try_acquire_owned() else {
+        log::debug!(
+            "Dropping datagram from {peer_addr:?}: Too many datagrams are being handled concurrently"
+        );
+        return Ok(());
+    };
+
+    let dedup = match recent {
+        Some(recent) => {
+            let received = recent
+                .lock()
+                .unwrap()
+                .check(peer_addr, &buf[0..len], Instant::now());
+
+            match received {
+                Received::New(key) => Some((recent, key)),
+                Received::Duplicate(Some(reply)) => {
+                    log::trace!(
+                        "Resending reply to {peer_addr:?}: Duplicate of a recently received datagram"
+                    );
+                    sock.send_to(&reply, peer_addr).await?;
+                    return Ok(());
+                }
+                Received::Duplicate(None) => {
+                    log::trace!(
+                        "Dropping datagram from {peer_addr:?}: Duplicate of a recently received one"
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        None => None,
+    };
 
     // Request shall be handled in a separate task, so the next datagram can be processed
     // immediately
     tokio::spawn(async move {
+        let _permit = permit;
+
         if let Err(err) = async {
             let header = deserialize_header(&buf[0..Header::LEN])?;
+            header.check_msg_len(len)?;
+
+            MSG_CAPTURE.record(Direction::Incoming, peer_addr, &buf[0..header.msg_len()]);
 
             let req = SocketRequest {
                 sock,
                 peer_addr,
                 buf: &mut buf,
                 header: &header,
+                dedup: dedup.clone(),
             };
 
             // Forward to the dispatcher
-            msg_handler.dispatch_request(req).await?;
+            dispatch_catching_panics(&msg_handler, req, header.msg_id()).await?;
 
             Ok::<(), anyhow::Error>(())
         }
         .await
         {
+            // Handle a retry of the failed datagram again
+            if let Some((recent, key)) = dedup {
+                recent.lock().unwrap().forget(key);
+            }
+
             log::error!("Error while handling datagram from {peer_addr:?}: {err:#}");
         }
     });
 
     Ok(())
 }
+
+/// Forwards a request to the dispatcher, turning a panic in the handler into an error.
+///
+/// Without this, a panicking handler takes down the task serving the stream or datagram without
+/// any hint about which message and peer caused it. The returned error is logged by the caller
+/// like any other handler error, and the handler permit and buffer are released the regular way.
+async fn dispatch_catching_panics(
+    dispatch: &impl DispatchRequest,
+    req: impl Request,
+    msg_id: MsgId,
+) -> Result<()> {
+    let mut fut = pin!(dispatch.dispatch_request(req));
+
+    poll_fn(
+        |cx| match panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                let reason = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown reason");
+
+                Poll::Ready(Err(anyhow!(
+                    "Handler for msg with id {msg_id} panicked: {reason}"
+                )))
+            }
+        },
+    )
+    .await
+}
+
+/// Returned by [read_stream()] when an unauthenticated stream sends a message other than
+/// [AuthenticateChannel]
+#[derive(Debug)]
+struct UnauthenticatedStream {
+    msg_id: MsgId,
+}
+
+impl std::fmt::Display for UnauthenticatedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Stream is not authenticated and received message with id {}",
+            self.msg_id
+        )
+    }
+}
+
+impl std::error::Error for UnauthenticatedStream {}
+
+/// Counts rejected unauthenticated streams and rate limits the warnings about them. A
+/// misconfigured node (e.g. using a wrong or no auth secret) reconnects continuously, which would
+/// flood the log otherwise.
+#[derive(Debug)]
+struct RejectedStreams {
+    rejected: u64,
+    suppressed: u64,
+    last_warning: Option<Instant>,
+}
+
+impl RejectedStreams {
+    const fn new() -> Self {
+        Self {
+            rejected: 0,
+            suppressed: 0,
+            last_warning: None,
+        }
+    }
+
+    /// Records a rejected stream.
+    ///
+    /// # Return value
+    /// Returns the number of rejections suppressed since the last warning if a warning shall be
+    /// logged now, `None` otherwise.
+    fn record(&mut self, now: Instant) -> Option<u64> {
+        self.rejected += 1;
+
+        if let Some(last) = self.last_warning
+            && now.duration_since(last) < UNAUTHENTICATED_WARN_INTERVAL
+        {
+            self.suppressed += 1;
+            return None;
+        }
+
+        self.last_warning = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Remembers the datagrams received within a time window to detect exact duplicates, together
+/// with the reply sent to each of them.
+///
+/// Since all entries live for the same duration, they expire in insertion order. Holds at most
+/// [DEDUP_MAX_ENTRIES] entries, the oldest ones are forgotten first.
+#[derive(Debug)]
+pub(crate) struct RecentDatagrams {
+    window: Duration,
+    order: VecDeque<(u64, Instant)>,
+    /// The time each datagram was received at and the reply sent to it
+    replies: HashMap<u64, (Instant, Option<Arc<[u8]>>)>,
+}
+
+/// The result of [RecentDatagrams::check()]
+#[derive(Debug, PartialEq, Eq)]
+enum Received {
+    /// The datagram has not been received from the peer within the window. Contains the key to
+    /// record the reply with.
+    New(u64),
+    /// The datagram has already been received from the peer within the window. Contains the reply
+    /// sent to the first one, if there is one (yet).
+    Duplicate(Option<Arc<[u8]>>),
+}
+
+impl RecentDatagrams {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            order: VecDeque::new(),
+            replies: HashMap::new(),
+        }
+    }
+
+    /// Checks whether the datagram has been received from the peer within the window. If not, it
+    /// is remembered.
+    fn check(&mut self, peer_addr: SocketAddr, datagram: &[u8], now: Instant) -> Received {
+        while let Some((key, received)) = self.order.front() {
+            if now.duration_since(*received) < self.window && self.order.len() < DEDUP_MAX_ENTRIES {
+                break;
+            }
+
+            // The datagram might have been forgotten and received again since
+            if self
+                .replies
+                .get(key)
+                .is_some_and(|(inserted, _)| inserted == received)
+            {
+                self.replies.remove(key);
+            }
+            self.order.pop_front();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        peer_addr.hash(&mut hasher);
+        datagram.hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some((_, reply)) = self.replies.get(&key) {
+            return Received::Duplicate(reply.clone());
+        }
+
+        self.replies.insert(key, (now, None));
+        self.order.push_back((key, now));
+        Received::New(key)
+    }
+
+    /// Remembers the reply sent to the datagram with `key`, so it can be sent again if the
+    /// datagram is received again (e.g. a retry because the reply got lost). Replies longer than
+    /// [DEDUP_MAX_REPLY_LEN] are not kept, the datagram is forgotten instead.
+    pub(super) fn set_reply(&mut self, key: u64, reply: &[u8]) {
+        if reply.len() > DEDUP_MAX_REPLY_LEN {
+            self.forget(key);
+        } else if let Some((_, entry)) = self.replies.get_mut(&key) {
+            *entry = Some(reply.into());
+        }
+    }
+
+    /// Forgets the datagram with `key`, so it is handled again if received again
+    fn forget(&mut self, key: u64) {
+        self.replies.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bee_msg::misc::Ack;
+    use crate::bee_msg::serialize;
+    use crate::conn::test::{stream_pair, wait_until};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Counts the handled requests
+    #[derive(Debug, Clone, Default)]
+    struct CountingDispatcher {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl DispatchRequest for CountingDispatcher {
+        async fn dispatch_request(&self, _req: impl Request) -> Result<()> {
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Counts the concurrently running handlers, which wait for a permit from `gate` to finish
+    #[derive(Debug, Clone)]
+    struct GatedDispatcher {
+        gate: Arc<Semaphore>,
+        in_flight: Arc<AtomicUsize>,
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl DispatchRequest for GatedDispatcher {
+        async fn dispatch_request(&self, _req: impl Request) -> Result<()> {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+            let _permit = self.gate.acquire().await?;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn read_stream_rejects_invalid_msg_len() {
+        let (mut client, mut server) = stream_pair().await;
+
+        let dispatcher = CountingDispatcher::default();
+        let mut buf = vec![0; TCP_BUF_LEN];
+
+        let mut msg_buf = vec![0; TCP_BUF_LEN];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut msg_buf,
+        )
+        .unwrap();
+
+        for declared_len in [MAX_MSG_LEN as u32 + 1, u32::MAX, Header::LEN as u32 - 1] {
+            // The message length is the first field of the header
+            msg_buf[0..4].copy_from_slice(&declared_len.to_le_bytes());
+            client.write_all(&msg_buf[0..len]).await.unwrap();
+
+            let err = read_stream(&mut server, &mut buf, &dispatcher, false)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().starts_with("Invalid BeeMsg length"));
+
+            // Skip the unread body
+            server
+                .read_exact(&mut buf[0..len - Header::LEN])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 0);
+    }
+
+    /// Deserializes every request as [Ack]
+    #[derive(Debug, Clone)]
+    struct AckDispatcher;
+
+    impl DispatchRequest for AckDispatcher {
+        async fn dispatch_request(&self, req: impl Request) -> Result<()> {
+            req.deserialize_msg::<Ack>()?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn read_stream_decode_error_names_msg_type() {
+        let (mut client, mut server) = stream_pair().await;
+
+        let mut buf = vec![0; TCP_BUF_LEN];
+
+        let mut msg_buf = vec![0; TCP_BUF_LEN];
+        serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut msg_buf,
+        )
+        .unwrap();
+
+        // Valid header, but the body is truncated in the middle of the strings length field
+        let len = Header::LEN + 2;
+        msg_buf[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        client.write_all(&msg_buf[0..len]).await.unwrap();
+
+        let err = read_stream(&mut server, &mut buf, &AckDispatcher, false)
+            .await
+            .unwrap_err();
+        let err = format!("{err:#}");
+
+        assert!(
+            err.contains(&format!("Decoding Ack (ID {})", Ack::ID)),
+            "{err}"
+        );
+        assert!(err.contains(&format!("{:?}", server.addr())), "{err}");
+    }
+
+    #[tokio::test]
+    async fn read_stream_rejects_unauthenticated() {
+        let (mut client, mut server) = stream_pair().await;
+
+        let dispatcher = CountingDispatcher::default();
+        let mut buf = vec![0; TCP_BUF_LEN];
+
+        let mut msg_buf = vec![0; TCP_BUF_LEN];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut msg_buf,
+        )
+        .unwrap();
+        client.write_all(&msg_buf[0..len]).await.unwrap();
+
+        let rejected_before = UNAUTHENTICATED_STREAMS.lock().unwrap().rejected;
+
+        let err = read_stream(&mut server, &mut buf, &dispatcher, true)
+            .await
+            .unwrap_err();
+        assert!(err.is::<UnauthenticatedStream>(), "{err:#}");
+
+        // Other tests might reject streams concurrently, so the counter can only be checked for
+        // having increased
+        assert!(UNAUTHENTICATED_STREAMS.lock().unwrap().rejected > rejected_before);
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn rejected_streams_rate_limit() {
+        let mut rejected = RejectedStreams::new();
+        let start = Instant::now();
+
+        assert_eq!(rejected.record(start), Some(0));
+        assert_eq!(rejected.record(start + Duration::from_secs(1)), None);
+        assert_eq!(rejected.record(start + Duration::from_secs(59)), None);
+        assert_eq!(
+            rejected.record(start + UNAUTHENTICATED_WARN_INTERVAL),
+            Some(2)
+        );
+        assert_eq!(
+            rejected.record(start + UNAUTHENTICATED_WARN_INTERVAL * 2),
+            Some(0)
+        );
+        assert_eq!(rejected.rejected, 5);
+    }
+
+    #[tokio::test]
+    async fn recv_datagram_bounded_handlers() {
+        let sock = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = sock.local_addr().unwrap();
+
+        let dispatcher = GatedDispatcher {
+            gate: Arc::new(Semaphore::new(0)),
+            in_flight: Default::default(),
+            handled: Default::default(),
+        };
+        let handler_permits = Arc::new(Semaphore::new(2));
+
+        let mut buf = vec![0; UDP_BUF_LEN];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut buf,
+        )
+        .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for _ in 0..3 {
+            sender.send_to(&buf[..len], addr).await.unwrap();
+        }
+
+        // The first two datagrams take all permits, the third one is dropped
+        for _ in 0..3 {
+            recv_datagram(
+                sock.clone(),
+                dispatcher.clone(),
+                handler_permits.clone(),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        wait_until(|| dispatcher.in_flight.load(Ordering::SeqCst) == 2).await;
+        assert_eq!(handler_permits.available_permits(), 0);
+
+        // Finishing the handlers returns the permits
+        dispatcher.gate.add_permits(2);
+        wait_until(|| handler_permits.available_permits() == 2).await;
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 2);
+        assert_eq!(dispatcher.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    /// Counts and responds to every request with the received [Ack]
+    #[derive(Debug, Clone, Default)]
+    struct RespondingDispatcher {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl DispatchRequest for RespondingDispatcher {
+        async fn dispatch_request(&self, req: impl Request) -> Result<()> {
+            let msg = req.deserialize_msg::<Ack>()?;
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            req.respond(&msg).await
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_udp_resends_reply_to_duplicates() {
+        let (run_state, _run_state_control) = crate::run_state::new();
+        let sock = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = sock.local_addr().unwrap();
+
+        let dispatcher = RespondingDispatcher::default();
+        recv_udp(
+            [sock],
+            dispatcher.clone(),
+            4,
+            Duration::from_secs(10),
+            run_state,
+        )
+        .unwrap();
+
+        let datagram = |ack_id: &[u8]| {
+            let mut buf = vec![0; UDP_BUF_LEN];
+            let len = serialize(
+                &Ack {
+                    ack_id: ack_id.into(),
+                },
+                &mut buf,
+            )
+            .unwrap();
+            buf.truncate(len);
+            buf
+        };
+
+        // Sends the datagram and waits for the reply, which must be the datagram itself
+        let request = async |sender: &UdpSocket, msg: &[u8]| {
+            sender.send_to(msg, addr).await.unwrap();
+            let mut buf = vec![0; UDP_BUF_LEN];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(5), sender.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..len], msg);
+        };
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        request(&sender, &datagram(b"ack")).await;
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 1);
+
+        // A retry after a lost reply gets the reply again without being handled again
+        request(&sender, &datagram(b"ack")).await;
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 1);
+
+        // A different datagram and the same one from a different peer are handled
+        request(&sender, &datagram(b"ack2")).await;
+        let sender2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        request(&sender2, &datagram(b"ack")).await;
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 3);
+    }
+
+    /// Panics on [Ack]s with id `panic`, counts the others
+    #[derive(Debug, Clone, Default)]
+    struct PanickingDispatcher {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl DispatchRequest for PanickingDispatcher {
+        async fn dispatch_request(&self, req: impl Request) -> Result<()> {
+            let msg = req.deserialize_msg::<Ack>()?;
+            if msg.ack_id.as_ref() == b"panic" {
+                panic!("handler failure");
+            }
+
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_datagram_survives_panicking_handler() {
+        let sock = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = sock.local_addr().unwrap();
+
+        // Only one handler permit - if it isn't returned after the panic, nothing else is handled
+        let dispatcher = PanickingDispatcher::default();
+        let handler_permits = Arc::new(Semaphore::new(1));
+        let recv = || {
+            recv_datagram(
+                sock.clone(),
+                dispatcher.clone(),
+                handler_permits.clone(),
+                None,
+            )
+        };
+
+        let datagram = |ack_id: &[u8]| {
+            let mut buf = vec![0; UDP_BUF_LEN];
+            let len = serialize(
+                &Ack {
+                    ack_id: ack_id.into(),
+                },
+                &mut buf,
+            )
+            .unwrap();
+            buf.truncate(len);
+            buf
+        };
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(&datagram(b"panic"), addr).await.unwrap();
+        recv().await.unwrap();
+        wait_until(|| handler_permits.available_permits() == 1).await;
+
+        sender.send_to(&datagram(b"ack"), addr).await.unwrap();
+        recv().await.unwrap();
+        wait_until(|| handler_permits.available_permits() == 1).await;
+
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn read_stream_panicking_handler() {
+        let (mut client, mut server) = stream_pair().await;
+
+        let dispatcher = PanickingDispatcher::default();
+        let mut buf = vec![0; TCP_BUF_LEN];
+        let mut msg_buf = vec![0; TCP_BUF_LEN];
+
+        for ack_id in [b"panic".as_slice(), b"ack"] {
+            let len = serialize(
+                &Ack {
+                    ack_id: ack_id.into(),
+                },
+                &mut msg_buf,
+            )
+            .unwrap();
+            client.write_all(&msg_buf[0..len]).await.unwrap();
+        }
+
+        let err = read_stream(&mut server, &mut buf, &dispatcher, false)
+            .await
+            .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(
+            err.contains(&format!(
+                "msg with id {} panicked: handler failure",
+                Ack::ID
+            )),
+            "{err}"
+        );
+
+        // The buffer and stream are still usable
+        read_stream(&mut server, &mut buf, &dispatcher, false)
+            .await
+            .unwrap();
+        assert_eq!(dispatcher.handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn bind_udp_reuseport() {
+        let socks = bind_udp("127.0.0.1:0".parse().unwrap(), 2).unwrap();
+        assert_eq!(socks.len(), 2);
+
+        let addr = socks[0].local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(socks[1].local_addr().unwrap(), addr);
+
+        // Datagrams sent to the shared port are handled
+        let (run_state, _run_state_control) = crate::run_state::new();
+        let dispatcher = CountingDispatcher::default();
+        recv_udp(socks, dispatcher.clone(), 4, Duration::ZERO, run_state).unwrap();
+
+        let mut buf = vec![0; UDP_BUF_LEN];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut buf,
+        )
+        .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(&buf[..len], addr).await.unwrap();
+
+        wait_until(|| dispatcher.handled.load(Ordering::SeqCst) == 1).await;
+
+        // Without SO_REUSEPORT, binding to the same port fails
+        bind_udp(addr, 1).unwrap_err();
+        bind_udp(addr, 0).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn bind_tcp_backlog() {
+        const BACKLOG: u32 = 64;
+
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), BACKLOG).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Nothing is accepted, so all connections must fit into the listen queue
+        let connects: Vec<_> = (0..BACKLOG)
+            .map(|_| {
+                tokio::spawn(tokio::time::timeout(
+                    Duration::from_secs(5),
+                    tokio::net::TcpStream::connect(addr),
+                ))
+            })
+            .collect();
+
+        for connect in connects {
+            connect.await.unwrap().unwrap().unwrap();
+        }
+
+        bind_tcp("127.0.0.1:0".parse().unwrap(), 0).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn listen_tcp_accept_tasks() {
+        const CONNS: usize = 200;
+
+        let (run_state, _run_state_control) = crate::run_state::new();
+        let dispatcher = CountingDispatcher::default();
+
+        // Find a free port for the listener
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        listen_tcp(addr, 64, 4, dispatcher.clone(), false, run_state.clone())
+            .await
+            .unwrap();
+
+        let mut buf = vec![0; 1024];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut buf,
+        )
+        .unwrap();
+        let msg = Arc::new(buf[..len].to_vec());
+
+        // Connect all at once, each sending one message
+        let conns: Vec<_> = (0..CONNS)
+            .map(|_| {
+                let msg = msg.clone();
+                tokio::spawn(async move {
+                    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+                    tokio::io::AsyncWriteExt::write_all(&mut stream, &msg).await?;
+                    anyhow::Ok(stream)
+                })
+            })
+            .collect();
+
+        // Keep the streams open until all messages have been handled
+        let mut streams = vec![];
+        for conn in conns {
+            streams.push(conn.await.unwrap().unwrap());
+        }
+
+        wait_until(|| dispatcher.handled.load(Ordering::SeqCst) == CONNS).await;
+        drop(streams);
+
+        listen_tcp(addr, 64, 0, dispatcher, false, run_state)
+            .await
+            .unwrap_err();
+    }
+
+    #[test]
+    fn recent_datagrams_expire() {
+        let peer: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut recent = RecentDatagrams::new(window);
+
+        let Received::New(key) = recent.check(peer, b"msg", start) else {
+            panic!("datagram reported as duplicate");
+        };
+        assert_eq!(
+            recent.check(peer, b"msg", start + Duration::from_secs(1)),
+            Received::Duplicate(None)
+        );
+
+        // The reply is sent again to duplicates
+        recent.set_reply(key, b"reply");
+        assert_eq!(
+            recent.check(peer, b"msg", start + Duration::from_secs(2)),
+            Received::Duplicate(Some(b"reply".as_slice().into()))
+        );
+
+        assert!(matches!(
+            recent.check(peer, b"msg", start + window),
+            Received::New(_)
+        ));
+
+        // Forgotten datagrams and those with too long replies are handled again
+        let Received::New(key) = recent.check(peer, b"other", start + window) else {
+            panic!("datagram reported as duplicate");
+        };
+        recent.forget(key);
+        let Received::New(key) = recent.check(peer, b"other", start + window) else {
+            panic!("datagram reported as duplicate");
+        };
+        recent.set_reply(key, &[0; DEDUP_MAX_REPLY_LEN + 1]);
+        assert!(matches!(
+            recent.check(peer, b"other", start + window),
+            Received::New(_)
+        ));
+
+        // The oldest entries are dropped when the limit is reached
+        let mut recent = RecentDatagrams::new(window);
+        for i in 0..=DEDUP_MAX_ENTRIES {
+            assert!(matches!(
+                recent.check(peer, &i.to_le_bytes(), start),
+                Received::New(_)
+            ));
+        }
+        assert_eq!(recent.order.len(), DEDUP_MAX_ENTRIES);
+        assert!(matches!(
+            recent.check(peer, &0usize.to_le_bytes(), start),
+            Received::New(_)
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+//! Short-lived capture of the BeeMsg traffic for debugging protocol issues
+//!
+//! When started, the incoming and outgoing BeeMsges passing the connection pool and the
+//! dispatchers are recorded into a bounded ring until the capture window expires. The ring keeps
+//! the most recent messages and drops the oldest ones on overflow. While no capture is running,
+//! recording a message costs a single atomic load.
+
+use super::msg_dispatch::hexdump;
+use crate::bee_msg::misc::AuthenticateChannel;
+use crate::bee_msg::{Msg, MsgId, deserialize_header};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Maximum number of bytes kept per message if the message data is captured
+pub const MAX_DATA_LEN: usize = 1024;
+
+/// The process wide message capture the connection code records into
+pub static MSG_CAPTURE: MsgCapture = MsgCapture::new();
+
+/// Whether a message has been received or sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// A captured message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedMsg {
+    pub time: SystemTime,
+    pub direction: Direction,
+    pub peer: SocketAddr,
+    pub msg_id: MsgId,
+    /// The length of the whole message, including the header
+    pub len: usize,
+    /// The first [MAX_DATA_LEN] bytes of the message, including the header. Only set if requested
+    /// when starting the capture. Never set for [AuthenticateChannel], which contains the secret.
+    pub data: Option<Vec<u8>>,
+}
+
+impl CapturedMsg {
+    /// The captured message data as space separated hex values
+    pub fn data_hex(&self) -> Option<String> {
+        self.data.as_deref().map(|d| hexdump(d, MAX_DATA_LEN))
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    until: Option<Instant>,
+    capacity: usize,
+    with_data: bool,
+    msgs: VecDeque<CapturedMsg>,
+}
+
+/// A bounded, time limited capture of BeeMsges
+///
+/// Inactive after construction, use [MsgCapture::start()] to start capturing. Meant to be defined
+/// as a static.
+#[derive(Debug)]
+pub struct MsgCapture {
+    active: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl Default for MsgCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MsgCapture {
+    pub const fn new() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            state: Mutex::new(State {
+                until: None,
+                capacity: 0,
+                with_data: false,
+                msgs: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Starts a new capture for `duration`, keeping up to `capacity` messages.
+    ///
+    /// The messages of a previous capture are discarded. If `with_data` is set, the beginning of
+    /// each message is captured as well.
+    pub fn start(&self, duration: Duration, capacity: usize, with_data: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        state.until = Some(Instant::now() + duration);
+        state.capacity = capacity;
+        state.with_data = with_data;
+        state.msgs.clear();
+
+        self.active.store(capacity > 0, Ordering::Relaxed);
+    }
+
+    /// Whether a capture is currently running
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+            && self
+                .state
+                .lock()
+                .unwrap()
+                .until
+                .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Returns a copy of the captured messages, oldest first.
+    ///
+    /// The messages are kept after the capture window expired until the next capture is started.
+    pub fn msgs(&self) -> Vec<CapturedMsg> {
+        self.state.lock().unwrap().msgs.iter().cloned().collect()
+    }
+
+    /// Records the message in `msg` (header and body) if a capture is running
+    #[inline]
+    pub fn record(&self, direction: Direction, peer: SocketAddr, msg: &[u8]) {
+        if self.active.load(Ordering::Relaxed) {
+            self.push(direction, peer, msg);
+        }
+    }
+
+    fn push(&self, direction: Direction, peer: SocketAddr, msg: &[u8]) {
+        // Messages with an invalid header are rejected by the callers anyway
+        let Ok(header) = deserialize_header(msg) else {
+            return;
+        };
+
+        let time = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+
+        if state.until.is_none_or(|until| Instant::now() >= until) {
+            self.active.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        let data = (state.with_data && header.msg_id() != AuthenticateChannel::ID)
+            .then(|| msg[..msg.len().min(MAX_DATA_LEN)].to_vec());
+
+        if state.msgs.len() >= state.capacity {
+            state.msgs.pop_front();
+        }
+
+        state.msgs.push_back(CapturedMsg {
+            time,
+            direction,
+            peer,
+            msg_id: header.msg_id(),
+            len: msg.len(),
+            data,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bee_msg::misc::Ack;
+    use crate::bee_msg::node::{GetNodes, GetNodesResp};
+    use crate::bee_msg::serialize;
+    use crate::conn::incoming::listen_tcp;
+    use crate::conn::msg_dispatch::{DispatchRequest, Request};
+    use crate::conn::outgoing::Pool;
+    use crate::types::NodeType;
+    use anyhow::Result;
+    use std::sync::Arc;
+    use tokio::net::{TcpListener, UdpSocket};
+
+    fn ack() -> Vec<u8> {
+        let mut buf = vec![0; 4096];
+        let len = serialize(
+            &Ack {
+                ack_id: "ack".into(),
+            },
+            &mut buf,
+        )
+        .unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn record() {
+        let capture = MsgCapture::new();
+        let peer: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let msg = ack();
+
+        // Nothing is recorded while inactive
+        capture.record(Direction::Incoming, peer, &msg);
+        assert!(!capture.is_active());
+        assert!(capture.msgs().is_empty());
+
+        capture.start(Duration::from_secs(60), 2, false);
+        assert!(capture.is_active());
+
+        for direction in [
+            Direction::Incoming,
+            Direction::Outgoing,
+            Direction::Incoming,
+        ] {
+            capture.record(direction, peer, &msg);
+        }
+        // Invalid messages are ignored
+        capture.record(Direction::Incoming, peer, &msg[..10]);
+
+        // Only the most recent messages are kept
+        let msgs = capture.msgs();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].direction, Direction::Outgoing);
+        assert_eq!(msgs[1].direction, Direction::Incoming);
+        assert_eq!(msgs[1].msg_id, Ack::ID);
+        assert_eq!(msgs[1].len, msg.len());
+        assert_eq!(msgs[1].data, None);
+
+        // Data is captured if requested and a new capture discards the old messages
+        capture.start(Duration::from_millis(50), 10, true);
+        capture.record(Direction::Incoming, peer, &msg);
+        let msgs = capture.msgs();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].data.as_deref(), Some(msg.as_slice()));
+        assert!(
+            msgs[0]
+                .data_hex()
+                .unwrap()
+                .starts_with(&format!("{:02x} {:02x}", msg[0], msg[1]))
+        );
+
+        // Nothing is recorded after the window expired, but the messages are kept
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!capture.is_active());
+        capture.record(Direction::Incoming, peer, &msg);
+        assert_eq!(capture.msgs().len(), 1);
+    }
+
+    /// Responds to [GetNodes] with an empty [GetNodesResp]
+    #[derive(Debug, Clone)]
+    struct GetNodesDispatcher;
+
+    impl DispatchRequest for GetNodesDispatcher {
+        async fn dispatch_request(&self, req: impl Request) -> Result<()> {
+            req.deserialize_msg::<GetNodes>()?;
+            req.respond(&GetNodesResp::default()).await
+        }
+    }
+
+    #[tokio::test]
+    async fn capture_get_nodes() {
+        let (run_state, _run_state_control) = crate::run_state::new();
+
+        // Find a free port for the listener
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        listen_tcp(addr, 128, 1, GetNodesDispatcher, false, run_state)
+            .await
+            .unwrap();
+
+        let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let pool = Pool::new(vec![udp_socket], 2, None, false);
+        pool.replace_node_addrs(1, [addr]);
+
+        MSG_CAPTURE.start(Duration::from_secs(60), 10000, true);
+
+        pool.request::<_, GetNodesResp>(
+            1,
+            &GetNodes {
+                node_type: NodeType::Storage,
+            },
+        )
+        .await
+        .unwrap();
+
+        // Other tests might communicate at the same time, so only look at the messages of this one
+        let msgs: Vec<_> = MSG_CAPTURE
+            .msgs()
+            .into_iter()
+            .filter(|m| m.msg_id == GetNodes::ID || m.msg_id == GetNodesResp::ID)
+            .collect();
+
+        let seen = |direction, msg_id, to_listener: bool| {
+            msgs.iter().any(|m| {
+                m.direction == direction && m.msg_id == msg_id && (m.peer == addr) == to_listener
+            })
+        };
+
+        // Sent by the pool and received by the listener
+        assert!(seen(Direction::Outgoing, GetNodes::ID, true), "{msgs:?}");
+        assert!(seen(Direction::Incoming, GetNodes::ID, false), "{msgs:?}");
+        // Sent back by the listener and received by the pool
+        assert!(
+            seen(Direction::Outgoing, GetNodesResp::ID, false),
+            "{msgs:?}"
+        );
+        assert!(
+            seen(Direction::Incoming, GetNodesResp::ID, true),
+            "{msgs:?}"
+        );
+
+        let get_nodes = msgs
+            .iter()
+            .find(|m| m.msg_id == GetNodes::ID && m.direction == Direction::Outgoing)
+            .unwrap();
+        assert_eq!(get_nodes.data.as_ref().unwrap().len(), get_nodes.len);
+    }
+}
@@ -1,13 +1,15 @@
 //! Facilities for dispatching TCP and UDP messages to their message handlers
 
+use super::incoming::RecentDatagrams;
+use super::msg_capture::{Direction, MSG_CAPTURE};
 use super::stream::Stream;
 use crate::bee_msg::{Header, Msg, deserialize_body, serialize};
 use crate::bee_serde::{Deserializable, Serializable};
-use anyhow::Result;
-use std::fmt::Debug;
+use anyhow::{Context, Result};
+use std::fmt::{Debug, Write};
 use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::net::UdpSocket;
 
 /// Enables an object to act as a message dispatcher and being called from the generic connection
@@ -29,6 +31,61 @@ pub trait Request: Send + Sync {
     fn deserialize_msg<M: Msg + Deserializable>(&self) -> Result<M>;
 }
 
+/// Maximum number of bytes of a message that failed to deserialize to include in the trace log
+const HEXDUMP_MAX_LEN: usize = 256;
+
+/// Deserializes the body of the message contained in `buf`.
+///
+/// On failure, the error names the expected message type, the message ID from the header and the
+/// peer. The beginning of the message is logged as hexdump at trace level.
+fn deserialize_request_msg<M: Msg + Deserializable>(
+    header: &Header,
+    buf: &[u8],
+    peer: SocketAddr,
+) -> Result<M> {
+    deserialize_body(header, &buf[Header::LEN..])
+        .inspect_err(|_| {
+            if log::log_enabled!(log::Level::Trace) {
+                let msg = &buf[..header.msg_len().min(buf.len())];
+                log::trace!(
+                    "Undecodable message from {peer:?} ({} bytes): {}",
+                    msg.len(),
+                    hexdump(msg, HEXDUMP_MAX_LEN)
+                );
+            }
+        })
+        .with_context(|| {
+            format!(
+                "Decoding {} (ID {}) from {peer:?} failed",
+                msg_type_name::<M>(),
+                header.msg_id()
+            )
+        })
+}
+
+/// The plain type name of a message, without the module path
+fn msg_type_name<M: Msg>() -> &'static str {
+    let name = std::any::type_name::<M>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Formats up to `max_len` bytes of `buf` as space separated hex values
+pub(super) fn hexdump(buf: &[u8], max_len: usize) -> String {
+    let mut out = String::with_capacity(buf.len().min(max_len) * 3);
+    for (i, b) in buf.iter().take(max_len).enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let _ = write!(out, "{b:02x}");
+    }
+
+    if buf.len() > max_len {
+        let _ = write!(out, " ... ({} more bytes)", buf.len() - max_len);
+    }
+
+    out
+}
+
 /// Represents a request made via a TCP stream
 #[derive(Debug)]
 pub struct StreamRequest<'a> {
@@ -40,6 +97,11 @@ pub struct StreamRequest<'a> {
 impl Request for StreamRequest<'_> {
     async fn respond<M: Msg + Serializable>(self, msg: &M) -> Result<()> {
         let msg_len = serialize(msg, self.buf)?;
+        MSG_CAPTURE.record(
+            Direction::Outgoing,
+            self.stream.addr(),
+            &self.buf[0..msg_len],
+        );
         self.stream.write_all(&self.buf[0..msg_len]).await
     }
 
@@ -58,7 +120,7 @@ impl Request for StreamRequest<'_> {
     }
 
     fn deserialize_msg<M: Msg + Deserializable>(&self) -> Result<M> {
-        deserialize_body(self.header, &self.buf[Header::LEN..])
+        deserialize_request_msg(self.header, self.buf, self.addr())
     }
 
     fn header(&self) -> &Header {
@@ -73,11 +135,21 @@ pub struct SocketRequest<'a> {
     pub(crate) peer_addr: SocketAddr,
     pub(crate) buf: &'a mut [u8],
     pub header: &'a Header,
+    /// The duplicate detection and the key of this datagram, if enabled. The reply is recorded
+    /// there to be sent again to duplicates.
+    pub(crate) dedup: Option<(Arc<Mutex<RecentDatagrams>>, u64)>,
 }
 
 impl Request for SocketRequest<'_> {
     async fn respond<M: Msg + Serializable>(self, msg: &M) -> Result<()> {
         let msg_len = serialize(msg, self.buf)?;
+        MSG_CAPTURE.record(Direction::Outgoing, self.peer_addr, &self.buf[0..msg_len]);
+        if let Some((recent, key)) = &self.dedup {
+            recent
+                .lock()
+                .unwrap()
+                .set_reply(*key, &self.buf[0..msg_len]);
+        }
         self.sock
             .send_to(&self.buf[0..msg_len], &self.peer_addr)
             .await?;
@@ -93,7 +165,7 @@ impl Request for SocketRequest<'_> {
     }
 
     fn deserialize_msg<M: Msg + Deserializable>(&self) -> Result<M> {
-        deserialize_body(self.header, &self.buf[Header::LEN..])
+        deserialize_request_msg(self.header, self.buf, self.peer_addr)
     }
 
     fn header(&self) -> &Header {
@@ -1,20 +1,33 @@
 //! Outgoing communication functionality
-use super::store::Store;
+use super::store::{Store, StoreStats};
 use crate::bee_msg::misc::AuthenticateChannel;
 use crate::bee_msg::{Header, Msg, deserialize_body, deserialize_header, serialize};
 use crate::bee_serde::{Deserializable, Serializable};
-use crate::conn::TCP_BUF_LEN;
+use crate::conn::msg_capture::{Direction, MSG_CAPTURE};
 use crate::conn::store::StoredStream;
 use crate::conn::stream::Stream;
+use crate::conn::{MAX_MSG_LEN, TCP_BUF_LEN};
 use crate::types::{AuthSecret, Uid};
 use anyhow::{Context, Result, bail};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
+use thiserror::Error;
 use tokio::net::UdpSocket;
 use tokio::time::timeout;
 
+/// Returned by [Pool::request_with_timeout()] if the peer didn't respond in time.
+///
+/// Can be obtained from the returned [anyhow::Error] using `downcast_ref()`.
+#[derive(Debug, Error)]
+#[error("Node with uid {node_uid} didn't respond within {timeout:?}")]
+pub struct RequestTimeout {
+    pub node_uid: Uid,
+    pub timeout: Duration,
+}
+
 /// The connection pool.
 ///
 /// Provides methods for making requests to nodes (streams and datagrams / UDP). Uses [Store]
@@ -26,23 +39,36 @@ use tokio::time::timeout;
 #[derive(Debug)]
 pub struct Pool {
     store: Store<Uid>,
-    udp_socket: Arc<UdpSocket>,
+    udp_sockets: Vec<Arc<UdpSocket>>,
+    next_udp_socket: AtomicUsize,
     auth_secret: Option<AuthSecret>,
     use_ipv6: bool,
 }
 
 impl Pool {
     /// Creates a new Pool.
+    ///
+    /// Datagrams are sent using the given `udp_sockets` in turns. Usually, these are bound to the
+    /// same address using `SO_REUSEPORT` (see [bind_udp()](super::incoming::bind_udp)).
+    ///
+    /// # Panics
+    /// If `udp_sockets` is empty.
     pub fn new(
-        udp_socket: Arc<UdpSocket>,
+        udp_sockets: Vec<Arc<UdpSocket>>,
         connection_limit: usize,
         auth_secret: Option<AuthSecret>,
         use_ipv6: bool,
     ) -> Self {
+        assert!(
+            !udp_sockets.is_empty(),
+            "At least one UDP socket is required"
+        );
+
         Self {
             store: Store::new(connection_limit),
             auth_secret,
-            udp_socket,
+            udp_sockets,
+            next_udp_socket: AtomicUsize::new(0),
             use_ipv6,
         }
     }
@@ -52,13 +78,38 @@ impl Pool {
         &self,
         node_uid: Uid,
         msg: &M,
+    ) -> Result<R> {
+        self.request_inner(node_uid, msg, None).await
+    }
+
+    /// Sends a [Msg] to a node and receives the response, waiting at most `read_timeout` for it.
+    ///
+    /// If the response doesn't arrive in time, a [RequestTimeout] error is returned and the used
+    /// stream is closed instead of being put back into the store - a late response would otherwise
+    /// be read by the next request using it.
+    pub async fn request_with_timeout<M: Msg + Serializable, R: Msg + Deserializable>(
+        &self,
+        node_uid: Uid,
+        msg: &M,
+        read_timeout: Duration,
+    ) -> Result<R> {
+        self.request_inner(node_uid, msg, Some(read_timeout)).await
+    }
+
+    async fn request_inner<M: Msg + Serializable, R: Msg + Deserializable>(
+        &self,
+        node_uid: Uid,
+        msg: &M,
+        read_timeout: Option<Duration>,
     ) -> Result<R> {
         log::trace!("REQUEST to {node_uid:?}: {msg:?}");
 
         let mut buf = self.store.pop_buf_or_create();
 
         let msg_len = serialize(msg, &mut buf)?;
-        let resp_header = self.comm_stream(node_uid, &mut buf, msg_len, true).await?;
+        let resp_header = self
+            .comm_stream(node_uid, &mut buf, msg_len, true, read_timeout)
+            .await?;
         let resp_msg = deserialize_body(&resp_header, &buf[Header::LEN..])?;
 
         self.store.push_buf(buf);
@@ -75,7 +126,8 @@ impl Pool {
         let mut buf = self.store.pop_buf_or_create();
 
         let msg_len = serialize(msg, &mut buf)?;
-        self.comm_stream(node_uid, &mut buf, msg_len, false).await?;
+        self.comm_stream(node_uid, &mut buf, msg_len, false, None)
+            .await?;
 
         self.store.push_buf(buf);
 
@@ -88,6 +140,9 @@ impl Pool {
     /// optionally reads the response into the same buffer. When done, the stream is pushed into the
     /// store.
     ///
+    /// If `read_timeout` is set, waiting for the response fails with [RequestTimeout] after that
+    /// duration. A stream that timed out is dropped and thus closed.
+    ///
     /// Acquisition happens in the following order:
     ///
     /// 1. Pop open streams from the store without waiting
@@ -100,16 +155,27 @@ impl Pool {
         buf: &mut [u8],
         send_len: usize,
         expect_response: bool,
+        read_timeout: Option<Duration>,
     ) -> Result<Header> {
         debug_assert_eq!(buf.len(), TCP_BUF_LEN);
 
         // 1. Pop open streams until communication succeeds or none are left
         while let Some(stream) = self.store.try_pop_stream(node_uid) {
             match self
-                .write_and_read_stream(buf, stream, send_len, expect_response)
+                .write_and_read_stream(
+                    node_uid,
+                    buf,
+                    stream,
+                    send_len,
+                    expect_response,
+                    read_timeout,
+                )
                 .await
             {
                 Ok(header) => return Ok(header),
+                // The peer is connected but doesn't respond - trying the next stream would most
+                // likely just wait again
+                Err(err) if err.is::<RequestTimeout>() => return Err(err),
                 Err(err) => {
                     // If the stream doesn't work anymore, just discard it and try the next one
                     log::debug!(
@@ -149,6 +215,7 @@ impl Pool {
                             let mut auth_buf = self.store.pop_buf_or_create();
                             let msg_len =
                                 serialize(&AuthenticateChannel { auth_secret }, &mut auth_buf)?;
+                            MSG_CAPTURE.record(Direction::Outgoing, *addr, &auth_buf[0..msg_len]);
 
                             stream
                                 .as_mut()
@@ -162,7 +229,14 @@ impl Pool {
                         // Communication using the newly opened stream should usually not fail. If
                         // it does, abort. It might be better to just try the next address though.
                         let resp_header = self
-                            .write_and_read_stream(buf, stream, send_len, expect_response)
+                            .write_and_read_stream(
+                                node_uid,
+                                buf,
+                                stream,
+                                send_len,
+                                expect_response,
+                                read_timeout,
+                            )
                             .await
                             .with_context(err_context)?;
 
@@ -189,7 +263,14 @@ impl Pool {
             })?;
 
         let resp_header = self
-            .write_and_read_stream(buf, stream, send_len, expect_response)
+            .write_and_read_stream(
+                node_uid,
+                buf,
+                stream,
+                send_len,
+                expect_response,
+                read_timeout,
+            )
             .await
             .with_context(|| {
                 format!("Communication using existing stream to node with uid {node_uid} failed")
@@ -199,27 +280,46 @@ impl Pool {
     }
 
     /// Writes data to the given stream, optionally receives a response and pushes the stream to
-    /// the store
+    /// the store.
+    ///
+    /// If reading the response times out, the stream is dropped instead.
     async fn write_and_read_stream(
         &self,
+        node_uid: Uid,
         buf: &mut [u8],
         mut stream: StoredStream<Uid>,
         send_len: usize,
         expect_response: bool,
+        read_timeout: Option<Duration>,
     ) -> Result<Header> {
+        let peer = stream.as_ref().addr();
+        MSG_CAPTURE.record(Direction::Outgoing, peer, &buf[0..send_len]);
         stream.as_mut().write_all(&buf[0..send_len]).await?;
 
         let header = if expect_response {
-            // Read header
-            stream.as_mut().read_exact(&mut buf[0..Header::LEN]).await?;
-            let header = deserialize_header(&buf[0..Header::LEN])?;
-
-            // Read body
-            stream
-                .as_mut()
-                .read_exact(&mut buf[Header::LEN..header.msg_len()])
-                .await?;
-            header
+            let read = async {
+                // Read header
+                stream.as_mut().read_exact(&mut buf[0..Header::LEN]).await?;
+                let header = deserialize_header(&buf[0..Header::LEN])?;
+                header.check_msg_len(MAX_MSG_LEN.min(buf.len()))?;
+
+                // Read body
+                stream
+                    .as_mut()
+                    .read_exact(&mut buf[Header::LEN..header.msg_len()])
+                    .await?;
+
+                MSG_CAPTURE.record(Direction::Incoming, peer, &buf[0..header.msg_len()]);
+                Ok(header) as Result<_>
+            };
+
+            match read_timeout {
+                Some(t) => timeout(t, read).await.map_err(|_| RequestTimeout {
+                    node_uid,
+                    timeout: t,
+                })??,
+                None => read.await?,
+            }
         } else {
             Header::default()
         };
@@ -242,6 +342,9 @@ impl Pool {
 
         let msg_len = serialize(msg, &mut buf)?;
 
+        let udp_socket = &self.udp_sockets
+            [self.next_udp_socket.fetch_add(1, Ordering::Relaxed) % self.udp_sockets.len()];
+
         for node_uid in peers {
             let addrs = self.store.get_node_addrs(node_uid).unwrap_or_default();
 
@@ -258,7 +361,8 @@ impl Pool {
                     continue;
                 }
 
-                if let Err(err) = self.udp_socket.send_to(&buf[0..msg_len], addr).await {
+                MSG_CAPTURE.record(Direction::Outgoing, *addr, &buf[0..msg_len]);
+                if let Err(err) = udp_socket.send_to(&buf[0..msg_len], addr).await {
                     log::debug!(
                         "Sending datagram to node with uid {node_uid} using {addr} failed: {err}"
                     );
@@ -281,4 +385,59 @@ impl Pool {
     pub fn replace_node_addrs(&self, node_uid: Uid, new_addrs: impl Into<Arc<[SocketAddr]>>) {
         self.store.replace_node_addrs(node_uid, new_addrs)
     }
+
+    /// Returns the current usage of the connection pool
+    pub fn stats(&self) -> StoreStats {
+        self.store.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bee_msg::misc::Ack;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn request_with_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Accept the connection and read the request, but never reply
+        let peer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            while stream.read(&mut buf).await.unwrap() > 0 {}
+        });
+
+        let udp_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let pool = Pool::new(vec![udp_socket], 2, None, false);
+        pool.replace_node_addrs(1, [addr]);
+
+        let err = pool
+            .request_with_timeout::<_, Ack>(
+                1,
+                &Ack {
+                    ack_id: "ack".into(),
+                },
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+
+        let timeout_err = err.downcast_ref::<RequestTimeout>().unwrap();
+        assert_eq!(timeout_err.node_uid, 1);
+        assert_eq!(timeout_err.timeout, Duration::from_millis(100));
+
+        // The stream must have been closed instead of being put back into the store
+        assert_eq!(pool.stats().open_streams, 0);
+        assert_eq!(pool.stats().idle_streams, 0);
+
+        // The peer notices the closed connection
+        timeout(Duration::from_secs(5), peer)
+            .await
+            .unwrap()
+            .unwrap();
+    }
 }
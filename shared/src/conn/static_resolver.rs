@@ -0,0 +1,125 @@
+//! Static node address mapping for building connection pools without a node database
+
+use super::outgoing::Pool;
+use crate::types::Uid;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// A fixed mapping from node UIDs to their network addresses
+///
+/// Meant for tests and tools that need deterministic routing of requests (e.g. to loopback ports)
+/// without the node information being provided by the management database. The mapping is
+/// applied to a [Pool] using [StaticAddrResolver::apply()].
+#[derive(Debug, Clone, Default)]
+pub struct StaticAddrResolver {
+    addrs: HashMap<Uid, Vec<SocketAddr>>,
+}
+
+impl StaticAddrResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the addresses of a node, replacing existing ones
+    pub fn insert(&mut self, node_uid: Uid, addrs: impl IntoIterator<Item = SocketAddr>) {
+        self.addrs.insert(node_uid, addrs.into_iter().collect());
+    }
+
+    /// Returns the addresses of a node
+    pub fn lookup(&self, node_uid: Uid) -> Option<&[SocketAddr]> {
+        self.addrs.get(&node_uid).map(Vec::as_slice)
+    }
+
+    /// Returns the node owning the given address
+    pub fn reverse_lookup(&self, addr: SocketAddr) -> Option<Uid> {
+        self.addrs
+            .iter()
+            .find(|(_, addrs)| addrs.contains(&addr))
+            .map(|(node_uid, _)| *node_uid)
+    }
+
+    /// Replaces the addresses of all contained nodes in the given pool
+    pub fn apply(&self, pool: &Pool) {
+        for (node_uid, addrs) in &self.addrs {
+            pool.replace_node_addrs(*node_uid, addrs.as_slice());
+        }
+    }
+}
+
+impl FromIterator<(Uid, Vec<SocketAddr>)> for StaticAddrResolver {
+    fn from_iter<T: IntoIterator<Item = (Uid, Vec<SocketAddr>)>>(iter: T) -> Self {
+        Self {
+            addrs: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bee_msg::misc::Ack;
+    use crate::conn::test::fake_node;
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+
+    async fn request(pool: &Pool, node_uid: Uid) -> String {
+        let resp: Ack = pool
+            .request(
+                node_uid,
+                &Ack {
+                    ack_id: "req".into(),
+                },
+            )
+            .await
+            .unwrap();
+
+        resp.ack_id.to_string()
+    }
+
+    #[tokio::test]
+    async fn static_routing() {
+        let addr_1 = fake_node("node_1").await;
+        let addr_2 = fake_node("node_2").await;
+
+        let resolver: StaticAddrResolver =
+            [(1, vec![addr_1]), (2, vec![addr_2])].into_iter().collect();
+
+        assert_eq!(resolver.lookup(1), Some([addr_1].as_slice()));
+        assert_eq!(resolver.lookup(3), None);
+        assert_eq!(resolver.reverse_lookup(addr_2), Some(2));
+        assert_eq!(
+            resolver.reverse_lookup("127.0.0.1:1".parse().unwrap()),
+            None
+        );
+
+        let pool_1 = Pool::new(
+            vec![Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap())],
+            2,
+            None,
+            false,
+        );
+        let pool_2 = Pool::new(
+            vec![Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap())],
+            2,
+            None,
+            false,
+        );
+        resolver.apply(&pool_1);
+        resolver.apply(&pool_2);
+
+        assert_eq!(request(&pool_1, 2).await, "node_2");
+        assert_eq!(request(&pool_2, 1).await, "node_1");
+        assert_eq!(request(&pool_1, 1).await, "node_1");
+
+        // Unknown nodes can't be routed
+        pool_1
+            .request::<_, Ack>(
+                3,
+                &Ack {
+                    ack_id: "req".into(),
+                },
+            )
+            .await
+            .unwrap_err();
+    }
+}
@@ -115,6 +115,25 @@ impl<T: Debug + Display + Clone + Default + PartialEq + Eq + Hash> Store<T> {
         self.bufs.lock().unwrap().push_back(buf);
     }
 
+    /// Returns the current number of open and idle streams and stored buffers
+    pub fn stats(&self) -> StoreStats {
+        let streams = self.streams.lock().unwrap();
+
+        let (open_streams, idle_streams) =
+            streams.values().fold((0, 0), |(open, idle), (queue, sem)| {
+                (
+                    open + self.connection_limit - sem.available_permits(),
+                    idle + queue.len(),
+                )
+            });
+
+        StoreStats {
+            open_streams,
+            idle_streams,
+            bufs: self.bufs.lock().unwrap().len(),
+        }
+    }
+
     /// Get a list of known addresses for the given node UID
     pub fn get_node_addrs(&self, key: T) -> Option<Arc<[SocketAddr]>> {
         self.addrs.read().unwrap().get(&key).cloned()
@@ -128,6 +147,17 @@ impl<T: Debug + Display + Clone + Default + PartialEq + Eq + Hash> Store<T> {
     }
 }
 
+/// A snapshot of the stores usage, see [Store::stats()]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Streams that are currently open, either in use or idle
+    pub open_streams: usize,
+    /// Open streams currently waiting in the store for reuse
+    pub idle_streams: usize,
+    /// Message buffers currently waiting in the store for reuse
+    pub bufs: usize,
+}
+
 /// A permit, representing the permission to open a new stream to a specific node
 #[derive(Debug)]
 pub struct StoredStreamPermit<T: Debug> {
@@ -0,0 +1,72 @@
+//! Shared fixtures for the connection tests
+
+use super::stream::Stream;
+use crate::bee_msg::misc::Ack;
+use crate::bee_msg::{Header, deserialize_body, deserialize_header, serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Connects a TCP stream to a local listener.
+///
+/// # Return value
+/// The connected (client, server) streams.
+pub(crate) async fn stream_pair() -> (Stream, Stream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = Stream::connect_tcp(&listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let server = listener.accept().await.unwrap().0.into();
+
+    (client, server)
+}
+
+/// Starts a fake node answering each [Ack] request with an [Ack] containing its name.
+///
+/// # Return value
+/// The address the fake node listens on.
+pub(crate) async fn fake_node(name: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 1024];
+                while stream.read_exact(&mut buf[0..Header::LEN]).await.is_ok() {
+                    let header = deserialize_header(&buf[0..Header::LEN]).unwrap();
+                    stream
+                        .read_exact(&mut buf[Header::LEN..header.msg_len()])
+                        .await
+                        .unwrap();
+                    let _: Ack = deserialize_body(&header, &buf[Header::LEN..]).unwrap();
+
+                    let len = serialize(
+                        &Ack {
+                            ack_id: name.into(),
+                        },
+                        &mut buf,
+                    )
+                    .unwrap();
+                    stream.write_all(&buf[0..len]).await.unwrap();
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+/// Waits until `cond` is true, letting the other tasks run in between. Panics if that takes longer
+/// than 5 seconds.
+pub(crate) async fn wait_until(mut cond: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !cond() {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("Condition not met within 5 seconds");
+}
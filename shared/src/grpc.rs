@@ -1,9 +1,12 @@
+use crate::metrics::Counter;
 use anyhow::Result;
 use std::fmt::Write;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use tokio::sync::mpsc;
 use tokio_stream::Stream;
+use tonic::metadata::MetadataValue;
 use tonic::{Code, Status};
 
 /// Autoimplements a gRPC handle function as expected by the auto-defined protobuf traits. It
@@ -17,6 +20,10 @@ use tonic::{Code, Status};
 /// back. For any other error, a generic Code::Internal will be sent back. Both  include the whole
 /// stringified error chain. Finally, if the Result is Ok, a tonic::Response containing the returned
 /// value will be sent back.
+///
+/// If the service enables response compression, unary responses are only compressed if the
+/// response message is wrapped in `COMPRESSED()`, which is meant for methods returning large lists.
+/// Compressing small responses costs more than it saves. Response streams are always compressed.
 #[macro_export]
 macro_rules! impl_grpc_handler {
     // Implements the function for a response stream RPC.
@@ -25,18 +32,23 @@ macro_rules! impl_grpc_handler {
         // as the response for the handler.
         type $resp_stream = RespStream<$resp_msg>;
 
-        impl_grpc_handler!(@INNER $impl_fn, $req_msg => Self::$resp_stream, $ctx_str);
+        impl_grpc_handler!(@INNER $impl_fn, $req_msg => Self::$resp_stream, $ctx_str, true);
+    };
+
+    // Implements the function for a unary RPC with a compressed response.
+    ($impl_fn:ident, $req_msg:path => COMPRESSED($resp_msg:path), $ctx_str:literal) => {
+        impl_grpc_handler!(@INNER $impl_fn, $req_msg => $resp_msg, $ctx_str, true);
     };
 
     // Implements the function for a unary RPC.
     ($impl_fn:ident, $req_msg:path => $resp_msg:path, $ctx_str:literal) => {
-        impl_grpc_handler!(@INNER $impl_fn, $req_msg => $resp_msg, $ctx_str);
+        impl_grpc_handler!(@INNER $impl_fn, $req_msg => $resp_msg, $ctx_str, false);
     };
 
     // Generates the actual function. Note that we implement the `async fn` manually to avoid having
     // to use `#[tonic::async_trait]`. This is exactly how that macro does it in the background, but
     // we can't rely on that here within this macro as attribute macros are evaluated first.
-    (@INNER $impl_fn:ident, $req_msg:path => $resp_msg:path, $ctx_str:literal) => {
+    (@INNER $impl_fn:ident, $req_msg:path => $resp_msg:path, $ctx_str:literal, $compress:literal) => {
         fn $impl_fn<'a, 'async_trait>(
             &'a self,
             req: Request<$req_msg>,
@@ -46,14 +58,35 @@ macro_rules! impl_grpc_handler {
             Self: 'async_trait,
         {
             Box::pin(async move {
+                if let Err(status) = $crate::grpc::check_allowed_method(&req, stringify!($impl_fn)) {
+                    $crate::grpc::GRPC_REQUESTS.inc(&[stringify!($impl_fn), "error"]);
+                    return Err(status);
+                }
+
                 // The self.app is misplaced here as this is supposed to be a reusable macro.
                 // It assumes what is passed to the handler function and that might be different
                 // for different users of this.
                 // I don't have a quick idea how to fix this in an elegant way, so we keep it for.
-                let res = $impl_fn::$impl_fn(&self.app, req.into_inner()).await;
+                //
+                // The remote address is made available to the handler via `request_peer()`.
+                let peer = req.remote_addr();
+                let res = $crate::grpc::REQUEST_PEER
+                    .scope(peer, $impl_fn::$impl_fn(&self.app, req.into_inner()))
+                    .await;
+
+                $crate::grpc::GRPC_REQUESTS.inc(&[
+                    stringify!($impl_fn),
+                    if res.is_ok() { "ok" } else { "error" },
+                ]);
 
                 match res {
-                    Ok(res) => Ok(Response::new(res)),
+                    Ok(res) => {
+                        let mut resp = Response::new(res);
+                        if !$compress {
+                            resp.disable_compression();
+                        }
+                        Ok(resp)
+                    }
                     Err(err) => {
                         let status = $crate::grpc::process_grpc_handler_error(err.context($ctx_str));
                         Err(status)
@@ -64,6 +97,55 @@ macro_rules! impl_grpc_handler {
     };
 }
 
+/// Counts the handled gRPC requests by method and result. Updated by `impl_grpc_handler!`.
+pub static GRPC_REQUESTS: Counter = Counter::new(
+    "beegfs_grpc_requests_total",
+    "Number of handled gRPC requests by method and result",
+    &["method", "result"],
+);
+
+/// Restricts the methods a request is allowed to call.
+///
+/// If put into the request extensions (e.g. by an interceptor), `impl_grpc_handler!` rejects the
+/// request with `Unauthenticated` unless the function returns true for the called methods name
+/// (the handler function name, e.g. `get_nodes`).
+#[derive(Debug, Clone, Copy)]
+pub struct AllowedMethods(pub fn(&str) -> bool);
+
+/// Checks that the request is allowed to call `method` if it carries an [AllowedMethods]
+/// restriction. Denied requests are logged with their source address. Called by
+/// `impl_grpc_handler!`.
+pub fn check_allowed_method<T>(req: &tonic::Request<T>, method: &str) -> Result<(), Status> {
+    if let Some(allowed) = req.extensions().get::<AllowedMethods>()
+        && !(allowed.0)(method)
+    {
+        match req.remote_addr() {
+            Some(addr) => log::warn!("Denied unauthenticated gRPC request from {addr} to {method}"),
+            None => log::warn!("Denied unauthenticated gRPC request to {method}"),
+        }
+
+        return Err(Status::unauthenticated(format!(
+            "Request to {method} requires authentication"
+        )));
+    }
+
+    Ok(())
+}
+
+tokio::task_local! {
+    /// The remote address of the gRPC request currently handled by the task. Set by
+    /// `impl_grpc_handler!`.
+    pub static REQUEST_PEER: Option<SocketAddr>;
+}
+
+/// Returns the remote address of the gRPC request currently handled by this task, if available.
+///
+/// Only works when called within the task executing the handler, not from spawned tasks or
+/// threads (e.g. within a database transaction closure).
+pub fn request_peer() -> Option<SocketAddr> {
+    REQUEST_PEER.try_with(|e| *e).ok().flatten()
+}
+
 // RESPONSE STREAM
 
 /// Wrapper around the stream channel sender
@@ -76,6 +158,12 @@ impl<Msg: Send + Sync + 'static> StreamSender<Msg> {
         self.0.send(Ok(value)).await?;
         Ok(())
     }
+
+    /// Completes when the receiving side of the stream has been dropped, e.g. because the client
+    /// disconnected
+    pub async fn closed(&self) {
+        self.0.closed().await
+    }
 }
 
 /// Convenience alias for the gRPC response stream future
@@ -142,6 +230,15 @@ where
 
 // ANYHOW CONTEXT STATUS
 
+/// Metadata key of the machine readable error reason attached to some error responses (see
+/// [AnyhowErrorStatusExt::status_code_with_reason()]). Allows clients to tell apart errors sharing
+/// the same status code without parsing the message.
+pub const ERROR_REASON_KEY: &str = "beegfs-error-reason";
+/// Error reason for requests needing a feature that is not covered by the installed license
+pub const REASON_LICENSE_DENIED: &str = "license-denied";
+/// Error reason for write requests while the management runs in read-only mode
+pub const REASON_READ_ONLY: &str = "read-only";
+
 /// A wrapper around an inner anyhow::Error, containing additional gRPC status code info. When
 /// handling an error chain, the generic `dyn Error` can be downcasted and the status code can be
 /// extracted. When logging errors, this also allows skipping this status code item as you normally
@@ -150,6 +247,7 @@ where
 #[derive(Debug)]
 pub struct AnyhowContextStatus {
     status_code: Code,
+    reason: Option<&'static str>,
     source: Option<anyhow::Error>,
 }
 
@@ -174,6 +272,13 @@ pub trait AnyhowErrorStatusExt {
     /// Attach a gRPC status code to the error chain which can be extracted by the error handler.
     /// Meant to be used for determining which error code to send back to the client.
     fn status_code(self, status_code: Code) -> Result<Self::Ok, anyhow::Error>;
+    /// Like [status_code()](Self::status_code), but additionally sends back `reason` in the
+    /// [ERROR_REASON_KEY] metadata entry. `reason` must be ASCII, e.g. `license-denied`.
+    fn status_code_with_reason(
+        self,
+        status_code: Code,
+        reason: &'static str,
+    ) -> Result<Self::Ok, anyhow::Error>;
 }
 
 impl<T, E> AnyhowErrorStatusExt for std::result::Result<T, E>
@@ -186,6 +291,21 @@ where
         self.map_err(|err| {
             anyhow::Error::new(AnyhowContextStatus {
                 status_code,
+                reason: None,
+                source: Some(err.into()),
+            })
+        })
+    }
+
+    fn status_code_with_reason(
+        self,
+        status_code: Code,
+        reason: &'static str,
+    ) -> Result<T, anyhow::Error> {
+        self.map_err(|err| {
+            anyhow::Error::new(AnyhowContextStatus {
+                status_code,
+                reason: Some(reason),
                 source: Some(err.into()),
             })
         })
@@ -195,15 +315,18 @@ where
 // UTIL
 
 /// Logs an error returned from a gRPC handler and extracts the set response status code or
-/// defaults to unknown (including the stringified error chain).
+/// defaults to unknown (including the stringified error chain). A set error reason is put into the
+/// [ERROR_REASON_KEY] metadata entry.
 pub fn process_grpc_handler_error(err: anyhow::Error) -> Status {
     let mut resp_code = Code::Unknown;
+    let mut reason = None;
     let mut err_string = String::new();
 
     let mut delim = "";
     for s in err.chain() {
         if let Some(d) = s.downcast_ref::<AnyhowContextStatus>() {
             resp_code = d.status_code;
+            reason = d.reason;
             continue;
         }
 
@@ -213,7 +336,14 @@ pub fn process_grpc_handler_error(err: anyhow::Error) -> Status {
 
     log::error!("[{resp_code:?}]: {err_string}");
 
-    Status::new(resp_code, err_string)
+    let mut status = Status::new(resp_code, err_string);
+    if let Some(reason) = reason {
+        status
+            .metadata_mut()
+            .insert(ERROR_REASON_KEY, MetadataValue::from_static(reason));
+    }
+
+    status
 }
 
 /// Unwraps an optional proto message field . If `None`, errors out providing the fields name in the
@@ -1,20 +1,51 @@
 //! Journald logger implementation for the `log` interface
+//!
+//! Besides the message itself, each entry carries structured fields that journald can index:
+//! `BEEGFS_MODULE` contains the log target and key-values attached to the record (e.g.
+//! `log::info!(node_uid = 5; "...")`) are forwarded as upper cased fields (`NODE_UID=5`). Keys
+//! that would result in a field with a special meaning to journald (e.g. `MESSAGE`) are prefixed
+//! with `BEEGFS_`.
 
+use log::kv::{Error as KvError, Key, Value, VisitSource};
 use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io;
 use std::os::unix::net::UnixDatagram;
 
+/// Receives the serialized journal entries
+trait Sink: std::fmt::Debug + Send + Sync {
+    fn send(&self, buf: &[u8]) -> io::Result<usize>;
+}
+
+impl Sink for UnixDatagram {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        UnixDatagram::send(self, buf)
+    }
+}
+
 #[derive(Debug)]
 pub struct JournaldLogger {
-    sock: UnixDatagram,
-    level_filter: LevelFilter,
+    sock: Box<dyn Sink>,
+}
+
+impl JournaldLogger {
+    /// Creates a [JournaldLogger] connected to the local journald socket
+    ///
+    /// Unlike [init()], this doesn't install the logger, so it can be wrapped by another one.
+    pub fn new() -> anyhow::Result<Self> {
+        let sock = UnixDatagram::unbound()?;
+        sock.connect("/run/systemd/journal/socket")?;
+
+        Ok(Self {
+            sock: Box::new(sock),
+        })
+    }
 }
 
 /// Initializes `log` logger with [JournaldLogger]
+///
+/// The level can be changed later using [log::set_max_level()].
 pub fn init(level_filter: LevelFilter) -> anyhow::Result<()> {
-    let sock = UnixDatagram::unbound()?;
-    sock.connect("/run/systemd/journal/socket")?;
-
-    log::set_boxed_logger(Box::new(JournaldLogger { sock, level_filter }))?;
+    log::set_boxed_logger(Box::new(JournaldLogger::new()?))?;
     log::set_max_level(level_filter);
     Ok(())
 }
@@ -22,34 +53,106 @@ pub fn init(level_filter: LevelFilter) -> anyhow::Result<()> {
 /// [Log] Interface implementation
 impl Log for JournaldLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level_filter
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let msg = format!("{}", record.args()).into_bytes();
-
-            let mut buf = format!(
-                "PRIORITY={}\nSYSLOG_IDENTIFIER=beegfs-mgmtd\nMESSAGE\n",
-                level_to_priority(record.level())
-            )
-            .into_bytes();
-
-            buf.reserve(msg.len() + 8 + 1);
-            buf.extend((msg.len() as u64).to_le_bytes());
-            buf.extend(msg);
-            buf.extend(b"\n");
-
-            // If sending the data to the socket fails, report this to stderr
-            if let Err(err) = self.sock.send(&buf) {
-                eprintln!("Sending log to systemd failed: {err}");
-            }
+        // Records exceeding log::max_level() are already filtered out by the log macros
+        let buf = serialize_record(record);
+
+        // If sending the data to the socket fails, report this to stderr
+        if let Err(err) = self.sock.send(&buf) {
+            eprintln!("Sending log to systemd failed: {err}");
         }
     }
 
     fn flush(&self) {}
 }
 
+/// Serializes a [Record] into the journald native protocol format
+fn serialize_record(record: &Record) -> Vec<u8> {
+    let mut buf = format!(
+        "PRIORITY={}\nSYSLOG_IDENTIFIER=beegfs-mgmtd\n",
+        level_to_priority(record.level())
+    )
+    .into_bytes();
+
+    append_field(&mut buf, "MESSAGE", record.args().to_string().as_bytes());
+    append_field(&mut buf, "BEEGFS_MODULE", record.target().as_bytes());
+
+    // Errors can't occur as the visitor never fails
+    let _ = record.key_values().visit(&mut FieldVisitor(&mut buf));
+
+    buf
+}
+
+/// Forwards the key-values attached to a [Record] as journald fields
+struct FieldVisitor<'a>(&'a mut Vec<u8>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        let name = field_name(key.as_str());
+        if !name.is_empty() {
+            append_field(self.0, &name, value.to_string().as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Field names that are set by the logger itself or have a special meaning to journald
+const RESERVED_FIELDS: &[&str] = &[
+    "MESSAGE",
+    "MESSAGE_ID",
+    "PRIORITY",
+    "BEEGFS_MODULE",
+    "CODE_FILE",
+    "CODE_LINE",
+    "CODE_FUNC",
+    "ERRNO",
+    "INVOCATION_ID",
+    "USER_INVOCATION_ID",
+    "DOCUMENTATION",
+    "TID",
+    "UNIT",
+    "USER_UNIT",
+];
+
+/// Converts a key into a valid journald field name.
+///
+/// Journald only accepts upper case letters, digits and underscores and the name must not start
+/// with an underscore (these are reserved for trusted fields). Names that would shadow one of the
+/// [RESERVED_FIELDS] or a `SYSLOG_` field are prefixed with `BEEGFS_`.
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .skip_while(|c| *c == '_')
+        .collect();
+
+    if RESERVED_FIELDS.contains(&name.as_str()) || name.starts_with("SYSLOG_") {
+        format!("BEEGFS_{name}")
+    } else {
+        name
+    }
+}
+
+/// Appends a field using the binary safe encoding (name, newline, little endian u64 length, value,
+/// newline), which allows values to contain newlines.
+fn append_field(buf: &mut Vec<u8>, name: &str, value: &[u8]) {
+    buf.reserve(name.len() + 1 + 8 + value.len() + 1);
+    buf.extend(name.as_bytes());
+    buf.extend(b"\n");
+    buf.extend((value.len() as u64).to_le_bytes());
+    buf.extend(value);
+    buf.extend(b"\n");
+}
+
 /// Convert [log::Level] into corresponding journald log level
 fn level_to_priority(level: Level) -> u8 {
     match level {
@@ -60,3 +163,94 @@ fn level_to_priority(level: Level) -> u8 {
         Level::Trace => 7,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default, Clone)]
+    struct MockSink(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Sink for MockSink {
+        fn send(&self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+    }
+
+    /// Parses a serialized entry back into its fields
+    fn parse_entry(mut buf: &[u8]) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+
+        while !buf.is_empty() {
+            let end = buf.iter().position(|b| *b == b'\n').unwrap();
+            let line = std::str::from_utf8(&buf[..end]).unwrap();
+            buf = &buf[end + 1..];
+
+            if let Some((name, value)) = line.split_once('=') {
+                fields.insert(name.to_string(), value.to_string());
+            } else {
+                let len = u64::from_le_bytes(buf[..8].try_into().unwrap()) as usize;
+                let value = std::str::from_utf8(&buf[8..8 + len]).unwrap();
+                fields.insert(line.to_string(), value.to_string());
+                assert_eq!(buf[8 + len], b'\n');
+                buf = &buf[8 + len + 1..];
+            }
+        }
+
+        fields
+    }
+
+    #[test]
+    fn structured_fields() {
+        let sink = MockSink::default();
+        let logger = JournaldLogger {
+            sock: Box::new(sink.clone()),
+        };
+
+        let kvs: &[(&str, u32)] = &[
+            ("node_uid", 5),
+            ("target_id", 101),
+            ("_hidden", 1),
+            ("message", 2),
+            ("priority", 3),
+        ];
+        logger.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("mgmtd::grpc::get_nodes")
+                .args(format_args!("multi\nline"))
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let entries = sink.0.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let fields = parse_entry(&entries[0]);
+        assert_eq!(fields["PRIORITY"], "4");
+        assert_eq!(fields["SYSLOG_IDENTIFIER"], "beegfs-mgmtd");
+        assert_eq!(fields["MESSAGE"], "multi\nline");
+        assert_eq!(fields["BEEGFS_MODULE"], "mgmtd::grpc::get_nodes");
+        assert_eq!(fields["NODE_UID"], "5");
+        assert_eq!(fields["TARGET_ID"], "101");
+        assert_eq!(fields["HIDDEN"], "1");
+        // Reserved fields can't be overwritten
+        assert_eq!(fields["BEEGFS_MESSAGE"], "2");
+        assert_eq!(fields["BEEGFS_PRIORITY"], "3");
+        assert_eq!(fields.len(), 9);
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(field_name("node_uid"), "NODE_UID");
+        assert_eq!(field_name("target.id"), "TARGET_ID");
+        assert_eq!(field_name("__x"), "X");
+        assert_eq!(field_name("_"), "");
+        assert_eq!(field_name("message"), "BEEGFS_MESSAGE");
+        assert_eq!(field_name("syslog_identifier"), "BEEGFS_SYSLOG_IDENTIFIER");
+        assert_eq!(field_name("beegfs_module"), "BEEGFS_BEEGFS_MODULE");
+    }
+}
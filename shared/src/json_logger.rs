@@ -0,0 +1,120 @@
+//! Formats log records as JSON objects, one per line
+//!
+//! Meant for log shipping pipelines that consume the stderr output. Each record becomes an object
+//! containing `timestamp`, `level`, `target` and `message`. Key-values attached to the record
+//! (e.g. `log::info!(node_uid = 5; "...")`) are put into a nested `fields` object, so they can't
+//! collide with the fixed keys.
+
+use log::Record;
+use log::kv::{Error as KvError, Key, Value, VisitSource};
+use serde_json::{Map, json};
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// Writes `record` as a single line JSON object to `out`
+pub fn write_record(
+    out: &mut impl Write,
+    timestamp: impl Display,
+    record: &Record,
+) -> io::Result<()> {
+    let mut obj = json!({
+        "timestamp": timestamp.to_string(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+
+    let mut fields = Map::new();
+    // Errors can't occur as the visitor never fails
+    let _ = record.key_values().visit(&mut FieldVisitor(&mut fields));
+    if !fields.is_empty() {
+        obj["fields"] = fields.into();
+    }
+
+    serde_json::to_writer(&mut *out, &obj)?;
+    writeln!(out)
+}
+
+/// Collects the key-values attached to a [Record], keeping numbers and booleans as such
+struct FieldVisitor<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        let value = if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_i64() {
+            v.into()
+        } else if let Some(v) = value.to_bool() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn json_record() {
+        let mut out = vec![];
+
+        let kvs: &[(&str, &dyn log::kv::ToValue)] = &[
+            ("node_uid", &5u32),
+            ("offset", &-3i32),
+            ("alias", &"meta_1"),
+        ];
+        write_record(
+            &mut out,
+            "2024-01-01T00:00:00.000Z",
+            &Record::builder()
+                .level(Level::Warn)
+                .target("mgmtd::grpc::get_nodes")
+                .args(format_args!("multi\n\"line\""))
+                .key_values(&kvs)
+                .build(),
+        )
+        .unwrap();
+
+        write_record(
+            &mut out,
+            "2024-01-01T00:00:01.000Z",
+            &Record::builder()
+                .level(Level::Info)
+                .target("mgmtd")
+                .args(format_args!("plain"))
+                .build(),
+        )
+        .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let rec: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            rec,
+            json!({
+                "timestamp": "2024-01-01T00:00:00.000Z",
+                "level": "WARN",
+                "target": "mgmtd::grpc::get_nodes",
+                "message": "multi\n\"line\"",
+                "fields": {
+                    "node_uid": 5,
+                    "offset": -3,
+                    "alias": "meta_1",
+                },
+            })
+        );
+
+        let rec: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(rec["level"], "INFO");
+        assert_eq!(rec["message"], "plain");
+        assert!(rec.get("fields").is_none());
+    }
+}
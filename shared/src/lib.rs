@@ -9,11 +9,16 @@ mod impl_macros;
 
 pub mod bee_msg;
 pub mod bee_serde;
+pub mod compact_records;
 pub mod conn;
 #[cfg(feature = "grpc")]
 pub mod grpc;
 pub mod journald_logger;
+pub mod json_logger;
+pub mod log_ring;
+pub mod metrics;
 pub mod nic;
 pub mod parser;
+pub mod quota_export;
 pub mod run_state;
 pub mod types;
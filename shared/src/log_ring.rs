@@ -0,0 +1,177 @@
+//! Keeps the most recent log records in memory
+//!
+//! Meant for a quick remote look at the logs without requiring access to the host (e.g. the
+//! journal). [RingLogger] wraps the actual logger and additionally records each log record into a
+//! [LogRing], which keeps a fixed number of records and drops the oldest ones on overflow.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// A recorded log entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// A bounded buffer of the most recent log records
+///
+/// Disabled (capacity 0) after construction, use [LogRing::configure()] to enable it. Meant to be
+/// defined as a static.
+#[derive(Debug)]
+pub struct LogRing {
+    capacity: AtomicUsize,
+    level: AtomicUsize,
+    entries: Mutex<VecDeque<LogEntry>>,
+}
+
+impl Default for LogRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogRing {
+    pub const fn new() -> Self {
+        Self {
+            capacity: AtomicUsize::new(0),
+            level: AtomicUsize::new(LevelFilter::Off as usize),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Sets the number of records to keep and the maximum level of records to record.
+    ///
+    /// Records exceeding the new capacity are dropped, oldest first. A capacity of 0 disables
+    /// recording.
+    pub fn configure(&self, capacity: usize, level: LevelFilter) {
+        let mut entries = self.entries.lock().unwrap();
+
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.level.store(level as usize, Ordering::Relaxed);
+
+        let excess = entries.len().saturating_sub(capacity);
+        entries.drain(..excess);
+    }
+
+    /// Whether records with the given level are recorded
+    pub fn enabled(&self, level: Level) -> bool {
+        self.capacity.load(Ordering::Relaxed) > 0
+            && level as usize <= self.level.load(Ordering::Relaxed)
+    }
+
+    /// Records a log record, dropping the oldest one if the ring is full.
+    ///
+    /// The message is formatted before taking the lock, so it is only held for the actual
+    /// insertion.
+    pub fn push(&self, record: &Record) {
+        if !self.enabled(record.level()) {
+            return;
+        }
+
+        let entry = LogEntry {
+            time: SystemTime::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        while !entries.is_empty() && entries.len() >= capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns a copy of the currently kept records, oldest first
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Forwards all log records to the inner logger and additionally records them into a [LogRing]
+///
+/// Note that records exceeding [log::max_level()] are filtered out before they reach any logger,
+/// so they are also never recorded.
+pub struct RingLogger {
+    inner: Box<dyn Log>,
+    ring: &'static LogRing,
+}
+
+impl RingLogger {
+    pub fn new(inner: Box<dyn Log>, ring: &'static LogRing) -> Self {
+        Self { inner, ring }
+    }
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || self.ring.enabled(metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        self.ring.push(record);
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push(ring: &LogRing, level: Level, msg: &str) {
+        ring.push(
+            &Record::builder()
+                .level(level)
+                .target("test")
+                .args(format_args!("{msg}"))
+                .build(),
+        );
+    }
+
+    fn messages(ring: &LogRing) -> Vec<String> {
+        ring.entries().into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn keeps_most_recent() {
+        let ring = LogRing::new();
+
+        // Disabled by default
+        push(&ring, Level::Error, "disabled");
+        assert!(ring.entries().is_empty());
+
+        ring.configure(3, LevelFilter::Info);
+
+        for i in 0..10 {
+            push(&ring, Level::Info, &format!("msg {i}"));
+        }
+        assert_eq!(messages(&ring), ["msg 7", "msg 8", "msg 9"]);
+
+        // Records above the configured level are ignored
+        push(&ring, Level::Debug, "debug");
+        assert_eq!(messages(&ring), ["msg 7", "msg 8", "msg 9"]);
+
+        push(&ring, Level::Error, "error");
+        assert_eq!(messages(&ring), ["msg 8", "msg 9", "error"]);
+
+        let entry = ring.entries().pop().unwrap();
+        assert_eq!(entry.level, Level::Error);
+        assert_eq!(entry.target, "test");
+
+        // Shrinking drops the oldest records
+        ring.configure(1, LevelFilter::Info);
+        assert_eq!(messages(&ring), ["error"]);
+    }
+}
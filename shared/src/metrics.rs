@@ -0,0 +1,365 @@
+//! Minimal in-process metrics collection with export in the Prometheus text exposition format
+//!
+//! Metrics are meant to be defined as statics (all constructors are `const`) and updated from
+//! anywhere in the code. On scrape, the metrics to be exported are passed to [encode()], which
+//! renders them into the text format understood by Prometheus and OpenMetrics compatible scrapers.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Default histogram buckets for durations in seconds
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0,
+];
+
+/// A metric that can be rendered in the Prometheus text format
+pub trait Metric: Sync {
+    /// Appends the metrics `# HELP` and `# TYPE` lines and all its samples to `out`
+    fn encode(&self, out: &mut String);
+}
+
+/// Renders the given metrics in the Prometheus text format
+pub fn encode(metrics: &[&dyn Metric]) -> String {
+    let mut out = String::new();
+    for m in metrics {
+        m.encode(&mut out);
+    }
+    out
+}
+
+/// A monotonically increasing counter, partitioned by a fixed set of labels
+///
+/// By convention, the name should end with `_total`.
+#[derive(Debug)]
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Increments the counter identified by `label_values` by one.
+    ///
+    /// `label_values` must match the labels given on construction in length and order.
+    pub fn inc(&self, label_values: &[&str]) {
+        self.inc_by(label_values, 1);
+    }
+
+    /// Increments the counter identified by `label_values` by `n`.
+    pub fn inc_by(&self, label_values: &[&str], n: u64) {
+        debug_assert_eq!(label_values.len(), self.labels.len());
+
+        let mut values = self.values.lock().unwrap();
+        *values.entry(to_key(label_values)).or_default() += n;
+    }
+
+    /// Returns the current value of the counter identified by `label_values`
+    pub fn get(&self, label_values: &[&str]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&to_key(label_values))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Metric for Counter {
+    fn encode(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "counter");
+        for (label_values, v) in self.values.lock().unwrap().iter() {
+            write_sample(out, self.name, "", self.labels, label_values, None, *v);
+        }
+    }
+}
+
+/// A value that can go up and down, partitioned by a fixed set of labels
+#[derive(Debug)]
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Gauge {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Sets the gauge identified by `label_values` to `v`.
+    ///
+    /// `label_values` must match the labels given on construction in length and order.
+    pub fn set(&self, label_values: &[&str], v: f64) {
+        debug_assert_eq!(label_values.len(), self.labels.len());
+
+        self.values.lock().unwrap().insert(to_key(label_values), v);
+    }
+}
+
+impl Metric for Gauge {
+    fn encode(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "gauge");
+        for (label_values, v) in self.values.lock().unwrap().iter() {
+            write_sample(out, self.name, "", self.labels, label_values, None, v);
+        }
+    }
+}
+
+/// Tracks the distribution of observed values in cumulative buckets, partitioned by a fixed set
+/// of labels
+#[derive(Debug)]
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValues>>,
+}
+
+#[derive(Debug, Default)]
+struct HistogramValues {
+    /// Non-cumulative counts per bucket. Summed up on encoding.
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Creates a new histogram. `buckets` must be sorted ascending and must not contain `+Inf`,
+    /// which is always added implicitly.
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            buckets,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records `v` for the histogram identified by `label_values`.
+    ///
+    /// `label_values` must match the labels given on construction in length and order.
+    pub fn observe(&self, label_values: &[&str], v: f64) {
+        debug_assert_eq!(label_values.len(), self.labels.len());
+
+        let mut values = self.values.lock().unwrap();
+        let entry = values.entry(to_key(label_values)).or_default();
+
+        if entry.bucket_counts.is_empty() {
+            entry.bucket_counts = vec![0; self.buckets.len()];
+        }
+        if let Some(i) = self.buckets.iter().position(|b| v <= *b) {
+            entry.bucket_counts[i] += 1;
+        }
+
+        entry.sum += v;
+        entry.count += 1;
+    }
+
+    /// Records a duration in seconds for the histogram identified by `label_values`.
+    pub fn observe_duration(&self, label_values: &[&str], d: Duration) {
+        self.observe(label_values, d.as_secs_f64());
+    }
+
+    /// Returns the number of observations for the histogram identified by `label_values`
+    pub fn count(&self, label_values: &[&str]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&to_key(label_values))
+            .map(|e| e.count)
+            .unwrap_or_default()
+    }
+}
+
+impl Metric for Histogram {
+    fn encode(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "histogram");
+        for (label_values, v) in self.values.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(v.bucket_counts.iter()) {
+                cumulative += count;
+                let le = bound.to_string();
+                write_sample(
+                    out,
+                    self.name,
+                    "_bucket",
+                    self.labels,
+                    label_values,
+                    Some(&le),
+                    cumulative,
+                );
+            }
+
+            write_sample(
+                out,
+                self.name,
+                "_bucket",
+                self.labels,
+                label_values,
+                Some("+Inf"),
+                v.count,
+            );
+            write_sample(
+                out,
+                self.name,
+                "_sum",
+                self.labels,
+                label_values,
+                None,
+                v.sum,
+            );
+            write_sample(
+                out,
+                self.name,
+                "_count",
+                self.labels,
+                label_values,
+                None,
+                v.count,
+            );
+        }
+    }
+}
+
+fn to_key(label_values: &[&str]) -> Vec<String> {
+    label_values.iter().map(|e| e.to_string()).collect()
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, typ: &str) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {}",
+        help.replace('\\', "\\\\").replace('\n', "\\n")
+    );
+    let _ = writeln!(out, "# TYPE {name} {typ}");
+}
+
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[&str],
+    label_values: &[String],
+    le: Option<&str>,
+    value: impl std::fmt::Display,
+) {
+    out.push_str(name);
+    out.push_str(suffix);
+
+    let mut pairs = labels
+        .iter()
+        .zip(label_values.iter().map(String::as_str))
+        .map(|(k, v)| (*k, v))
+        .chain(le.map(|le| ("le", le)))
+        .peekable();
+
+    if pairs.peek().is_some() {
+        out.push('{');
+        for (i, (k, v)) in pairs.enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{k}=\"{}\"", escape_label_value(v));
+        }
+        out.push('}');
+    }
+
+    let _ = writeln!(out, " {value}");
+}
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_counter() {
+        let c = Counter::new(
+            "requests_total",
+            "Number of requests",
+            &["method", "result"],
+        );
+        c.inc(&["get", "ok"]);
+        c.inc(&["get", "ok"]);
+        c.inc_by(&["set \"x\"", "error"], 3);
+
+        assert_eq!(c.get(&["get", "ok"]), 2);
+        assert_eq!(
+            encode(&[&c]),
+            "# HELP requests_total Number of requests\n\
+             # TYPE requests_total counter\n\
+             requests_total{method=\"get\",result=\"ok\"} 2\n\
+             requests_total{method=\"set \\\"x\\\"\",result=\"error\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn encode_gauge() {
+        let g = Gauge::new("streams", "Open streams", &[]);
+        g.set(&[], 5.0);
+        g.set(&[], 4.5);
+
+        assert_eq!(
+            encode(&[&g]),
+            "# HELP streams Open streams\n# TYPE streams gauge\nstreams 4.5\n"
+        );
+    }
+
+    #[test]
+    fn encode_histogram() {
+        let h = Histogram::new("duration", "Duration", &["op"], &[0.1, 1.0]);
+        h.observe(&["read"], 0.25);
+        h.observe(&["read"], 0.5);
+        h.observe(&["read"], 2.0);
+
+        assert_eq!(h.count(&["read"]), 3);
+        assert_eq!(
+            encode(&[&h]),
+            "# HELP duration Duration\n\
+             # TYPE duration histogram\n\
+             duration_bucket{op=\"read\",le=\"0.1\"} 0\n\
+             duration_bucket{op=\"read\",le=\"1\"} 2\n\
+             duration_bucket{op=\"read\",le=\"+Inf\"} 3\n\
+             duration_sum{op=\"read\"} 2.75\n\
+             duration_count{op=\"read\"} 3\n"
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use crate::types::NicType;
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use serde::Deserializer;
 use serde::de::{Unexpected, Visitor};
-use std::net::{IpAddr, Ipv6Addr};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
 use std::str::FromStr;
 
 /// Network protocol
@@ -31,20 +33,34 @@ pub struct NicFilter {
     pub invert: bool,
     pub name: Option<String>,
     pub address: Option<IpAddr>,
+    /// If set, `address` is the network address of a subnet in CIDR notation and all addresses
+    /// within it match. Otherwise, only `address` itself matches.
+    pub prefix_len: Option<u8>,
     pub protocol: Option<Protocol>,
     pub nic_type: Option<NicType>,
+    /// If set, the priority of matching nics instead of the entries position in the filter list.
+    /// Lower means higher preference.
+    pub weight: Option<usize>,
 }
 
 impl NicFilter {
-    const EXPECT_STR: &str =
-        "a nic filter in the form \"[!] [<name>|*] [<addr>|*] [4|6|*] [tcp|rdma|*]\"";
+    const EXPECT_STR: &str = "a nic filter in the form \
+        \"[!] [<name>|*] [<addr>[/<prefix_len>]|*] [4|6|*] [tcp|rdma|*] [@<weight>]\"";
 
-    /// Parses a string in the form `[!] [name] [addr] [protocol] [type]` into a [NicFilter]
+    /// Parses a string in the form `[!] [name] [addr] [protocol] [type] [@weight]` into a
+    /// [NicFilter]
     #[rustfmt::skip] // opt out because if let chaings are misformatted
     pub fn parse_optional(input: &str) -> Option<Self> {
-        let mut split = input.split_whitespace().peekable();
+        let mut fields: Vec<_> = input.split_whitespace().collect();
         let mut res = Self::default();
 
+        if let Some(weight) = fields.last().and_then(|e| e.strip_prefix('@')) {
+            res.weight = Some(weight.parse().ok()?);
+            fields.pop();
+        }
+
+        let mut split = fields.into_iter().peekable();
+
         if let Some(field) = split.peek()
             && *field == "!" {
                 res.invert = true;
@@ -56,7 +72,19 @@ impl NicFilter {
         }
 
         if let Some(field) = split.next() && field != "*" {
-            res.address = Some(field.parse().ok()?);
+            if let Some((addr, prefix_len)) = field.split_once('/') {
+                let addr: IpAddr = addr.parse().ok()?;
+                let prefix_len: u8 = prefix_len.parse().ok()?;
+                let max_len = if addr.is_ipv4() { 32 } else { 128 };
+                if prefix_len > max_len {
+                    return None;
+                }
+
+                res.address = Some(addr);
+                res.prefix_len = Some(prefix_len);
+            } else {
+                res.address = Some(field.parse().ok()?);
+            }
         }
 
         if let Some(field) = split.next() && field != "*" {
@@ -67,13 +95,43 @@ impl NicFilter {
             res.nic_type = Some(field.parse().ok()?);
         }
 
+        // Blacklisted nics are not reported, so a weight would have no meaning
+        if res.invert && res.weight.is_some() {
+            return None;
+        }
+
         Some(res)
     }
 
-    /// Parses a string in the form `[!] [name] [addr] [protocol] [type]` into a [NicFilter]
+    /// Parses a string in the form `[!] [name] [addr] [protocol] [type] [@weight]` into a
+    /// [NicFilter]
     pub fn parse(input: &str) -> Result<Self> {
         Self::parse_optional(input).ok_or_else(|| anyhow!(Self::EXPECT_STR))
     }
+
+    /// Checks whether `ip` matches the address field - exactly for a plain address, by subnet
+    /// containment if a prefix length is set. Always matches if no address is set.
+    fn matches_address(&self, ip: &IpAddr) -> bool {
+        let Some(address) = self.address else {
+            return true;
+        };
+
+        let Some(prefix_len) = self.prefix_len else {
+            return address == *ip;
+        };
+
+        match (address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -106,9 +164,10 @@ impl<'de> serde::Deserialize<'de> for NicFilter {
 
 // NIC FILTERING AND QUERYING
 
-/// Returns a priority for a given nic info based on the filter list. Returns `None` if there is
-/// no match or the nic is matched on a `!` entry.
-fn nic_priority(filter: &[NicFilter], name: &str, ip: &IpAddr) -> Option<usize> {
+/// Returns a priority for a given nic info based on the filter list. The priority is the weight of
+/// the first matching entry if it has one, its index otherwise. Returns `None` if there is no match
+/// or the nic is matched on a `!` entry.
+fn nic_priority(filter: &[NicFilter], name: &str, ip: &IpAddr, nic_type: NicType) -> Option<usize> {
     // Always ignore link local addresses
     if match ip {
         IpAddr::V4(a) => a.is_link_local(),
@@ -125,7 +184,7 @@ fn nic_priority(filter: &[NicFilter], name: &str, ip: &IpAddr) -> Option<usize>
         if fil.name.as_ref().is_some_and(|e| e != name) {
             continue;
         }
-        if fil.address.as_ref().is_some_and(|e| e != ip) {
+        if !fil.matches_address(ip) {
             continue;
         }
         if fil.protocol.as_ref().is_some_and(|e| match e {
@@ -134,18 +193,14 @@ fn nic_priority(filter: &[NicFilter], name: &str, ip: &IpAddr) -> Option<usize>
         }) {
             continue;
         }
-        // We don't detect rdma interfaces yet
-        if fil.nic_type.as_ref().is_some_and(|e| match e {
-            NicType::Tcp => false,
-            NicType::Rdma => true,
-        }) {
+        if fil.nic_type.is_some_and(|e| e != nic_type) {
             continue;
         }
 
         if fil.invert {
             return None;
         } else {
-            return Some(i);
+            return Some(fil.weight.unwrap_or(i));
         }
     }
 
@@ -163,6 +218,15 @@ pub struct Nic {
     interface_index: u32,
     addr_index: usize,
 }
+
+impl Nic {
+    /// The weight or, if it has none, the index of the `interfaces` filter entry this nic matched.
+    /// 0 if the filter is empty.
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+}
+
 impl PartialOrd for Nic {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -184,24 +248,92 @@ impl Ord for Nic {
     }
 }
 
+/// The sysfs directory containing the RDMA devices
+const RDMA_SYSFS_PATH: &str = "/sys/class/infiniband";
+
+/// A local network interface and its addresses as reported by the operating system
+#[derive(Debug, Clone)]
+struct Interface {
+    name: String,
+    index: u32,
+    addrs: Vec<IpAddr>,
+}
+
 /// Retrieve the systems available network interfaces with their addresses
 ///
 /// Only interfaces matching one of the given names in `filter` will be returned, unless the list
-/// is empty.
-pub fn query_nics(filter: &[NicFilter], use_ipv6: bool) -> Result<Vec<Nic>> {
+/// is empty. Interfaces belonging to an RDMA device are reported as [NicType::Rdma]. If the RDMA
+/// detection fails, all interfaces are reported as [NicType::Tcp].
+///
+/// If `strict_order` is set and `filter` is not empty, the nics are ordered by the filter entry
+/// they matched only, skipping the loopback, protocol and type based ordering.
+///
+/// If `nic_type_override` is set, all interfaces are reported with that type instead of the
+/// detected one. The type filter entries are matched against the overridden type.
+pub fn query_nics(
+    filter: &[NicFilter],
+    use_ipv6: bool,
+    strict_order: bool,
+    nic_type_override: Option<NicType>,
+) -> Result<Vec<Nic>> {
+    let rdma_interfaces = if nic_type_override.is_some() {
+        HashSet::new()
+    } else {
+        rdma_interfaces(Path::new(RDMA_SYSFS_PATH)).unwrap_or_else(|err| {
+            log::debug!(
+                "Could not detect RDMA interfaces, treating all interfaces as TCP: {err:#}"
+            );
+            HashSet::new()
+        })
+    };
+
+    let interfaces = pnet_datalink::interfaces().into_iter().map(|e| Interface {
+        name: e.name,
+        index: e.index,
+        addrs: e.ips.iter().map(|ip| ip.ip()).collect(),
+    });
+
+    Ok(filter_nics(
+        filter,
+        use_ipv6,
+        strict_order,
+        interfaces,
+        &rdma_interfaces,
+        nic_type_override,
+    ))
+}
+
+/// Filters and sorts the given interfaces addresses, tagging the ones whose interface name is
+/// contained in `rdma_interfaces` as RDMA capable, unless `nic_type_override` is set.
+fn filter_nics(
+    filter: &[NicFilter],
+    use_ipv6: bool,
+    strict_order: bool,
+    interfaces: impl IntoIterator<Item = Interface>,
+    rdma_interfaces: &HashSet<String>,
+    nic_type_override: Option<NicType>,
+) -> Vec<Nic> {
     let mut filtered_nics = vec![];
 
-    for interface in pnet_datalink::interfaces() {
-        for (addr_index, ip) in interface.ips.iter().enumerate() {
+    for interface in interfaces {
+        let nic_type = if let Some(nic_type) = nic_type_override {
+            nic_type
+        } else if rdma_interfaces.contains(&interface.name) {
+            NicType::Rdma
+        } else {
+            NicType::Tcp
+        };
+
+        for (addr_index, ip) in interface.addrs.iter().enumerate() {
             if !use_ipv6 && ip.is_ipv6() {
                 continue;
             }
 
-            if let Some(priority) = nic_priority(filter, &interface.name, &ip.ip()) {
+            if let Some(priority) = nic_priority(filter, &interface.name, ip, nic_type) {
                 filtered_nics.push(Nic {
                     name: interface.name.clone(),
-                    address: ip.ip(),
-                    nic_type: NicType::Tcp,
+                    address: *ip,
+                    nic_type,
                     priority,
                     interface_index: interface.index,
                     addr_index,
@@ -210,9 +342,120 @@ pub fn query_nics(filter: &[NicFilter], use_ipv6: bool) -> Result<Vec<Nic>> {
         }
     }
 
-    filtered_nics.sort();
+    if strict_order && !filter.is_empty() {
+        // Keep the order of the filter entries, within one entry the order reported by the OS
+        filtered_nics.sort_by_key(|e| (e.priority, e.interface_index, e.addr_index));
+    } else {
+        filtered_nics.sort();
+    }
 
-    Ok(filtered_nics)
+    filtered_nics
+}
+
+/// Checks that all interface names used in `filter` belong to an existing interface.
+///
+/// A typo in an interface name would otherwise silently result in an unexpected nic list.
+///
+/// # Return value
+/// Returns an error listing the unmatched names and the existing interfaces if at least one name
+/// doesn't match.
+pub fn check_filter_names(filter: &[NicFilter]) -> Result<()> {
+    let names: Vec<_> = pnet_datalink::interfaces()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+
+    check_filter_names_against(filter, &names)
+}
+
+fn check_filter_names_against(filter: &[NicFilter], names: &[String]) -> Result<()> {
+    let unmatched: Vec<_> = filter
+        .iter()
+        .filter_map(|e| e.name.as_deref())
+        .filter(|e| !names.iter().any(|n| n == e))
+        .collect();
+
+    if !unmatched.is_empty() {
+        bail!(
+            "Interface filter contains names that don't match any existing interface: \
+{unmatched:?}. Existing interfaces: {names:?}"
+        );
+    }
+
+    Ok(())
+}
+
+/// Collects the names of the network interfaces belonging to an RDMA device.
+///
+/// Each RDMA device in `sysfs_path` (usually `/sys/class/infiniband`) links to its underlying
+/// device, which lists the associated network interfaces in its `net` subdirectory. Devices
+/// without a network interface are skipped.
+fn rdma_interfaces(sysfs_path: &Path) -> Result<HashSet<String>> {
+    let mut res = HashSet::new();
+
+    for dev in std::fs::read_dir(sysfs_path)? {
+        let dev = dev?.path();
+
+        // A device without an active port can't be used for RDMA (e.g. no link or no subnet
+        // manager), so its interfaces are treated as TCP
+        if !rdma_port_active(&dev) {
+            continue;
+        }
+
+        let Ok(netdevs) = std::fs::read_dir(dev.join("device/net")) else {
+            continue;
+        };
+
+        for netdev in netdevs {
+            res.insert(netdev?.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(res)
+}
+
+/// Checks whether the RDMA device at `dev` has at least one port in the ACTIVE state.
+///
+/// The port state file contains e.g. `4: ACTIVE` or `1: DOWN`.
+fn rdma_port_active(dev: &Path) -> bool {
+    let Ok(ports) = std::fs::read_dir(dev.join("ports")) else {
+        return false;
+    };
+
+    ports.flatten().any(|port| {
+        std::fs::read_to_string(port.path().join("state"))
+            .is_ok_and(|state| state.trim().ends_with("ACTIVE"))
+    })
+}
+
+/// Selects the address to bind a listening socket to.
+///
+/// Uses `bind_addr` if given. Otherwise binds to all addresses, using a dual stack IPv6 socket if
+/// `use_ipv6` is set (see [check_ipv6()]) and an IPv4 socket if not.
+pub fn select_bind_addr(bind_addr: Option<IpAddr>, use_ipv6: bool, port: u16) -> SocketAddr {
+    let ip = match bind_addr {
+        Some(ip) => ip,
+        None if use_ipv6 => Ipv6Addr::UNSPECIFIED.into(),
+        None => Ipv4Addr::UNSPECIFIED.into(),
+    };
+
+    SocketAddr::new(ip, port)
+}
+
+/// Removes the nics that can't be reached when listening on `bind_addr`.
+///
+/// If `bind_addr` is a specific address, only the nics with that address are kept. If it is the
+/// unspecified IPv4 address, only IPv4 nics are kept. The unspecified IPv6 address (dual stack) and
+/// `None` keep all nics.
+pub fn restrict_to_bind_addr(nics: Vec<Nic>, bind_addr: Option<IpAddr>) -> Vec<Nic> {
+    match bind_addr {
+        None => nics,
+        Some(IpAddr::V6(addr)) if addr.is_unspecified() => nics,
+        Some(IpAddr::V4(addr)) if addr.is_unspecified() => {
+            nics.into_iter().filter(|e| e.address.is_ipv4()).collect()
+        }
+        Some(addr) => nics.into_iter().filter(|e| e.address == addr).collect(),
+    }
 }
 
 /// Checks if IPv6 sockets are available on this host
@@ -286,14 +529,64 @@ pub fn check_ipv6(port: u16, use_ipv6: bool) -> bool {
 mod test {
     use super::*;
 
+    #[test]
+    fn select_bind_addr() {
+        assert_eq!(
+            super::select_bind_addr(None, true, 8008),
+            "[::]:8008".parse().unwrap()
+        );
+        assert_eq!(
+            super::select_bind_addr(None, false, 8008),
+            "0.0.0.0:8008".parse().unwrap()
+        );
+
+        // Only the given address accepts connections
+        let addr = super::select_bind_addr(Some(Ipv4Addr::LOCALHOST.into()), true, 0);
+        let listener = std::net::TcpListener::bind(addr).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let err = std::net::TcpStream::connect((Ipv4Addr::new(127, 0, 0, 2), port)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn restrict_to_bind_addr() {
+        let nic = |addr: &str| Nic {
+            address: addr.parse().unwrap(),
+            nic_type: NicType::Tcp,
+            name: "eth0".into(),
+            priority: 0,
+            interface_index: 0,
+            addr_index: 0,
+        };
+        let nics = vec![nic("10.0.0.1"), nic("10.0.0.2"), nic("fd00::1")];
+
+        let addrs = |bind_addr: Option<&str>| -> Vec<String> {
+            super::restrict_to_bind_addr(nics.clone(), bind_addr.map(|a| a.parse().unwrap()))
+                .iter()
+                .map(|e| e.address.to_string())
+                .collect()
+        };
+
+        assert_eq!(addrs(None), ["10.0.0.1", "10.0.0.2", "fd00::1"]);
+        assert_eq!(addrs(Some("::")), ["10.0.0.1", "10.0.0.2", "fd00::1"]);
+        assert_eq!(addrs(Some("0.0.0.0")), ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(addrs(Some("10.0.0.2")), ["10.0.0.2"]);
+        assert_eq!(addrs(Some("fd00::1")), ["fd00::1"]);
+        assert!(addrs(Some("10.0.0.3")).is_empty());
+    }
+
     #[test]
     fn parse_nic_filter() {
         let any = NicFilter {
             invert: false,
             name: None,
             address: None,
+            prefix_len: None,
             protocol: None,
             nic_type: None,
+            weight: None,
         };
 
         assert_eq!(NicFilter::parse_optional("").unwrap(), any);
@@ -371,6 +664,60 @@ mod test {
                 ..Default::default()
             }
         );
+
+        // Subnets
+        assert_eq!(
+            NicFilter::parse_optional("* 192.168.0.0/24").unwrap(),
+            NicFilter {
+                address: Some("192.168.0.0".parse().unwrap()),
+                prefix_len: Some(24),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            NicFilter::parse_optional("! * fd00::/8 6").unwrap(),
+            NicFilter {
+                invert: true,
+                address: Some("fd00::".parse().unwrap()),
+                prefix_len: Some(8),
+                protocol: Some(Protocol::IPv6),
+                ..Default::default()
+            }
+        );
+        assert!(NicFilter::parse_optional("* 192.168.0.0/33").is_none());
+        assert!(NicFilter::parse_optional("* fd00::/129").is_none());
+        assert!(NicFilter::parse_optional("* 192.168.0.0/").is_none());
+        assert!(NicFilter::parse_optional("* 192.168.0.0/x").is_none());
+
+        // Weights
+        assert_eq!(
+            NicFilter::parse_optional("* * * rdma @100").unwrap(),
+            NicFilter {
+                nic_type: Some(NicType::Rdma),
+                weight: Some(100),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            NicFilter::parse_optional("* 10.0.0.0/8 @50").unwrap(),
+            NicFilter {
+                address: Some("10.0.0.0".parse().unwrap()),
+                prefix_len: Some(8),
+                weight: Some(50),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            NicFilter::parse_optional("@0").unwrap(),
+            NicFilter {
+                weight: Some(0),
+                ..Default::default()
+            }
+        );
+        assert!(NicFilter::parse_optional("eth0 @").is_none());
+        assert!(NicFilter::parse_optional("eth0 @-1").is_none());
+        assert!(NicFilter::parse_optional("eth0 @x").is_none());
+        assert!(NicFilter::parse_optional("! eth0 @5").is_none());
     }
 
     #[test]
@@ -382,15 +729,30 @@ mod test {
             NicFilter::parse("* * 4").unwrap(),
         ];
         assert_eq!(
-            nic_priority(f_prefer_ipv6, "eth0", &"127.0.0.1".parse().unwrap()),
+            nic_priority(
+                f_prefer_ipv6,
+                "eth0",
+                &"127.0.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(1)
         );
         assert_eq!(
-            nic_priority(f_prefer_ipv6, "eth0", &"192.168.0.1".parse().unwrap()),
+            nic_priority(
+                f_prefer_ipv6,
+                "eth0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(1)
         );
         assert_eq!(
-            nic_priority(f_prefer_ipv6, "eth0", &"fd00::1".parse().unwrap()),
+            nic_priority(
+                f_prefer_ipv6,
+                "eth0",
+                &"fd00::1".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(0)
         );
 
@@ -401,23 +763,48 @@ mod test {
             NicFilter::parse("eth2").unwrap(),
         ];
         assert_eq!(
-            nic_priority(f_prefer_addr, "eth0", &"192.168.0.2".parse().unwrap()),
+            nic_priority(
+                f_prefer_addr,
+                "eth0",
+                &"192.168.0.2".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(2)
         );
         assert_eq!(
-            nic_priority(f_prefer_addr, "eth0", &"192.168.0.1".parse().unwrap()),
+            nic_priority(
+                f_prefer_addr,
+                "eth0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(1)
         );
         assert_eq!(
-            nic_priority(f_prefer_addr, "eth0", &"fd00::1".parse().unwrap()),
+            nic_priority(
+                f_prefer_addr,
+                "eth0",
+                &"fd00::1".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(0)
         );
         assert_eq!(
-            nic_priority(f_prefer_addr, "eth1", &"192.168.0.1".parse().unwrap()),
+            nic_priority(
+                f_prefer_addr,
+                "eth1",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
             None
         );
         assert_eq!(
-            nic_priority(f_prefer_addr, "eth2", &"fd00::123".parse().unwrap()),
+            nic_priority(
+                f_prefer_addr,
+                "eth2",
+                &"fd00::123".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(3)
         );
 
@@ -429,31 +816,422 @@ mod test {
             NicFilter::parse("*").unwrap(),
         ];
         assert_eq!(
-            nic_priority(f_invert, "eth0", &"192.168.0.2".parse().unwrap()),
+            nic_priority(
+                f_invert,
+                "eth0",
+                &"192.168.0.2".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(4)
         );
         assert_eq!(
-            nic_priority(f_invert, "eth1", &"192.168.0.2".parse().unwrap()),
+            nic_priority(
+                f_invert,
+                "eth1",
+                &"192.168.0.2".parse().unwrap(),
+                NicType::Tcp
+            ),
             None
         );
         assert_eq!(
-            nic_priority(f_invert, "eth1", &"fd00::1".parse().unwrap()),
+            nic_priority(f_invert, "eth1", &"fd00::1".parse().unwrap(), NicType::Tcp),
             Some(3)
         );
         assert_eq!(
-            nic_priority(f_invert, "eth2", &"192.168.0.2".parse().unwrap()),
+            nic_priority(
+                f_invert,
+                "eth2",
+                &"192.168.0.2".parse().unwrap(),
+                NicType::Tcp
+            ),
             Some(4)
         );
         assert_eq!(
-            nic_priority(f_invert, "eth2", &"fd00::1".parse().unwrap()),
+            nic_priority(f_invert, "eth2", &"fd00::1".parse().unwrap(), NicType::Tcp),
+            None
+        );
+        assert_eq!(
+            nic_priority(f_invert, "lo", &"fd00::1".parse().unwrap(), NicType::Tcp),
+            None
+        );
+    }
+
+    #[test]
+    fn match_nic_filter_subnet() {
+        let f_subnet = &[NicFilter::parse("* 192.168.0.0/24").unwrap()];
+        for (ip, expected) in [
+            ("192.168.0.0", Some(0)),
+            ("192.168.0.42", Some(0)),
+            ("192.168.0.255", Some(0)),
+            ("192.168.1.1", None),
+            ("10.0.0.1", None),
+            ("fd00::1", None),
+        ] {
+            assert_eq!(
+                nic_priority(f_subnet, "eth0", &ip.parse().unwrap(), NicType::Tcp),
+                expected,
+                "{ip}"
+            );
+        }
+
+        // Inverted
+        let f_deny_subnet = &[
+            NicFilter::parse("! * 10.0.0.0/8").unwrap(),
+            NicFilter::parse("* fd00::/16").unwrap(),
+            NicFilter::parse("*").unwrap(),
+        ];
+        for (ip, expected) in [
+            ("10.1.2.3", None),
+            ("11.0.0.1", Some(2)),
+            ("fd00::1", Some(1)),
+            ("fd01::1", Some(2)),
+        ] {
+            assert_eq!(
+                nic_priority(f_deny_subnet, "eth0", &ip.parse().unwrap(), NicType::Tcp),
+                expected,
+                "{ip}"
+            );
+        }
+
+        // A zero prefix length matches all addresses of the same protocol
+        let f_all_ipv4 = &[NicFilter::parse("* 0.0.0.0/0").unwrap()];
+        assert_eq!(
+            nic_priority(
+                f_all_ipv4,
+                "eth0",
+                &"1.2.3.4".parse().unwrap(),
+                NicType::Tcp
+            ),
+            Some(0)
+        );
+        assert_eq!(
+            nic_priority(
+                f_all_ipv4,
+                "eth0",
+                &"fd00::1".parse().unwrap(),
+                NicType::Tcp
+            ),
             None
         );
+
+        // Plain addresses still need to match exactly
+        let f_addr = &[NicFilter::parse("* 192.168.0.0").unwrap()];
         assert_eq!(
-            nic_priority(f_invert, "lo", &"fd00::1".parse().unwrap()),
+            nic_priority(
+                f_addr,
+                "eth0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
             None
         );
     }
 
+    #[test]
+    fn match_nic_filter_nic_type() {
+        let f_prefer_rdma = &[
+            NicFilter::parse("* * * rdma").unwrap(),
+            NicFilter::parse("* * * tcp").unwrap(),
+        ];
+        assert_eq!(
+            nic_priority(
+                f_prefer_rdma,
+                "ib0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Rdma
+            ),
+            Some(0)
+        );
+        assert_eq!(
+            nic_priority(
+                f_prefer_rdma,
+                "eth0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
+            Some(1)
+        );
+
+        let f_rdma_only = &[NicFilter::parse("* * * rdma").unwrap()];
+        assert_eq!(
+            nic_priority(
+                f_rdma_only,
+                "eth0",
+                &"192.168.0.1".parse().unwrap(),
+                NicType::Tcp
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn filter_nics_rdma() {
+        let interfaces = [
+            Interface {
+                name: "eth0".into(),
+                index: 0,
+                addrs: vec!["192.168.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "ib0".into(),
+                index: 1,
+                addrs: vec!["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            },
+        ];
+        let rdma_interfaces = HashSet::from(["ib0".to_string()]);
+
+        let nics = filter_nics(&[], true, false, interfaces.clone(), &rdma_interfaces, None);
+        assert_eq!(nics.len(), 3);
+        assert_eq!(nics[0].address, IpAddr::from_str("10.0.0.1").unwrap());
+        assert_eq!(nics[0].nic_type, NicType::Rdma);
+        assert_eq!(nics[1].address, IpAddr::from_str("192.168.0.1").unwrap());
+        assert_eq!(nics[1].nic_type, NicType::Tcp);
+        assert_eq!(nics[2].nic_type, NicType::Rdma);
+
+        let nics = filter_nics(
+            &[NicFilter::parse("* * * rdma").unwrap()],
+            false,
+            false,
+            interfaces.clone(),
+            &rdma_interfaces,
+            None,
+        );
+        assert_eq!(nics.len(), 1);
+        assert_eq!(nics[0].name, "ib0");
+
+        // Failed detection results in an empty set, making everything TCP
+        assert!(rdma_interfaces(Path::new("/nonexistent/infiniband")).is_err());
+        let nics = filter_nics(&[], true, false, interfaces, &HashSet::new(), None);
+        assert!(nics.iter().all(|e| e.nic_type == NicType::Tcp));
+    }
+
+    #[test]
+    fn filter_nics_type_override() {
+        let interfaces = [
+            Interface {
+                name: "eth0".into(),
+                index: 0,
+                addrs: vec!["192.168.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "ib0".into(),
+                index: 1,
+                addrs: vec!["10.0.0.1".parse().unwrap()],
+            },
+        ];
+        let rdma_interfaces = HashSet::from(["ib0".to_string()]);
+        let tcp_only = [NicFilter::parse("* * * tcp").unwrap()];
+
+        // Without the override, the IPoIB interface is dropped by the tcp filter
+        let nics = filter_nics(
+            &tcp_only,
+            false,
+            false,
+            interfaces.clone(),
+            &rdma_interfaces,
+            None,
+        );
+        assert_eq!(nics.len(), 1);
+        assert_eq!(nics[0].name, "eth0");
+
+        // With the override, it is turned into TCP before filtering and kept
+        let nics = filter_nics(
+            &tcp_only,
+            false,
+            false,
+            interfaces,
+            &rdma_interfaces,
+            Some(NicType::Tcp),
+        );
+        assert_eq!(nics.len(), 2);
+        assert!(nics.iter().any(|e| e.name == "ib0"));
+        assert!(nics.iter().all(|e| e.nic_type == NicType::Tcp));
+    }
+
+    #[test]
+    fn rdma_interfaces_port_state() {
+        let sysfs = std::env::temp_dir().join(format!("nic-rdma-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&sysfs);
+
+        let add_dev = |dev: &str, netdev: &str, port_states: &[&str]| {
+            let dev = sysfs.join(dev);
+            std::fs::create_dir_all(dev.join("device/net").join(netdev)).unwrap();
+            for (i, state) in port_states.iter().enumerate() {
+                let port = dev.join("ports").join((i + 1).to_string());
+                std::fs::create_dir_all(&port).unwrap();
+                std::fs::write(port.join("state"), format!("{state}\n")).unwrap();
+            }
+        };
+
+        add_dev("mlx5_0", "ib0", &["4: ACTIVE"]);
+        add_dev("mlx5_1", "ib1", &["1: DOWN", "4: ACTIVE"]);
+        add_dev("mlx5_2", "ib2", &["1: DOWN"]);
+        add_dev("mlx5_3", "ib3", &[]);
+
+        let res = rdma_interfaces(&sysfs);
+        std::fs::remove_dir_all(&sysfs).unwrap();
+
+        assert_eq!(
+            res.unwrap(),
+            HashSet::from(["ib0".to_string(), "ib1".to_string()])
+        );
+    }
+
+    #[test]
+    fn filter_nics_strict_order() {
+        let interfaces = [
+            Interface {
+                name: "lo".into(),
+                index: 0,
+                addrs: vec!["127.0.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "eth0".into(),
+                index: 1,
+                addrs: vec!["fd00::1".parse().unwrap(), "192.168.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "ib0".into(),
+                index: 2,
+                addrs: vec!["10.0.0.1".parse().unwrap()],
+            },
+        ];
+        let rdma_interfaces = HashSet::from(["ib0".to_string()]);
+        let filter = [
+            NicFilter::parse("lo").unwrap(),
+            NicFilter::parse("eth0").unwrap(),
+            NicFilter::parse("ib0").unwrap(),
+        ];
+
+        let addrs = |nics: Vec<Nic>| -> Vec<String> {
+            nics.into_iter().map(|e| e.address.to_string()).collect()
+        };
+
+        // Default: Filter order first, then non-loopback, ipv4 and rdma within the same priority
+        let nics = filter_nics(
+            &filter,
+            true,
+            false,
+            interfaces.clone(),
+            &rdma_interfaces,
+            None,
+        );
+        assert_eq!(
+            addrs(nics),
+            ["127.0.0.1", "192.168.0.1", "fd00::1", "10.0.0.1"]
+        );
+
+        // Strict: Filter order, then the order reported by the OS
+        let nics = filter_nics(
+            &filter,
+            true,
+            true,
+            interfaces.clone(),
+            &rdma_interfaces,
+            None,
+        );
+        assert_eq!(
+            addrs(nics),
+            ["127.0.0.1", "fd00::1", "192.168.0.1", "10.0.0.1"]
+        );
+
+        // Strict without a filter falls back to the default order
+        let nics = filter_nics(&[], true, true, interfaces, &rdma_interfaces, None);
+        assert_eq!(
+            addrs(nics),
+            ["10.0.0.1", "192.168.0.1", "fd00::1", "127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn filter_nics_weighted() {
+        let interfaces = [
+            Interface {
+                name: "eth0".into(),
+                index: 0,
+                addrs: vec!["192.168.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "eth1".into(),
+                index: 1,
+                addrs: vec!["10.0.0.1".parse().unwrap()],
+            },
+            Interface {
+                name: "ib0".into(),
+                index: 2,
+                addrs: vec!["10.1.0.1".parse().unwrap()],
+            },
+        ];
+        let rdma_interfaces = HashSet::from(["ib0".to_string()]);
+
+        let addrs = |nics: Vec<Nic>| -> Vec<String> {
+            nics.into_iter().map(|e| e.address.to_string()).collect()
+        };
+
+        // Weights override the position in the list
+        let filter = [
+            NicFilter::parse("* 10.0.0.0/16 @50").unwrap(),
+            NicFilter::parse("* * * rdma @10").unwrap(),
+            NicFilter::parse("* * * * @100").unwrap(),
+        ];
+        for strict_order in [false, true] {
+            let nics = filter_nics(
+                &filter,
+                true,
+                strict_order,
+                interfaces.clone(),
+                &rdma_interfaces,
+                None,
+            );
+            assert_eq!(
+                nics.iter().map(|e| e.priority()).collect::<Vec<_>>(),
+                [10, 50, 100]
+            );
+            assert_eq!(addrs(nics), ["10.1.0.1", "10.0.0.1", "192.168.0.1"]);
+        }
+
+        // Entries without a weight keep their positional priority. Equal priorities are ordered
+        // by the built-in tie-breakers.
+        let filter = [
+            NicFilter::parse("eth0").unwrap(),
+            NicFilter::parse("eth1 @0").unwrap(),
+            NicFilter::parse("*").unwrap(),
+        ];
+        let nics = filter_nics(&filter, true, false, interfaces, &rdma_interfaces, None);
+        assert_eq!(
+            nics.iter().map(|e| e.priority()).collect::<Vec<_>>(),
+            [0, 0, 2]
+        );
+        assert_eq!(addrs(nics), ["192.168.0.1", "10.0.0.1", "10.1.0.1"]);
+    }
+
+    #[test]
+    fn check_filter_names() {
+        let names = ["lo".to_string(), "eth0".to_string()];
+
+        check_filter_names_against(&[], &names).unwrap();
+        check_filter_names_against(
+            &[
+                NicFilter::parse("eth0").unwrap(),
+                NicFilter::parse("! lo").unwrap(),
+                NicFilter::parse("* * 4").unwrap(),
+            ],
+            &names,
+        )
+        .unwrap();
+
+        let err = check_filter_names_against(
+            &[
+                NicFilter::parse("eth0").unwrap(),
+                NicFilter::parse("etj1").unwrap(),
+            ],
+            &names,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains(r#"["etj1"]"#));
+        assert!(err.to_string().contains(r#"["lo", "eth0"]"#));
+    }
+
     #[test]
     fn sort_nics() {
         let mut nics = [
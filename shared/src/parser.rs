@@ -1,5 +1,7 @@
 //! Custom parsers for config or command line parameters
 
+pub mod byte_size;
 pub mod duration;
 pub mod integer_range;
 pub mod integer_unit;
+pub mod quota_limits;
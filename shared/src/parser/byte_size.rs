@@ -0,0 +1,194 @@
+//! Custom serde parser for byte sizes (like `"10GiB"` or `"1.5T"`)
+//!
+//! Meant for command line argument and config file parsing.
+
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use serde::Deserializer;
+use serde::de::{Unexpected, Visitor as VisitorT};
+use std::sync::LazyLock;
+
+static REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\d+)(?:\.(\d+))? *(?:([kKMGTPE])(i)?)?B?$").expect("Regex must be valid")
+});
+
+const EXPECT_STR: &str = "a positive integer representing the size in bytes or a string containing \
+     a positive integer or decimal number n with an optional prefix and byte unit in the form \
+     \"<number>[k|M|G|T|P|E][i][B]\"";
+
+/// Parses a string in the form `<number>[kMGTPE][i][B]` into a number of bytes.
+///
+/// Takes the given number and multiplies it according to the given SI prefix, using base 10
+/// (`10kB` becomes 10000). When the `[i]` is given, base 2 is used (`10kiB` becomes 10240). The
+/// number may contain a fractional part (`1.5T`), but the resulting value must be a whole number of
+/// bytes.
+///
+/// Plain numbers and prefixed numbers in the `integer_unit` syntax (`100k`, `2 ki`) result in the
+/// same value as with [super::integer_unit::parse_optional()]. Anything else than the optional `B`
+/// as unit is rejected, as well as values that overflow a `u64`.
+pub fn parse_optional(input: &str) -> Option<u64> {
+    let captures = REGEX.captures(input.trim())?;
+
+    let multiplier: u128 = match (
+        captures.get(3).map(|e| e.as_str()),
+        captures.get(4).is_some(),
+    ) {
+        (None, _) => 1,
+        (Some("k" | "K"), false) => 10u128.pow(3),
+        (Some("M"), false) => 10u128.pow(6),
+        (Some("G"), false) => 10u128.pow(9),
+        (Some("T"), false) => 10u128.pow(12),
+        (Some("P"), false) => 10u128.pow(15),
+        (Some("E"), false) => 10u128.pow(18),
+
+        (Some("k" | "K"), true) => 2u128.pow(10), // 1024
+        (Some("M"), true) => 2u128.pow(20),       // 1024^2
+        (Some("G"), true) => 2u128.pow(30),
+        (Some("T"), true) => 2u128.pow(40),
+        (Some("P"), true) => 2u128.pow(50),
+        (Some("E"), true) => 2u128.pow(60),
+        _ => return None,
+    };
+
+    let whole: u128 = captures.get(1)?.as_str().parse().ok()?;
+    let mut bytes = whole.checked_mul(multiplier)?;
+
+    if let Some(fraction) = captures.get(2) {
+        let fraction = fraction.as_str();
+        let divisor = 10u128.checked_pow(fraction.len().try_into().ok()?)?;
+        let fraction_bytes = fraction.parse::<u128>().ok()?.checked_mul(multiplier)?;
+
+        // Fractional bytes are not allowed
+        if fraction_bytes % divisor != 0 {
+            return None;
+        }
+
+        bytes = bytes.checked_add(fraction_bytes / divisor)?;
+    }
+
+    bytes.try_into().ok()
+}
+
+/// Parses a string in the form `<number>[kMGTPE][i][B]` into a number of bytes.
+///
+/// Takes the given number and multiplies it according to the given SI prefix, using base 10
+/// (`10kB` becomes 10000). When the `[i]` is given, base 2 is used (`10kiB` becomes 10240). The
+/// number may contain a fractional part (`1.5T`), but the resulting value must be a whole number of
+/// bytes.
+pub fn parse(input: &str) -> Result<u64> {
+    parse_optional(input).ok_or_else(|| anyhow!(EXPECT_STR))
+}
+
+#[derive(Debug, Default)]
+struct Visitor {}
+
+impl VisitorT<'_> for Visitor {
+    type Value = u64;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(EXPECT_STR)
+    }
+
+    fn visit_str<E>(self, input: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        parse_optional(input).ok_or_else(|| E::invalid_value(Unexpected::Str(input), &self))
+    }
+
+    fn visit_u64<E>(self, input: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(input)
+    }
+
+    fn visit_i64<E>(self, input: i64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let v = input
+            .try_into()
+            .map_err(|_| E::invalid_value(Unexpected::Signed(input), &self))?;
+
+        self.visit_u64(v)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<u64, D::Error> {
+    de.deserialize_str(Visitor {})
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suffixes() {
+        assert_eq!(parse_optional("100").unwrap(), 100);
+        assert_eq!(parse_optional(" 200B  ").unwrap(), 200);
+        assert_eq!(parse_optional("10kB").unwrap(), 10_000);
+        assert_eq!(parse_optional("10 KB").unwrap(), 10_000);
+        assert_eq!(parse_optional("512M").unwrap(), 512_000_000);
+        assert_eq!(parse_optional("3GB").unwrap(), 3_000_000_000);
+        assert_eq!(parse_optional("2T").unwrap(), 2 * 10u64.pow(12));
+        assert_eq!(parse_optional("2PB").unwrap(), 2 * 10u64.pow(15));
+        assert_eq!(parse_optional("2E").unwrap(), 2 * 10u64.pow(18));
+
+        assert_eq!(parse_optional("1KiB").unwrap(), 1024);
+        assert_eq!(parse_optional("2 ki").unwrap(), 2048);
+        assert_eq!(parse_optional("3MiB").unwrap(), 3 * 1024 * 1024);
+        assert_eq!(parse_optional("10GiB").unwrap(), 10 * 1024 * 1024 * 1024);
+        assert_eq!(parse_optional("512Gi").unwrap(), 512 * 2u64.pow(30));
+        assert_eq!(parse_optional("1TiB").unwrap(), 2u64.pow(40));
+        assert_eq!(parse_optional("1PiB").unwrap(), 2u64.pow(50));
+        assert_eq!(parse_optional("1EiB").unwrap(), 2u64.pow(60));
+    }
+
+    #[test]
+    fn fractional() {
+        assert_eq!(parse_optional("1.5T").unwrap(), 1_500_000_000_000);
+        assert_eq!(parse_optional("0.5 KiB").unwrap(), 512);
+        assert_eq!(parse_optional("1.25GiB").unwrap(), 5 * 2u64.pow(28));
+        assert_eq!(parse_optional("2.0").unwrap(), 2);
+        assert_eq!(parse_optional("0.001k").unwrap(), 1);
+
+        // Results in fractional bytes
+        assert!(parse_optional("1.5").is_none());
+        assert!(parse_optional("0.0001k").is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_optional("").is_none());
+        assert!(parse_optional("GiB").is_none());
+        assert!(parse_optional("garbage").is_none());
+        assert!(parse_optional("-10 k").is_none());
+        assert!(parse_optional("10 i").is_none());
+        assert!(parse_optional("1.5 GiBs").is_none());
+        assert!(parse_optional("1.5 XB").is_none());
+        assert!(parse_optional("1.").is_none());
+        assert!(parse_optional(".5G").is_none());
+        assert!(parse_optional("1.5.5G").is_none());
+        assert!(parse_optional("15.5EiB").is_none());
+        assert!(parse_optional("99999999999999999999999").is_none());
+        assert!(parse_optional("16EiB").is_none());
+        assert!(parse_optional("10 GiBs").is_none());
+        assert!(parse_optional("10 Gb").is_none());
+        assert!(parse_optional("10 kbytes").is_none());
+    }
+
+    #[test]
+    fn integer_unit_compatible() {
+        for input in [
+            "100", " 200  ", "100k", "100 k", "123 M", "0 T", "1ki", "2 ki ", "1000 Mi", "10 GiB",
+        ] {
+            assert_eq!(
+                parse_optional(input),
+                crate::parser::integer_unit::parse_optional(input),
+                "{input:?}"
+            );
+            assert!(parse_optional(input).is_some(), "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Parser for quota limit files (e.g. for `beegfs-mgmtd --import-quota-limits`)
+//!
+//! Each line contains the comma or tab separated fields
+//! `<id_type>,<quota_id>,<pool>,<space_limit>,<inode_limit>`:
+//! * `id_type` is `user`, `group` or `project`
+//! * `pool` is the numeric storage pool id
+//! * `space_limit` is a byte size (see [super::byte_size]), `inode_limit` an integer with
+//!   optional SI prefix (see [super::integer_unit])
+//! * A limit can be `unlimited` to remove it or left empty to not change it
+//!
+//! Empty lines and lines starting with `#` are ignored.
+
+use super::{byte_size, integer_unit};
+use crate::types::{PoolId, QuotaId, QuotaIdType};
+use anyhow::{Context, Result, anyhow, bail};
+
+/// A single quota limit entry. A limit of `-1` means unlimited, `None` means unchanged, matching
+/// the `SetQuotaLimits` RPC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaLimitEntry {
+    pub id_type: QuotaIdType,
+    pub quota_id: QuotaId,
+    pub pool: PoolId,
+    pub space_limit: Option<i64>,
+    pub inode_limit: Option<i64>,
+}
+
+/// A line that could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineError {
+    /// The line number, starting at 1
+    pub line: usize,
+    pub error: String,
+}
+
+/// Parses the contents of a quota limit file.
+///
+/// If `strict` is set, the first malformed line aborts parsing with an error mentioning its line
+/// number. Otherwise, malformed lines are skipped and returned together with the valid entries.
+pub fn parse(input: &str, strict: bool) -> Result<(Vec<QuotaLimitEntry>, Vec<LineError>)> {
+    let mut entries = vec![];
+    let mut errors = vec![];
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_line(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) if strict => return Err(err).with_context(|| format!("Line {}", i + 1)),
+            Err(err) => errors.push(LineError {
+                line: i + 1,
+                error: format!("{err:#}"),
+            }),
+        }
+    }
+
+    Ok((entries, errors))
+}
+
+fn parse_line(line: &str) -> Result<QuotaLimitEntry> {
+    let fields: Vec<_> = line.split([',', '\t']).map(str::trim).collect();
+
+    let [id_type, quota_id, pool, space_limit, inode_limit] = fields[..] else {
+        bail!("Expected 5 fields, got {}", fields.len());
+    };
+
+    Ok(QuotaLimitEntry {
+        id_type: match id_type {
+            "user" => QuotaIdType::User,
+            "group" => QuotaIdType::Group,
+            "project" => QuotaIdType::Project,
+            _ => bail!("Invalid id type {id_type:?}: Must be user, group or project"),
+        },
+        quota_id: quota_id
+            .parse()
+            .with_context(|| format!("Invalid quota id {quota_id:?}"))?,
+        pool: pool
+            .parse()
+            .with_context(|| format!("Invalid pool id {pool:?}"))?,
+        space_limit: parse_limit(space_limit, byte_size::parse).context("Invalid space limit")?,
+        inode_limit: parse_limit(inode_limit, integer_unit::parse)
+            .context("Invalid inode limit")?,
+    })
+}
+
+fn parse_limit(input: &str, parse: impl Fn(&str) -> Result<u64>) -> Result<Option<i64>> {
+    match input {
+        "" => Ok(None),
+        "unlimited" => Ok(Some(-1)),
+        _ => Ok(Some(
+            parse(input)?
+                .try_into()
+                .map_err(|_| anyhow!("{input} is too big"))?,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INPUT: &str = "# id_type,quota_id,pool,space_limit,inode_limit
+user,1000,1,10GiB,1M
+group\t2000\t2\tunlimited\t
+
+project,3000,1,1G
+user,4000,1,,unlimited
+";
+
+    #[test]
+    fn lenient() {
+        let (entries, errors) = parse(INPUT, false).unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                QuotaLimitEntry {
+                    id_type: QuotaIdType::User,
+                    quota_id: 1000,
+                    pool: 1,
+                    space_limit: Some(10 * 1024 * 1024 * 1024),
+                    inode_limit: Some(1_000_000),
+                },
+                QuotaLimitEntry {
+                    id_type: QuotaIdType::Group,
+                    quota_id: 2000,
+                    pool: 2,
+                    space_limit: Some(-1),
+                    inode_limit: None,
+                },
+                QuotaLimitEntry {
+                    id_type: QuotaIdType::User,
+                    quota_id: 4000,
+                    pool: 1,
+                    space_limit: None,
+                    inode_limit: Some(-1),
+                },
+            ]
+        );
+
+        assert_eq!(
+            errors,
+            [LineError {
+                line: 5,
+                error: "Expected 5 fields, got 4".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn strict() {
+        let err = parse(INPUT, true).unwrap_err();
+        assert_eq!(format!("{err:#}"), "Line 5: Expected 5 fields, got 4");
+
+        let err = parse("user,1000,1,10 XB,1M", true).unwrap_err();
+        assert!(format!("{err:#}").starts_with("Line 1: Invalid space limit"));
+    }
+}
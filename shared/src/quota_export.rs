@@ -0,0 +1,212 @@
+//! File format for exporting quota usage snapshots (e.g. by `beegfs-mgmtd --export-quota-usage`)
+//!
+//! A snapshot is newline delimited JSON: The first line is a [Header] containing metadata like the
+//! quota refresh period, each following line is one [QuotaUsageEntry]. The format is line based
+//! so it can be written while consuming the `GetQuotaUsage` stream and be processed by common
+//! tools afterwards.
+//!
+//! Compression is left to the writing side by wrapping the writer passed in (e.g. with a
+//! [flate2::write::GzEncoder]). [read()] detects gzip compressed input and decompresses it.
+
+use crate::types::{PoolId, QuotaId, QuotaIdType};
+use anyhow::{Context, Result, bail};
+use flate2::bufread::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+
+/// Identifies the format in the [Header]
+pub const FORMAT: &str = "beegfs-quota-usage";
+/// The current format version. Increased on incompatible changes.
+pub const VERSION: u32 = 1;
+/// The first bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The first line of a snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+    /// The quota refresh period of the management at the time of the export
+    pub refresh_period_s: Option<u64>,
+}
+
+impl Header {
+    pub fn new(refresh_period_s: Option<u64>) -> Self {
+        Self {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            refresh_period_s,
+        }
+    }
+}
+
+/// A single quota usage entry, matching the fields of the `GetQuotaUsage` RPC. Limits are `None`
+/// if not set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsageEntry {
+    #[serde(with = "id_type")]
+    pub id_type: QuotaIdType,
+    pub quota_id: QuotaId,
+    pub pool: PoolId,
+    pub space_limit: Option<i64>,
+    pub inode_limit: Option<i64>,
+    pub space_used: Option<i64>,
+    pub inode_used: Option<i64>,
+}
+
+/// (De-)serializes the quota id type as its user string (`user`, `group`, `project`)
+mod id_type {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &QuotaIdType, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(value.user_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(des: D) -> Result<QuotaIdType, D::Error> {
+        let s = String::deserialize(des)?;
+        match s.as_str() {
+            "user" => Ok(QuotaIdType::User),
+            "group" => Ok(QuotaIdType::Group),
+            "project" => Ok(QuotaIdType::Project),
+            _ => Err(D::Error::custom(format!("Invalid quota id type {s:?}"))),
+        }
+    }
+}
+
+/// Writes a snapshot entry by entry
+pub struct Writer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the header and returns the writer for the entries
+    pub fn new(mut out: W, header: &Header) -> Result<Self> {
+        serde_json::to_writer(&mut out, header)?;
+        out.write_all(b"\n")?;
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, entry: &QuotaUsageEntry) -> Result<()> {
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer, e.g. for finishing a compression stream
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads a snapshot written by [Writer], either plain or gzip compressed
+pub fn read(mut input: impl BufRead) -> Result<(Header, Vec<QuotaUsageEntry>)> {
+    if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        read_plain(BufReader::new(MultiGzDecoder::new(input)))
+    } else {
+        read_plain(input)
+    }
+}
+
+fn read_plain(input: impl BufRead) -> Result<(Header, Vec<QuotaUsageEntry>)> {
+    let mut lines = input.lines();
+
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).context("Invalid header")?,
+        None => bail!("Missing header"),
+    };
+
+    if header.format != FORMAT {
+        bail!("Unknown format {:?}", header.format);
+    }
+    if header.version != VERSION {
+        bail!("Unsupported format version {}", header.version);
+    }
+
+    let mut entries = vec![];
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        // The header is line 1
+        entries.push(serde_json::from_str(&line).with_context(|| format!("Line {}", i + 2))?);
+    }
+
+    Ok((header, entries))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    #[test]
+    fn round_trip() {
+        let entries: Vec<_> = (0..10)
+            .map(|i| QuotaUsageEntry {
+                id_type: [QuotaIdType::User, QuotaIdType::Group, QuotaIdType::Project][i % 3],
+                quota_id: 1000 + i as u32,
+                pool: 1,
+                space_limit: (i % 2 == 0).then_some(1 << 30),
+                inode_limit: None,
+                space_used: Some(i as i64 * 4096),
+                inode_used: Some(i as i64),
+            })
+            .collect();
+
+        let mut writer = Writer::new(vec![], &Header::new(Some(30))).unwrap();
+        for e in &entries {
+            writer.write(e).unwrap();
+        }
+        let buf = writer.finish().unwrap();
+
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert_eq!(text.lines().count(), 11);
+        assert!(text.lines().nth(1).unwrap().contains(r#""id_type":"user""#));
+
+        let (header, read_entries) = read(buf.as_slice()).unwrap();
+        assert_eq!(header, Header::new(Some(30)));
+        assert_eq!(read_entries, entries);
+
+        // Gzip compressed
+        let mut writer = Writer::new(
+            GzEncoder::new(vec![], Compression::default()),
+            &Header::new(Some(30)),
+        )
+        .unwrap();
+        for e in &entries {
+            writer.write(e).unwrap();
+        }
+        let compressed = writer.finish().unwrap().finish().unwrap();
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+
+        let (header, read_entries) = read(compressed.as_slice()).unwrap();
+        assert_eq!(header, Header::new(Some(30)));
+        assert_eq!(read_entries, entries);
+
+        // Empty snapshot
+        let buf = Writer::new(vec![], &Header::new(None))
+            .unwrap()
+            .finish()
+            .unwrap();
+        let (header, read_entries) = read(buf.as_slice()).unwrap();
+        assert_eq!(header.refresh_period_s, None);
+        assert!(read_entries.is_empty());
+
+        // Invalid input
+        read(b"".as_slice()).unwrap_err();
+        read(br#"{"format":"other","version":1,"refresh_period_s":null}"#.as_slice()).unwrap_err();
+        let mut buf = Writer::new(vec![], &Header::new(None))
+            .unwrap()
+            .finish()
+            .unwrap();
+        buf.extend_from_slice(b"{\"id_type\":\"nobody\"}\n");
+        let err = read(buf.as_slice()).unwrap_err();
+        assert!(format!("{err:#}").contains("Line 2"), "{err:#}");
+    }
+}
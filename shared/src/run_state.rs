@@ -1,6 +1,7 @@
 //! Defines an application run state including handles to access and update it.
 
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 use tokio::sync::watch;
 
 /// Represents an overall application run state.
@@ -8,6 +9,7 @@ use tokio::sync::watch;
 enum RunState {
     Running,
     PreShutdown,
+    Draining,
     Shutdown,
 }
 
@@ -24,6 +26,18 @@ pub struct RunStateHandle {
     /// Only used to wait for awaiting handle drop
     #[allow(unused)]
     count_rx: watch::Receiver<()>,
+    /// Used to create [DrainHandle]s
+    drain_count_tx: watch::Sender<()>,
+}
+
+/// Handle for tasks processing requests. Blocks the drain phase on the control side while hold, but
+/// does not block shutdown.
+#[derive(Clone, Debug)]
+pub struct DrainHandle {
+    weak: WeakRunStateHandle,
+    /// Only used to wait for awaiting handle drop
+    #[allow(unused)]
+    drain_count_rx: watch::Receiver<()>,
 }
 
 /// Control handle for signaling app shutdown.
@@ -31,19 +45,26 @@ pub struct RunStateHandle {
 pub struct RunStateControl {
     tx: watch::Sender<RunState>,
     count_tx: watch::Sender<()>,
+    drain_count_tx: watch::Sender<()>,
 }
 
 /// Create a new connected signaler / receiver pair.
 pub fn new() -> (RunStateHandle, RunStateControl) {
     let (tx, rx) = watch::channel(RunState::Running);
     let (count_tx, count_rx) = watch::channel(());
+    let (drain_count_tx, _) = watch::channel(());
 
     (
         RunStateHandle {
             weak: WeakRunStateHandle { rx },
             count_rx,
+            drain_count_tx: drain_count_tx.clone(),
+        },
+        RunStateControl {
+            tx,
+            count_tx,
+            drain_count_tx,
         },
-        RunStateControl { tx, count_tx },
     )
 }
 
@@ -77,13 +98,30 @@ impl WeakRunStateHandle {
         }
     }
 
+    /// Asynchronously wait for the drain phase or shutdown.
+    ///
+    /// When this future completes, no new requests shall be accepted anymore. Requests already
+    /// being processed shall be finished.
+    pub async fn wait_for_drain(&mut self) {
+        while !self.draining() {
+            if self.rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
     /// Returns true if the control handle has changed state to (pre) shutdown.
     pub fn pre_shutdown(&self) -> bool {
         matches!(
             *self.rx.borrow(),
-            RunState::PreShutdown | RunState::Shutdown
+            RunState::PreShutdown | RunState::Draining | RunState::Shutdown
         )
     }
+
+    /// Returns true if the control handle has changed state to draining or shutdown.
+    pub fn draining(&self) -> bool {
+        matches!(*self.rx.borrow(), RunState::Draining | RunState::Shutdown)
+    }
 }
 
 impl Deref for RunStateHandle {
@@ -104,6 +142,27 @@ impl RunStateHandle {
     pub fn clone_weak(&self) -> WeakRunStateHandle {
         self.weak.clone()
     }
+
+    pub fn clone_drain(&self) -> DrainHandle {
+        DrainHandle {
+            weak: self.weak.clone(),
+            drain_count_rx: self.drain_count_tx.subscribe(),
+        }
+    }
+}
+
+impl Deref for DrainHandle {
+    type Target = WeakRunStateHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.weak
+    }
+}
+
+impl DerefMut for DrainHandle {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.weak
+    }
 }
 
 impl RunStateControl {
@@ -127,6 +186,23 @@ impl RunStateControl {
             let _ = self.tx.send(RunState::PreShutdown);
         }
     }
+
+    /// Signals the drain phase to all receiving handles and awaits completion or the timeout.
+    ///
+    /// After sending the signal, this function awaits all [DrainHandle]s being dropped (= usually
+    /// meaning all outstanding requests have been processed), but at most for `timeout`.
+    ///
+    /// # Return value
+    /// `true` if all drain handles have been dropped, `false` if the timeout was hit.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        if matches!(*self.tx.borrow(), RunState::Running | RunState::PreShutdown) {
+            let _ = self.tx.send(RunState::Draining);
+        }
+
+        tokio::time::timeout(timeout, self.drain_count_tx.closed())
+            .await
+            .is_ok()
+    }
 }
 
 #[cfg(test)]
@@ -157,4 +233,35 @@ mod test {
             _ = sc.shutdown() => {}
         }
     }
+
+    #[tokio::test]
+    async fn drain() {
+        let (s, sc) = new();
+
+        // A request taking some time to process
+        let mut d = s.clone_drain();
+        let request = tokio::spawn(async move {
+            d.wait_for_drain().await;
+            sleep(Duration::from_millis(50)).await;
+            drop(d);
+            "done"
+        });
+
+        assert!(!s.draining());
+        assert!(sc.drain(Duration::from_secs(5)).await);
+        assert!(s.draining());
+        assert!(s.pre_shutdown());
+        assert_eq!(request.await.unwrap(), "done");
+
+        // A request not finishing in time
+        let _d = s.clone_drain();
+        assert!(!sc.drain(Duration::from_millis(50)).await);
+
+        // Shutdown is not blocked by drain handles
+        drop(s);
+        tokio::select! {
+            _ = sleep(Duration::from_millis(100)) => { panic!("Timeout hit");}
+            _ = sc.shutdown() => {}
+        }
+    }
 }
@@ -238,16 +238,19 @@ pub enum QuotaIdType {
     #[default]
     User,
     Group,
+    Project,
 }
 
 impl_enum_bee_msg_traits!(QuotaIdType,
     User => 1,
-    Group => 2
+    Group => 2,
+    Project => 3
 );
 
 impl_enum_user_str! {QuotaIdType,
     QuotaIdType::User => "user",
     QuotaIdType::Group => "group",
+    QuotaIdType::Project => "project",
 }
 
 #[cfg(feature = "grpc")]
@@ -255,6 +258,7 @@ impl_enum_protobuf_traits! {QuotaIdType=> pb::QuotaIdType,
     unspecified => pb::QuotaIdType::Unspecified,
     QuotaIdType::User => pb::QuotaIdType::User,
     QuotaIdType::Group => pb::QuotaIdType::Group,
+    QuotaIdType::Project => pb::QuotaIdType::Project,
 }
 
 /// Type of a quota entry as used by BeeMsg
@@ -325,3 +329,107 @@ impl FromStr for AuthSecret {
         })?))
     }
 }
+
+/// The ID of a BeeMsg that can be acknowledged using an `Ack` message
+///
+/// Receivers of a message with a non-empty ack ID are expected to respond with an `Ack` carrying
+/// the same ID, so the sender can correlate them. An empty ID requests no acknowledgement. The
+/// management doesn't track acknowledgements, so it always sends empty IDs. Serialized as c string,
+/// e.g. using `#[bee_serde(as = CStr<0>)]`.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct AckId(Vec<u8>);
+
+impl AckId {
+    /// An empty ack ID, requesting no acknowledgement
+    pub fn empty() -> Self {
+        Self(vec![])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AsRef<[u8]> for AckId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for AckId {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&[u8]> for AckId {
+    fn from(value: &[u8]) -> Self {
+        Self(value.to_vec())
+    }
+}
+
+impl From<&str> for AckId {
+    fn from(value: &str) -> Self {
+        Self(value.as_bytes().to_vec())
+    }
+}
+
+impl std::fmt::Display for AckId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl Debug for AckId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ack_id_empty() {
+        assert!(AckId::empty().is_empty());
+        assert!(!AckId::from("ack").is_empty());
+        assert_eq!(AckId::empty(), AckId::default());
+    }
+
+    #[test]
+    fn ack_id_serialization() {
+        #[derive(Debug, Default, PartialEq, Eq, BeeSerde)]
+        struct WithAckId {
+            #[bee_serde(as = CStr<0>)]
+            unaligned: AckId,
+            #[bee_serde(as = CStr<4>)]
+            aligned: AckId,
+        }
+
+        for ack_id in [
+            AckId::empty(),
+            AckId::from("ack"),
+            AckId::from("0a1b2c3d-42"),
+        ] {
+            let data = WithAckId {
+                unaligned: ack_id.clone(),
+                aligned: ack_id.clone(),
+            };
+
+            let mut buf = vec![0; 128];
+            let mut ser = Serializer::new(&mut buf);
+            data.serialize(&mut ser).unwrap();
+            let len = ser.bytes_written();
+
+            // Length prefix, data and terminator, with the second one padded to 4 bytes
+            let cstr_len = 4 + ack_id.as_ref().len() + 1;
+            assert_eq!(len, cstr_len + cstr_len.next_multiple_of(4));
+            assert_eq!(&buf[4..4 + ack_id.as_ref().len()], ack_id.as_ref());
+
+            let mut des = Deserializer::new(&buf[..len]);
+            assert_eq!(WithAckId::deserialize(&mut des).unwrap(), data);
+            des.finish().unwrap();
+        }
+    }
+}
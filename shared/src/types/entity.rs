@@ -3,9 +3,7 @@ use anyhow::{Result, bail};
 use core::hash::Hash;
 #[cfg(feature = "grpc")]
 use protobuf::beegfs as pb;
-use regex::Regex;
 use std::fmt::{Debug, Display};
-use std::sync::LazyLock;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum EntityType {
@@ -30,38 +28,68 @@ impl_enum_protobuf_traits! {EntityType => pb::EntityType,
     EntityType::Pool => pb::EntityType::Pool,
     EntityType::BuddyGroup => pb::EntityType::BuddyGroup,
 }
+/// A validated, human readable entity identifier
+///
+/// Can only be constructed using [Alias::new()] (or the [TryFrom] impls, which call it), so every
+/// instance is guaranteed to follow the alias rules.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Alias(String);
 
-static REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9-_.]+$").expect("Regex must be valid"));
+impl Alias {
+    /// The minimum length of an alias
+    pub const MIN_LEN: usize = 2;
+    /// The maximum length of an alias. Equal to 32 bytes, since only ASCII characters are allowed.
+    ///
+    /// If the length limit is ever changed, it should be reflected on the client which uses fixed
+    /// size buffers to store the alias.
+    pub const MAX_LEN: usize = 32;
 
-impl TryFrom<String> for Alias {
-    type Error = anyhow::Error;
+    /// Validates `value` and creates an [Alias] from it.
+    ///
+    /// A valid alias
+    /// * is between [Alias::MIN_LEN] and [Alias::MAX_LEN] characters long
+    /// * starts with an ASCII letter. Client node IDs generated by BeeGFS versions prior to 8.0
+    ///   start with a digit and are therefore rejected.
+    /// * only contains ASCII letters, digits, `-`, `_` and `.`
+    pub fn new(value: impl Into<String>) -> Result<Self> {
+        let value = value.into();
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        // Max length allowed is 32 bytes (which is equal to 32 characters with the allowed set). If
-        // the length limit is ever changed, it should be reflected on the client which uses fixed
-        // size buffers to store the alias.
-        if value.len() > 32 {
-            bail!("invalid alias '{value}': max length is 32 characters");
-        }
-
-        if !REGEX.is_match(&value) {
+        if value.len() < Self::MIN_LEN || value.len() > Self::MAX_LEN {
             bail!(
-                "invalid alias '{value}': must start with a letter and may only contain letters, digits, '-', '_' and '.'"
+                "invalid alias '{value}': length must be between {} and {} characters",
+                Self::MIN_LEN,
+                Self::MAX_LEN
             );
         }
 
+        if !value.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            bail!("invalid alias '{value}': must start with a letter");
+        }
+
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!("invalid alias '{value}': may only contain letters, digits, '-', '_' and '.'");
+        }
+
         Ok(Self(value))
     }
 }
 
+impl TryFrom<String> for Alias {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
 impl TryFrom<&str> for Alias {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Self::try_from(value.to_owned())
+        Self::new(value)
     }
 }
 
@@ -212,3 +240,59 @@ impl From<EntityIdSet> for pb::EntityIdSet {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alias_valid() {
+        let longest = "a".repeat(Alias::MAX_LEN);
+
+        for a in [
+            "ab",
+            "node_meta_1",
+            "a-b_c.d",
+            "Z9",
+            "a.",
+            "a-",
+            "a_",
+            "storage.pool-1_x",
+            longest.as_str(),
+        ] {
+            assert_eq!(Alias::new(a).unwrap().as_ref(), a);
+            assert!(Alias::try_from(a).is_ok());
+            assert!(Alias::try_from(a.to_string()).is_ok());
+        }
+    }
+
+    #[test]
+    fn alias_invalid() {
+        let too_long = "a".repeat(Alias::MAX_LEN + 1);
+
+        for a in [
+            // Length bounds
+            "",
+            "a",
+            too_long.as_str(),
+            // Leading character
+            "1abc",
+            "0",
+            "-abc",
+            "_abc",
+            ".abc",
+            " abc",
+            // Disallowed characters
+            "ab c",
+            "ab/c",
+            "ab:c",
+            "ab\tc",
+            "abc\n",
+            "äbc",
+            "abç",
+        ] {
+            assert!(Alias::new(a).is_err(), "'{a}' must be rejected");
+            assert!(Alias::try_from(a).is_err());
+        }
+    }
+}
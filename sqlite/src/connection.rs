@@ -6,9 +6,79 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::Semaphore;
 
-/// Sets connection parameters on an SQLite connection.
+/// The default maximum waiting time on immediate transactions if the write lock is already taken
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How durable committed transactions are. Higher durability costs write performance.
+///
+/// All modes use WAL journal mode, which is required for the separate read connections and keeps
+/// the database consistent on application crashes. They differ in how often SQLite syncs to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// Never sync (`synchronous = OFF`). Fastest, but on power loss or OS crash, recently committed
+    /// transactions can be lost and the database file might even get corrupted.
+    Fast,
+    /// Only sync on WAL checkpoints (`synchronous = NORMAL`). On power loss or OS crash, recently
+    /// committed transactions can be lost, but the database stays consistent.
+    Normal,
+    /// Sync after each committed transaction (`synchronous = FULL`). Committed transactions survive
+    /// power loss.
+    #[default]
+    Full,
+}
+
+impl Durability {
+    /// The value of the `synchronous` pragma for this mode
+    pub fn synchronous(self) -> &'static str {
+        match self {
+            Self::Fast => "off",
+            Self::Normal => "normal",
+            Self::Full => "full",
+        }
+    }
+
+    /// The value of the `journal_mode` pragma for this mode
+    pub fn journal_mode(self) -> &'static str {
+        "wal"
+    }
+}
+
+/// Sets connection parameters on an SQLite connection using the default durability.
 pub fn setup_connection(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    setup_connection_with_durability(conn, Durability::default())
+}
+
+/// Sets connection parameters on an SQLite connection using the given durability.
+pub fn setup_connection_with_durability(
+    conn: &rusqlite::Connection,
+    durability: Durability,
+) -> rusqlite::Result<()> {
+    setup_common(conn)?;
+
+    // We want to use WAL mode (https://www.sqlite.org/wal.html) as we write a lot and in this
+    // mode, a writer does not block readers (they will just see the old state if they started a
+    // transaction before the write happened) and writing also should be faster.
+    // In WAL mode, SQLite creates the two additional files `<db_file>-wal` and `<db_file>-shm`
+    // next to the database file while it is opened. These belong to the database and must not be
+    // deleted while the database is in use.
+    // Note that the WAL is merged into the main db file automatically by SQLite after it has
+    // reached a certain size and on the last connection being closed. This could be configured or
+    // even disabled so we can run it manually.
+    conn.pragma_update(None, "journal_mode", durability.journal_mode())?;
+    // Note that run_op() below temporarily lowers this for write_tx_no_sync() and resets it
+    // afterwards.
+    conn.pragma_update(None, "synchronous", durability.synchronous())?;
+
+    Ok(())
+}
+
+/// Sets the connection parameters shared by read-write and read-only connections.
+///
+/// The journal mode is persisted in the database file and can't be changed on a read-only
+/// connection, so it is only set in [setup_connection()].
+fn setup_common(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
     // We use the carray extension to bind arrays to parameters
     rusqlite::vtab::array::load_module(conn)?;
 
@@ -19,32 +89,37 @@ pub fn setup_connection(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
     // Maximum waiting time on immediate transactions if the write lock is already taken.
     // Note that this does NOT apply to upgrading a deferred transaction from read to write,
     // these will fail immediately.
-    conn.busy_timeout(Duration::from_secs(30))?;
-
-    // We want to use WAL mode (https://www.sqlite.org/wal.html) as we write a lot and in this
-    // mode, a writer does not block readers (they will just see the old state if they started a
-    // transaction before the write happened) and writing also should be faster.
-    // Note that the WAL is merged into the main db file automatically by SQLite after it has
-    // reached a certain size and on the last connection being closed. This could be configured or
-    // even disabled so we can run it manually.
-    conn.pragma_update(None, "journal_mode", "wal")?;
-    // Default to fsync after each transaction. If this is changed, make sure to update the
-    // transaction methods below to match the change, especially in run_op() where a temporary
-    // change happens.
-    conn.pragma_update(None, "synchronous", "full")?;
+    conn.busy_timeout(DEFAULT_BUSY_TIMEOUT)?;
 
     Ok(())
 }
 
 /// Opens an existing sqlite database for read and write and configures the connection
 pub fn open(db_file: impl AsRef<Path>) -> Result<rusqlite::Connection> {
+    open_with_durability(db_file, Durability::default())
+}
+
+/// Opens an existing sqlite database for read and write and configures the connection using the
+/// given durability
+pub fn open_with_durability(
+    db_file: impl AsRef<Path>,
+    durability: Durability,
+) -> Result<rusqlite::Connection> {
     let conn = rusqlite::Connection::open_with_flags(
         db_file,
         // We don't want to accidentally create a nonexisting file, thus we pass this flag
         // explicitly instead of just using open()
         rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
     )?;
-    setup_connection(&conn)?;
+    setup_connection_with_durability(&conn, durability)?;
+    Ok(conn)
+}
+
+/// Opens an existing sqlite database for reading only and configures the connection
+pub fn open_read_only(db_file: impl AsRef<Path>) -> Result<rusqlite::Connection> {
+    let conn =
+        rusqlite::Connection::open_with_flags(db_file, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    setup_common(&conn)?;
     Ok(conn)
 }
 
@@ -77,7 +152,19 @@ pub enum SyncMode {
 #[derive(Debug)]
 pub struct InnerConnections {
     conns: Mutex<Vec<Connection>>,
+    /// Separate read-only connections [Connections::read_tx()] is routed to, if enabled
+    read_conns: Option<ReadConnections>,
     db_file: PathBuf,
+    busy_timeout: Duration,
+    durability: Durability,
+    read_only: bool,
+}
+
+/// A pool of read-only connections, limited to a fixed number of concurrently open ones
+#[derive(Debug)]
+struct ReadConnections {
+    conns: Mutex<Vec<Connection>>,
+    permits: Arc<Semaphore>,
 }
 
 /// Increased whenever new_in_memory is called. Makes sure that the test binary can run multiple
@@ -85,16 +172,65 @@ pub struct InnerConnections {
 static MEMORY_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 impl Connections {
-    /// Create a new db connection pool using the given db file
-    pub fn new(db_file: impl AsRef<Path>) -> Self {
+    /// Create a new db connection pool using the given db file.
+    ///
+    /// `busy_timeout` defines the maximum waiting time on immediate transactions if the write lock
+    /// is already taken.
+    pub fn new(db_file: impl AsRef<Path>, busy_timeout: Duration) -> Self {
+        Self::new_with_read_connections(db_file, busy_timeout, Durability::default(), 0)
+    }
+
+    /// Create a new db connection pool using the given db file and route read transactions to
+    /// up to `read_connections` separate read-only connections. The read-write connections are
+    /// configured with the given `durability`.
+    ///
+    /// Since the database is used in WAL mode, reads on these connections neither block nor are
+    /// blocked by concurrent writes, which go through the read-write connections as usual. If
+    /// `read_connections` is 0, all operations share the read-write connections.
+    ///
+    /// Must not be used with an in memory database, which doesn't support WAL mode.
+    pub fn new_with_read_connections(
+        db_file: impl AsRef<Path>,
+        busy_timeout: Duration,
+        durability: Durability,
+        read_connections: usize,
+    ) -> Self {
+        Self {
+            inner: Arc::new(InnerConnections {
+                conns: Mutex::new(vec![]),
+                read_conns: (read_connections > 0).then(|| ReadConnections {
+                    conns: Mutex::new(vec![]),
+                    permits: Arc::new(Semaphore::new(read_connections)),
+                }),
+                db_file: db_file.as_ref().to_path_buf(),
+                busy_timeout,
+                durability,
+                read_only: false,
+            }),
+        }
+    }
+
+    /// Create a new db connection pool using the given db file, opening all connections read-only.
+    ///
+    /// All write attempts on the returned pool fail.
+    pub fn new_read_only(db_file: impl AsRef<Path>, busy_timeout: Duration) -> Self {
         Self {
             inner: Arc::new(InnerConnections {
                 conns: Mutex::new(vec![]),
+                read_conns: None,
                 db_file: db_file.as_ref().to_path_buf(),
+                busy_timeout,
+                durability: Durability::default(),
+                read_only: true,
             }),
         }
     }
 
+    /// Whether the connections are opened read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Create a new db connection pool using an in memory db
     pub fn new_in_memory() -> Self {
         let count = MEMORY_COUNTER.fetch_add(1, Ordering::Relaxed);
@@ -102,7 +238,11 @@ impl Connections {
         Self {
             inner: Arc::new(InnerConnections {
                 conns: Mutex::new(vec![]),
+                read_conns: None,
                 db_file: format!("file:memdb{count}?mode=memory&cache=shared").into(),
+                busy_timeout: DEFAULT_BUSY_TIMEOUT,
+                durability: Durability::default(),
+                read_only: false,
             }),
         }
     }
@@ -127,8 +267,8 @@ impl Connections {
         .await
     }
 
-    /// Same as `write_tx()`, but changes sqlite sync mode temporarily from `full` to `normal` to
-    /// avoid syncing the transaction to disk immediately. Meant for transactions that can cause
+    /// Same as `write_tx()`, but changes sqlite sync mode temporarily to `normal` (if it is `full`)
+    /// to avoid syncing the transaction to disk immediately. Meant for transactions that can cause
     /// heavy load on bigger systems and are not that critical if they get lost.
     pub async fn write_tx_no_sync<
         T: Send + 'static + FnOnce(&Transaction) -> Result<R>,
@@ -152,6 +292,9 @@ impl Connections {
     /// the whole transaction is spoiled and needs to be rolled back
     /// (that's at least what SQLite recommends: https://sqlite.org/lang_transaction.html).
     /// The busy handler / timeout does not apply here.
+    ///
+    /// If separate read connections are enabled, the transaction runs on one of them and can't
+    /// write at all.
     pub async fn read_tx<
         T: Send + 'static + FnOnce(&Transaction) -> Result<R>,
         R: Send + 'static,
@@ -159,14 +302,19 @@ impl Connections {
         &self,
         op: T,
     ) -> Result<R> {
-        self.run_op(SyncMode::Full, move |conn| {
+        let op = move |conn: &mut Connection| {
             let tx = conn.transaction_with_behavior(TransactionBehavior::Deferred)?;
             let res = op(&tx)?;
             tx.commit()?;
 
             Ok(res)
-        })
-        .await
+        };
+
+        if self.read_conns.is_some() {
+            self.run_read_op(op).await
+        } else {
+            self.run_op(SyncMode::Full, op).await
+        }
     }
 
     /// Execute code using a connection handle. This requires the caller to start a transaction
@@ -201,11 +349,38 @@ impl Connections {
             let mut conn = if let Some(conn) = conn {
                 conn
             } else {
-                open(this.db_file.as_path())?
+                let conn = if this.read_only {
+                    open_read_only(this.db_file.as_path())?
+                } else {
+                    open_with_durability(this.db_file.as_path(), this.durability)?
+                };
+                conn.busy_timeout(this.busy_timeout)?;
+                conn
             };
 
+            // Lowering the sync mode is only necessary if the connection syncs more often than
+            // `normal` anyway
+            let relaxed = this.durability.min(Durability::Normal);
+
             match sync_mode {
-                SyncMode::Full => {
+                SyncMode::Normal if relaxed != this.durability => {
+                    conn.pragma_update(None, "synchronous", relaxed.synchronous())?;
+                    let res = op(&mut conn);
+                    // If the sync mode could not be reset (should most likely never happen), we
+                    // don't error out as the transaction already completed.
+                    // Instead we just drop it to prevent future usage with the lowered mode.
+                    if conn
+                        .pragma_update(None, "synchronous", this.durability.synchronous())
+                        .is_ok()
+                    {
+                        this.conns.lock().unwrap().push(conn);
+                    } else {
+                        log::error!("Failed to change db connection sync mode back");
+                    }
+
+                    res
+                }
+                _ => {
                     let res = op(&mut conn);
                     // Push the connection to the stack
                     // We assume that sqlite connections never invalidate on errors, so there is no
@@ -215,24 +390,293 @@ impl Connections {
                     // are unrecoverable anyway and new connections won't fix anything there.
                     this.conns.lock().unwrap().push(conn);
 
-                    res
-                }
-                SyncMode::Normal => {
-                    conn.pragma_update(None, "synchronous", "normal")?;
-                    let res = op(&mut conn);
-                    // If the sync mode could not be reset (should most likely never happen), we
-                    // don't error out as the transaction already completed.
-                    // Instead we just dorop it to prevent future usage with FULL mode.
-                    if conn.pragma_update(None, "synchronous", "full").is_ok() {
-                        this.conns.lock().unwrap().push(conn);
-                    } else {
-                        log::error!("Failed to change db connection sync mode back to full");
-                    }
-
                     res
                 }
             }
         })
         .await?
     }
+
+    /// Runs the operation on one of the separate read-only connections. Waits for one to become
+    /// available if the limit is reached.
+    async fn run_read_op<
+        T: Send + 'static + FnOnce(&mut Connection) -> Result<R>,
+        R: Send + 'static,
+    >(
+        &self,
+        op: T,
+    ) -> Result<R> {
+        let Some(ref read_conns) = self.read_conns else {
+            anyhow::bail!("Separate read connections are not enabled");
+        };
+
+        let permit = read_conns.permits.clone().acquire_owned().await?;
+
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let read_conns = this
+                .read_conns
+                .as_ref()
+                .expect("read connections must be enabled");
+
+            let conn = read_conns.conns.lock().unwrap().pop();
+            let mut conn = if let Some(conn) = conn {
+                conn
+            } else {
+                let conn = open_read_only(this.db_file.as_path())?;
+                conn.busy_timeout(this.busy_timeout)?;
+                conn
+            };
+
+            let res = op(&mut conn);
+            read_conns.conns.lock().unwrap().push(conn);
+
+            res
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    /// Creates a fresh database file in the temp dir and returns its path
+    fn create_db_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE t (v INTEGER); INSERT INTO t VALUES (1);")
+            .unwrap();
+
+        path
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let path = create_db_file("sqlite-concurrent-access");
+
+        let mut other = open(&path).unwrap();
+        other.busy_timeout(Duration::from_secs(5)).unwrap();
+
+        let journal_mode: String = other
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // Hold the write lock in another thread for some time
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let writer_path = path.clone();
+        let handle = std::thread::spawn(move || {
+            let mut writer = open(&writer_path).unwrap();
+            let tx = writer
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .unwrap();
+            tx.execute("UPDATE t SET v = 2", []).unwrap();
+
+            locked_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+
+            tx.commit().unwrap();
+        });
+
+        locked_rx.recv().unwrap();
+
+        // In WAL mode, readers are not blocked by a writer and see the old state
+        let v: i64 = other
+            .query_row("SELECT v FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(v, 1);
+
+        // A second writer waits for the lock instead of failing immediately
+        let start = Instant::now();
+        let tx = other
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let v: i64 = tx
+            .query_row("SELECT v FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(v, 2);
+
+        tx.commit().unwrap();
+        handle.join().unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn busy_timeout_expires() {
+        let path = create_db_file("sqlite-busy-timeout");
+
+        let mut writer = open(&path).unwrap();
+        let mut other = open(&path).unwrap();
+        other.busy_timeout(Duration::from_millis(100)).unwrap();
+
+        let _tx = writer
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .unwrap();
+
+        let start = Instant::now();
+        other
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn durability() {
+        let path = create_db_file("sqlite-durability");
+
+        let pragmas = |conn: &Connection| -> (String, i64) {
+            (
+                conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                    .unwrap(),
+                conn.query_row("PRAGMA synchronous", [], |row| row.get(0))
+                    .unwrap(),
+            )
+        };
+
+        for (durability, synchronous) in [
+            (Durability::Fast, 0),
+            (Durability::Normal, 1),
+            (Durability::Full, 2),
+        ] {
+            let conn = open_with_durability(&path, durability).unwrap();
+            assert_eq!(
+                pragmas(&conn),
+                ("wal".into(), synchronous),
+                "{durability:?}"
+            );
+
+            // Connections from the pool use the configured mode, also after a no sync transaction
+            let conns = Connections::new_with_read_connections(
+                &path,
+                Duration::from_secs(5),
+                durability,
+                0,
+            );
+            conns.write_tx_no_sync(|_| Ok(())).await.unwrap();
+            let res = conns.conn(move |conn| Ok(pragmas(conn))).await.unwrap();
+            assert_eq!(res, ("wal".into(), synchronous), "{durability:?}");
+        }
+
+        let conn = open(&path).unwrap();
+        assert_eq!(pragmas(&conn), ("wal".into(), 2));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn read_only() {
+        let path = create_db_file("sqlite-read-only");
+
+        let conn = open_read_only(&path).unwrap();
+
+        let v: i64 = conn
+            .query_row("SELECT v FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(v, 1);
+
+        conn.execute("UPDATE t SET v = 2", []).unwrap_err();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn separate_read_connections() {
+        let path = create_db_file("sqlite-read-connections");
+        // Switch the database to WAL mode
+        open(&path).unwrap();
+
+        let conns = Connections::new_with_read_connections(
+            &path,
+            Duration::from_secs(5),
+            Durability::default(),
+            2,
+        );
+
+        // A long running read must not block a concurrent write
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let reader = tokio::spawn({
+            let conns = conns.clone();
+            async move {
+                conns
+                    .read_tx(move |tx| {
+                        let before: i64 = tx.query_row("SELECT v FROM t", [], |row| row.get(0))?;
+                        started_tx.send(()).unwrap();
+                        std::thread::sleep(Duration::from_millis(300));
+                        let after: i64 = tx.query_row("SELECT v FROM t", [], |row| row.get(0))?;
+
+                        Ok((before, after))
+                    })
+                    .await
+            }
+        });
+
+        started_rx.await.unwrap();
+
+        let start = Instant::now();
+        conns
+            .write_tx(|tx| {
+                tx.execute("UPDATE t SET v = 2", [])?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // The reader keeps seeing the state from when its transaction started
+        assert_eq!(reader.await.unwrap().unwrap(), (1, 1));
+
+        // Concurrent reads and writes must not fail with SQLITE_BUSY
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let conns = conns.clone();
+            tasks.spawn(async move {
+                if i % 2 == 0 {
+                    conns
+                        .write_tx(move |tx| {
+                            tx.execute("INSERT INTO t VALUES (?1)", [i])?;
+                            Ok(())
+                        })
+                        .await
+                } else {
+                    conns
+                        .read_tx(|tx| {
+                            tx.query_row("SELECT COUNT(*) FROM t", [], |row| row.get::<_, i64>(0))?;
+                            Ok(())
+                        })
+                        .await
+                }
+            });
+        }
+
+        while let Some(res) = tasks.join_next().await {
+            res.unwrap().unwrap();
+        }
+
+        let count: i64 = conns
+            .read_tx(|tx| Ok(tx.query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(count, 11);
+
+        // Read transactions can't write
+        conns
+            .read_tx(|tx| {
+                tx.execute("UPDATE t SET v = 3", [])?;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+
+        let _ = std::fs::remove_file(&path);
+    }
 }
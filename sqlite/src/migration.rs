@@ -1,6 +1,12 @@
 use anyhow::{Context, Result, anyhow, bail};
+use rusqlite::backup::{Backup, Progress, StepResult};
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Number of pages copied per backup step. With the default page size of 4 KiB, progress is
+/// reported every 4 MiB.
+pub const BACKUP_PAGES_PER_STEP: i32 = 1024;
 
 /// Represents a migration step using a static SQL string. A slice of these is generated as Rust
 /// code and stored to disk by write_migrations_file() to be read in at runtime.
@@ -98,6 +104,14 @@ pub fn flatten_migrations(migrations: &[OwnedMigration]) -> Result<String> {
         conn.execute_batch(&m.sql)?;
     }
 
+    schema_sql(&conn)
+}
+
+/// Generates an SQL schema string from the schema of the given database.
+///
+/// The statements are ordered by type and name, so two databases with the same schema produce the
+/// same string, regardless of the order the schema objects have been created in.
+pub fn schema_sql(conn: &rusqlite::Connection) -> Result<String> {
     // The order of the SQL statements is important as generating views or triggers will fail if
     // the corresponding tables don't exist yet.
     let mut stmt = conn.prepare(
@@ -126,7 +140,8 @@ pub fn flatten_migrations(migrations: &[OwnedMigration]) -> Result<String> {
 /// Checks that the database schema is up to date to the current latest migrations
 ///
 /// # Return value
-/// Returns true if the schema must be migrated, false if it is up to date.
+/// Returns true if the schema must be migrated, false if it is up to date. Returns an error
+/// telling the user how to proceed if the schema can't be migrated by the given migrations.
 pub fn check_schema(tx: &rusqlite::Transaction, migrations: &'static [Migration]) -> Result<bool> {
     let (base, latest) = check_migration_versions(migrations.iter().map(|m| m.version))?;
 
@@ -137,63 +152,209 @@ pub fn check_schema(tx: &rusqlite::Transaction, migrations: &'static [Migration]
         Ok(false)
     } else if (base..latest).contains(&version) {
         Ok(true)
+    } else if version > latest {
+        bail!(
+            "Database schema version {version} is newer than the latest version {latest} \
+supported by this binary. The database has been created or migrated by a newer version, use \
+that version or a later one"
+        );
+    } else if version == 0 {
+        bail!("Database schema version is 0, the database has not been initialized");
     } else {
         bail!(
-            "Database schema version {version} is outside of the valid range ({base} to {latest})"
+            "Database schema version {version} is older than version {base}, the oldest one this \
+binary can migrate from. Migrate the database using an older version supporting schema version \
+{version} first"
         );
     }
 }
 
-/// Migrates a database to the latest version using the given migration list.
+/// The migrations required to bring a database to the latest version
+#[derive(Debug)]
+pub struct PendingMigrations<'a> {
+    /// The current schema version of the database. 0 if it has not been initialized.
+    pub current: u32,
+    /// The schema version after applying the migrations
+    pub latest: u32,
+    /// The migrations to apply, in order. Empty if the database is up to date.
+    pub migrations: &'a [Migration],
+}
+
+/// Determines the migrations that need to be applied to bring a database to the latest version.
 ///
-/// This function is meant to be called at runtime to upgrade the database. Remember to commit
-/// the transaction after calling this function.
-pub fn migrate_schema(tx: &rusqlite::Transaction, migrations: &[Migration]) -> Result<u32> {
+/// Does not modify the database.
+pub fn pending_migrations<'a>(
+    tx: &rusqlite::Transaction,
+    migrations: &'a [Migration],
+) -> Result<PendingMigrations<'a>> {
     let (base, latest) = check_migration_versions(migrations.iter().map(|m| m.version))?;
 
     // The databases version is stored in this special sqlite header variable
-    let mut version: u32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let current: u32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
 
-    if version == latest {
-        bail!("Database schema is up to date with version {version}");
-    } else if version != 0 && !(base..latest).contains(&version) {
+    if current != 0 && current != latest && !(base..latest).contains(&current) {
         bail!(
-            "Database schema version {version} is outside of the valid range ({base} to {latest})",
+            "Database schema version {current} is outside of the valid range ({base} to {latest})",
         )
     };
 
     // Since the base migration is the starting point for new databases, a new database version can
     // be handled like the version before the current base
-    if version == 0 {
-        version = base - 1;
+    let applied = if current == 0 { base - 1 } else { current };
+
+    Ok(PendingMigrations {
+        current,
+        latest,
+        migrations: &migrations[(1 + applied - base) as usize..],
+    })
+}
+
+/// Migrates a database to the latest version using the given migration list.
+///
+/// This function is meant to be called at runtime to upgrade the database. Remember to commit
+/// the transaction after calling this function.
+pub fn migrate_schema(tx: &rusqlite::Transaction, migrations: &[Migration]) -> Result<u32> {
+    let pending = pending_migrations(tx, migrations)?;
+
+    if pending.migrations.is_empty() {
+        bail!(
+            "Database schema is up to date with version {}",
+            pending.current
+        );
     }
 
     // Apply the migrations
-    for Migration { version, sql } in migrations.iter().skip((1 + version - base) as usize) {
+    for Migration { version, sql } in pending.migrations {
         tx.execute_batch(sql)
             .with_context(|| format!("Database migration {version} failed"))?;
     }
 
     // update the database version to the latest schema version
-    tx.pragma_update(None, "user_version", latest)?;
+    tx.pragma_update(None, "user_version", pending.latest)?;
+
+    Ok(pending.latest)
+}
+
+/// Applies the given migrations one by one to a new database and checks that the resulting schema
+/// matches `expected_schema` (in the format generated by [flatten_migrations()]), usually a
+/// committed snapshot of the canonical schema.
+///
+/// Each step is applied using [migrate_schema()], the same way an existing database is upgraded
+/// at runtime. Catches migrations that fail on top of the previous version and schemas that drift
+/// from the canonical one.
+///
+/// This function is meant to be called from tests.
+pub fn check_incremental_migrations(migrations: &[Migration], expected_schema: &str) -> Result<()> {
+    let mut conn = crate::connection::open_in_memory()?;
+    let tx = conn.transaction()?;
+
+    for (i, m) in migrations.iter().enumerate() {
+        let version = migrate_schema(&tx, &migrations[..=i])
+            .with_context(|| format!("Incremental migration to version {} failed", m.version))?;
+
+        if version != m.version {
+            bail!(
+                "Incremental migration to version {} resulted in version {version}",
+                m.version
+            );
+        }
+    }
+
+    let schema = schema_sql(&tx)?;
+    if schema != expected_schema {
+        let mut diff = String::new();
+        for l in expected_schema
+            .lines()
+            .filter(|l| !schema.lines().any(|e| e == *l))
+        {
+            writeln!(diff, "- {l}")?;
+        }
+        for l in schema
+            .lines()
+            .filter(|l| !expected_schema.lines().any(|e| e == *l))
+        {
+            writeln!(diff, "+ {l}")?;
+        }
+
+        bail!(
+            "Schema after incremental migration differs from the expected schema \
+(- expected, + actual):\n{diff}"
+        );
+    }
 
-    Ok(latest)
+    Ok(())
 }
 
-/// Safely backs up the database
-pub fn backup_db(conn: &rusqlite::Connection) -> Result<PathBuf> {
+/// Safely backs up the database, logging the progress.
+///
+/// The backup is aborted when `cancel` returns true. In that case or on any other error, the
+/// incomplete backup file is removed.
+pub fn backup_db(conn: &rusqlite::Connection, cancel: impl Fn() -> bool) -> Result<PathBuf> {
     let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
 
     let Some(db_file) = conn.path() else {
         bail!("Database connection has no file assigned");
     };
 
-    let backup_file = format!("{db_file}.v{version}");
+    let backup_file = PathBuf::from(format!("{db_file}.v{version}"));
+
+    backup_to_file(
+        conn,
+        &backup_file,
+        BACKUP_PAGES_PER_STEP,
+        |p| log::info!("Database backup {}% completed", backup_percentage(p)),
+        cancel,
+    )
+    .inspect_err(|_| {
+        if let Err(err) = std::fs::remove_file(&backup_file) {
+            log::error!("Cleaning up incomplete backup {backup_file:?} failed: {err}");
+        }
+    })
+    .with_context(|| format!("Database backup to {backup_file:?} failed"))?;
+
+    Ok(backup_file)
+}
 
-    conn.backup(rusqlite::MAIN_DB, &backup_file, None)
-        .with_context(|| format!("Database backup to {backup_file} failed"))?;
+/// Copies the main database of `conn` into the database file `dst` using SQLite's online backup
+/// API.
+///
+/// The data is copied in steps of `pages_per_step` pages. After each step, `progress` is called
+/// and `cancel` is checked. If it returns true, the backup is aborted with an error. The possibly
+/// incomplete `dst` file is left to the caller to clean up.
+pub fn backup_to_file(
+    conn: &rusqlite::Connection,
+    dst: &Path,
+    pages_per_step: i32,
+    mut progress: impl FnMut(Progress),
+    cancel: impl Fn() -> bool,
+) -> Result<()> {
+    let mut dst_conn = rusqlite::Connection::open(dst)?;
+    let backup = Backup::new(conn, &mut dst_conn)?;
+
+    loop {
+        let res = backup.step(pages_per_step)?;
+        progress(backup.progress());
+
+        match res {
+            StepResult::Done => return Ok(()),
+            // Database is in use, wait a bit before retrying
+            StepResult::Busy | StepResult::Locked => std::thread::sleep(Duration::from_millis(10)),
+            _ => {}
+        }
 
-    Ok(PathBuf::from(backup_file))
+        if cancel() {
+            bail!("Backup has been interrupted");
+        }
+    }
+}
+
+/// Calculates the completed percentage of a backup
+pub fn backup_percentage(p: Progress) -> i64 {
+    if p.pagecount <= 0 {
+        return 100;
+    }
+
+    i64::from(p.pagecount - p.remaining) * 100 / i64::from(p.pagecount)
 }
 
 /// Checks the given migration versions for being valid and contiguous
@@ -217,6 +378,52 @@ fn check_migration_versions(migrations: impl IntoIterator<Item = u32>) -> Result
 mod test {
     use super::*;
 
+    #[test]
+    fn check_schema() {
+        static MIGRATIONS: &[Migration] = &[
+            Migration {
+                version: 3,
+                sql: "",
+            },
+            Migration {
+                version: 4,
+                sql: "",
+            },
+        ];
+
+        let mut conn = crate::connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+
+        let check = |version: u32| {
+            tx.pragma_update(None, "user_version", version).unwrap();
+            super::check_schema(&tx, MIGRATIONS)
+        };
+
+        assert!(!check(4).unwrap());
+        assert!(check(3).unwrap());
+
+        // Newer db
+        assert_eq!(
+            check(5).unwrap_err().to_string(),
+            "Database schema version 5 is newer than the latest version 4 supported by this \
+binary. The database has been created or migrated by a newer version, use that version or a \
+later one"
+        );
+
+        // Older db
+        assert_eq!(
+            check(2).unwrap_err().to_string(),
+            "Database schema version 2 is older than version 3, the oldest one this binary can \
+migrate from. Migrate the database using an older version supporting schema version 2 first"
+        );
+
+        // Uninitialized db
+        assert_eq!(
+            check(0).unwrap_err().to_string(),
+            "Database schema version is 0, the database has not been initialized"
+        );
+    }
+
     #[test]
     fn migrate_schema() {
         let mut conn = crate::connection::open_in_memory().unwrap();
@@ -282,6 +489,119 @@ mod test {
         super::migrate_schema(&tx, &migrations).unwrap_err();
     }
 
+    #[test]
+    fn check_incremental_migrations() {
+        static MIGRATIONS: &[Migration] = &[
+            Migration {
+                version: 1,
+                sql: "CREATE TABLE t1 (id INTEGER)",
+            },
+            Migration {
+                version: 2,
+                sql: "CREATE TABLE t2 (id INTEGER); CREATE INDEX i2 ON t2 (id)",
+            },
+            Migration {
+                version: 3,
+                sql: "ALTER TABLE t1 ADD COLUMN name TEXT",
+            },
+        ];
+
+        let flatten = |migrations: &[Migration]| {
+            flatten_migrations(
+                &migrations
+                    .iter()
+                    .map(|m| OwnedMigration {
+                        version: m.version,
+                        sql: m.sql.to_string(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap()
+        };
+
+        super::check_incremental_migrations(MIGRATIONS, &flatten(MIGRATIONS)).unwrap();
+
+        // Diverging schema
+        let err = super::check_incremental_migrations(MIGRATIONS, &flatten(&MIGRATIONS[..2]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("- CREATE TABLE t1 (id INTEGER);"), "{err}");
+        assert!(
+            err.contains("+ CREATE TABLE t1 (id INTEGER, name TEXT);"),
+            "{err}"
+        );
+
+        // Migration failing on top of the previous version
+        let failing = &[
+            Migration {
+                version: 1,
+                sql: "CREATE TABLE t1 (id INTEGER)",
+            },
+            Migration {
+                version: 2,
+                sql: "ALTER TABLE t2 ADD COLUMN name TEXT",
+            },
+        ];
+        let err = super::check_incremental_migrations(failing, "")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Incremental migration to version 2 failed");
+    }
+
+    #[test]
+    fn pending_migrations() {
+        static MIGRATIONS: &[Migration] = &[
+            Migration {
+                version: 3,
+                sql: "CREATE TABLE t3 (id INTEGER)",
+            },
+            Migration {
+                version: 4,
+                sql: "CREATE TABLE t4 (id INTEGER)",
+            },
+            Migration {
+                version: 5,
+                sql: "CREATE TABLE t5 (id INTEGER)",
+            },
+        ];
+
+        let mut conn = crate::connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+
+        let pending = |version: u32| {
+            tx.pragma_update(None, "user_version", version).unwrap();
+            super::pending_migrations(&tx, MIGRATIONS).map(|p| {
+                (
+                    p.current,
+                    p.latest,
+                    p.migrations.iter().map(|m| m.version).collect::<Vec<_>>(),
+                )
+            })
+        };
+
+        assert_eq!(pending(3).unwrap(), (3, 5, vec![4, 5]));
+        assert_eq!(pending(4).unwrap(), (4, 5, vec![5]));
+        assert_eq!(pending(5).unwrap(), (5, 5, vec![]));
+        assert_eq!(pending(0).unwrap(), (0, 5, vec![3, 4, 5]));
+        pending(2).unwrap_err();
+        pending(6).unwrap_err();
+
+        // The database is not touched
+        pending(3).unwrap();
+        let version = tx
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, u32>(0))
+            .unwrap();
+        assert_eq!(version, 3);
+        let tables = tx
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_schema WHERE type == 'table'",
+                [],
+                |row| row.get::<_, u32>(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
     #[test]
     fn flatten_migrations() {
         let migrations = &[
@@ -322,4 +642,43 @@ CREATE VIEW v AS SELECT * FROM t1;",
             sql.trim()
         );
     }
+
+    #[test]
+    fn backup_to_file() {
+        let conn = crate::connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, data BLOB NOT NULL);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+            INSERT INTO t (data) SELECT RANDOMBLOB(4096) FROM n;",
+        )
+        .unwrap();
+
+        let dst = std::env::temp_dir().join(format!(".sqlite_backup_test_{}", std::process::id()));
+
+        // Interrupted
+        let res = super::backup_to_file(&conn, &dst, 100, |_| {}, || true);
+        assert!(res.is_err());
+
+        // Complete
+        let mut percentages = vec![];
+        super::backup_to_file(
+            &conn,
+            &dst,
+            100,
+            |p| percentages.push(backup_percentage(p)),
+            || false,
+        )
+        .unwrap();
+
+        let count: usize = rusqlite::Connection::open(&dst)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+            .unwrap();
+        std::fs::remove_file(&dst).unwrap();
+
+        assert_eq!(count, 2000);
+        assert!(percentages.len() > 10);
+        assert!(percentages.is_sorted());
+        assert_eq!(percentages.last(), Some(&100));
+    }
 }